@@ -0,0 +1,136 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use chrono::{DateTime, Duration, FixedOffset, Timelike};
+use log::info;
+use tabled::{Style, Table, Tabled};
+
+use crate::duration::format_duration;
+use crate::intent::Intent;
+use crate::job::Job;
+
+const HOUR: u32 = 3600;
+
+/// When in the hour the existing snapshots of a job were created
+#[derive(Debug, PartialEq, Eq, Tabled)]
+pub struct CreationTiming {
+    pub subvolume: String,
+    pub snapshots: usize,
+    pub earliest: String,
+    pub latest: String,
+    pub spread: String,
+    /// Whether the creation times stay clear of the full hour, so the snapshots of one schedule fall into the same bins
+    pub aligned: bool,
+}
+
+impl CreationTiming {
+    /// Analyzes the creation times of the existing snapshots of each job
+    pub fn gather(jobs: &[&Job], intents: &[Rc<RefCell<Intent>>]) -> Vec<Self> {
+        jobs.iter()
+            .map(|&job| {
+                let timestamps = intents
+                    .iter()
+                    .filter(|int| int.borrow().job == job)
+                    .filter_map(|int| int.borrow().timestamp().ok())
+                    .collect::<Vec<_>>();
+                Self::from_timestamps(&job.subvolume, &timestamps)
+            })
+            .collect()
+    }
+
+    pub fn from_timestamps(subvolume: &str, timestamps: &[DateTime<FixedOffset>]) -> Self {
+        let mut offsets = timestamps
+            .iter()
+            .map(|ts| ts.minute() * 60 + ts.second())
+            .collect::<Vec<_>>();
+        offsets.sort_unstable();
+        offsets.dedup();
+
+        // the shortest stretch of the hour holding all creation times starts after the largest gap between them
+        let (start, gap) = offsets
+            .iter()
+            .enumerate()
+            .map(|(i, &offset)| {
+                let previous = offsets[(i + offsets.len() - 1) % offsets.len()];
+                (offset, (offset + HOUR - previous - 1) % HOUR + 1)
+            })
+            .max_by_key(|&(_offset, gap)| gap)
+            .unwrap_or((0, HOUR));
+        let spread = HOUR - gap;
+        let display_offset = |offset: u32| {
+            if offsets.is_empty() {
+                "-".to_string()
+            } else {
+                format!(":{:02}:{:02}", offset / 60, offset % 60)
+            }
+        };
+
+        CreationTiming {
+            subvolume: subvolume.to_string(),
+            snapshots: timestamps.len(),
+            earliest: display_offset(start),
+            latest: display_offset((start + spread) % HOUR),
+            spread: format_duration(Duration::seconds(spread as i64)),
+            aligned: start + spread < HOUR,
+        }
+    }
+
+    pub fn print_tabled(timings: &[Self]) {
+        let table = Table::new(timings).with(Style::modern()).to_string();
+        info!("{}", table);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, FixedOffset, TimeZone};
+
+    use crate::audit::CreationTiming;
+
+    fn timestamps(times: &[(u32, u32, u32)]) -> Vec<DateTime<FixedOffset>> {
+        times
+            .iter()
+            .map(|&(h, m, s)| {
+                FixedOffset::east_opt(0)
+                    .unwrap()
+                    .with_ymd_and_hms(2022, 1, 1, h, m, s)
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn creation_times_within_the_hour() {
+        let jittered = CreationTiming::from_timestamps("/home", &timestamps(&[(0, 5, 0), (1, 7, 30), (13, 5, 10)]));
+        assert_eq!(jittered.snapshots, 3);
+        assert_eq!(
+            (jittered.earliest.as_str(), jittered.latest.as_str()),
+            (":05:00", ":07:30")
+        );
+        assert_eq!(jittered.spread, "2min");
+        assert!(jittered.aligned);
+
+        let straddling = CreationTiming::from_timestamps("/home", &timestamps(&[(23, 58, 0), (0, 1, 0), (23, 59, 0)]));
+        assert_eq!(
+            (straddling.earliest.as_str(), straddling.latest.as_str()),
+            (":58:00", ":01:00")
+        );
+        assert_eq!(straddling.spread, "3min");
+        assert!(!straddling.aligned);
+
+        let on_the_hour = CreationTiming::from_timestamps("/home", &timestamps(&[(0, 0, 0), (6, 0, 2)]));
+        assert_eq!(
+            (on_the_hour.earliest.as_str(), on_the_hour.latest.as_str()),
+            (":00:00", ":00:02")
+        );
+        assert!(on_the_hour.aligned);
+
+        let single = CreationTiming::from_timestamps("/home", &timestamps(&[(4, 20, 0)]));
+        assert_eq!(single.spread, "0min");
+        assert!(single.aligned);
+
+        let none = CreationTiming::from_timestamps("/home", &[]);
+        assert_eq!((none.snapshots, none.earliest.as_str()), (0, "-"));
+        assert!(none.aligned);
+    }
+}
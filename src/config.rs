@@ -0,0 +1,697 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fs;
+use std::io::{self, Read};
+use std::mem;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::thread;
+
+use clap::ValueEnum;
+use log::{debug, error, info, warn};
+use serde::Deserialize;
+use tabled::{Style, Table, Tabled};
+
+use crate::error::{ConfigIncludeCycleError, ConfigfileExtensionError, InvalidJobConfigError, StrictValidationError};
+use crate::filesystem::{filesystem_device, is_subvolume};
+use crate::job::{Job, JobTargets};
+use crate::notify::Notify;
+use crate::scheduling::Scheduling;
+
+/// The formats a configuration can be written in
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ConfigFormat {
+    Yaml,
+    Json,
+    Toml,
+}
+
+impl ConfigFormat {
+    /// The format of a configuration file by its extension
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "yaml" | "yml" => Some(Self::Yaml),
+            "json" => Some(Self::Json),
+            "toml" => Some(Self::Toml),
+            _ => None,
+        }
+    }
+
+    fn parse(self, content: &str) -> Result<Config, Box<dyn Error>> {
+        let config = match self {
+            Self::Yaml => serde_yaml::from_str(content)?,
+            Self::Json => serde_json::from_str(content)?,
+            Self::Toml => toml::from_str(content)?,
+        };
+
+        Ok(config)
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(from = "ConfigFile")]
+pub struct Config {
+    pub jobs: Vec<Job>,
+    /// Further configuration files whose jobs are added, relative to the directory of this file
+    pub include: Vec<String>,
+    /// Time zone of the snapshot names of the jobs of this file that don't set their own `timestamp_tz`
+    pub timestamp_tz: Option<String>,
+    /// How many filesystems are worked on at once with `--parallel-targets`, unless `--jobs` is given
+    pub max_parallel: Option<usize>,
+    /// The most bins a tier of a job's retention may have, 10000 unless set
+    pub max_retention_bins: Option<usize>,
+    /// Niceness and IO scheduling class ghee and the commands it runs work with
+    pub scheduling: Option<Scheduling>,
+    /// Where the failed intents of a run are reported to
+    pub notify: Option<Notify>,
+    /// Leave out jobs whose configuration is invalid and run the others, instead of refusing to load the configuration
+    pub skip_invalid_jobs: Option<bool>,
+    /// The jobs whose configuration is invalid, which are not in `jobs`
+    pub invalid_jobs: Vec<InvalidJob>,
+    /// The jobs that expanded to no jobs, e.g. because their `subvolume_command` failed, which are not in `jobs`
+    pub unexpanded_jobs: Vec<InvalidJob>,
+}
+
+/// A configuration as it is written, whose jobs are parsed one by one when it is converted to a `Config`
+#[derive(Deserialize)]
+struct ConfigFile {
+    #[serde(default)]
+    jobs: Vec<serde_json::Value>,
+    #[serde(default)]
+    include: Vec<String>,
+    timestamp_tz: Option<String>,
+    max_parallel: Option<usize>,
+    max_retention_bins: Option<usize>,
+    scheduling: Option<Scheduling>,
+    notify: Option<Notify>,
+    skip_invalid_jobs: Option<bool>,
+}
+
+impl From<ConfigFile> for Config {
+    fn from(file: ConfigFile) -> Self {
+        let (jobs, invalid_jobs) = parse_jobs(file.jobs);
+        Self {
+            jobs,
+            include: file.include,
+            timestamp_tz: file.timestamp_tz,
+            max_parallel: file.max_parallel,
+            max_retention_bins: file.max_retention_bins,
+            scheduling: file.scheduling,
+            notify: file.notify,
+            skip_invalid_jobs: file.skip_invalid_jobs,
+            invalid_jobs,
+            unexpanded_jobs: Vec::new(),
+        }
+    }
+}
+
+/// A job that was left out of the configuration because its configuration is invalid
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InvalidJob {
+    pub subvolume: String,
+    pub target: String,
+    pub error: String,
+}
+
+/// Parses each job on its own, so that a malformed job is set apart instead of failing the whole configuration
+fn parse_jobs(values: Vec<serde_json::Value>) -> (Vec<Job>, Vec<InvalidJob>) {
+    let mut jobs = Vec::new();
+    let mut invalid_jobs = Vec::new();
+    for (i, value) in values.into_iter().enumerate() {
+        let subvolume = value
+            .get("subvolume")
+            .and_then(|s| s.as_str())
+            .unwrap_or("?")
+            .to_string();
+        let target = value.get("target").and_then(|t| t.as_str()).unwrap_or("?").to_string();
+        let parsed = split_targets(value).and_then(|values| {
+            values
+                .into_iter()
+                .map(serde_json::from_value)
+                .collect::<Result<Vec<Job>, _>>()
+        });
+        match parsed {
+            Ok(parsed) => jobs.extend(parsed),
+            Err(e) => {
+                error!(
+                    "job #{} ({}) is invalid and can't be run!\nerror: {}",
+                    i + 1,
+                    subvolume,
+                    e
+                );
+                invalid_jobs.push(InvalidJob {
+                    subvolume,
+                    target,
+                    error: e.to_string(),
+                });
+            }
+        }
+    }
+
+    (jobs, invalid_jobs)
+}
+
+/// Splits a job whose `target` is a list into a job per entry, which has the entry's `path` as its target and the
+/// entry's `preserve`, if it sets one, instead of the job's
+fn split_targets(value: serde_json::Value) -> Result<Vec<serde_json::Value>, serde_json::Error> {
+    let entries = match value.get("target").map(JobTargets::deserialize).transpose()? {
+        Some(JobTargets::Multiple(entries)) => entries,
+        _ => return Ok(vec![value]),
+    };
+    if entries.is_empty() {
+        let subvolume = value.get("subvolume").and_then(|s| s.as_str()).unwrap_or("?");
+        warn!(
+            "the job of {} has an empty list of targets, no jobs are created from it",
+            subvolume
+        );
+    }
+
+    let jobs = entries
+        .into_iter()
+        .map(|entry| {
+            let mut job = value.clone();
+            job["target"] = serde_json::Value::String(entry.path);
+            if let Some(preserve) = entry.preserve {
+                job["preserve"] = preserve;
+            }
+            job
+        })
+        .collect();
+
+    Ok(jobs)
+}
+
+impl Config {
+    /// Loads the configuration file in the given format, else the one of its extension, along with all files it
+    /// includes. If `path` is a directory, all configuration files in it are loaded. A `path` of `-` reads the
+    /// configuration from stdin, whose includes are resolved relative to the working directory.
+    /// Fails if a job is invalid, unless the configuration sets `skip_invalid_jobs`.
+    pub fn load(path: &Path, format: Option<ConfigFormat>) -> Result<Self, Box<dyn Error>> {
+        let mut config = if path == Path::new("-") {
+            Self::load_stdin(format)?
+        } else if path.is_dir() {
+            Self::load_directory(path)?
+        } else {
+            Self::load_including(path, format, &mut Vec::new())?
+        };
+
+        for job in &mut config.jobs {
+            job.max_retention_bins = config.max_retention_bins;
+        }
+        config.set_apart_invalid_jobs(|job| {
+            job.retention().map(|_| ()).map_err(|e| {
+                let retention = job.preserve.retention.clone().unwrap_or_default();
+                format!("invalid preserve retention {:?}: {}", retention, e)
+            })
+        });
+        config.check_invalid_jobs()?;
+
+        Ok(config)
+    }
+
+    /// Moves the jobs that `check` finds invalid to `invalid_jobs`, logging why each of them is invalid
+    pub fn set_apart_invalid_jobs(&mut self, check: impl Fn(&Job) -> Result<(), String>) {
+        for job in mem::take(&mut self.jobs) {
+            match check(&job) {
+                Ok(()) => self.jobs.push(job),
+                Err(e) => {
+                    error!(
+                        "job of {} to {} is invalid and can't be run!\nerror: {}",
+                        &job.subvolume, &job.target, e
+                    );
+                    self.invalid_jobs.push(InvalidJob {
+                        subvolume: job.subvolume,
+                        target: job.target,
+                        error: e,
+                    });
+                }
+            }
+        }
+    }
+
+    /// Fails if any job was found invalid, unless the configuration sets `skip_invalid_jobs`, so that the other jobs
+    /// run. Each invalid job was logged when it was found.
+    pub fn check_invalid_jobs(&self) -> Result<(), Box<dyn Error>> {
+        if self.invalid_jobs.is_empty() || self.skip_invalid_jobs.unwrap_or(false) {
+            return Ok(());
+        }
+
+        error!(
+            "{} jobs are invalid, refusing to run any jobs! Set `skip_invalid_jobs: true` to run the others.",
+            self.invalid_jobs.len()
+        );
+        Err(Box::new(InvalidJobConfigError))
+    }
+
+    fn load_stdin(format: Option<ConfigFormat>) -> Result<Self, Box<dyn Error>> {
+        let format = match format {
+            Some(format) => format,
+            None => {
+                error!("the format of a configuration read from stdin must be given with --config-format!");
+                return Err(Box::new(ConfigfileExtensionError));
+            }
+        };
+
+        let mut content = String::new();
+        io::stdin().read_to_string(&mut content)?;
+        debug!("configuration content of stdin:\n{}", content);
+
+        format.parse(&content)?.resolve(Path::new("-"), &mut Vec::new())
+    }
+
+    /// Loads every configuration file in `directory` in the order of their names and concatenates their jobs. The
+    /// other settings are taken from the first file that sets them.
+    fn load_directory(directory: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut config = Self::default();
+        let mut sources: BTreeMap<(String, String), PathBuf> = BTreeMap::new();
+        for path in config_files(directory)? {
+            let fragment = Self::load_including(&path, None, &mut Vec::new())?;
+            for job in &fragment.jobs {
+                let key = (job.subvolume.clone(), job.target.clone());
+                if let Some(other) = sources.get(&key) {
+                    warn!(
+                        "the job of {} to {} is configured in both {:?} and {:?}",
+                        &job.subvolume, &job.target, other, path
+                    );
+                } else {
+                    sources.insert(key, path.clone());
+                }
+            }
+
+            config.jobs.extend(fragment.jobs);
+            config.invalid_jobs.extend(fragment.invalid_jobs);
+            config.unexpanded_jobs.extend(fragment.unexpanded_jobs);
+            config.max_parallel = config.max_parallel.or(fragment.max_parallel);
+            config.max_retention_bins = config.max_retention_bins.or(fragment.max_retention_bins);
+            config.scheduling = config.scheduling.or(fragment.scheduling);
+            config.notify = config.notify.or(fragment.notify);
+            config.skip_invalid_jobs = config.skip_invalid_jobs.or(fragment.skip_invalid_jobs);
+        }
+
+        Ok(config)
+    }
+
+    fn load_including(
+        path: &Path,
+        format: Option<ConfigFormat>,
+        including: &mut Vec<PathBuf>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let canonical = fs::canonicalize(path)?;
+        if including.contains(&canonical) {
+            error!("configuration file {:?} includes itself via {:?}!", path, including);
+            return Err(Box::new(ConfigIncludeCycleError));
+        }
+
+        let config = Self::parse_file(path, format)?;
+        including.push(canonical);
+        let config = config.resolve(path, including);
+        including.pop();
+
+        config
+    }
+
+    /// Expands the jobs of a configuration read from `path` and adds the jobs of the files it includes
+    fn resolve(self, path: &Path, including: &mut Vec<PathBuf>) -> Result<Self, Box<dyn Error>> {
+        let mut config = self;
+        for job in &mut config.jobs {
+            job.expand_variables()?;
+        }
+        for job in mem::take(&mut config.jobs) {
+            let subvolume = job.subvolume_command.clone().unwrap_or_else(|| job.subvolume.clone());
+            let target = job.target.clone();
+            let expanded = job.expand(is_subvolume);
+            if expanded.is_empty() {
+                config.unexpanded_jobs.push(InvalidJob {
+                    subvolume,
+                    target,
+                    error: "expanded to no jobs".to_string(),
+                });
+            }
+            config.jobs.extend(expanded);
+        }
+
+        let directory = path.parent().unwrap_or_else(|| Path::new("."));
+        for include in mem::take(&mut config.include) {
+            let included = Self::load_including(&directory.join(include), None, including)?;
+            config.jobs.extend(included.jobs);
+            config.invalid_jobs.extend(included.invalid_jobs);
+            config.unexpanded_jobs.extend(included.unexpanded_jobs);
+        }
+
+        // included files may set their own time zone, which takes precedence for their jobs
+        if let Some(tz) = &config.timestamp_tz {
+            for job in config.jobs.iter_mut().filter(|j| j.timestamp_tz.is_none()) {
+                job.timestamp_tz = Some(tz.clone());
+                job.origins
+                    .insert("timestamp_tz".to_string(), format!("config file {}", path.display()));
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// How many filesystems to work on at once: the `--jobs` given on the command line, else the configured
+    /// `max_parallel`, else the available parallelism of the machine
+    pub fn max_parallel(&self, cli: Option<usize>) -> usize {
+        cli.or(self.max_parallel)
+            .unwrap_or_else(|| thread::available_parallelism().map_or(1, NonZeroUsize::get))
+            .max(1)
+    }
+
+    fn parse_file(path: &Path, format: Option<ConfigFormat>) -> Result<Self, Box<dyn Error>> {
+        let config = fs::read_to_string(path)?;
+        debug!("configuration content of {:?}:\n{}", path, config);
+
+        let format = format
+            .or_else(|| ConfigFormat::from_path(path))
+            .ok_or(ConfigfileExtensionError)?;
+        format.parse(&config)
+    }
+
+    pub fn validate(&self, strict: bool) -> Result<(), Box<dyn Error>> {
+        let mut valid = true;
+
+        if self.jobs.is_empty() {
+            warn!("no jobs are configured, there is nothing to do");
+            valid = false;
+        }
+
+        // invalid jobs were logged when they were found, and only get here with `skip_invalid_jobs`
+        if !self.invalid_jobs.is_empty() {
+            valid = false;
+        }
+
+        for job in &self.jobs {
+            for warning in job.validate() {
+                warn!("{}", warning);
+                valid = false;
+            }
+        }
+
+        let mut group_devices: BTreeMap<&str, BTreeSet<String>> = BTreeMap::new();
+        for job in &self.jobs {
+            let device = match filesystem_device(Path::new(&job.target)) {
+                Some(device) => device,
+                None => continue,
+            };
+            debug!(
+                "target {} of job {} is on device {}",
+                &job.target, &job.subvolume, device
+            );
+            for group in job.groups.iter().flatten() {
+                group_devices.entry(group.as_str()).or_default().insert(device.clone());
+            }
+        }
+        for (group, devices) in group_devices {
+            if devices.len() > 1 {
+                warn!(
+                    "the targets of the jobs in group {} are on different filesystems (devices {:?})",
+                    group, devices
+                );
+                valid = false;
+            }
+        }
+
+        if strict && !valid {
+            return Err(Box::new(StrictValidationError));
+        }
+
+        Ok(())
+    }
+}
+
+/// The files in `directory` with a configuration extension, sorted by name
+fn config_files(directory: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut files = fs::read_dir(directory)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.is_file())
+        .filter(|path| ConfigFormat::from_path(path).is_some())
+        .collect::<Vec<_>>();
+    files.sort();
+
+    Ok(files)
+}
+
+/// The outcome of loading one configuration file of a directory
+#[derive(Debug, Tabled)]
+pub struct ConfigCheck {
+    pub file: String,
+    pub status: String,
+    pub error: String,
+}
+
+impl ConfigCheck {
+    /// Loads every configuration file in `directory`, including the files each one includes. Files without a
+    /// configuration extension are ignored.
+    pub fn gather(directory: &Path) -> Result<Vec<Self>, Box<dyn Error>> {
+        let checks = config_files(directory)?
+            .iter()
+            .map(|path| {
+                let (status, error) = match Config::load(path, None) {
+                    Ok(_) => ("ok", String::new()),
+                    Err(e) => ("failed", e.to_string()),
+                };
+                Self {
+                    file: path.display().to_string(),
+                    status: status.to_string(),
+                    error,
+                }
+            })
+            .collect();
+
+        Ok(checks)
+    }
+
+    pub fn failed(&self) -> bool {
+        self.status != "ok"
+    }
+
+    pub fn print_tabled(checks: &[Self]) {
+        let table = Table::new(checks).with(Style::modern()).to_string();
+        info!("{}", table);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use crate::config::{Config, ConfigCheck, ConfigFormat};
+
+    #[test]
+    fn empty_jobs() {
+        let config: Config = serde_yaml::from_str("jobs: []").unwrap();
+        assert!(config.jobs.is_empty());
+        assert!(config.validate(false).is_ok());
+        assert!(config.validate(true).is_err());
+
+        let config: Config = serde_yaml::from_str("include: []").unwrap();
+        assert!(config.validate(true).is_err());
+    }
+
+    #[test]
+    fn max_parallel_precedence() {
+        let configured: Config = serde_yaml::from_str("{max_parallel: 3, jobs: []}").unwrap();
+        let unconfigured: Config = serde_yaml::from_str("jobs: []").unwrap();
+
+        assert_eq!(configured.max_parallel(Some(2)), 2);
+        assert_eq!(configured.max_parallel(None), 3);
+        assert_eq!(unconfigured.max_parallel(Some(2)), 2);
+        assert!(unconfigured.max_parallel(None) >= 1);
+        assert_eq!(configured.max_parallel(Some(0)), 1);
+    }
+
+    #[test]
+    fn nested_includes_and_cycles() {
+        let dir = std::env::temp_dir().join(format!("ghee-include-test-{}", std::process::id()));
+        fs::create_dir_all(dir.join("fragments")).unwrap();
+
+        fs::write(
+            dir.join("ghee.yaml"),
+            "include: [fragments/etc.json]\njobs:\n  - subvolume: /home\n    target: /snaps\n    preserve:\n      min: all\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("fragments/etc.json"),
+            r#"{"include": ["postgres.toml"], "jobs": [{"subvolume": "/etc", "target": "/snaps", "preserve": {"min": 5}}]}"#,
+        )
+        .unwrap();
+        fs::write(
+            dir.join("fragments/postgres.toml"),
+            "[[jobs]]\nsubvolume = \"/var/lib/postgres\"\ntarget = \"/snaps\"\n[jobs.preserve]\nmin = \"all\"\n",
+        )
+        .unwrap();
+
+        let config = Config::load(&dir.join("ghee.yaml"), None).unwrap();
+        let subvolumes = config.jobs.iter().map(|j| j.subvolume.as_str()).collect::<Vec<_>>();
+        assert_eq!(subvolumes, vec!["/home", "/etc", "/var/lib/postgres"]);
+
+        fs::write(dir.join("a.yaml"), "include: [b.yaml]\n").unwrap();
+        fs::write(dir.join("b.yaml"), "include: [a.yaml]\n").unwrap();
+        assert!(Config::load(&dir.join("a.yaml"), None).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn splits_list_of_targets() {
+        let config: Config = serde_yaml::from_str(
+            "jobs:
+  - subvolume: /home
+    target:
+      - path: /snaps
+      - path: /mnt/backup
+        preserve: {min: 30d, retention: 12m}
+    preserve: {min: 5}
+  - subvolume: /etc
+    target: /snaps
+    preserve: {min: all}
+",
+        )
+        .unwrap();
+
+        let targets = config
+            .jobs
+            .iter()
+            .map(|j| (j.subvolume.as_str(), j.target.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            targets,
+            vec![("/home", "/snaps"), ("/home", "/mnt/backup"), ("/etc", "/snaps")]
+        );
+        assert_eq!(config.jobs[0].preserve.retention, None);
+        assert_eq!(config.jobs[1].preserve.retention, Some("12m".to_string()));
+    }
+
+    #[test]
+    fn invalid_jobs_fail_unless_skipped() {
+        let dir = std::env::temp_dir().join(format!("ghee-invalid-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let jobs = "jobs:
+  - {subvolume: /home, target: /snaps, preserve: {min: all}}
+  - {subvolume: /srv, target: /snaps, preserve: {min: all}, layout: sideways}
+";
+        fs::write(dir.join("strict.yaml"), jobs).unwrap();
+        fs::write(dir.join("lenient.yaml"), format!("skip_invalid_jobs: true\n{}", jobs)).unwrap();
+
+        assert!(Config::load(&dir.join("strict.yaml"), None).is_err());
+        let config = Config::load(&dir.join("lenient.yaml"), None).unwrap();
+        assert_eq!(config.jobs.len(), 1);
+        assert_eq!(config.jobs[0].subvolume, "/home");
+        assert_eq!(config.invalid_jobs.len(), 1);
+        assert_eq!(config.invalid_jobs[0].subvolume, "/srv");
+        assert_eq!(config.invalid_jobs[0].target, "/snaps");
+        assert!(config.validate(false).is_ok());
+        assert!(config.validate(true).is_err());
+
+        // a template that expands to no jobs is kept apart, without failing the configuration
+        fs::write(
+            dir.join("template.yaml"),
+            "jobs:\n  - {subvolume_command: 'false', target: '/snaps/{name}', preserve: {min: all}}\n",
+        )
+        .unwrap();
+        let config = Config::load(&dir.join("template.yaml"), None).unwrap();
+        assert!(config.jobs.is_empty());
+        assert_eq!(config.unexpanded_jobs.len(), 1);
+        assert_eq!(config.unexpanded_jobs[0].subvolume, "false");
+        assert_eq!(config.unexpanded_jobs[0].target, "/snaps/{name}");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn configurable_retention_limit() {
+        let dir = std::env::temp_dir().join(format!("ghee-retention-limit-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let jobs = "jobs:\n  - {subvolume: /home, target: /snaps, preserve: {retention: 20000h, min: 3}}\n";
+        fs::write(dir.join("default.yaml"), jobs).unwrap();
+        fs::write(dir.join("raised.yaml"), format!("max_retention_bins: 20000\n{}", jobs)).unwrap();
+
+        assert!(Config::load(&dir.join("default.yaml"), None).is_err());
+        let config = Config::load(&dir.join("raised.yaml"), None).unwrap();
+        assert_eq!(config.jobs[0].max_retention_bins, Some(20000));
+        assert_eq!(config.jobs[0].retention().unwrap().h, 20000);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn format_overrides_extension() {
+        let dir = std::env::temp_dir().join(format!("ghee-format-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("ghee.conf"),
+            r#"{"jobs": [{"subvolume": "/home", "target": "/snaps", "preserve": {"min": 5}}]}"#,
+        )
+        .unwrap();
+
+        assert!(Config::load(&dir.join("ghee.conf"), None).is_err());
+        let config = Config::load(&dir.join("ghee.conf"), Some(ConfigFormat::Json)).unwrap();
+        assert_eq!(config.jobs[0].subvolume, "/home");
+        assert_eq!(ConfigFormat::from_path(Path::new("ghee.yml")), Some(ConfigFormat::Yaml));
+        assert_eq!(ConfigFormat::from_path(Path::new("-")), None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn loads_directory_of_fragments() {
+        let dir = std::env::temp_dir().join(format!("ghee-fragments-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        fs::write(
+            dir.join("20-home.yaml"),
+            "max_parallel: 4\njobs:\n  - subvolume: /home\n    target: /snaps\n    preserve:\n      min: all\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("10-postgres.toml"),
+            "max_parallel = 2\n[[jobs]]\nsubvolume = \"/var/lib/postgres\"\ntarget = \"/snaps\"\n[jobs.preserve]\nmin = \"all\"\n",
+        )
+        .unwrap();
+        // the same job again only warns
+        fs::write(
+            dir.join("30-home.json"),
+            r#"{"jobs": [{"subvolume": "/home", "target": "/snaps", "preserve": {"min": 5}}]}"#,
+        )
+        .unwrap();
+        fs::write(dir.join("README"), "not a configuration").unwrap();
+
+        let config = Config::load(&dir, None).unwrap();
+        let subvolumes = config.jobs.iter().map(|j| j.subvolume.as_str()).collect::<Vec<_>>();
+        assert_eq!(subvolumes, vec!["/var/lib/postgres", "/home", "/home"]);
+        assert_eq!(config.max_parallel, Some(2));
+
+        fs::write(dir.join("40-broken.yaml"), "jobs: [").unwrap();
+        assert!(Config::load(&dir, None).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn checks_directory() {
+        let dir = std::env::temp_dir().join(format!("ghee-config-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        fs::write(
+            dir.join("a.yaml"),
+            "jobs:\n  - subvolume: /home\n    target: /snaps\n    preserve:\n      min: all\n",
+        )
+        .unwrap();
+        fs::write(dir.join("b.json"), r#"{"jobs": [}"#).unwrap();
+        fs::write(dir.join("c.toml"), "include = [\"missing.toml\"]\n").unwrap();
+        fs::write(dir.join("README"), "not a configuration").unwrap();
+
+        let checks = ConfigCheck::gather(&dir).unwrap();
+        let outcomes = checks
+            .iter()
+            .map(|c| (c.file.rsplit('/').next().unwrap(), c.failed()))
+            .collect::<Vec<_>>();
+        assert_eq!(outcomes, vec![("a.yaml", false), ("b.json", true), ("c.toml", true)]);
+        assert!(checks[0].error.is_empty());
+        assert!(!checks[1].error.is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,151 @@
+use std::error::Error;
+use std::fmt::Write;
+
+use serde::Serialize;
+
+use crate::filesystem::FoundSubvolume;
+use crate::job::is_excluded;
+
+/// Retention suggested for discovered subvolumes
+const STARTER_RETENTION: &str = "48h 14d 4w 6m";
+
+/// Directory the snapshots of a filesystem are suggested to go to, below its mount closest to the top level
+const STARTER_TARGET: &str = "gheesnaps";
+
+#[derive(Serialize)]
+struct StarterJob {
+    subvolume: String,
+    target: String,
+    preserve: StarterPreserve,
+}
+
+#[derive(Serialize)]
+struct StarterPreserve {
+    retention: String,
+    min: String,
+}
+
+/// Drops the found subvolumes whose path within the filesystem or location matches any of the glob `patterns`
+pub fn without_excluded(found: Vec<FoundSubvolume>, patterns: &[String]) -> Vec<FoundSubvolume> {
+    found
+        .into_iter()
+        .filter(|s| {
+            let paths = [Some(&s.subvolume), s.location.as_ref()];
+            !paths
+                .iter()
+                .flatten()
+                .any(|path| is_excluded(&path.to_string_lossy(), patterns))
+        })
+        .collect()
+}
+
+/// Renders a starter configuration with a job for each found subvolume that is accessible, and a comment for each
+/// that isn't
+pub fn render(found: &[FoundSubvolume]) -> Result<String, Box<dyn Error>> {
+    let mut config = String::from("# Starter configuration found by `ghee discover`, review it before use\n");
+    if found.iter().any(|s| s.location.is_some()) {
+        config.push_str("jobs:\n");
+    } else {
+        config.push_str("jobs: []\n");
+    }
+
+    for subvolume in found {
+        let location = match &subvolume.location {
+            Some(location) => location,
+            None => {
+                let _ = writeln!(
+                    config,
+                    "  # subvolume {} of device {} is not mounted, mount it to snapshot it",
+                    subvolume.subvolume.display(),
+                    &subvolume.device
+                );
+                continue;
+            }
+        };
+
+        let _ = writeln!(
+            config,
+            "  # subvolume {} of device {}, {} at {}",
+            subvolume.subvolume.display(),
+            &subvolume.device,
+            if subvolume.mounted { "mounted" } else { "accessible" },
+            location.display()
+        );
+        let job = StarterJob {
+            subvolume: location.to_string_lossy().to_string(),
+            target: subvolume
+                .filesystem_mount
+                .join(STARTER_TARGET)
+                .to_string_lossy()
+                .to_string(),
+            preserve: StarterPreserve {
+                retention: STARTER_RETENTION.to_string(),
+                min: "latest".to_string(),
+            },
+        };
+        for line in serde_yaml::to_string(&[job])?.lines() {
+            let _ = writeln!(config, "  {}", line);
+        }
+    }
+
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::config::Config;
+    use crate::discover::{render, without_excluded};
+    use crate::filesystem::FoundSubvolume;
+
+    #[test]
+    fn renders_starter_config() {
+        let found = |subvolume: &str, location: Option<&str>, mounted| FoundSubvolume {
+            device: "0:30".to_string(),
+            subvolume: PathBuf::from(subvolume),
+            location: location.map(PathBuf::from),
+            mounted,
+            filesystem_mount: PathBuf::from("/mnt/btrfs"),
+        };
+        let rendered = render(&[
+            found("/@home", Some("/home"), true),
+            found("/@/var/lib/machines", Some("/var/lib/machines"), false),
+            found("/@old", None, false),
+        ])
+        .unwrap();
+
+        assert!(rendered.contains("  # subvolume /@home of device 0:30, mounted at /home\n"));
+        assert!(
+            rendered.contains("  # subvolume /@/var/lib/machines of device 0:30, accessible at /var/lib/machines\n")
+        );
+        assert!(rendered.contains("  # subvolume /@old of device 0:30 is not mounted, mount it to snapshot it\n"));
+
+        let config: Config = serde_yaml::from_str(&rendered).unwrap();
+        let jobs = config
+            .jobs
+            .iter()
+            .map(|j| (j.subvolume.as_str(), j.target.as_str(), j.preserve.retention.as_deref()))
+            .collect::<Vec<_>>();
+        let kept = without_excluded(
+            vec![
+                found("/@home", Some("/home"), true),
+                found("/@/var/lib/docker/btrfs/1", Some("/var/lib/docker/btrfs/1"), false),
+                found("/@snapshots", None, false),
+            ],
+            &["/var/lib/docker/*".to_string(), "/@snapshots".to_string()],
+        );
+        assert_eq!(kept, vec![found("/@home", Some("/home"), true)]);
+
+        let empty: Config = serde_yaml::from_str(&render(&[found("/@old", None, false)]).unwrap()).unwrap();
+        assert!(empty.jobs.is_empty());
+
+        assert_eq!(
+            jobs,
+            vec![
+                ("/home", "/mnt/btrfs/gheesnaps", Some("48h 14d 4w 6m")),
+                ("/var/lib/machines", "/mnt/btrfs/gheesnaps", Some("48h 14d 4w 6m")),
+            ]
+        );
+    }
+}
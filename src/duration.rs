@@ -1,19 +1,67 @@
 use std::error::Error;
 use std::ops::Add;
 
-use chrono::LocalResult::Single;
-use chrono::{DateTime, Datelike, Duration, FixedOffset, TimeZone, Timelike, Weekday};
+use chrono::{DateTime, Datelike, Duration, FixedOffset, NaiveDate, NaiveDateTime, TimeZone, Timelike};
 use log::trace;
 use regex::Regex;
 
-use crate::error::DurationParseError;
+use crate::error::{DurationDuplicateUnitError, DurationParseError, DurationUnitOrderError};
+
+/// The units of durations and retentions, in the order they must be given in. `M` are minutes, only used in retentions.
+const UNITS: [&str; 6] = ["M", "h", "d", "w", "m", "y"];
+
+/// Explains why `s` isn't a valid duration. Units that are repeated or out of order are pointed out, since the
+/// string is otherwise well-formed.
+pub fn duration_parse_error(s: &str) -> Box<dyn Error> {
+    let well_formed = Regex::new(r"^\s*(?:\d+[Mhdwmy]\s*)+$").expect("duration token regex is valid");
+    if !well_formed.is_match(s) {
+        return Box::new(DurationParseError);
+    }
+
+    let token = Regex::new(r"\d+([Mhdwmy])").expect("duration token regex is valid");
+    let ranks = token
+        .captures_iter(s)
+        .filter_map(|c| UNITS.iter().position(|u| *u == &c[1]))
+        .collect::<Vec<_>>();
+    if ranks.iter().enumerate().any(|(i, r)| ranks[..i].contains(r)) {
+        Box::new(DurationDuplicateUnitError)
+    } else if ranks.windows(2).any(|w| w[0] > w[1]) {
+        Box::new(DurationUnitOrderError)
+    } else {
+        Box::new(DurationParseError)
+    }
+}
+
+/// Parses an ISO 8601 duration made of days, hours, minutes and seconds, e.g. `P7D` or `PT1H30M`
+fn duration_from_iso(s: &str) -> Result<Duration, Box<dyn Error>> {
+    let re = Regex::new(r"^P(?:(\d+)D)?(?:T(?:(\d+)H)?(?:(\d+)M)?(?:(\d+)S)?)?$")?;
+    let capture = re.captures(s).ok_or(DurationParseError)?;
+    if s == "P" || s.ends_with('T') {
+        return Err(Box::new(DurationParseError));
+    }
+
+    let mut d = Duration::zero();
+    let units: [fn(i64) -> Duration; 4] = [Duration::days, Duration::hours, Duration::minutes, Duration::seconds];
+    for (i, unit) in units.iter().enumerate() {
+        if let Some(n) = capture.get(i + 1) {
+            d = d.add(unit(n.as_str().parse()?));
+        }
+    }
+
+    Ok(d)
+}
+
+/// Splits a duration such as `3m 1y` into its part of fixed length, its months and its years
+fn duration_parts(s: &str) -> Result<(Duration, i64, i64), Box<dyn Error>> {
+    if s.starts_with('P') {
+        return Ok((duration_from_iso(s)?, 0, 0));
+    }
 
-pub fn duration_from_str(s: &str) -> Result<Duration, Box<dyn Error>> {
     let re = Regex::new(r"^(?:(\d+)h)?\s*(?:(\d+)d)?\s*(?:(\d+)w)?\s*(?:(\d+)m)?\s*(?:(\d+)y)?$")?;
     let mut d = Duration::zero();
 
     if !re.is_match(s) {
-        return Err(Box::new(DurationParseError));
+        return Err(duration_parse_error(s));
     };
 
     let capture = re.captures(s).ok_or(Box::new(DurationParseError))?;
@@ -39,119 +87,156 @@ pub fn duration_from_str(s: &str) -> Result<Duration, Box<dyn Error>> {
     if let Some(w) = weeks {
         d = d.add(Duration::weeks(w.as_str().parse()?));
     }
-    if let Some(m) = months {
-        d = d.add(Duration::weeks(4 * m.as_str().parse::<i64>()?));
-    }
-    if let Some(y) = years {
-        d = d.add(Duration::days(365 * y.as_str().parse::<i64>()?));
-    }
+    let months = match months {
+        Some(m) => m.as_str().parse::<i64>()?,
+        None => 0,
+    };
+    let years = match years {
+        Some(y) => y.as_str().parse::<i64>()?,
+        None => 0,
+    };
 
-    Ok(d)
+    Ok((d, months, years))
 }
 
-pub fn duration_trunc_hour(ts: &DateTime<FixedOffset>) -> DateTime<FixedOffset> {
-    FixedOffset::from_offset(&ts.timezone())
-        .ymd(ts.year(), ts.month(), ts.day())
-        .and_hms(ts.hour(), 0, 0)
+/// Parses a duration such as `2d 12h`, or in ISO 8601 such as `P2DT12H`. A month is taken as 4 weeks and a year as
+/// 365 days; use `subtract_from` to go back by calendar months and years.
+pub fn duration_from_str(s: &str) -> Result<Duration, Box<dyn Error>> {
+    let (d, months, years) = duration_parts(s)?;
+
+    Ok(d + Duration::weeks(4 * months) + Duration::days(365 * years))
 }
 
-pub fn duration_trunc_day(ts: &DateTime<FixedOffset>) -> DateTime<FixedOffset> {
-    FixedOffset::from_offset(&ts.timezone())
-        .ymd(ts.year(), ts.month(), ts.day())
-        .and_hms(0, 0, 0)
+/// The point in time the duration `s` lies before `now`. Months and years are calendar months and years, so `3m`
+/// before May 15th is February 15th. A day that doesn't exist in the resulting month becomes its last day, e.g.
+/// `1y` before February 29th is February 28th.
+pub fn subtract_from(now: &DateTime<FixedOffset>, s: &str) -> Result<DateTime<FixedOffset>, Box<dyn Error>> {
+    let (d, months, years) = duration_parts(s)?;
+
+    Ok(sub_months(now, months + 12 * years).ok_or(DurationParseError)? - d)
 }
 
-pub fn duration_trunc_week(ts: &DateTime<FixedOffset>) -> DateTime<FixedOffset> {
-    let year = ts.year();
-
-    // last week of last year?
-    let mon = FixedOffset::from_offset(&ts.timezone())
-        .isoywd(year - 1, 52, Weekday::Mon)
-        .and_hms(0, 0, 0);
-    let sun = FixedOffset::from_offset(&ts.timezone())
-        .isoywd(year - 1, 52, Weekday::Sun)
-        .and_hms(23, 59, 59);
-    if mon <= *ts && *ts <= sun {
-        return FixedOffset::from_offset(&ts.timezone())
-            .isoywd(year - 1, 52, Weekday::Mon)
-            .and_hms(0, 0, 0);
-    }
+/// Goes back by calendar months, keeping the time of day and clamping the day to the length of the month
+pub fn sub_months(ts: &DateTime<FixedOffset>, months: i64) -> Option<DateTime<FixedOffset>> {
+    let total = i64::from(ts.year()) * 12 + i64::from(ts.month0()) - months;
+    let year = i32::try_from(total.div_euclid(12)).ok()?;
+    let month = total.rem_euclid(12) as u32 + 1;
+    let date = (1..=ts.day())
+        .rev()
+        .find_map(|day| NaiveDate::from_ymd_opt(year, month, day))?;
 
-    // last week of last year? (sometimes years have 53 weeks)
-    let mon = FixedOffset::from_offset(&ts.timezone()).isoywd_opt(year - 1, 53, Weekday::Mon);
-    if let Single(mon) = mon {
-        let mon = mon.and_hms(0, 0, 0);
-        let sun = FixedOffset::from_offset(&ts.timezone())
-            .isoywd(year - 1, 53, Weekday::Sun)
-            .and_hms(23, 59, 59);
-        if mon <= *ts && *ts <= sun {
-            return FixedOffset::from_offset(&ts.timezone())
-                .isoywd(year - 1, 53, Weekday::Mon)
-                .and_hms(0, 0, 0);
-        }
+    ts.timezone().from_local_datetime(&date.and_time(ts.time())).single()
+}
+
+/// Formats a duration compactly using its two most significant units, e.g. `2d 3h` or `5h 12min`
+pub fn format_duration(d: Duration) -> String {
+    let days = d.num_days();
+    let hours = d.num_hours() - days * 24;
+    let minutes = d.num_minutes() - d.num_hours() * 60;
+
+    if days > 0 {
+        format!("{}d {}h", days, hours)
+    } else if hours > 0 {
+        format!("{}h {}min", hours, minutes)
+    } else {
+        format!("{}min", minutes)
     }
+}
 
-    // any week this year? (rarely years have 53 weeks)
-    for i in 1..=53 {
-        let mon = FixedOffset::from_offset(&ts.timezone())
-            .isoywd(year, i, Weekday::Mon)
-            .and_hms(0, 0, 0);
-        let sun = FixedOffset::from_offset(&ts.timezone())
-            .isoywd(year, i, Weekday::Sun)
-            .and_hms(23, 59, 59);
-        if mon <= *ts && *ts <= sun {
-            return FixedOffset::from_offset(&ts.timezone())
-                .isoywd(year, i, Weekday::Mon)
-                .and_hms(0, 0, 0);
+/// Formats a duration in ISO 8601, e.g. `P2DT3H` or `PT5H12M`, for tools that expect that format
+pub fn format_duration_iso(d: Duration) -> String {
+    let sign = if d < Duration::zero() { "-" } else { "" };
+    let seconds = d.num_seconds().abs();
+    let (days, hours, minutes, seconds) = (seconds / 86400, seconds / 3600 % 24, seconds / 60 % 60, seconds % 60);
+
+    let mut iso = format!("{}P", sign);
+    if days > 0 {
+        iso.push_str(&format!("{}D", days));
+    }
+    if hours > 0 || minutes > 0 || seconds > 0 || days == 0 {
+        iso.push('T');
+        for (n, unit) in [(hours, 'H'), (minutes, 'M'), (seconds, 'S')] {
+            if n > 0 {
+                iso.push_str(&format!("{}{}", n, unit));
+            }
+        }
+        if iso.ends_with('T') {
+            iso.push_str("0S");
         }
     }
 
-    // first week of next year?
-    let mon = FixedOffset::from_offset(&ts.timezone())
-        .isoywd(year + 1, 1, Weekday::Mon)
-        .and_hms(0, 0, 0);
-    let sun = FixedOffset::from_offset(&ts.timezone())
-        .isoywd(year + 1, 1, Weekday::Sun)
-        .and_hms(23, 59, 59);
-    if mon <= *ts && *ts <= sun {
-        return FixedOffset::from_offset(&ts.timezone())
-            .isoywd(year + 1, 1, Weekday::Mon)
-            .and_hms(0, 0, 0);
-    }
+    iso
+}
 
-    panic!("did not find week");
+/// `local` with the offset of `ts`. A fixed offset has every local time exactly once, but should `local` not exist,
+/// `ts` itself is returned rather than panicking in the middle of a run.
+fn at_local_time(ts: &DateTime<FixedOffset>, local: Option<NaiveDateTime>) -> DateTime<FixedOffset> {
+    local
+        .and_then(|local| ts.offset().from_local_datetime(&local).single())
+        .unwrap_or(*ts)
+}
+
+pub fn duration_trunc_minute(ts: &DateTime<FixedOffset>) -> DateTime<FixedOffset> {
+    let local = ts.naive_local();
+    at_local_time(ts, local.date().and_hms_opt(local.hour(), local.minute(), 0))
+}
+
+pub fn duration_trunc_hour(ts: &DateTime<FixedOffset>) -> DateTime<FixedOffset> {
+    let local = ts.naive_local();
+    at_local_time(ts, local.date().and_hms_opt(local.hour(), 0, 0))
+}
+
+pub fn duration_trunc_day(ts: &DateTime<FixedOffset>) -> DateTime<FixedOffset> {
+    at_local_time(ts, ts.naive_local().date().and_hms_opt(0, 0, 0))
+}
+
+/// The start of the ISO week `ts` is in, Monday at midnight
+pub fn duration_trunc_week(ts: &DateTime<FixedOffset>) -> DateTime<FixedOffset> {
+    let local = ts.naive_local();
+    let monday = local.date() - Duration::days(local.weekday().num_days_from_monday() as i64);
+    at_local_time(ts, monday.and_hms_opt(0, 0, 0))
 }
 
 pub fn duration_trunc_month(ts: &DateTime<FixedOffset>) -> DateTime<FixedOffset> {
-    FixedOffset::from_offset(&ts.timezone())
-        .ymd(ts.year(), ts.month(), 1)
-        .and_hms(0, 0, 0)
+    let local = ts.naive_local();
+    at_local_time(
+        ts,
+        local.date().with_day(1).and_then(|first| first.and_hms_opt(0, 0, 0)),
+    )
 }
 
 pub fn duration_trunc_year(ts: &DateTime<FixedOffset>) -> DateTime<FixedOffset> {
-    FixedOffset::from_offset(&ts.timezone())
-        .ymd(ts.year(), 1, 1)
-        .and_hms(0, 0, 0)
+    let local = ts.naive_local();
+    at_local_time(
+        ts,
+        NaiveDate::from_ymd_opt(local.year(), 1, 1).and_then(|first| first.and_hms_opt(0, 0, 0)),
+    )
 }
 
 #[cfg(test)]
 mod tests {
-    use chrono::{Duration, FixedOffset, Local, TimeZone, Utc, Weekday};
+    use chrono::{Duration, FixedOffset, Local, NaiveDate, TimeZone, Utc, Weekday};
 
     use crate::duration::{
-        duration_trunc_day, duration_trunc_hour, duration_trunc_month, duration_trunc_week, duration_trunc_year,
+        duration_from_str, duration_trunc_day, duration_trunc_hour, duration_trunc_minute, duration_trunc_month,
+        duration_trunc_week, duration_trunc_year, format_duration, format_duration_iso, subtract_from,
     };
+    use crate::error::{DurationDuplicateUnitError, DurationParseError, DurationUnitOrderError};
+    use crate::retention::Retention;
 
     #[test]
     fn weekdays_as_expected() {
-        let mon = Local.isoywd(2022, 5, Weekday::Mon);
-        let sun = Local.isoywd(2022, 5, Weekday::Sun);
+        let mon = NaiveDate::from_isoywd_opt(2022, 5, Weekday::Mon).unwrap();
+        let sun = NaiveDate::from_isoywd_opt(2022, 5, Weekday::Sun).unwrap();
         let duration = sun - mon;
         assert_eq!(duration, Duration::days(6));
 
-        let mon = Utc.isoywd(2022, 5, Weekday::Mon);
-        let sun = Utc.isoywd(2022, 5, Weekday::Sun);
-        let duration = sun - mon;
+        let midnight = |date: NaiveDate| date.and_hms_opt(0, 0, 0).unwrap();
+        let duration =
+            Local.from_local_datetime(&midnight(sun)).unwrap() - Local.from_local_datetime(&midnight(mon)).unwrap();
+        assert_eq!(duration, Duration::days(6));
+
+        let duration = Utc.from_utc_datetime(&midnight(sun)) - Utc.from_utc_datetime(&midnight(mon));
         assert_eq!(duration, Duration::days(6));
     }
 
@@ -159,32 +244,32 @@ mod tests {
     fn week_edge_cases() {
         let cases = Vec::from([
             (
-                Local.ymd(2022, 1, 1).and_hms(0, 0, 0),
-                Local.ymd(2021, 12, 27).and_hms(0, 0, 0),
+                Local.with_ymd_and_hms(2022, 1, 1, 0, 0, 0).unwrap(),
+                Local.with_ymd_and_hms(2021, 12, 27, 0, 0, 0).unwrap(),
             ),
             (
-                Local.ymd(2022, 1, 2).and_hms(0, 0, 0),
-                Local.ymd(2021, 12, 27).and_hms(0, 0, 0),
+                Local.with_ymd_and_hms(2022, 1, 2, 0, 0, 0).unwrap(),
+                Local.with_ymd_and_hms(2021, 12, 27, 0, 0, 0).unwrap(),
             ),
             (
-                Local.ymd(2022, 1, 3).and_hms(0, 0, 0),
-                Local.ymd(2022, 1, 3).and_hms(0, 0, 0),
+                Local.with_ymd_and_hms(2022, 1, 3, 0, 0, 0).unwrap(),
+                Local.with_ymd_and_hms(2022, 1, 3, 0, 0, 0).unwrap(),
             ),
             (
-                Local.ymd(2022, 1, 4).and_hms(0, 0, 0),
-                Local.ymd(2022, 1, 3).and_hms(0, 0, 0),
+                Local.with_ymd_and_hms(2022, 1, 4, 0, 0, 0).unwrap(),
+                Local.with_ymd_and_hms(2022, 1, 3, 0, 0, 0).unwrap(),
             ),
             (
-                Local.ymd(2022, 10, 22).and_hms(0, 0, 0),
-                Local.ymd(2022, 10, 17).and_hms(0, 0, 0),
+                Local.with_ymd_and_hms(2022, 10, 22, 0, 0, 0).unwrap(),
+                Local.with_ymd_and_hms(2022, 10, 17, 0, 0, 0).unwrap(),
             ),
             (
-                Local.ymd(2020, 12, 29).and_hms(0, 0, 0),
-                Local.ymd(2020, 12, 28).and_hms(0, 0, 0),
+                Local.with_ymd_and_hms(2020, 12, 29, 0, 0, 0).unwrap(),
+                Local.with_ymd_and_hms(2020, 12, 28, 0, 0, 0).unwrap(),
             ),
             (
-                Local.ymd(2021, 1, 2).and_hms(0, 0, 0),
-                Local.ymd(2020, 12, 28).and_hms(0, 0, 0),
+                Local.with_ymd_and_hms(2021, 1, 2, 0, 0, 0).unwrap(),
+                Local.with_ymd_and_hms(2020, 12, 28, 0, 0, 0).unwrap(),
             ),
         ]);
 
@@ -195,24 +280,75 @@ mod tests {
         }
     }
 
+    #[test]
+    fn week_around_spring_forward() {
+        // Central Europe switched to summer time on Sunday, 2022-03-27 at 02:00
+        let winter = FixedOffset::east_opt(3600).unwrap();
+        let summer = FixedOffset::east_opt(7200).unwrap();
+        let cases = [
+            (
+                winter.with_ymd_and_hms(2022, 3, 27, 1, 30, 0).unwrap(),
+                winter.with_ymd_and_hms(2022, 3, 21, 0, 0, 0).unwrap(),
+            ),
+            (
+                summer.with_ymd_and_hms(2022, 3, 27, 3, 30, 0).unwrap(),
+                summer.with_ymd_and_hms(2022, 3, 21, 0, 0, 0).unwrap(),
+            ),
+            (
+                summer.with_ymd_and_hms(2022, 3, 28, 0, 0, 0).unwrap(),
+                summer.with_ymd_and_hms(2022, 3, 28, 0, 0, 0).unwrap(),
+            ),
+            (
+                summer.with_ymd_and_hms(2022, 3, 27, 23, 59, 59).unwrap(),
+                summer.with_ymd_and_hms(2022, 3, 21, 0, 0, 0).unwrap(),
+            ),
+            // the earliest and latest representable offsets
+            (
+                FixedOffset::east_opt(86399)
+                    .unwrap()
+                    .with_ymd_and_hms(2022, 3, 27, 0, 0, 0)
+                    .unwrap(),
+                FixedOffset::east_opt(86399)
+                    .unwrap()
+                    .with_ymd_and_hms(2022, 3, 21, 0, 0, 0)
+                    .unwrap(),
+            ),
+            (
+                FixedOffset::west_opt(86399)
+                    .unwrap()
+                    .with_ymd_and_hms(2022, 3, 27, 23, 0, 0)
+                    .unwrap(),
+                FixedOffset::west_opt(86399)
+                    .unwrap()
+                    .with_ymd_and_hms(2022, 3, 21, 0, 0, 0)
+                    .unwrap(),
+            ),
+        ];
+
+        for (ts, monday) in cases {
+            assert_eq!(duration_trunc_week(&ts), monday);
+            assert_eq!(duration_trunc_week(&ts).offset(), ts.offset());
+        }
+    }
+
     #[test]
     fn years() {
         let cases = Vec::from([
             (
-                Local.ymd(2022, 1, 1).and_hms(0, 0, 0),
-                Local.ymd(2022, 1, 1).and_hms(0, 0, 0),
+                Local.with_ymd_and_hms(2022, 1, 1, 0, 0, 0).unwrap(),
+                Local.with_ymd_and_hms(2022, 1, 1, 0, 0, 0).unwrap(),
             ),
             (
-                Local.ymd(2022, 1, 2).and_hms(0, 0, 0),
-                Local.ymd(2022, 1, 1).and_hms(0, 0, 0),
+                Local.with_ymd_and_hms(2022, 1, 2, 0, 0, 0).unwrap(),
+                Local.with_ymd_and_hms(2022, 1, 1, 0, 0, 0).unwrap(),
             ),
             (
-                Local.ymd(2022, 12, 30).and_hms(0, 0, 0),
-                Local.ymd(2022, 1, 1).and_hms(0, 0, 0),
+                Local.with_ymd_and_hms(2022, 12, 30, 0, 0, 0).unwrap(),
+                Local.with_ymd_and_hms(2022, 1, 1, 0, 0, 0).unwrap(),
             ),
             (
-                Local.ymd(2022, 12, 31).and_hms(0, 0, 0),
-                Local.ymd(2022, 1, 1).and_hms(0, 0, 0),
+                Local.with_ymd_and_hms(2022, 12, 31, 0, 0, 0).unwrap(),
+                Local.with_ymd_and_hms(2022, 1, 1, 0, 0, 0).unwrap(),
             ),
         ]);
 
@@ -227,20 +363,20 @@ mod tests {
     fn days() {
         let cases = Vec::from([
             (
-                Local.ymd(2022, 1, 1).and_hms(1, 0, 0),
-                Local.ymd(2022, 1, 1).and_hms(0, 0, 0),
+                Local.with_ymd_and_hms(2022, 1, 1, 1, 0, 0).unwrap(),
+                Local.with_ymd_and_hms(2022, 1, 1, 0, 0, 0).unwrap(),
             ),
             (
-                Local.ymd(2022, 1, 2).and_hms(10, 0, 0),
-                Local.ymd(2022, 1, 2).and_hms(0, 0, 0),
+                Local.with_ymd_and_hms(2022, 1, 2, 10, 0, 0).unwrap(),
+                Local.with_ymd_and_hms(2022, 1, 2, 0, 0, 0).unwrap(),
             ),
             (
-                Local.ymd(2022, 12, 30).and_hms(23, 59, 59),
-                Local.ymd(2022, 12, 30).and_hms(0, 0, 0),
+                Local.with_ymd_and_hms(2022, 12, 30, 23, 59, 59).unwrap(),
+                Local.with_ymd_and_hms(2022, 12, 30, 0, 0, 0).unwrap(),
             ),
             (
-                Local.ymd(2022, 12, 31).and_hms(15, 38, 17),
-                Local.ymd(2022, 12, 31).and_hms(0, 0, 0),
+                Local.with_ymd_and_hms(2022, 12, 31, 15, 38, 17).unwrap(),
+                Local.with_ymd_and_hms(2022, 12, 31, 0, 0, 0).unwrap(),
             ),
         ]);
 
@@ -255,20 +391,20 @@ mod tests {
     fn months() {
         let cases = Vec::from([
             (
-                Local.ymd(2022, 1, 1).and_hms(1, 0, 0),
-                Local.ymd(2022, 1, 1).and_hms(0, 0, 0),
+                Local.with_ymd_and_hms(2022, 1, 1, 1, 0, 0).unwrap(),
+                Local.with_ymd_and_hms(2022, 1, 1, 0, 0, 0).unwrap(),
             ),
             (
-                Local.ymd(2022, 1, 2).and_hms(10, 0, 0),
-                Local.ymd(2022, 1, 1).and_hms(0, 0, 0),
+                Local.with_ymd_and_hms(2022, 1, 2, 10, 0, 0).unwrap(),
+                Local.with_ymd_and_hms(2022, 1, 1, 0, 0, 0).unwrap(),
             ),
             (
-                Local.ymd(2022, 12, 30).and_hms(23, 59, 59),
-                Local.ymd(2022, 12, 1).and_hms(0, 0, 0),
+                Local.with_ymd_and_hms(2022, 12, 30, 23, 59, 59).unwrap(),
+                Local.with_ymd_and_hms(2022, 12, 1, 0, 0, 0).unwrap(),
             ),
             (
-                Local.ymd(2022, 12, 31).and_hms(15, 38, 17),
-                Local.ymd(2022, 12, 1).and_hms(0, 0, 0),
+                Local.with_ymd_and_hms(2022, 12, 31, 15, 38, 17).unwrap(),
+                Local.with_ymd_and_hms(2022, 12, 1, 0, 0, 0).unwrap(),
             ),
         ]);
 
@@ -283,20 +419,20 @@ mod tests {
     fn hours() {
         let cases = Vec::from([
             (
-                Local.ymd(2022, 1, 1).and_hms(0, 0, 0),
-                Local.ymd(2022, 1, 1).and_hms(0, 0, 0),
+                Local.with_ymd_and_hms(2022, 1, 1, 0, 0, 0).unwrap(),
+                Local.with_ymd_and_hms(2022, 1, 1, 0, 0, 0).unwrap(),
             ),
             (
-                Local.ymd(2022, 1, 2).and_hms(10, 0, 0),
-                Local.ymd(2022, 1, 2).and_hms(10, 0, 0),
+                Local.with_ymd_and_hms(2022, 1, 2, 10, 0, 0).unwrap(),
+                Local.with_ymd_and_hms(2022, 1, 2, 10, 0, 0).unwrap(),
             ),
             (
-                Local.ymd(2022, 12, 30).and_hms(23, 59, 59),
-                Local.ymd(2022, 12, 30).and_hms(23, 0, 0),
+                Local.with_ymd_and_hms(2022, 12, 30, 23, 59, 59).unwrap(),
+                Local.with_ymd_and_hms(2022, 12, 30, 23, 0, 0).unwrap(),
             ),
             (
-                Local.ymd(2022, 12, 31).and_hms(15, 38, 17),
-                Local.ymd(2022, 12, 31).and_hms(15, 0, 0),
+                Local.with_ymd_and_hms(2022, 12, 31, 15, 38, 17).unwrap(),
+                Local.with_ymd_and_hms(2022, 12, 31, 15, 0, 0).unwrap(),
             ),
         ]);
 
@@ -306,4 +442,132 @@ mod tests {
             assert_eq!(calculated, hour_trunced);
         }
     }
+
+    #[test]
+    fn minutes() {
+        let ts = FixedOffset::east_opt(3600)
+            .unwrap()
+            .with_ymd_and_hms(2022, 12, 31, 15, 38, 17)
+            .unwrap();
+        assert_eq!(
+            duration_trunc_minute(&ts),
+            FixedOffset::east_opt(3600)
+                .unwrap()
+                .with_ymd_and_hms(2022, 12, 31, 15, 38, 0)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn minutes_and_months() {
+        let minutes = "30M 24h".parse::<Retention>().unwrap();
+        assert_eq!((minutes.n, minutes.h, minutes.m), (30, 24, 0));
+        let months = "24h 30m".parse::<Retention>().unwrap();
+        assert_eq!((months.n, months.h, months.m), (0, 24, 30));
+        let both = "2h-all 15M 6m".parse::<Retention>().unwrap();
+        assert_eq!((both.all_h, both.n, both.m), (2, 15, 6));
+
+        assert_eq!(
+            "24h 30M".parse::<Retention>().err().unwrap().to_string(),
+            DurationUnitOrderError.to_string()
+        );
+    }
+
+    #[test]
+    fn formatted_durations() {
+        assert_eq!(format_duration(Duration::minutes(12)), "12min");
+        assert_eq!(format_duration(Duration::minutes(5 * 60 + 12)), "5h 12min");
+        assert_eq!(format_duration(Duration::hours(2 * 24 + 3)), "2d 3h");
+    }
+
+    #[test]
+    fn unit_order_errors() {
+        let order = DurationUnitOrderError.to_string();
+        let duplicate = DurationDuplicateUnitError.to_string();
+        let generic = DurationParseError.to_string();
+
+        assert_eq!(duration_from_str("7d 24h").unwrap_err().to_string(), order);
+        assert_eq!(duration_from_str("2y1m").unwrap_err().to_string(), order);
+        assert_eq!(duration_from_str("1d 2d").unwrap_err().to_string(), duplicate);
+        assert_eq!(duration_from_str("7 days").unwrap_err().to_string(), generic);
+        assert_eq!(duration_from_str("24h 7d").unwrap(), Duration::days(8));
+
+        assert_eq!("14d 48h".parse::<Retention>().err().unwrap().to_string(), order);
+        assert_eq!(
+            "48h 14d 4w 4w".parse::<Retention>().err().unwrap().to_string(),
+            duplicate
+        );
+        assert_eq!("48h 14d 4x".parse::<Retention>().err().unwrap().to_string(), generic);
+    }
+
+    #[test]
+    fn iso_durations() {
+        assert_eq!(format_duration_iso(Duration::hours(1)), "PT1H");
+        assert_eq!(format_duration_iso(Duration::days(7)), "P7D");
+        assert_eq!(
+            format_duration_iso(Duration::minutes(2 * 24 * 60 + 3 * 60 + 12)),
+            "P2DT3H12M"
+        );
+        assert_eq!(format_duration_iso(Duration::seconds(90)), "PT1M30S");
+        assert_eq!(format_duration_iso(Duration::zero()), "PT0S");
+        assert_eq!(format_duration_iso(Duration::hours(-2)), "-PT2H");
+
+        for d in [
+            Duration::zero(),
+            Duration::seconds(59),
+            Duration::hours(36),
+            Duration::weeks(4),
+            Duration::days(400) + Duration::seconds(3661),
+        ] {
+            assert_eq!(duration_from_str(&format_duration_iso(d)).unwrap(), d);
+        }
+        assert_eq!(duration_from_str("PT36H").unwrap(), Duration::hours(36));
+        assert!(duration_from_str("P").is_err());
+        assert!(duration_from_str("P1DT").is_err());
+        assert!(duration_from_str("P1H").is_err());
+    }
+
+    #[test]
+    fn calendar_months_and_years() {
+        let utc = FixedOffset::east_opt(0).unwrap();
+        let cases = [
+            ((2022, 5, 15), "3m", (2022, 2, 15)),
+            // December to February, across the turn of the year
+            ((2023, 2, 10), "2m", (2022, 12, 10)),
+            ((2022, 12, 31), "10m", (2022, 2, 28)),
+            ((2024, 12, 31), "10m", (2024, 2, 29)),
+            ((2022, 3, 31), "1m", (2022, 2, 28)),
+            // February 29th
+            ((2024, 2, 29), "1y", (2023, 2, 28)),
+            ((2024, 2, 29), "4y", (2020, 2, 29)),
+            ((2024, 2, 29), "12m", (2023, 2, 28)),
+            ((2024, 3, 29), "1m", (2024, 2, 29)),
+            ((2024, 1, 31), "1m 1y", (2022, 12, 31)),
+        ];
+        for ((y, m, d), s, (ey, em, ed)) in cases {
+            let now = utc.with_ymd_and_hms(y, m, d, 12, 30, 0).unwrap();
+            assert_eq!(
+                subtract_from(&now, s).unwrap(),
+                utc.with_ymd_and_hms(ey, em, ed, 12, 30, 0).unwrap(),
+                "{} before {}",
+                s,
+                now
+            );
+        }
+
+        let now = utc.with_ymd_and_hms(2024, 3, 1, 0, 0, 0).unwrap();
+        assert_eq!(
+            subtract_from(&now, "12h 1d 1m").unwrap(),
+            utc.with_ymd_and_hms(2024, 1, 30, 12, 0, 0).unwrap()
+        );
+        assert_eq!(
+            subtract_from(&now, "P2D").unwrap(),
+            utc.with_ymd_and_hms(2024, 2, 28, 0, 0, 0).unwrap()
+        );
+        assert!(subtract_from(&now, "1y 1m").is_err());
+
+        // the fixed-length approximation is unchanged
+        assert_eq!(duration_from_str("3m").unwrap(), Duration::weeks(12));
+        assert_eq!(duration_from_str("1y").unwrap(), Duration::days(365));
+    }
 }
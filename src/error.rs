@@ -12,6 +12,49 @@ impl Display for DurationParseError {
 
 impl Error for DurationParseError {}
 
+#[derive(Debug)]
+pub struct DurationUnitOrderError;
+
+impl Display for DurationUnitOrderError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Error while parsing Duration from String, units must be given in the order h, d, w, m, y, e.g. 24h 7d 4w"
+        )
+    }
+}
+
+impl Error for DurationUnitOrderError {}
+
+#[derive(Debug)]
+pub struct DurationDuplicateUnitError;
+
+impl Display for DurationDuplicateUnitError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Error while parsing Duration from String, each of the units h, d, w, m and y may only be given once"
+        )
+    }
+}
+
+impl Error for DurationDuplicateUnitError {}
+
+#[derive(Debug)]
+pub struct RetentionTooLongError(pub usize);
+
+impl Display for RetentionTooLongError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Error while parsing retention, each tier may keep at most {} snapshots, see max_retention_bins",
+            self.0
+        )
+    }
+}
+
+impl Error for RetentionTooLongError {}
+
 #[derive(Debug)]
 pub struct ConfigfileExtensionError;
 
@@ -22,3 +65,403 @@ impl Display for ConfigfileExtensionError {
 }
 
 impl Error for ConfigfileExtensionError {}
+
+#[derive(Debug)]
+pub struct StrictValidationError;
+
+impl Display for StrictValidationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Configuration validation failed in strict mode")
+    }
+}
+
+impl Error for StrictValidationError {}
+
+#[derive(Debug)]
+pub struct SnapshotTimestampError;
+
+impl Display for SnapshotTimestampError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The timestamp of the snapshot can't be parsed from its name")
+    }
+}
+
+impl Error for SnapshotTimestampError {}
+
+#[derive(Debug)]
+pub struct NameTemplateError;
+
+impl Display for NameTemplateError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Error while parsing snapshot name template")
+    }
+}
+
+impl Error for NameTemplateError {}
+
+#[derive(Debug)]
+pub struct StaleJobsError;
+
+impl Display for StaleJobsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "At least one job has not created a snapshot successfully for too long"
+        )
+    }
+}
+
+impl Error for StaleJobsError {}
+
+#[derive(Debug)]
+pub struct ModeParseError;
+
+impl Display for ModeParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Error while parsing octal permission mode")
+    }
+}
+
+impl Error for ModeParseError {}
+
+#[derive(Debug)]
+pub struct ConfigIncludeCycleError;
+
+impl Display for ConfigIncludeCycleError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Configuration files include each other in a cycle")
+    }
+}
+
+impl Error for ConfigIncludeCycleError {}
+
+#[derive(Debug)]
+pub struct NotBtrfsError;
+
+impl Display for NotBtrfsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Snapshots can only be created on a local btrfs filesystem. To back up to a network share or another host, create the snapshots locally and transfer them with btrfs send and receive"
+        )
+    }
+}
+
+impl Error for NotBtrfsError {}
+
+#[derive(Debug)]
+pub struct NoJobsError;
+
+impl Display for NoJobsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "No jobs are configured or match the filter")
+    }
+}
+
+impl Error for NoJobsError {}
+
+#[derive(Debug)]
+pub struct EmptySnapshotError;
+
+impl Display for EmptySnapshotError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The snapshot holds no files")
+    }
+}
+
+impl Error for EmptySnapshotError {}
+
+#[derive(Debug)]
+pub struct SourceLockedError;
+
+impl Display for SourceLockedError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The subvolume is locked by another process")
+    }
+}
+
+impl Error for SourceLockedError {}
+
+#[derive(Debug)]
+pub struct BeforeSealError;
+
+impl Display for BeforeSealError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The before_seal command of the job failed")
+    }
+}
+
+impl Error for BeforeSealError {}
+
+#[derive(Debug)]
+pub struct ReadOnlyError;
+
+impl Display for ReadOnlyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "`btrfs property set` failed to make the snapshot read-only")
+    }
+}
+
+impl Error for ReadOnlyError {}
+
+#[derive(Debug)]
+pub struct RunLockedError;
+
+impl Display for RunLockedError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Another ghee run holds the lock file, not executing anything")
+    }
+}
+
+impl Error for RunLockedError {}
+
+#[derive(Debug)]
+pub struct HookError;
+
+impl Display for HookError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The hook command of the job exited unsuccessfully")
+    }
+}
+
+impl Error for HookError {}
+
+#[derive(Debug)]
+pub struct PlanHookError;
+
+impl Display for PlanHookError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The plan_hook command of the job failed")
+    }
+}
+
+impl Error for PlanHookError {}
+
+#[derive(Debug)]
+pub struct ConfigTestError;
+
+impl Display for ConfigTestError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Some configuration files failed to load")
+    }
+}
+
+impl Error for ConfigTestError {}
+
+#[derive(Debug)]
+pub struct InvalidJobsError;
+
+impl Display for InvalidJobsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Some jobs are invalid")
+    }
+}
+
+impl Error for InvalidJobsError {}
+
+#[derive(Debug)]
+pub struct InvalidJobConfigError;
+
+impl Display for InvalidJobConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The configuration of at least one job is invalid")
+    }
+}
+
+impl Error for InvalidJobConfigError {}
+
+#[derive(Debug)]
+pub struct TimezoneParseError;
+
+impl Display for TimezoneParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Unknown time zone, expected e.g. Europe/Berlin or UTC")
+    }
+}
+
+impl Error for TimezoneParseError {}
+
+#[derive(Debug)]
+pub struct TimeOfDayParseError;
+
+impl Display for TimeOfDayParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Error while parsing time of day, expected e.g. 12:00")
+    }
+}
+
+impl Error for TimeOfDayParseError {}
+
+#[derive(Debug)]
+pub struct UnreadableTargetsError;
+
+impl Display for UnreadableTargetsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Some targets could not be read, their jobs were not pruned")
+    }
+}
+
+impl Error for UnreadableTargetsError {}
+
+#[derive(Debug)]
+pub struct FreeSpaceError;
+
+impl Display for FreeSpaceError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Not enough free space on the filesystem of the target")
+    }
+}
+
+impl Error for FreeSpaceError {}
+
+#[derive(Debug)]
+pub struct SizeParseError;
+
+impl Display for SizeParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Error while parsing size, expected e.g. 10%, 512M or 2G")
+    }
+}
+
+impl Error for SizeParseError {}
+
+#[derive(Debug)]
+pub struct UnexpectedDeleteError;
+
+impl Display for UnexpectedDeleteError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Refusing to delete a path that is not a snapshot of the job in its target directory"
+        )
+    }
+}
+
+impl Error for UnexpectedDeleteError {}
+
+#[derive(Debug)]
+pub struct NotSubvolumeError;
+
+impl Display for NotSubvolumeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Refusing to delete a path that is not a btrfs subvolume")
+    }
+}
+
+impl Error for NotSubvolumeError {}
+
+#[derive(Debug)]
+pub struct ConsistencyGroupError;
+
+impl Display for ConsistencyGroupError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Rolled back because another snapshot of its consistency group could not be created"
+        )
+    }
+}
+
+impl Error for ConsistencyGroupError {}
+
+#[derive(Debug)]
+pub struct ExpectedCountError;
+
+impl Display for ExpectedCountError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Some jobs have more or fewer snapshots than expected")
+    }
+}
+
+impl Error for ExpectedCountError {}
+
+#[derive(Debug)]
+pub struct IntentTypeParseError;
+
+impl Display for IntentTypeParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Error while parsing intent, expected create, keep or delete")
+    }
+}
+
+impl Error for IntentTypeParseError {}
+
+#[derive(Debug)]
+pub struct StateIssuesError;
+
+impl Display for StateIssuesError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "The state file has entries of jobs that are no longer configured, `ghee state compact` removes them"
+        )
+    }
+}
+
+impl Error for StateIssuesError {}
+
+#[derive(Debug)]
+pub struct FailedIntentsError;
+
+impl Display for FailedIntentsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Intents failed")
+    }
+}
+
+impl Error for FailedIntentsError {}
+
+#[derive(Debug)]
+pub struct FailedMigrationsError;
+
+impl Display for FailedMigrationsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Renaming snapshots failed")
+    }
+}
+
+impl Error for FailedMigrationsError {}
+
+#[derive(Debug)]
+pub struct EmptyTrashError;
+
+impl Display for EmptyTrashError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Deleting trashed snapshots failed")
+    }
+}
+
+impl Error for EmptyTrashError {}
+
+#[derive(Debug)]
+pub struct DeviceStatsError;
+
+impl Display for DeviceStatsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "`btrfs device stats` failed")
+    }
+}
+
+impl Error for DeviceStatsError {}
+
+#[derive(Debug)]
+pub struct UnsetVariableError;
+
+impl Display for UnsetVariableError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "A variable in a configured path is not set")
+    }
+}
+
+impl Error for UnsetVariableError {}
+
+#[derive(Debug)]
+pub struct ReplicateError;
+
+impl Display for ReplicateError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "btrfs send or receive failed")
+    }
+}
+
+impl Error for ReplicateError {}
@@ -1,9 +1,16 @@
-use log::info;
-use tabled::{Style, Table, Tabled};
+use std::error::Error;
+use std::fmt::Display;
+use std::path::Path;
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use tabled::Tabled;
 
 use crate::intent::{Intent, IntentType};
+use crate::job::{FailurePolicy, Job};
+use crate::output::{column_width, elide_middle, elided_column_width, TableStyle};
 
-#[derive(Tabled)]
+#[derive(Debug, Tabled, Clone, Serialize, Deserialize)]
 pub struct ExecutedIntent {
     #[tabled(display_with("Self::display_intent", args))]
     pub intent: IntentType,
@@ -11,6 +18,12 @@ pub struct ExecutedIntent {
     pub target: String,
     pub name: String,
     pub success: bool,
+    /// Number of transactions that changed the subvolume since its previous snapshot
+    #[tabled(display_with("Self::display_changes", args))]
+    pub changes: Option<u64>,
+    /// Why the intent failed
+    #[tabled(display_with("Self::display_error", args))]
+    pub error: Option<String>,
 }
 
 impl ExecutedIntent {
@@ -21,19 +34,228 @@ impl ExecutedIntent {
             target: intent.target.clone(),
             name: intent.name.clone(),
             success,
+            changes: None,
+            error: None,
+        }
+    }
+
+    /// An intent that failed with `error`
+    pub fn failed(intent: &Intent, error: impl Display) -> Self {
+        Self {
+            error: Some(error.to_string()),
+            ..Self::new(intent, false)
+        }
+    }
+
+    /// Whether the intent was executed for `job`: a snapshot created in its target, kept or deleted in its snapshot
+    /// directory, or sent to its `replicate` destination
+    pub fn belongs_to(&self, job: &Job) -> bool {
+        if job.subvolume != self.subvolume {
+            return false;
+        }
+        match self.intent {
+            IntentType::Create => job.target == self.target,
+            IntentType::Keep | IntentType::Delete => {
+                Path::new(&self.target).parent() == Some(Path::new(&job.snapshot_directory()))
+            }
+            IntentType::Send => job.replicate.as_ref().is_some_and(|r| r.destination == self.target),
         }
     }
 
+    /// The job of `jobs` the intent was executed for
+    fn job<'j>(&self, jobs: &[&'j Job]) -> Option<&'j Job> {
+        jobs.iter().copied().find(|j| self.belongs_to(j))
+    }
+
+    /// Warns about the failed intents according to the `on_failure` of their jobs, and returns whether any of them
+    /// fails the run
+    pub fn fails_run(intents: &[Self], jobs: &[&Job]) -> bool {
+        let mut fails_run = false;
+        for intent in intents.iter().filter(|i| !i.success) {
+            let policy = intent
+                .job(jobs)
+                .and_then(|j| j.on_failure)
+                .unwrap_or(FailurePolicy::FailRun);
+            match policy {
+                FailurePolicy::Warn => warn!("{} {} of {} failed!", intent.intent, &intent.name, &intent.subvolume),
+                FailurePolicy::FailRun => {
+                    warn!(
+                        "{} {} of {} failed, failing the run!",
+                        intent.intent, &intent.name, &intent.subvolume
+                    );
+                    fails_run = true;
+                }
+                FailurePolicy::Ignore => (),
+            }
+        }
+
+        fails_run
+    }
+
+    /// How many of the intents failed, if any did
+    pub fn failure_summary(intents: &[Self]) -> Option<String> {
+        let failed = intents.iter().filter(|i| !i.success).count();
+        if failed == 0 {
+            return None;
+        }
+
+        Some(format!("{} of {} intents failed", failed, intents.len()))
+    }
+
+    /// How many of the intents succeeded and how many failed, e.g. `succeeded: 12, failed: 1`
+    pub fn summary(intents: &[Self]) -> String {
+        let succeeded = intents.iter().filter(|i| i.success).count();
+        format!("succeeded: {}, failed: {}", succeeded, intents.len() - succeeded)
+    }
+
+    /// The executed intents as a JSON array
+    pub fn to_json(intents: &[Self]) -> Result<String, Box<dyn Error>> {
+        Ok(serde_json::to_string(intents)?)
+    }
+
     fn display_intent(&self) -> String {
         match self.intent {
             IntentType::Create => "++++++".to_string(),
             IntentType::Keep => "======".to_string(),
             IntentType::Delete => "------".to_string(),
+            IntentType::Send => ">>>>>>".to_string(),
+        }
+    }
+
+    fn display_changes(&self) -> String {
+        match self.changes {
+            Some(changes) => changes.to_string(),
+            None => "-".to_string(),
+        }
+    }
+
+    fn display_error(&self) -> String {
+        self.error.clone().unwrap_or_else(|| "-".to_string())
+    }
+
+    pub fn print_tabled(intents: &[Self], style: TableStyle) {
+        let changes = intents.iter().map(|i| i.display_changes()).collect::<Vec<_>>();
+        let fixed_columns = [
+            column_width("intent", ["++++++"].into_iter()),
+            column_width("subvolume", intents.iter().map(|i| i.subvolume.as_str())),
+            column_width("success", ["false"].into_iter()),
+            column_width("changes", changes.iter().map(String::as_str)),
+        ];
+        let mut intents = intents.to_vec();
+        if let Some(width) = elided_column_width(&fixed_columns, 3) {
+            for intent in &mut intents {
+                intent.target = elide_middle(&intent.target, width);
+                intent.name = elide_middle(&intent.name, width);
+                intent.error = intent.error.as_deref().map(|e| elide_middle(e, width));
+            }
         }
+
+        let summary = Self::failure_summary(&intents);
+        info!("{}", style.render(intents));
+        if let Some(summary) = summary {
+            warn!("{}", summary);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::executed_intent::ExecutedIntent;
+    use crate::intent::IntentType;
+    use crate::job::Job;
+
+    fn executed(intent: IntentType, subvolume: &str, target: &str, success: bool) -> ExecutedIntent {
+        ExecutedIntent {
+            intent,
+            subvolume: subvolume.to_string(),
+            target: target.to_string(),
+            name: String::new(),
+            success,
+            changes: None,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn failure_policies() {
+        let job_with = |subvolume: &str, on_failure: &str| -> Job {
+            serde_yaml::from_str(&format!(
+                "{{subvolume: {}, target: /snaps, preserve: {{min: all}}, on_failure: {}}}",
+                subvolume, on_failure
+            ))
+            .unwrap()
+        };
+        let database = job_with("/var/lib/postgres", "fail_run");
+        let cache = job_with("/var/cache", "ignore");
+        let home = job_with("/home", "warn");
+        let unset: Job = serde_yaml::from_str("{subvolume: /srv, target: /snaps, preserve: {min: all}}").unwrap();
+        let jobs = [&database, &cache, &home, &unset];
+
+        let fails = |intents: &[ExecutedIntent]| ExecutedIntent::fails_run(intents, &jobs);
+        assert!(fails(&[executed(
+            IntentType::Create,
+            "/var/lib/postgres",
+            "/snaps",
+            false
+        )]));
+        assert!(fails(&[
+            executed(IntentType::Create, "/home", "/snaps", false),
+            executed(IntentType::Delete, "/var/lib/postgres", "/snaps/postgres.old", false),
+        ]));
+        assert!(!fails(&[executed(
+            IntentType::Create,
+            "/var/lib/postgres",
+            "/snaps",
+            true
+        )]));
+        assert!(!fails(&[executed(IntentType::Create, "/var/cache", "/snaps", false)]));
+        assert!(!fails(&[executed(
+            IntentType::Delete,
+            "/home",
+            "/snaps/home.old",
+            false
+        )]));
+        // failing the run is the default
+        assert!(fails(&[executed(IntentType::Create, "/srv", "/snaps", false)]));
+        // a failure of the same subvolume in another target is not the job's
+        assert!(fails(&[executed(IntentType::Create, "/var/cache", "/other", false)]));
+    }
+
+    #[test]
+    fn failure_summary() {
+        let intents = [
+            executed(IntentType::Create, "/home", "/snaps", true),
+            executed(IntentType::Delete, "/home", "/snaps/home.old", false),
+            executed(IntentType::Delete, "/home", "/snaps/home.older", true),
+        ];
+        assert_eq!(
+            ExecutedIntent::failure_summary(&intents),
+            Some("1 of 3 intents failed".to_string())
+        );
+        assert_eq!(ExecutedIntent::failure_summary(&intents[..1]), None);
+        assert_eq!(ExecutedIntent::failure_summary(&[]), None);
+
+        assert_eq!(ExecutedIntent::summary(&intents), "succeeded: 2, failed: 1");
+        assert_eq!(ExecutedIntent::summary(&[]), "succeeded: 0, failed: 0");
     }
 
-    pub fn print_tabled(intents: &[Self]) {
-        let table = Table::new(intents).with(Style::modern()).to_string();
-        info!("{}", table);
+    #[test]
+    fn json_output() {
+        let mut failed = executed(IntentType::Delete, "/home", "/snaps/home.old", false);
+        failed.name = "home.old".to_string();
+        failed.error = Some("Permission denied".to_string());
+        let json: serde_json::Value = serde_json::from_str(&ExecutedIntent::to_json(&[failed]).unwrap()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!([{
+                "intent": "delete",
+                "subvolume": "/home",
+                "target": "/snaps/home.old",
+                "name": "home.old",
+                "success": false,
+                "changes": null,
+                "error": "Permission denied",
+            }])
+        );
     }
 }
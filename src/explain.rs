@@ -0,0 +1,183 @@
+use log::info;
+use tabled::{Style, Table, Tabled};
+
+use crate::job::Job;
+use crate::policies::{PreservePolicyMin, PreservePolicyMinVariants};
+
+/// An effective setting of a job and where its value came from
+#[derive(Debug, PartialEq, Eq, Tabled)]
+pub struct Setting {
+    pub setting: String,
+    pub value: String,
+    pub origin: String,
+}
+
+/// Describes a min rule the way it is written in the configuration
+fn describe_min(min: &PreservePolicyMin) -> String {
+    fn describe_all(rules: &[PreservePolicyMin]) -> String {
+        rules.iter().map(describe_min).collect::<Vec<_>>().join(", ")
+    }
+
+    match min {
+        PreservePolicyMin::Variant(PreservePolicyMinVariants::All) => "all".to_string(),
+        PreservePolicyMin::Variant(PreservePolicyMinVariants::Latest) => "latest".to_string(),
+        PreservePolicyMin::Variant(PreservePolicyMinVariants::None) => "none".to_string(),
+        PreservePolicyMin::Timespan(ts) => ts.clone(),
+        PreservePolicyMin::Count(n) => n.to_string(),
+        PreservePolicyMin::Percent { percent } => format!("{}%", percent),
+        PreservePolicyMin::AllOf { all_of } => format!("all_of [{}]", describe_all(all_of)),
+        PreservePolicyMin::AnyOf { any_of } => format!("any_of [{}]", describe_all(any_of)),
+    }
+}
+
+impl Setting {
+    /// Lists every setting of the job with its effective value. Settings the job doesn't set are shown with the value
+    /// used in their place and `default` as origin, settings filled in from elsewhere with where they came from.
+    pub fn gather(job: &Job) -> Vec<Self> {
+        let show = |value: &Option<String>, default: &str| value.clone().unwrap_or_else(|| default.to_string());
+        let preserve = &job.preserve;
+        let settings: Vec<(&str, Option<String>, &str)> = vec![
+            ("subvolume", Some(job.subvolume.clone()), ""),
+            ("target", Some(job.target.clone()), ""),
+            ("groups", job.groups.as_ref().map(|g| g.join(", ")), "(ungrouped)"),
+            ("enabled", job.enabled.map(|e| e.to_string()), "true"),
+            ("exclude", job.exclude.as_ref().map(|e| e.join(", ")), "none"),
+            ("preserve.retention", preserve.retention.clone(), "none"),
+            ("preserve.min", Some(describe_min(&preserve.min)), ""),
+            (
+                "preserve.retention_anchor",
+                preserve.retention_anchor.map(|a| format!("{:?}", a)),
+                "Now",
+            ),
+            (
+                "preserve.bin_anchor",
+                preserve.bin_anchor.map(|a| format!("{:?}", a)),
+                "First",
+            ),
+            ("preserve.bin_time", preserve.bin_time.clone(), "none"),
+            (
+                "preserve.keep_beyond_window",
+                preserve.keep_beyond_window.map(|k| k.to_string()),
+                "false",
+            ),
+            (
+                "preserve.prefer_created",
+                preserve.prefer_created.map(|p| p.to_string()),
+                "false",
+            ),
+            (
+                "preserve.expected_count",
+                preserve
+                    .expected_count
+                    .map(|(lowest, highest)| format!("{}-{}", lowest, highest)),
+                "any",
+            ),
+            ("preserve.min_free", preserve.min_free.clone(), "none"),
+            ("stale_after", job.stale_after.clone(), "never"),
+            ("follow_symlinks", job.follow_symlinks.map(|f| f.to_string()), "true"),
+            ("target_mode", job.target_mode.clone(), "unchanged"),
+            ("create_target", job.create_target.map(|c| c.to_string()), "false"),
+            (
+                "prune_before_create",
+                job.prune_before_create.map(|p| p.to_string()),
+                "false",
+            ),
+            ("deduplicate", job.deduplicate.map(|d| d.to_string()), "false"),
+            ("timestamp_format", job.timestamp_format.clone(), "rfc3339"),
+            (
+                "timestamp_precision",
+                job.timestamp_precision.map(|p| format!("{:?}", p)),
+                "Seconds",
+            ),
+            ("name_template", job.name_template.clone(), "{subvol}.{timestamp}"),
+            ("timestamp_tz", job.timestamp_tz.clone(), "local"),
+            (
+                "timestamp_source",
+                job.timestamp_source.map(|t| format!("{:?}", t)),
+                "Name",
+            ),
+            ("source_tag", job.source_tag.clone(), "none"),
+            ("when", job.when.clone(), "always"),
+            ("layout", job.layout.map(|l| format!("{:?}", l)), "Flat"),
+            (
+                "snapshot_listing",
+                job.snapshot_listing.map(|l| format!("{:?}", l)),
+                "ReadDir",
+            ),
+            ("min_free_space", job.min_free_space.clone(), "none"),
+            ("sync_before", job.sync_before.map(|s| s.to_string()), "false"),
+            ("pre_hook", job.pre_hook.clone(), "none"),
+            ("post_hook", job.post_hook.clone(), "none"),
+            ("before_seal", job.before_seal.clone(), "none"),
+            ("read_only", job.read_only.map(|r| r.to_string()), "true"),
+            (
+                "replicate",
+                job.replicate.as_ref().map(|r| match &r.ssh {
+                    Some(ssh) => format!("{} via {}", &r.destination, ssh),
+                    None => r.destination.clone(),
+                }),
+                "none",
+            ),
+            ("plan_hook", job.plan_hook.clone(), "none"),
+            ("skip_if_locked", job.skip_if_locked.map(|s| s.to_string()), "false"),
+            ("consistency_group", job.consistency_group.clone(), "none"),
+            ("verify_sample", job.verify_sample.map(|v| v.to_string()), "none"),
+            ("trash", job.trash.map(|t| t.to_string()), "false"),
+            ("on_failure", job.on_failure.map(|o| format!("{:?}", o)), "FailRun"),
+            ("write_manifest", job.write_manifest.map(|w| w.to_string()), "false"),
+        ];
+
+        settings
+            .into_iter()
+            .map(|(setting, value, default)| {
+                let origin = match (job.origins.get(setting), &value) {
+                    (Some(origin), _) => origin.clone(),
+                    (None, Some(_)) => "job".to_string(),
+                    (None, None) => "default".to_string(),
+                };
+                Self {
+                    setting: setting.to_string(),
+                    value: show(&value, default),
+                    origin,
+                }
+            })
+            .collect()
+    }
+
+    pub fn print_tabled(job: &Job, settings: &[Self]) {
+        let table = Table::new(settings).with(Style::modern()).to_string();
+        info!("job {}:\n{}", &job.subvolume, table);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::explain::Setting;
+    use crate::job::Job;
+
+    #[test]
+    fn settings_with_origins() {
+        let mut job: Job = serde_yaml::from_str(
+            "{subvolume: /home, target: /snaps, preserve: {retention: 48h 14d, min: {any_of: [latest, 2d]}}}",
+        )
+        .unwrap();
+        job.timestamp_tz = Some("UTC".to_string());
+        job.origins.insert(
+            "timestamp_tz".to_string(),
+            "config file /etc/ghee/ghee.yaml".to_string(),
+        );
+
+        let settings = Setting::gather(&job);
+        let find = |name: &str| {
+            let setting = settings.iter().find(|s| s.setting == name).unwrap();
+            (setting.value.as_str(), setting.origin.as_str())
+        };
+
+        assert_eq!(find("subvolume"), ("/home", "job"));
+        assert_eq!(find("preserve.retention"), ("48h 14d", "job"));
+        assert_eq!(find("preserve.min"), ("any_of [latest, 2d]", "job"));
+        assert_eq!(find("follow_symlinks"), ("true", "default"));
+        assert_eq!(find("layout"), ("Flat", "default"));
+        assert_eq!(find("timestamp_tz"), ("UTC", "config file /etc/ghee/ghee.yaml"));
+    }
+}
@@ -0,0 +1,832 @@
+use std::error::Error;
+use std::fs::{self, File, OpenOptions, Permissions};
+use std::io::{self, Read};
+use std::num::NonZeroU64;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use chrono::{DateTime, FixedOffset, Utc};
+use libbtrfsutil as btrfs;
+use log::{error, warn};
+use nix::errno::Errno;
+use nix::fcntl::{flock, FlockArg};
+use nix::sys::statfs::{statfs, BTRFS_SUPER_MAGIC};
+use nix::sys::statvfs::{statvfs, FsFlags};
+use nix::unistd::syncfs;
+
+use crate::error::{
+    DeviceStatsError, EmptySnapshotError, ModeParseError, NotBtrfsError, ReadOnlyError, SizeParseError,
+};
+
+/// The extended attribute operators leave notes on snapshots in
+const NOTE_XATTR: &str = "user.ghee.note";
+
+/// The id of the filesystem `path` is on. On btrfs, this id also distinguishes subvolumes.
+#[allow(clippy::unnecessary_cast)] // `c_ulong` is only `u64` on 64-bit targets
+pub fn filesystem_id(path: &Path) -> Option<u64> {
+    match statvfs(path) {
+        Ok(stat) => Some(stat.filesystem_id() as u64),
+        Err(e) => {
+            warn!("Unable to stat filesystem of {:?}! Error: {}", path, e);
+            None
+        }
+    }
+}
+
+/// A minimum amount of free space, either in bytes or relative to the size of the filesystem
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum FreeSpace {
+    Bytes(u64),
+    Percent(u64),
+}
+
+impl FreeSpace {
+    /// Parses sizes such as `10%`, `512M` or `2G`. Units are powers of 1024.
+    pub fn parse(s: &str) -> Result<Self, Box<dyn Error>> {
+        let s = s.trim();
+        if let Some(percent) = s.strip_suffix('%') {
+            let percent = percent.trim().parse::<u64>().map_err(|_| SizeParseError)?;
+            if percent > 100 {
+                return Err(Box::new(SizeParseError));
+            }
+            return Ok(Self::Percent(percent));
+        }
+
+        let (number, exponent) = match s.char_indices().last() {
+            Some((i, 'K' | 'k')) => (&s[..i], 1),
+            Some((i, 'M' | 'm')) => (&s[..i], 2),
+            Some((i, 'G' | 'g')) => (&s[..i], 3),
+            Some((i, 'T' | 't')) => (&s[..i], 4),
+            _ => (s, 0),
+        };
+        let number = number.trim().parse::<u64>().map_err(|_| SizeParseError)?;
+
+        Ok(Self::Bytes(
+            number.checked_mul(1024u64.pow(exponent)).ok_or(SizeParseError)?,
+        ))
+    }
+
+    /// Whether `available` out of `total` bytes are at least this much free space
+    pub fn is_satisfied(&self, available: u64, total: u64) -> bool {
+        match *self {
+            Self::Bytes(bytes) => available >= bytes,
+            Self::Percent(percent) => available as u128 * 100 >= total as u128 * percent as u128,
+        }
+    }
+}
+
+/// The available and total bytes of the filesystem `path` is on
+#[allow(clippy::unnecessary_cast)] // `c_ulong` and `fsblkcnt_t` are only `u64` on 64-bit targets
+pub fn free_space(path: &Path) -> Option<(u64, u64)> {
+    match statvfs(path) {
+        Ok(stat) => {
+            let fragment = stat.fragment_size() as u64;
+            Some((
+                stat.blocks_available() as u64 * fragment,
+                stat.blocks() as u64 * fragment,
+            ))
+        }
+        Err(e) => {
+            warn!("Unable to stat filesystem of {:?}! Error: {}", path, e);
+            None
+        }
+    }
+}
+
+/// The id of the transaction that last changed the subvolume at `path`. Snapshots keep the one of their source at the
+/// time they were taken.
+pub fn change_transid(path: &Path) -> Option<u64> {
+    match btrfs::subvolume_info(path) {
+        Ok(info) => Some(info.ctransid()),
+        Err(e) => {
+            warn!("Unable to read subvolume information of {:?}! Error: {}", path, e);
+            None
+        }
+    }
+}
+
+/// When the subvolume at `path` was created, as recorded by btrfs
+pub fn creation_time(path: &Path) -> Result<DateTime<FixedOffset>, Box<dyn Error>> {
+    let info = btrfs::subvolume_info(path)?;
+    Ok(DateTime::<Utc>::from(info.created()).into())
+}
+
+/// The note left on the snapshot at `path`, or an empty string if it has none or it can't be read
+pub fn snapshot_note(path: &Path) -> String {
+    match xattr::get(path, NOTE_XATTR) {
+        Ok(Some(note)) => String::from_utf8_lossy(&note).trim().to_string(),
+        _ => String::new(),
+    }
+}
+
+/// Whether `path` is a btrfs subvolume
+pub fn is_subvolume(path: &Path) -> bool {
+    btrfs::is_subvolume(path).unwrap_or(false)
+}
+
+/// The directories in `directory`. Entries that can't be read are skipped with an error.
+pub fn list_directories(directory: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut directories = Vec::new();
+    for entry in fs::read_dir(directory)? {
+        match entry {
+            Ok(entry) => match entry.metadata() {
+                Ok(metadata) if metadata.is_dir() => directories.push(entry.path()),
+                Ok(_) => {}
+                Err(e) => error!("Unable to read metadata of {:?}! Error: {}", entry.path(), e),
+            },
+            Err(e) => error!("IO error occured when accessing {:?}! Error: {}", directory, e),
+        }
+    }
+
+    Ok(directories)
+}
+
+/// The apparent size of the files below `path` in bytes. Nested subvolumes and mounts are not descended into.
+pub fn directory_size(path: &Path) -> io::Result<u64> {
+    let device = fs::symlink_metadata(path)?.dev();
+    let mut size = 0;
+    let mut pending = vec![path.to_path_buf()];
+    while let Some(directory) = pending.pop() {
+        for entry in fs::read_dir(&directory)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if metadata.dev() != device {
+                continue;
+            }
+            if metadata.is_dir() {
+                pending.push(entry.path());
+            } else {
+                size += metadata.len();
+            }
+        }
+    }
+
+    Ok(size)
+}
+
+/// The bytes only the snapshot at `path` occupies, from its btrfs qgroup. With quotas disabled, the apparent size of
+/// its files is used instead, which overestimates what deleting it frees. `None` if neither can be determined.
+pub fn exclusive_size(path: &Path) -> Option<u64> {
+    let qgroup = btrfs::subvolume_info(path).ok().and_then(|info| {
+        let output = Command::new("btrfs")
+            .args(["qgroup", "show", "--raw", "-f"])
+            .arg(path)
+            .output()
+            .ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        parse_qgroup_exclusive(&String::from_utf8_lossy(&output.stdout), info.id())
+    });
+
+    qgroup.or_else(|| match directory_size(path) {
+        Ok(size) => Some(size),
+        Err(e) => {
+            warn!("Unable to determine the size of {:?}! Error: {}", path, e);
+            None
+        }
+    })
+}
+
+/// The exclusive size of the qgroup `0/<id>` in the output of `btrfs qgroup show --raw`
+fn parse_qgroup_exclusive(output: &str, id: u64) -> Option<u64> {
+    let qgroup = format!("0/{}", id);
+    output.lines().find_map(|line| {
+        let mut columns = line.split_whitespace();
+        if columns.next()? != qgroup {
+            return None;
+        }
+        columns.nth(1)?.parse().ok()
+    })
+}
+
+/// Formats `bytes` with the largest binary unit that keeps at least one whole unit, e.g. `1.5G`
+pub fn format_size(bytes: u64) -> String {
+    let units = ["B", "K", "M", "G", "T", "P"];
+    let exponent = (0..units.len())
+        .rev()
+        .find(|&e| bytes >= 1024u64.pow(e as u32))
+        .unwrap_or(0);
+    if exponent == 0 {
+        return format!("{}B", bytes);
+    }
+    let value = bytes as f64 / 1024u64.pow(exponent as u32) as f64;
+    format!("{:.1}{}", value, units[exponent])
+}
+
+/// The subvolumes directly in `directory`, as btrfs lists them. Unlike the directory entries, these are exactly the
+/// subvolumes of the filesystem, but listing them usually requires root privileges.
+pub fn list_subvolumes(directory: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    fs::metadata(directory)?;
+    let top = directory
+        .ancestors()
+        .find(|path| is_subvolume(path))
+        .ok_or(NotBtrfsError)?;
+    let relative = btrfs::SubvolumeIterator::new(top, None, btrfs::SubvolumeIteratorFlags::empty())?
+        .map(|subvolume| subvolume.map(|(path, _id)| path))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(children_of(top, directory, relative))
+}
+
+/// The paths `relative` to the subvolume at `top` that are directly in `directory`
+fn children_of(top: &Path, directory: &Path, relative: Vec<PathBuf>) -> Vec<PathBuf> {
+    let mut children = relative
+        .into_iter()
+        .map(|path| top.join(path))
+        .filter(|path| path.parent() == Some(directory))
+        .collect::<Vec<_>>();
+    children.sort();
+
+    children
+}
+
+/// Whether `path` is on a btrfs filesystem, or `None` if that can't be determined
+pub fn is_btrfs(path: &Path) -> Option<bool> {
+    statfs(path)
+        .ok()
+        .map(|stat| stat.filesystem_type() == BTRFS_SUPER_MAGIC)
+}
+
+/// A mount as listed in `/proc/self/mountinfo`
+#[derive(Debug, PartialEq, Eq)]
+struct Mount {
+    device: String,
+    fstype: String,
+    root: PathBuf,
+    mount_point: PathBuf,
+    subvol: Option<PathBuf>,
+}
+
+impl Mount {
+    /// Bind mounts of directories on btrfs expose something other than the root of the subvolume they are in
+    fn is_bind(&self) -> bool {
+        self.subvol.as_ref().is_some_and(|subvol| *subvol != self.root)
+    }
+}
+
+/// Undoes the octal escaping of whitespace and backslashes in mountinfo fields
+fn unescape_mountinfo(field: &str) -> String {
+    let mut unescaped = String::new();
+    let mut rest = field;
+
+    while let Some(start) = rest.find('\\') {
+        unescaped.push_str(&rest[..start]);
+        match rest
+            .get(start + 1..start + 4)
+            .and_then(|o| u8::from_str_radix(o, 8).ok())
+        {
+            Some(c) => {
+                unescaped.push(c as char);
+                rest = &rest[start + 4..];
+            }
+            None => {
+                unescaped.push('\\');
+                rest = &rest[start + 1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+
+    unescaped
+}
+
+fn parse_mountinfo(content: &str) -> Vec<Mount> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            let separator = fields.iter().position(|f| *f == "-")?;
+            if separator < 5 {
+                return None;
+            }
+
+            let subvol = fields
+                .get(separator + 3)?
+                .split(',')
+                .find_map(|o| o.strip_prefix("subvol="))
+                .map(|s| PathBuf::from(unescape_mountinfo(s)));
+
+            Some(Mount {
+                device: fields[2].to_string(),
+                fstype: fields.get(separator + 1)?.to_string(),
+                root: PathBuf::from(unescape_mountinfo(fields[3])),
+                mount_point: PathBuf::from(unescape_mountinfo(fields[4])),
+                subvol,
+            })
+        })
+        .collect()
+}
+
+fn mounts() -> Option<Vec<Mount>> {
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo").ok()?;
+    Some(parse_mountinfo(&mountinfo))
+}
+
+fn resolve_bind_mount_in(mounts: &[Mount], path: &Path) -> Option<Result<PathBuf, PathBuf>> {
+    // later mounts on the same mount point shadow earlier ones
+    let bind = mounts.iter().rev().find(|m| m.mount_point == path)?;
+    if bind.fstype != "btrfs" || !bind.is_bind() {
+        return None;
+    }
+    let subvol = bind.subvol.as_ref()?;
+
+    let resolved: Option<PathBuf> = mounts
+        .iter()
+        .filter(|m| m.fstype == "btrfs" && m.device == bind.device && !m.is_bind())
+        .find_map(|m| {
+            let relative = subvol.strip_prefix(&m.root).ok()?;
+            Some(m.mount_point.components().chain(relative.components()).collect())
+        });
+
+    Some(resolved.ok_or_else(|| subvol.clone()))
+}
+
+/// Detects whether `path` is a bind mount of a directory on btrfs. Returns the location the subvolume containing that
+/// directory is accessible at, or, if it isn't mounted anywhere, the path of the subvolume within the filesystem.
+pub fn resolve_bind_mount(path: &Path) -> Option<Result<PathBuf, PathBuf>> {
+    resolve_bind_mount_in(&mounts()?, path)
+}
+
+/// ID of the top-level subvolume of every btrfs filesystem
+const FS_TREE_OBJECTID: u64 = 5;
+
+/// A subvolume found on a mounted btrfs filesystem
+#[derive(Debug, PartialEq, Eq)]
+pub struct FoundSubvolume {
+    /// Device number (`major:minor`) of the filesystem
+    pub device: String,
+    /// Path of the subvolume within the filesystem
+    pub subvolume: PathBuf,
+    /// Where the subvolume is accessible, if it is mounted or below a mounted subvolume
+    pub location: Option<PathBuf>,
+    /// Whether the subvolume itself is mounted, rather than only accessible below another one
+    pub mounted: bool,
+    /// Where the filesystem's subvolume closest to its top level is mounted
+    pub filesystem_mount: PathBuf,
+}
+
+/// Where the subvolume at `subvolume` within the filesystem of `device` is accessible, and whether it is mounted there
+/// itself. A mount of the subvolume is preferred over the closest mount of a subvolume containing it.
+fn locate_subvolume_in(mounts: &[Mount], device: &str, subvolume: &Path) -> Option<(PathBuf, bool)> {
+    mounts
+        .iter()
+        .filter(|m| m.fstype == "btrfs" && m.device == device && !m.is_bind())
+        .filter_map(|m| {
+            let relative = subvolume.strip_prefix(&m.root).ok()?;
+            Some((relative.components().count(), m.mount_point.join(relative)))
+        })
+        .min_by_key(|(depth, _location)| *depth)
+        .map(|(depth, location)| (location, depth == 0))
+}
+
+/// Lists the subvolumes of every mounted btrfs filesystem, except their top-level subvolume and read-only subvolumes,
+/// which are usually snapshots. Listing them requires root privileges; filesystems whose subvolumes can't be listed
+/// are skipped with a warning.
+pub fn btrfs_subvolumes() -> Vec<FoundSubvolume> {
+    let mounts = mounts().unwrap_or_default();
+    let mut filesystems: Vec<&Mount> = Vec::new();
+    for mount in mounts.iter().filter(|m| m.fstype == "btrfs" && !m.is_bind()) {
+        match filesystems.iter_mut().find(|f| f.device == mount.device) {
+            Some(top) if mount.root.components().count() < top.root.components().count() => *top = mount,
+            Some(_) => {}
+            None => filesystems.push(mount),
+        }
+    }
+
+    let mut found = Vec::new();
+    for filesystem in filesystems {
+        let iterator = btrfs::SubvolumeIterator::new(
+            &filesystem.mount_point,
+            NonZeroU64::new(FS_TREE_OBJECTID),
+            btrfs::SubvolumeIteratorFlags::empty(),
+        );
+        let subvolumes = match iterator.map(|i| i.collect::<Result<Vec<_>, _>>()) {
+            Ok(Ok(subvolumes)) => subvolumes,
+            Ok(Err(e)) | Err(e) => {
+                warn!(
+                    "Unable to list the subvolumes of the filesystem mounted at {:?}! Error: {}",
+                    &filesystem.mount_point, e
+                );
+                continue;
+            }
+        };
+
+        for (relative, id) in subvolumes {
+            let read_only = btrfs::subvolume_info_with_id(&filesystem.mount_point, id.get())
+                .is_ok_and(|info| info.flags() & 1 != 0);
+            if read_only {
+                continue;
+            }
+            let subvolume = Path::new("/").join(relative);
+            let location = locate_subvolume_in(&mounts, &filesystem.device, &subvolume);
+            found.push(FoundSubvolume {
+                device: filesystem.device.clone(),
+                mounted: location.as_ref().is_some_and(|(_location, mounted)| *mounted),
+                location: location.map(|(location, _mounted)| location),
+                subvolume,
+                filesystem_mount: filesystem.mount_point.clone(),
+            });
+        }
+    }
+
+    found
+}
+
+fn filesystem_device_in(mounts: &[Mount], path: &Path) -> Option<String> {
+    // of several mounts on the same mount point, the last one is visible
+    mounts
+        .iter()
+        .filter(|m| path.starts_with(&m.mount_point))
+        .max_by_key(|m| m.mount_point.components().count())
+        .map(|m| m.device.clone())
+}
+
+/// The device number (`major:minor`) of the filesystem `path` is on. Unlike `filesystem_id`, it is the same for all
+/// subvolumes of a btrfs filesystem.
+pub fn filesystem_device(path: &Path) -> Option<String> {
+    let path = fs::canonicalize(path).ok()?;
+    filesystem_device_in(&mounts()?, &path)
+}
+
+/// Parses an octal permission mode such as `0750`
+pub fn parse_mode(mode: &str) -> Result<u32, Box<dyn Error>> {
+    let mode = u32::from_str_radix(mode.trim_start_matches("0o"), 8).map_err(|_| ModeParseError)?;
+    if mode > 0o7777 {
+        return Err(Box::new(ModeParseError));
+    }
+
+    Ok(mode)
+}
+
+/// Sets the permission bits of `path` to the octal `mode`
+pub fn set_mode(path: &Path, mode: &str) -> Result<(), Box<dyn Error>> {
+    fs::set_permissions(path, Permissions::from_mode(parse_mode(mode)?))?;
+    Ok(())
+}
+
+/// How often a random descent is started to find each sampled file
+const SAMPLE_ATTEMPTS: usize = 16;
+/// How much of each sampled file is read
+const SAMPLE_READ_BYTES: u64 = 1 << 20;
+
+/// Reads the beginning of `count` files picked at random from the directory tree at `path`. Each file is found by
+/// descending into random entries from the top, so the tree isn't walked in full. Fails if no file is found or
+/// reading one fails, and returns the files read otherwise.
+pub fn read_sample(path: &Path, count: usize, seed: u64) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    // xorshift, which is plenty for picking files
+    let mut state = seed | 1;
+    let mut random = |n: usize| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        (state % n as u64) as usize
+    };
+
+    let mut read = Vec::new();
+    for _ in 0..count {
+        for _ in 0..SAMPLE_ATTEMPTS {
+            let mut current = path.to_path_buf();
+            let file = loop {
+                let mut entries = fs::read_dir(&current)?
+                    .map(|e| e.map(|e| e.path()))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if entries.is_empty() {
+                    break None;
+                }
+                entries.sort();
+                let entry = entries.swap_remove(random(entries.len()));
+                let file_type = fs::symlink_metadata(&entry)?.file_type();
+                if file_type.is_file() {
+                    break Some(entry);
+                } else if file_type.is_dir() {
+                    current = entry;
+                } else {
+                    break None;
+                }
+            };
+
+            if let Some(file) = file {
+                io::copy(&mut File::open(&file)?.take(SAMPLE_READ_BYTES), &mut io::sink())?;
+                read.push(file);
+                break;
+            }
+        }
+    }
+
+    if read.is_empty() && count > 0 {
+        return Err(Box::new(EmptySnapshotError));
+    }
+
+    Ok(read)
+}
+
+/// Takes an exclusive advisory lock on `path` without waiting. Returns `None` if another process holds the lock.
+/// The lock is released when the returned file is dropped.
+pub fn try_lock(path: &Path) -> Result<Option<File>, Box<dyn Error>> {
+    lock_nonblocking(File::open(path)?)
+}
+
+/// Like `try_lock`, but for a lock file that is created if it doesn't exist
+pub fn try_lock_file(path: &Path) -> Result<Option<File>, Box<dyn Error>> {
+    lock_nonblocking(OpenOptions::new().create(true).truncate(false).write(true).open(path)?)
+}
+
+fn lock_nonblocking(file: File) -> Result<Option<File>, Box<dyn Error>> {
+    match flock(file.as_raw_fd(), FlockArg::LockExclusiveNonblock) {
+        Ok(()) => Ok(Some(file)),
+        Err(Errno::EWOULDBLOCK) => Ok(None),
+        Err(e) => Err(Box::new(e)),
+    }
+}
+
+/// Flushes the buffered writes of the filesystem `path` is on
+pub fn sync_filesystem(path: &Path) -> Result<(), Box<dyn Error>> {
+    let file = fs::File::open(path)?;
+    syncfs(file.as_raw_fd())?;
+    Ok(())
+}
+
+/// Makes the subvolume at `path` read-only
+pub fn set_read_only(path: &Path) -> Result<(), Box<dyn Error>> {
+    let status = Command::new("btrfs")
+        .args(["property", "set", "-ts"])
+        .arg(path)
+        .args(["ro", "true"])
+        .status()?;
+    if !status.success() {
+        return Err(Box::new(ReadOnlyError));
+    }
+    Ok(())
+}
+
+/// The nonzero error counters in the output of `btrfs device stats`, e.g. `[/dev/sda].corruption_errs 3`
+fn parse_device_stats(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (counter, value) = line.split_once(char::is_whitespace)?;
+            let value = value.trim().parse::<u64>().ok()?;
+            (value > 0).then(|| format!("{} {}", counter, value))
+        })
+        .collect()
+}
+
+/// The errors of the filesystem `path` is on: whether it was remounted read-only, and the nonzero error counters of
+/// its devices
+pub fn filesystem_errors(path: &Path) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut errors = Vec::new();
+    if statvfs(path)?.flags().contains(FsFlags::ST_RDONLY) {
+        errors.push("mounted read-only".to_string());
+    }
+
+    let output = Command::new("btrfs").arg("device").arg("stats").arg(path).output()?;
+    if !output.status.success() {
+        return Err(Box::new(DeviceStatsError));
+    }
+    errors.extend(parse_device_stats(&String::from_utf8_lossy(&output.stdout)));
+
+    Ok(errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    use std::path::{Path, PathBuf};
+
+    use crate::filesystem::{
+        children_of, filesystem_device_in, format_size, is_subvolume, list_directories, list_subvolumes,
+        locate_subvolume_in, parse_device_stats, parse_mode, parse_mountinfo, parse_qgroup_exclusive, read_sample,
+        resolve_bind_mount_in, set_mode, snapshot_note, try_lock_file, FreeSpace, NOTE_XATTR,
+    };
+
+    #[test]
+    fn qgroup_exclusive() {
+        let output = "qgroupid         rfer         excl
+--------         ----         ----
+0/5             16384        16384
+0/257      1073741824     52428800
+0/2570           4096         4096
+";
+        assert_eq!(parse_qgroup_exclusive(output, 257), Some(52428800));
+        assert_eq!(parse_qgroup_exclusive(output, 5), Some(16384));
+        assert_eq!(parse_qgroup_exclusive(output, 258), None);
+        assert_eq!(parse_qgroup_exclusive("", 257), None);
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(format_size(0), "0B");
+        assert_eq!(format_size(1023), "1023B");
+        assert_eq!(format_size(1536), "1.5K");
+        assert_eq!(format_size(3 * 1024 * 1024 * 1024), "3.0G");
+    }
+
+    #[test]
+    fn nonzero_device_stats() {
+        let output = "[/dev/sda1].write_io_errs    0
+[/dev/sda1].read_io_errs     2
+[/dev/sda1].flush_io_errs    0
+[/dev/sda1].corruption_errs  0
+[/dev/sda1].generation_errs  0
+[/dev/sdb1].write_io_errs    0
+[/dev/sdb1].read_io_errs     0
+[/dev/sdb1].flush_io_errs    0
+[/dev/sdb1].corruption_errs  17
+[/dev/sdb1].generation_errs  0
+";
+        assert_eq!(
+            parse_device_stats(output),
+            vec!["[/dev/sda1].read_io_errs 2", "[/dev/sdb1].corruption_errs 17"]
+        );
+        assert!(parse_device_stats(&output.replace("17", "0").replace('2', "0")).is_empty());
+        assert!(parse_device_stats("").is_empty());
+    }
+
+    #[test]
+    fn parse_modes() {
+        assert_eq!(parse_mode("0750").unwrap(), 0o750);
+        assert_eq!(parse_mode("0o2775").unwrap(), 0o2775);
+        assert!(parse_mode("0758").is_err());
+        assert!(parse_mode("17777").is_err());
+    }
+
+    #[test]
+    fn applies_mode() {
+        let dir = std::env::temp_dir().join(format!("ghee-mode-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        set_mode(&dir, "0710").unwrap();
+        assert_eq!(fs::metadata(&dir).unwrap().permissions().mode() & 0o7777, 0o710);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reads_notes() {
+        let dir = std::env::temp_dir().join(format!("ghee-note-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        assert_eq!(snapshot_note(&dir), "");
+        assert_eq!(snapshot_note(&dir.join("missing")), "");
+        // not every filesystem the tests run on supports user attributes
+        if xattr::set(&dir, NOTE_XATTR, b"before the upgrade\n").is_ok() {
+            assert_eq!(snapshot_note(&dir), "before the upgrade");
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn lock_file_excludes_second_run() {
+        let path = std::env::temp_dir().join(format!("ghee-lock-test-{}.lock", std::process::id()));
+
+        let first = try_lock_file(&path).unwrap();
+        assert!(first.is_some());
+        assert!(try_lock_file(&path).unwrap().is_none());
+        drop(first);
+        assert!(try_lock_file(&path).unwrap().is_some());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn reads_samples() {
+        let dir = std::env::temp_dir().join(format!("ghee-sample-test-{}", std::process::id()));
+        fs::create_dir_all(dir.join("empty/nested")).unwrap();
+        assert!(read_sample(&dir, 3, 42).is_err());
+        assert!(read_sample(&dir, 0, 42).unwrap().is_empty());
+
+        fs::create_dir_all(dir.join("etc/ssh")).unwrap();
+        fs::write(dir.join("etc/hostname"), "host\n").unwrap();
+        fs::write(dir.join("etc/ssh/sshd_config"), "Port 22\n").unwrap();
+        for seed in [1, 42, 1337] {
+            let read = read_sample(&dir, 3, seed).unwrap();
+            assert!(!read.is_empty() && read.len() <= 3);
+            assert!(read.iter().all(|f| f.starts_with(dir.join("etc"))));
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn resolves_bind_mounts() {
+        let mounts = parse_mountinfo(
+            "22 1 0:19 / / rw - ext4 /dev/sda1 rw\n\
+             36 22 0:30 /@ /mnt/btrfs rw,relatime shared:1 - btrfs /dev/sda2 rw,subvolid=256,subvol=/@\n\
+             37 22 0:30 /@home /home rw,relatime - btrfs /dev/sda2 rw,subvolid=257,subvol=/@home\n\
+             38 22 0:30 /@/srv/my\\040data /srv/data rw - btrfs /dev/sda2 rw,subvolid=256,subvol=/@\n\
+             39 22 0:31 /@/data /data rw - btrfs /dev/sdb1 rw,subvolid=256,subvol=/@\n",
+        );
+        assert_eq!(mounts.len(), 5);
+        assert_eq!(mounts[3].root, PathBuf::from("/@/srv/my data"));
+
+        assert_eq!(resolve_bind_mount_in(&mounts, Path::new("/home")), None);
+        assert_eq!(resolve_bind_mount_in(&mounts, Path::new("/")), None);
+        assert_eq!(
+            resolve_bind_mount_in(&mounts, Path::new("/srv/data")),
+            Some(Ok(PathBuf::from("/mnt/btrfs")))
+        );
+        assert_eq!(
+            resolve_bind_mount_in(&mounts, Path::new("/data")),
+            Some(Err(PathBuf::from("/@")))
+        );
+    }
+
+    #[test]
+    fn locates_subvolumes() {
+        let mounts = parse_mountinfo(
+            "36 22 0:30 /@ / rw - btrfs /dev/sda2 rw,subvolid=256,subvol=/@\n\
+             37 22 0:30 /@home /home rw - btrfs /dev/sda2 rw,subvolid=257,subvol=/@home\n\
+             38 22 0:30 /@/srv/data /srv/data rw - btrfs /dev/sda2 rw,subvolid=256,subvol=/@\n\
+             39 22 0:31 / /mnt/backup rw - btrfs /dev/sdb1 rw,subvolid=5,subvol=/\n",
+        );
+        let locate = |device, subvolume| locate_subvolume_in(&mounts, device, Path::new(subvolume));
+
+        assert_eq!(locate("0:30", "/@home"), Some((PathBuf::from("/home"), true)));
+        assert_eq!(
+            locate("0:30", "/@/var/lib/machines"),
+            Some((PathBuf::from("/var/lib/machines"), false))
+        );
+        assert_eq!(locate("0:30", "/@old"), None);
+        assert_eq!(
+            locate("0:31", "/@data/db"),
+            Some((PathBuf::from("/mnt/backup/@data/db"), false))
+        );
+    }
+
+    #[test]
+    fn devices_of_paths() {
+        let mounts = parse_mountinfo(
+            "22 1 0:19 / / rw - ext4 /dev/sda1 rw\n\
+             36 22 0:30 /@ /mnt/btrfs rw - btrfs /dev/sda2 rw,subvolid=256,subvol=/@\n\
+             37 22 0:30 /@home /home rw - btrfs /dev/sda2 rw,subvolid=257,subvol=/@home\n\
+             38 22 0:31 /@backup /mnt/btrfs rw - btrfs /dev/sdb1 rw,subvolid=256,subvol=/@backup\n",
+        );
+
+        assert_eq!(filesystem_device_in(&mounts, Path::new("/home/user")).unwrap(), "0:30");
+        assert_eq!(filesystem_device_in(&mounts, Path::new("/homework")).unwrap(), "0:19");
+        assert_eq!(
+            filesystem_device_in(&mounts, Path::new("/mnt/btrfs/snaps")).unwrap(),
+            "0:31"
+        );
+    }
+
+    #[test]
+    fn free_space_thresholds() {
+        assert_eq!(FreeSpace::parse("10%").unwrap(), FreeSpace::Percent(10));
+        assert_eq!(FreeSpace::parse("512M").unwrap(), FreeSpace::Bytes(512 * 1024 * 1024));
+        assert_eq!(
+            FreeSpace::parse("2G").unwrap(),
+            FreeSpace::Bytes(2 * 1024 * 1024 * 1024)
+        );
+        assert_eq!(FreeSpace::parse("4096").unwrap(), FreeSpace::Bytes(4096));
+        assert!(FreeSpace::parse("110%").is_err());
+        assert!(FreeSpace::parse("lots").is_err());
+
+        let gib = 1024 * 1024 * 1024;
+        let low = (gib / 2, 100 * gib);
+        assert!(!FreeSpace::parse("1G").unwrap().is_satisfied(low.0, low.1));
+        assert!(!FreeSpace::parse("1%").unwrap().is_satisfied(low.0, low.1));
+        assert!(FreeSpace::parse("256M").unwrap().is_satisfied(low.0, low.1));
+        assert!(FreeSpace::parse("0%").unwrap().is_satisfied(0, low.1));
+    }
+
+    #[test]
+    fn subvolumes_in_directory() {
+        let relative = ["snaps/home.1", "snaps/nested/home.2", "home", "snaps/home.3"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+        assert_eq!(
+            children_of(Path::new("/mnt/btrfs"), Path::new("/mnt/btrfs/snaps/"), relative),
+            vec![
+                PathBuf::from("/mnt/btrfs/snaps/home.1"),
+                PathBuf::from("/mnt/btrfs/snaps/home.3")
+            ]
+        );
+    }
+
+    /// Needs root and a directory on a mounted btrfs test image holding some subvolumes and plain directories, e.g.
+    /// `GHEE_BTRFS_TEST_DIR=/mnt/test/snaps cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn subvolume_listing_matches_directory() {
+        let directory = PathBuf::from(std::env::var("GHEE_BTRFS_TEST_DIR").unwrap());
+
+        let mut from_directory = list_directories(&directory)
+            .unwrap()
+            .into_iter()
+            .filter(|path| is_subvolume(path))
+            .collect::<Vec<_>>();
+        from_directory.sort();
+        let from_btrfs = list_subvolumes(&directory).unwrap();
+
+        assert!(!from_btrfs.is_empty());
+        assert_eq!(from_btrfs, from_directory);
+    }
+}
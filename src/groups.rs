@@ -0,0 +1,83 @@
+use std::collections::BTreeMap;
+
+use log::info;
+use tabled::{Style, Table, Tabled};
+
+use crate::job::Job;
+
+const UNGROUPED: &str = "(ungrouped)";
+
+#[derive(Debug, PartialEq, Eq, Tabled)]
+pub struct GroupSummary {
+    pub group: String,
+    pub jobs: usize,
+}
+
+impl GroupSummary {
+    /// Counts the jobs in each configured group. Jobs without groups are counted as `(ungrouped)`, which comes last.
+    pub fn gather(jobs: &[Job]) -> Vec<Self> {
+        let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+        let mut ungrouped = 0;
+
+        for job in jobs {
+            match &job.groups {
+                Some(groups) if !groups.is_empty() => {
+                    for group in groups {
+                        *counts.entry(group).or_default() += 1;
+                    }
+                }
+                _ => ungrouped += 1,
+            }
+        }
+
+        let mut summaries = counts
+            .into_iter()
+            .map(|(group, jobs)| Self {
+                group: group.to_string(),
+                jobs,
+            })
+            .collect::<Vec<_>>();
+        if ungrouped > 0 {
+            summaries.push(Self {
+                group: UNGROUPED.to_string(),
+                jobs: ungrouped,
+            });
+        }
+
+        summaries
+    }
+
+    pub fn print_tabled(summaries: &[Self]) {
+        let table = Table::new(summaries).with(Style::modern()).to_string();
+        info!("{}", table);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::groups::GroupSummary;
+    use crate::job::Job;
+
+    #[test]
+    fn counts_groups() {
+        let jobs: Vec<Job> = serde_yaml::from_str(
+            "[{subvolume: /home, target: /snaps, groups: [daily, home], preserve: {min: all}},
+              {subvolume: /etc, target: /snaps, groups: [daily], preserve: {min: all}},
+              {subvolume: /var, target: /snaps, preserve: {min: all}}]",
+        )
+        .unwrap();
+
+        let summaries = GroupSummary::gather(&jobs)
+            .into_iter()
+            .map(|s| (s.group, s.jobs))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summaries,
+            vec![
+                ("daily".to_string(), 2),
+                ("home".to_string(), 1),
+                ("(ungrouped)".to_string(), 1)
+            ]
+        );
+    }
+}
@@ -1,28 +1,217 @@
 use std::cell::RefCell;
 use std::cmp::Reverse;
-use std::fs;
-use std::ops::Sub;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::fs::{self, File};
+use std::io::{ErrorKind, Write};
 use std::path::Path;
+use std::process::{Command, Stdio};
 use std::rc::Rc;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
-use chrono::{DateTime, FixedOffset, Local, SecondsFormat};
+use chrono::{DateTime, Duration, FixedOffset, Local};
 use libbtrfsutil as btrfs;
 use log::{debug, error, info, warn};
-use regex::Regex;
-use tabled::{Style, Table, Tabled};
+use serde::{Deserialize, Serialize};
+use tabled::Tabled;
 
-use crate::duration::duration_from_str;
+use crate::duration::subtract_from;
+use crate::error::{
+    BeforeSealError, ConsistencyGroupError, FreeSpaceError, HookError, IntentTypeParseError, NotSubvolumeError,
+    PlanHookError, SnapshotTimestampError, SourceLockedError, UnexpectedDeleteError,
+};
 use crate::executed_intent::ExecutedIntent;
-use crate::job::Job;
-use crate::policies::{PreservePolicyMin, PreservePolicyMinVariants};
-use crate::retention::Retention;
+use crate::filesystem::{
+    change_transid, creation_time, exclusive_size, filesystem_id, format_size, free_space, list_directories,
+    list_subvolumes, read_sample, set_mode, set_read_only, snapshot_note, sync_filesystem, try_lock, FreeSpace,
+};
+use crate::job::{Job, SnapshotListing, TimestampSource};
+use crate::metrics::BinUsage;
+use crate::naming::NAME_MAX;
+use crate::output::{column_width, elide_middle, elided_column_width, Progress, TableStyle};
+use crate::policies::{BinAnchor, RetentionAnchor};
+use crate::skipped::{SkipReason, SkippedJob};
 use crate::timebins::TimeBins;
+use crate::trash::{move_to_trash, trash_path};
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+/// How many hours the newest snapshot may be dated after now before the clock is considered to have gone backwards
+const CLOCK_SKEW_TOLERANCE_HOURS: i64 = 1;
+
+/// What is done with a snapshot. Serialized, displayed and parsed as `create`, `keep`, `delete` and `send`; these
+/// strings are stable, as they are read back from the state file and may be relied upon by other tools.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum IntentType {
     Create,
     Keep,
     Delete,
+    /// Copying a created snapshot to the job's `replicate` destination
+    Send,
+}
+
+impl Display for IntentType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let intent = match self {
+            IntentType::Create => "create",
+            IntentType::Keep => "keep",
+            IntentType::Delete => "delete",
+            IntentType::Send => "send",
+        };
+        write!(f, "{}", intent)
+    }
+}
+
+impl FromStr for IntentType {
+    type Err = IntentTypeParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "create" => Ok(IntentType::Create),
+            "keep" => Ok(IntentType::Keep),
+            "delete" => Ok(IntentType::Delete),
+            "send" => Ok(IntentType::Send),
+            _ => Err(IntentTypeParseError),
+        }
+    }
+}
+
+/// Why a snapshot is kept. The order of the variants is the order of the sections of the grouped table.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum KeepReason {
+    Min,
+    Recent,
+    Minutely,
+    Hourly,
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+    Beyond,
+    Safety,
+    Hold,
+    Veto,
+}
+
+impl Display for KeepReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let reason = match self {
+            KeepReason::Min => "Min",
+            KeepReason::Recent => "Recent",
+            KeepReason::Minutely => "Minutely",
+            KeepReason::Hourly => "Hourly",
+            KeepReason::Daily => "Daily",
+            KeepReason::Weekly => "Weekly",
+            KeepReason::Monthly => "Monthly",
+            KeepReason::Yearly => "Yearly",
+            KeepReason::Beyond => "Beyond window",
+            KeepReason::Safety => "Safety",
+            KeepReason::Hold => "Hold",
+            KeepReason::Veto => "Veto",
+        };
+        write!(f, "{}", reason)
+    }
+}
+
+/// A target directory whose snapshots couldn't be listed, so its job wasn't checked for snapshots to prune
+#[derive(Debug, Tabled)]
+pub struct UnreadableTarget {
+    pub subvolume: String,
+    pub directory: String,
+    pub error: String,
+}
+
+impl UnreadableTarget {
+    /// Warns about the targets that couldn't be read, which is distinct from a job having nothing to prune
+    pub fn report(unreadable: &[Self], style: TableStyle) {
+        if unreadable.is_empty() {
+            return;
+        }
+
+        warn!(
+            "{} targets could not be read, their jobs were NOT checked for snapshots to prune:",
+            unreadable.len()
+        );
+        warn!("{}", style.render(unreadable));
+    }
+}
+
+/// A job that is left with more or fewer snapshots than its `expected_count` once the plan is executed
+#[derive(Debug, PartialEq, Eq, Tabled)]
+pub struct CountViolation {
+    pub subvolume: String,
+    pub expected: String,
+    pub actual: usize,
+}
+
+impl CountViolation {
+    /// Counts the snapshots that the intents keep or create for each job with an `expected_count`, and lists the jobs
+    /// outside their range. Too few point at failing snapshots, too many at failing pruning.
+    pub fn check(intents: &[Rc<RefCell<Intent>>], jobs: &[&Job]) -> Vec<Self> {
+        jobs.iter()
+            .filter_map(|&job| {
+                let (lowest, highest) = job.preserve.expected_count?;
+                let actual = intents
+                    .iter()
+                    .map(|int| int.borrow())
+                    .filter(|int| int.job == job && matches!(int.intent, IntentType::Create | IntentType::Keep))
+                    .count();
+                (actual < lowest || actual > highest).then(|| Self {
+                    subvolume: job.subvolume.clone(),
+                    expected: format!("{}-{}", lowest, highest),
+                    actual,
+                })
+            })
+            .collect()
+    }
+
+    pub fn report(violations: &[Self], style: TableStyle) {
+        if violations.is_empty() {
+            return;
+        }
+
+        warn!("{} jobs have more or fewer snapshots than expected:", violations.len());
+        warn!("{}", style.render(violations));
+    }
+}
+
+/// An intent as it is passed to a job's `plan_hook` and printed with `--output json`
+#[derive(Serialize)]
+struct PlannedIntent<'i> {
+    intent: &'i IntentType,
+    subvolume: &'i str,
+    target: &'i str,
+    name: &'i str,
+    keep_reason: Option<String>,
+    size: Option<u64>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    displaced: bool,
+}
+
+/// What `--output json` prints: the planned intents, once they were executed the executed intents, and the jobs that
+/// were skipped
+#[derive(Serialize)]
+struct Report<'i> {
+    intents: Vec<PlannedIntent<'i>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    executed: Option<&'i [ExecutedIntent]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    skipped: Option<&'i [SkippedJob]>,
+}
+
+/// An intent read back from a plan written with `--plan-out`
+#[derive(Debug, Deserialize)]
+pub struct PlanEntry {
+    pub intent: IntentType,
+    pub subvolume: String,
+    pub target: String,
+    pub name: String,
+    #[serde(default)]
+    pub displaced: bool,
 }
 
 #[derive(Debug, Tabled, Clone)]
@@ -32,86 +221,955 @@ pub struct Intent<'a> {
     pub subvolume: String,
     pub target: String,
     pub name: String,
+    pub notes: String,
+    #[tabled(skip)]
+    pub keep_reason: Option<KeepReason>,
+    /// Bytes exclusively occupied by the snapshot, only determined with `--show-usage`
+    #[tabled(skip)]
+    pub size: Option<u64>,
+    /// Whether the snapshot lost its bin to a snapshot created in this run, so that it is only deleted once that one
+    /// has been created
+    #[tabled(skip)]
+    pub displaced: bool,
     #[tabled(skip)]
     pub job: &'a Job,
 }
 
+/// An intent printed along with the size of its snapshot
+#[derive(Tabled)]
+struct SizedIntent<'a> {
+    #[tabled(inline)]
+    intent: Intent<'a>,
+    #[tabled(display_with("Self::display_size", args))]
+    size: Option<u64>,
+}
+
+impl SizedIntent<'_> {
+    fn display_size(&self) -> String {
+        self.size.map_or_else(|| "n/a".to_string(), format_size)
+    }
+}
+
 impl<'a> Intent<'a> {
     fn display_intent(&self) -> String {
         match self.intent {
             IntentType::Create => "++++++".to_string(),
             IntentType::Keep => "======".to_string(),
             IntentType::Delete => "------".to_string(),
+            IntentType::Send => ">>>>>>".to_string(),
+        }
+    }
+
+    /// Turns the intent into keeping its snapshot for the given reason
+    pub fn keep(&mut self, reason: KeepReason) {
+        self.intent = IntentType::Keep;
+        self.keep_reason = Some(reason);
+    }
+
+    /// When the snapshot was taken: by default the timestamp in its name, or with `timestamp_source: metadata` the
+    /// creation time btrfs recorded for it, as long as that can be read
+    pub fn timestamp(&self) -> Result<DateTime<FixedOffset>, Box<dyn Error>> {
+        let existing = matches!(self.intent, IntentType::Keep | IntentType::Delete);
+        if existing && self.job.timestamp_source == Some(TimestampSource::Metadata) {
+            match creation_time(Path::new(&self.target)) {
+                Ok(created) => return Ok(created),
+                Err(e) => debug!(
+                    "Unable to read the creation time of {}, using its name! Error: {}",
+                    &self.target, e
+                ),
+            }
+        }
+
+        self.job
+            .name_scheme()
+            .parse_timestamp(&self.job.snapshot_base_name(), &self.name)
+            .ok_or_else(|| SnapshotTimestampError.into())
+    }
+
+    /// Prints the intents in sections: creates, then keeps grouped by the reason they are kept for, then deletes
+    pub fn print_grouped(intents: &[Rc<RefCell<Self>>], style: TableStyle) {
+        let mut intents = intents.to_vec();
+        intents.sort_by_key(|i| {
+            let i = i.borrow();
+            let rank = match i.intent {
+                IntentType::Create => 0,
+                IntentType::Send => 1,
+                IntentType::Keep => 2,
+                IntentType::Delete => 3,
+            };
+            (rank, i.keep_reason)
+        });
+
+        let mut start = 0;
+        while start < intents.len() {
+            let section = intents[start].borrow().section();
+            let len = intents[start..]
+                .iter()
+                .take_while(|i| i.borrow().section() == section)
+                .count();
+
+            info!("{}:", section);
+            Self::print_tabled(&intents[start..start + len], style);
+            start += len;
+        }
+    }
+
+    fn section(&self) -> String {
+        match (&self.intent, self.keep_reason) {
+            (IntentType::Create, _) => "Creates".to_string(),
+            (IntentType::Keep, Some(reason)) => format!("{} keeps", reason),
+            (IntentType::Keep, None) => "Keeps".to_string(),
+            (IntentType::Delete, _) => "Deletes".to_string(),
+            (IntentType::Send, _) => "Sends".to_string(),
+        }
+    }
+
+    fn planned(&self) -> PlannedIntent<'_> {
+        PlannedIntent {
+            intent: &self.intent,
+            subvolume: &self.subvolume,
+            target: &self.target,
+            name: &self.name,
+            keep_reason: self.keep_reason.map(|r| r.to_string()),
+            size: self.size,
+            displaced: self.displaced,
+        }
+    }
+
+    /// The intents as a JSON array
+    pub fn to_json(intents: &[Rc<RefCell<Self>>]) -> Result<String, Box<dyn Error>> {
+        let intents = intents.iter().map(|int| int.borrow()).collect::<Vec<_>>();
+        let planned = intents.iter().map(|int| int.planned()).collect::<Vec<_>>();
+
+        Ok(serde_json::to_string(&planned)?)
+    }
+
+    /// The intents and, if they were executed, the executed intents as a single JSON object with the fields `intents`
+    /// and `executed`, along with the `skipped` jobs of commands that report them
+    pub fn report_json(
+        intents: &[Rc<RefCell<Self>>],
+        executed: Option<&[ExecutedIntent]>,
+        skipped: Option<&[SkippedJob]>,
+    ) -> Result<String, Box<dyn Error>> {
+        let intents = intents.iter().map(|int| int.borrow()).collect::<Vec<_>>();
+        let report = Report {
+            intents: intents.iter().map(|int| int.planned()).collect(),
+            executed,
+            skipped,
+        };
+
+        Ok(serde_json::to_string(&report)?)
+    }
+
+    /// How many intents there are of each type, e.g. `create: 4, keep: 37, delete: 9`. Sends are only counted if
+    /// there are any.
+    pub fn summary(intents: &[Rc<RefCell<Self>>]) -> String {
+        let count = |intent: IntentType| intents.iter().filter(|i| i.borrow().intent == intent).count();
+        let mut counts = vec![
+            (IntentType::Create, count(IntentType::Create)),
+            (IntentType::Keep, count(IntentType::Keep)),
+            (IntentType::Delete, count(IntentType::Delete)),
+        ];
+        let sends = count(IntentType::Send);
+        if sends > 0 {
+            counts.push((IntentType::Send, sends));
+        }
+
+        counts
+            .iter()
+            .map(|(intent, count)| format!("{}: {}", intent, count))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    pub fn print_summary(intents: &[Rc<RefCell<Self>>]) {
+        info!("{}", Self::summary(intents));
+    }
+
+    pub fn print_tabled(intents: &[Rc<RefCell<Self>>], style: TableStyle) {
+        let mut intents = intents.iter().map(|r| (*r.borrow()).clone()).collect::<Vec<_>>();
+
+        // the size column is only shown if the sizes were determined
+        let sized = intents.iter().any(|i| i.size.is_some());
+        let mut fixed_columns = vec![
+            column_width("intent", ["++++++"].into_iter()),
+            column_width("subvolume", intents.iter().map(|i| i.subvolume.as_str())),
+            column_width("notes", intents.iter().map(|i| i.notes.as_str())),
+        ];
+        if sized {
+            fixed_columns.push(column_width("size", ["1023.9K"].into_iter()));
+        }
+        if let Some(width) = elided_column_width(&fixed_columns, 2) {
+            for intent in &mut intents {
+                intent.target = elide_middle(&intent.target, width);
+                intent.name = elide_middle(&intent.name, width);
+            }
+        }
+
+        if sized {
+            let intents = intents.into_iter().map(|intent| SizedIntent {
+                size: intent.size,
+                intent,
+            });
+            info!("{}", style.render(intents));
+        } else {
+            info!("{}", style.render(intents));
+        }
+    }
+
+    /// The directory the snapshot of this intent is created in or deleted from. A send reads from the target the
+    /// snapshot was created in, so that it is executed after the create.
+    pub fn target_directory(&self) -> &Path {
+        let target = Path::new(&self.target);
+        match self.intent {
+            IntentType::Create => target,
+            IntentType::Keep | IntentType::Delete => target.parent().unwrap_or(target),
+            IntentType::Send => Path::new(&self.job.target),
+        }
+    }
+
+    /// Executes the intents in order. With `parallel_targets`, the intents are grouped by the filesystem of their
+    /// target directory, and up to that many groups are executed concurrently while each group is executed in order.
+    /// The creates of a consistency group are executed together, after syncing their sources once.
+    /// Once the `deadline` has passed, the remaining intents are not executed but returned as deferred.
+    /// Displaced snapshots are kept unless the job's snapshot that took their bin has been created before.
+    pub fn execute_all(
+        intents: &[Rc<RefCell<Self>>],
+        parallel_targets: Option<usize>,
+        deadline: Option<Instant>,
+    ) -> (Vec<ExecutedIntent>, Vec<Rc<RefCell<Self>>>) {
+        let originals = intents;
+        let intents = intents.iter().map(|i| i.borrow().clone()).collect::<Vec<_>>();
+        let progress = Progress::new(intents.iter().filter(|i| i.intent != IntentType::Keep).count());
+        let created: Mutex<Vec<&Job>> = Mutex::new(Vec::new());
+        let record_created = |intent: &Self, executed: &ExecutedIntent| {
+            if intent.intent == IntentType::Create && executed.success {
+                created.lock().unwrap().push(intent.job);
+            }
+        };
+        let execute = |intent: &Self| {
+            if intent.intent == IntentType::Keep {
+                return Some(intent.execute());
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return None;
+            }
+            let executed = if intent.displaced && !created.lock().unwrap().contains(&intent.job) {
+                warn!(
+                    "keeping {}, the snapshot of {} that was to take its place was not created",
+                    &intent.target, &intent.subvolume
+                );
+                let mut held = intent.clone();
+                held.keep(KeepReason::Safety);
+                held.execute()
+            } else {
+                intent.execute()
+            };
+            record_created(intent, &executed);
+            progress.step();
+            Some(executed)
+        };
+
+        let execute_unit = |unit: &[usize]| -> Vec<(usize, Option<ExecutedIntent>)> {
+            if let [i] = unit {
+                return vec![(*i, execute(&intents[*i]))];
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return unit.iter().map(|&i| (i, None)).collect();
+            }
+
+            let members = unit.iter().map(|&i| &intents[i]).collect::<Vec<_>>();
+            let mut synced: Vec<&str> = Vec::new();
+            for member in &members {
+                if !synced.contains(&member.subvolume.as_str()) {
+                    if let Err(e) = sync_filesystem(Path::new(&member.subvolume)) {
+                        warn!("syncing the filesystem of {} failed! error: {}", &member.subvolume, e);
+                    }
+                    synced.push(&member.subvolume);
+                }
+            }
+            let executed = Self::execute_consistency_group(
+                &members,
+                |intent| {
+                    let executed = intent.execute();
+                    progress.step();
+                    executed
+                },
+                |path| Ok(btrfs::delete_subvolume(path, btrfs::DeleteSubvolumeFlags::empty())?),
+            );
+            for (member, executed) in members.iter().zip(&executed) {
+                record_created(member, executed);
+            }
+            unit.iter().copied().zip(executed.into_iter().map(Some)).collect()
+        };
+
+        let units = Self::execution_units(&intents);
+        let mut executed_intents = Vec::with_capacity(intents.len());
+        executed_intents.resize_with(intents.len(), || None);
+        if let Some(max_parallel) = parallel_targets {
+            Self::execute_parallel(&intents, &units, &execute_unit, max_parallel, &mut executed_intents);
+        } else {
+            for unit in &units {
+                for (i, executed) in execute_unit(unit) {
+                    executed_intents[i] = executed;
+                }
+            }
+        }
+
+        let deferred = originals
+            .iter()
+            .zip(&executed_intents)
+            .filter(|(_, executed)| executed.is_none())
+            .map(|(int, _)| Rc::clone(int))
+            .collect();
+        (executed_intents.into_iter().flatten().collect(), deferred)
+    }
+
+    /// Splits the intents into the units they are executed in, by their indices: each intent on its own, except the
+    /// creates of jobs sharing a consistency group, which are executed together at the position of the first of them
+    fn execution_units(intents: &[Self]) -> Vec<Vec<usize>> {
+        let mut units: Vec<Vec<usize>> = Vec::new();
+        let mut consistency_groups: HashMap<&str, usize> = HashMap::new();
+        for (i, intent) in intents.iter().enumerate() {
+            let group = match (&intent.intent, &intent.job.consistency_group) {
+                (IntentType::Create, Some(group)) => group.as_str(),
+                _ => {
+                    units.push(vec![i]);
+                    continue;
+                }
+            };
+            match consistency_groups.get(group) {
+                Some(&unit) => units[unit].push(i),
+                None => {
+                    consistency_groups.insert(group, units.len());
+                    units.push(vec![i]);
+                }
+            }
+        }
+
+        units
+    }
+
+    /// Creates the snapshots of a consistency group one right after the other with `create`. Once one of them fails,
+    /// the remaining ones are not created and those already created are deleted again with `delete`, so that either
+    /// all snapshots of the group exist or none.
+    fn execute_consistency_group(
+        members: &[&Self],
+        create: impl Fn(&Self) -> ExecutedIntent,
+        delete: impl Fn(&Path) -> Result<(), Box<dyn Error>>,
+    ) -> Vec<ExecutedIntent> {
+        let mut executed: Vec<ExecutedIntent> = Vec::with_capacity(members.len());
+        for member in members {
+            if executed.iter().all(|e| e.success) {
+                executed.push(create(member));
+            } else {
+                executed.push(ExecutedIntent::failed(member, ConsistencyGroupError));
+            }
+        }
+        if executed.iter().all(|e| e.success) {
+            return executed;
+        }
+
+        members
+            .iter()
+            .zip(executed)
+            .map(|(member, executed)| {
+                if !executed.success {
+                    return executed;
+                }
+                let path = member.snapshot_path();
+                warn!(
+                    "another snapshot of consistency group {} failed, deleting {}",
+                    member.job.consistency_group.as_deref().unwrap_or_default(),
+                    path
+                );
+                if let Err(e) = delete(Path::new(&path)) {
+                    error!(
+                        "deleting snapshot {} of a failed consistency group failed! error: {}",
+                        path, e
+                    );
+                }
+                ExecutedIntent::failed(member, ConsistencyGroupError)
+            })
+            .collect()
+    }
+
+    /// Executes the units of intents on each filesystem concurrently, at most `max_parallel` filesystems at once,
+    /// storing the outcome of each intent at its index. A unit belongs to the filesystem of its first intent.
+    fn execute_parallel(
+        intents: &[Self],
+        units: &[Vec<usize>],
+        execute_unit: &(impl Fn(&[usize]) -> Vec<(usize, Option<ExecutedIntent>)> + Sync),
+        max_parallel: usize,
+        executed_intents: &mut [Option<ExecutedIntent>],
+    ) {
+        let mut groups: Vec<(Option<u64>, Vec<&[usize]>)> = Vec::new();
+        for unit in units {
+            let fsid = filesystem_id(intents[unit[0]].target_directory());
+            match groups.iter_mut().find(|(id, _)| *id == fsid) {
+                Some((_, group)) => group.push(unit),
+                None => groups.push((fsid, vec![unit])),
+            }
+        }
+        for (fsid, group) in &groups {
+            let names = group
+                .iter()
+                .flat_map(|unit| unit.iter().map(|&i| &intents[i].name))
+                .collect::<Vec<_>>();
+            debug!("intents on filesystem {:?}: {:?}", fsid, names);
+        }
+
+        // each worker takes the next group not yet taken and executes it in order
+        let next_group = AtomicUsize::new(0);
+        thread::scope(|scope| {
+            let handles = (0..max_parallel.clamp(1, groups.len().max(1)))
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut executed = Vec::new();
+                        while let Some((_, group)) = groups.get(next_group.fetch_add(1, Ordering::Relaxed)) {
+                            executed.extend(group.iter().flat_map(|unit| execute_unit(unit)));
+                        }
+                        executed
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            for handle in handles {
+                for (i, executed) in handle.join().expect("executing intents panicked") {
+                    executed_intents[i] = executed;
+                }
+            }
+        });
+    }
+
+    /// Checks the job's `min_free_space` on the target's filesystem and warns if there is less
+    fn has_free_space(&self) -> bool {
+        let min_free_space = match &self.job.min_free_space {
+            Some(min_free_space) => min_free_space,
+            None => return true,
+        };
+        let threshold = match FreeSpace::parse(min_free_space) {
+            Ok(threshold) => threshold,
+            Err(e) => {
+                warn!(
+                    "invalid min_free_space {}, not checking it! error: {}",
+                    min_free_space, e
+                );
+                return true;
+            }
+        };
+
+        match free_space(Path::new(&self.target)) {
+            Some((available, total)) if !threshold.is_satisfied(available, total) => {
+                warn!(
+                    "only {} of {} bytes are free on the filesystem of {}, less than {}! Not creating snapshot {}",
+                    available, total, &self.target, min_free_space, &self.name
+                );
+                false
+            }
+            _ => true,
+        }
+    }
+
+    /// Locks the subvolume while its snapshot is created, if the job skips subvolumes locked by other processes.
+    /// Fails if another process holds the lock. If the lock can't be taken for another reason, it is done without.
+    fn lock_source(&self) -> Result<Option<File>, Box<dyn Error>> {
+        if !self.job.skip_if_locked.unwrap_or(false) {
+            return Ok(None);
+        }
+
+        match try_lock(Path::new(&self.subvolume)) {
+            Ok(Some(lock)) => Ok(Some(lock)),
+            Ok(None) => Err(Box::new(SourceLockedError)),
+            Err(e) => {
+                warn!(
+                    "Unable to lock {}, snapshotting it anyway! Error: {}",
+                    &self.subvolume, e
+                );
+                Ok(None)
+            }
+        }
+    }
+
+    /// Flushes the source's filesystem if the job asks to sync before snapshotting. Returns whether syncing succeeded,
+    /// or `None` if the job doesn't sync.
+    fn sync_source(&self) -> Option<bool> {
+        if !self.job.sync_before.unwrap_or(false) {
+            return None;
+        }
+
+        debug!("executing syncfs({:?})", &self.subvolume);
+        match sync_filesystem(Path::new(&self.subvolume)) {
+            Ok(()) => Some(true),
+            Err(e) => {
+                warn!("syncing the filesystem of {} failed! error: {}", &self.subvolume, e);
+                Some(false)
+            }
+        }
+    }
+
+    /// Runs a `pre_hook` or `post_hook` command of the job, with the paths of the subvolume and the snapshot in
+    /// `GHEE_SUBVOLUME` and `GHEE_SNAPSHOT`. Its output is logged at debug level.
+    fn run_hook(&self, command: &str) -> Result<(), Box<dyn Error>> {
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .env("GHEE_SUBVOLUME", &self.subvolume)
+            .env("GHEE_SNAPSHOT", self.snapshot_path())
+            .output()?;
+        debug!(
+            "`{}` stdout: {}",
+            command,
+            String::from_utf8_lossy(&output.stdout).trim_end()
+        );
+        debug!(
+            "`{}` stderr: {}",
+            command,
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        );
+        if !output.status.success() {
+            warn!("`{}` exited with {} for {}", command, output.status, &self.subvolume);
+            return Err(Box::new(HookError));
+        }
+
+        Ok(())
+    }
+
+    /// Runs the job's `before_seal` command, if it has one, on the writable snapshot at `path`, with the path in
+    /// `GHEE_SNAPSHOT`
+    fn run_before_seal(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let command = match &self.job.before_seal {
+            Some(command) => command,
+            None => return Ok(()),
+        };
+
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .env("GHEE_SNAPSHOT", path)
+            .status()?;
+        if !status.success() {
+            warn!("`{}` exited with {} for {:?}", command, status, path);
+            return Err(Box::new(BeforeSealError));
+        }
+
+        Ok(())
+    }
+
+    /// Runs the `before_seal` command on the writable snapshot at `path` and makes it read-only with `seal`. If either
+    /// fails, the snapshot is deleted with `delete`, so a writable snapshot never passes for a backup.
+    fn seal(
+        &self,
+        path: &Path,
+        seal: impl Fn(&Path) -> Result<(), Box<dyn Error>>,
+        delete: impl Fn(&Path) -> Result<(), Box<dyn Error>>,
+    ) -> Result<(), Box<dyn Error>> {
+        let sealed = self.run_before_seal(path).and_then(|()| seal(path));
+        match sealed {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                warn!("sealing snapshot {:?} failed, deleting it! error: {}", path, e);
+                if let Err(e) = delete(path) {
+                    error!(
+                        "deleting unsealed snapshot {:?} failed, it is still writable! error: {}",
+                        path, e
+                    );
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Reads the job's `verify_sample` of random files from the new snapshot at `path`. Fails if the snapshot holds no
+    /// files or they can't be read.
+    fn verify(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let count = match self.job.verify_sample {
+            Some(count) if count > 0 => count,
+            _ => return Ok(()),
+        };
+
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        match read_sample(path, count, seed) {
+            Ok(read) => {
+                debug!("verified snapshot {:?} by reading {:?}", path, read);
+                Ok(())
+            }
+            Err(e) => {
+                warn!("verifying snapshot {:?} failed! error: {}", path, e);
+                Err(e)
+            }
+        }
+    }
+
+    /// The path a created snapshot is placed at
+    fn snapshot_path(&self) -> String {
+        format!("{}/{}", self.job.snapshot_directory(), self.name)
+    }
+
+    /// Whether the target of this intent is a snapshot of its job, directly in the job's snapshot directory and named
+    /// by its naming scheme, so that nothing else is ever deleted
+    fn is_own_snapshot(&self) -> bool {
+        let target = Path::new(&self.target);
+        let scheme = self.job.name_scheme();
+
+        target.parent() == Some(Path::new(&self.job.snapshot_directory()))
+            && target.file_name().and_then(|f| f.to_str()) == Some(self.name.as_str())
+            && scheme.regex(&self.job.snapshot_base_name()).is_match(&self.name)
+            && scheme
+                .parse_timestamp(&self.job.snapshot_base_name(), &self.name)
+                .is_some()
+    }
+
+    /// Describes the btrfs operation executing this intent performs, including its flags
+    pub fn operation(&self) -> Option<String> {
+        match self.intent {
+            IntentType::Create => {
+                let read_only = self.job.read_only.unwrap_or(true);
+                let create = match &self.job.before_seal {
+                    Some(command) if !read_only => format!(
+                        "create_snapshot({:?}, {:?}, empty, qgroup: none); sh -c {:?}",
+                        &self.subvolume,
+                        self.snapshot_path(),
+                        command
+                    ),
+                    Some(command) => format!(
+                        "create_snapshot({:?}, {:?}, empty, qgroup: none); sh -c {:?}; set_subvolume_read_only({:?}, true)",
+                        &self.subvolume,
+                        self.snapshot_path(),
+                        command,
+                        self.snapshot_path()
+                    ),
+                    None if !read_only => format!(
+                        "create_snapshot({:?}, {:?}, empty, qgroup: none)",
+                        &self.subvolume,
+                        self.snapshot_path()
+                    ),
+                    None => format!(
+                        "create_snapshot({:?}, {:?}, READ_ONLY, qgroup: none)",
+                        &self.subvolume,
+                        self.snapshot_path()
+                    ),
+                };
+                match self.job.sync_before {
+                    Some(true) => Some(format!("syncfs({:?}); {}", &self.subvolume, create)),
+                    _ => Some(create),
+                }
+            }
+            IntentType::Keep => None,
+            IntentType::Delete if self.job.trash.unwrap_or(false) => {
+                let local_now = Local::now();
+                let trash = trash_path(Path::new(&self.target), &local_now.with_timezone(local_now.offset()));
+                Some(format!("rename({:?}, {:?})", &self.target, trash))
+            }
+            IntentType::Delete => Some(format!("delete_subvolume({:?}, empty)", &self.target)),
+            IntentType::Send => self
+                .job
+                .replicate
+                .as_ref()
+                .map(|replicate| replicate.operation(&self.snapshot_path())),
         }
     }
 
-    pub fn timestamp(&self) -> DateTime<FixedOffset> {
-        let time_re = Regex::new(r".*\.(\d{4}-\d{2}-\d{2}T\d{2}:\d{2}:\d{2}([+-]\d{2}:\d{2})?)").unwrap();
-        let timestamp = time_re.captures(&self.name).unwrap().get(1).unwrap();
-        let timestamp = DateTime::parse_from_rfc3339(timestamp.as_str()).unwrap();
-        timestamp
+    /// Logs the target directories that would be created before snapshotting into them
+    pub fn print_created_targets(intents: &[Rc<RefCell<Self>>]) {
+        for int in intents {
+            let int = int.borrow();
+            if int.intent == IntentType::Create && int.job.creates_target() {
+                info!("Would create target directory {}", &int.job.target);
+            }
+        }
     }
 
-    pub fn print_tabled(intents: &[Rc<RefCell<Self>>]) {
-        let intents = intents.iter().map(|r| (*r.borrow()).clone()).collect::<Vec<_>>();
-        let table = Table::new(intents).with(Style::modern()).to_string();
-        info!("{}", table);
+    /// Logs the btrfs operation of each intent that performs one
+    pub fn print_operations(intents: &[Rc<RefCell<Self>>]) {
+        for int in intents {
+            if let Some(operation) = int.borrow().operation() {
+                info!("{}", operation);
+            }
+        }
     }
 
     pub fn execute(&self) -> ExecutedIntent {
         match self.intent {
             IntentType::Create => {
-                let res = btrfs::create_snapshot(
-                    &self.subvolume,
-                    &format!("{}/{}", self.target, self.name),
-                    btrfs::CreateSnapshotFlags::READ_ONLY,
-                    None,
-                );
-                match res {
-                    Ok(_) => ExecutedIntent::new(self, true),
+                if self.job.creates_target() {
+                    if let Err(e) = fs::create_dir_all(&self.job.target) {
+                        warn!("creating target directory {} failed! error: {}", &self.job.target, e);
+                    }
+                }
+                if let Some(mode) = &self.job.target_mode {
+                    if let Err(e) = set_mode(Path::new(&self.target), mode) {
+                        warn!("setting mode {} of {} failed! error: {}", mode, &self.target, e);
+                    }
+                }
+
+                if !self.has_free_space() {
+                    return ExecutedIntent::failed(self, FreeSpaceError);
+                }
+
+                let directory = self.job.snapshot_directory();
+                if directory != self.target {
+                    if let Err(e) = fs::create_dir_all(&directory) {
+                        warn!("creating directory {} failed! error: {}", &directory, e);
+                    }
+                }
+
+                let _lock = match self.lock_source() {
+                    Ok(lock) => lock,
                     Err(e) => {
-                        warn!("creating snapshot failed! error: {}", e);
-                        ExecutedIntent::new(self, false)
+                        warn!("skipping snapshot {} of {}! error: {}", &self.name, &self.subvolume, e);
+                        return ExecutedIntent::failed(self, e);
+                    }
+                };
+
+                self.sync_source();
+                if let Some(command) = &self.job.pre_hook {
+                    if let Err(e) = self.run_hook(command) {
+                        warn!("skipping snapshot {} of {}! error: {}", &self.name, &self.subvolume, e);
+                        return ExecutedIntent::failed(self, e);
                     }
                 }
+                let executed = self.create_snapshot();
+                // whatever the pre_hook prepared is undone even if the snapshot failed
+                if let Some(command) = &self.job.post_hook {
+                    if let Err(e) = self.run_hook(command) {
+                        warn!("post_hook of {} failed! error: {}", &self.subvolume, e);
+                    }
+                }
+
+                executed
             }
             IntentType::Keep => ExecutedIntent::new(self, true),
+            IntentType::Send => self.send_snapshot(),
             IntentType::Delete => {
+                if !self.is_own_snapshot() {
+                    error!(
+                        "NOT DELETING {}, it is not a snapshot of {} in {}! This is a bug, please report it.",
+                        &self.target,
+                        &self.subvolume,
+                        self.job.snapshot_directory()
+                    );
+                    return ExecutedIntent::failed(self, UnexpectedDeleteError);
+                }
+                // the snapshot may have been deleted by someone else since the intents were gathered
+                if !Path::new(&self.target).exists() {
+                    info!("Snapshot {} has already been deleted, nothing to do.", &self.target);
+                    return ExecutedIntent::new(self, true);
+                }
+                // and whatever is there now must still be a subvolume
+                match btrfs::is_subvolume(&self.target) {
+                    Ok(true) => (),
+                    Ok(false) => {
+                        warn!("{} is not a subvolume, not deleting it!", &self.target);
+                        return ExecutedIntent::failed(self, NotSubvolumeError);
+                    }
+                    Err(e) => {
+                        warn!(
+                            "Unable to check whether {} is a subvolume, not deleting it! Error: {}",
+                            &self.target, e
+                        );
+                        return ExecutedIntent::failed(self, e);
+                    }
+                }
+                debug!("executing {}", self.operation().unwrap_or_default());
+                if self.job.trash.unwrap_or(false) {
+                    let local_now = Local::now();
+                    return match move_to_trash(Path::new(&self.target), &local_now.with_timezone(local_now.offset())) {
+                        Ok(_) => ExecutedIntent::new(self, true),
+                        Err(e) => {
+                            warn!("moving snapshot to the trash failed! error: {}", e);
+                            ExecutedIntent::failed(self, e)
+                        }
+                    };
+                }
                 let res = btrfs::delete_subvolume(&self.target, btrfs::DeleteSubvolumeFlags::empty());
                 match res {
                     Ok(_) => ExecutedIntent::new(self, true),
                     Err(e) => {
                         warn!("deleting snapshot failed! error: {}", e);
-                        ExecutedIntent::new(self, false)
+                        ExecutedIntent::failed(self, e)
                     }
                 }
             }
         }
     }
 
-    pub fn gather_create_intents(jobs: &'a [Job]) -> Vec<Rc<RefCell<Self>>> {
-        let now = Local::now();
-        let now_str = now.to_rfc3339_opts(SecondsFormat::Secs, true);
+    /// Creates the snapshot, seals it if the job has a `before_seal` command, and verifies it. Snapshots of jobs with
+    /// `read_only: false` are left writable.
+    fn create_snapshot(&self) -> ExecutedIntent {
+        debug!("executing {}", self.operation().unwrap_or_default());
+        let read_only = self.job.read_only.unwrap_or(true);
+        let flags = match self.job.before_seal {
+            Some(_) => btrfs::CreateSnapshotFlags::empty(),
+            None if !read_only => btrfs::CreateSnapshotFlags::empty(),
+            None => btrfs::CreateSnapshotFlags::READ_ONLY,
+        };
+        let res = btrfs::create_snapshot(&self.subvolume, self.snapshot_path(), flags, None);
+        match res {
+            Ok(_) => {
+                let path = self.snapshot_path();
+                let sealed = match self.job.before_seal {
+                    Some(_) => self.seal(
+                        Path::new(&path),
+                        |path| {
+                            if read_only {
+                                set_read_only(path)?;
+                            }
+                            Ok(())
+                        },
+                        |path| Ok(btrfs::delete_subvolume(path, btrfs::DeleteSubvolumeFlags::empty())?),
+                    ),
+                    None => Ok(()),
+                };
+                match sealed.and_then(|()| self.verify(Path::new(&path))) {
+                    Ok(()) => ExecutedIntent::new(self, true),
+                    Err(e) => ExecutedIntent::failed(self, e),
+                }
+            }
+            Err(e) => {
+                warn!("creating snapshot failed! error: {}", e);
+                ExecutedIntent::failed(self, e)
+            }
+        }
+    }
+
+    /// Sends the created snapshot to the job's `replicate` destination, incrementally against the newest snapshot the
+    /// destination already has
+    fn send_snapshot(&self) -> ExecutedIntent {
+        let replicate = match &self.job.replicate {
+            Some(replicate) => replicate,
+            None => return ExecutedIntent::new(self, true),
+        };
+        debug!("executing {}", self.operation().unwrap_or_default());
 
-        let mut create_intents = Vec::new();
-        for job in jobs {
-            let subvolume_path = Path::new(&job.subvolume);
+        let directory = self.job.snapshot_directory();
+        let local = fs::read_dir(&directory)
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .map(|e| e.file_name().to_string_lossy().to_string())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        let sent = replicate.received().and_then(|received| {
+            let parent = self.common_parent(&local, &received);
+            let parent = parent.map(|p| Path::new(&directory).join(p));
+            replicate.send(Path::new(&self.snapshot_path()), parent.as_deref())
+        });
+        match sent {
+            Ok(()) => ExecutedIntent::new(self, true),
+            Err(e) => {
+                warn!("sending snapshot failed! error: {}", e);
+                ExecutedIntent::failed(self, e)
+            }
+        }
+    }
+
+    /// The newest of the job's `local` snapshots, other than this one, that is also among the `received` ones, for
+    /// sending this snapshot incrementally
+    fn common_parent(&self, local: &[String], received: &[String]) -> Option<String> {
+        let scheme = self.job.name_scheme();
+        let base_name = self.job.snapshot_base_name();
+        local
+            .iter()
+            .filter(|name| **name != self.name && received.contains(name))
+            .filter_map(|name| Some((scheme.parse_timestamp(&base_name, name)?, name)))
+            .max()
+            .map(|(_ts, name)| name.clone())
+    }
 
+    /// Plans sending each of the created snapshots whose job replicates its snapshots
+    pub fn gather_send_intents(intents: &mut Vec<Rc<RefCell<Self>>>) {
+        let sends = intents
+            .iter()
+            .map(|int| int.borrow())
+            .filter(|int| int.intent == IntentType::Create)
+            .filter_map(|int| {
+                let replicate = int.job.replicate.as_ref()?;
+                Some(Rc::new(RefCell::new(Intent {
+                    intent: IntentType::Send,
+                    subvolume: int.subvolume.clone(),
+                    target: replicate.destination.clone(),
+                    name: int.name.clone(),
+                    notes: String::new(),
+                    keep_reason: None,
+                    size: None,
+                    displaced: false,
+                    job: int.job,
+                })))
+            })
+            .collect::<Vec<_>>();
+        intents.extend(sends);
+    }
+
+    /// Plans a snapshot of each job, named after `now`. Also returns the jobs that no snapshot can be created of.
+    pub fn gather_create_intents(
+        jobs: &[&'a Job],
+        now: &DateTime<FixedOffset>,
+        label: Option<&str>,
+    ) -> (Vec<Rc<RefCell<Self>>>, Vec<SkippedJob>) {
+        let mut create_intents = Vec::new();
+        let mut skipped = Vec::new();
+        for &job in jobs {
+            if job.excludes_subvolume() {
+                debug!("{} is excluded, not creating a snapshot of it", &job.subvolume);
+                continue;
+            }
             let subvolume_test = btrfs::is_subvolume(&job.subvolume);
             match subvolume_test {
-                Err(e) => warn!("{} is not a btrfs subvolume! Error: {}", &job.subvolume, e),
+                Err(e) => {
+                    warn!("{} is not a btrfs subvolume! Error: {}", &job.subvolume, e);
+                    skipped.push(SkippedJob::new(job, IntentType::Create, SkipReason::NotSubvolume));
+                }
                 Ok(is_subvol) => {
                     if !is_subvol {
                         warn!(
                             "{} is not a btrfs subvolume! Can't create a snapshot of it!",
                             &job.subvolume
                         );
+                        skipped.push(SkippedJob::new(job, IntentType::Create, SkipReason::NotSubvolume));
                     } else {
+                        let scheme = job.name_scheme();
+                        let base_name = job.snapshot_base_name();
+                        let name = scheme.format_labeled(&base_name, now, label);
+                        if name.len() > NAME_MAX {
+                            warn!(
+                                "Snapshot name {} of {} exceeds {} bytes! Can't create a snapshot of it!",
+                                &name, &job.subvolume, NAME_MAX
+                            );
+                            skipped.push(SkippedJob::new(job, IntentType::Create, SkipReason::NameTooLong));
+                            continue;
+                        }
+                        if job.has_snapshot(&name) {
+                            debug!(
+                                "Snapshot {} of {} already exists, not creating it again",
+                                &name, &job.subvolume
+                            );
+                            skipped.push(SkippedJob::new(job, IntentType::Create, SkipReason::AlreadyExists));
+                            continue;
+                        }
+                        if scheme.subvol_component(&base_name) != base_name {
+                            warn!(
+                                "Name of {} is too long for snapshot names, shortening it to {}",
+                                &job.subvolume, &name
+                            );
+                        }
+
+                        let notes = match job.read_only {
+                            Some(false) => "writable".to_string(),
+                            _ => String::new(),
+                        };
                         create_intents.push(Rc::new(RefCell::new(Intent {
                             intent: IntentType::Create,
                             subvolume: job.subvolume.clone(),
                             target: job.target.clone(),
-                            name: format!("{}.{}", subvolume_path.file_name().unwrap().to_str().unwrap(), now_str),
+                            name,
+                            notes,
+                            keep_reason: None,
+                            size: None,
+                            displaced: false,
                             job,
                         })));
                     }
@@ -119,96 +1177,484 @@ impl<'a> Intent<'a> {
             }
         }
 
-        create_intents
+        (create_intents, skipped)
     }
 
-    pub fn gather_delete_intents(jobs: &'a [Job]) -> Vec<Rc<RefCell<Self>>> {
-        let mut delete_intents = Vec::new();
-        for job in jobs {
-            let subvolume_path = Path::new(&job.subvolume);
-            let re = format!(
-                "{}.{}",
-                &subvolume_path.file_name().unwrap().to_str().unwrap(),
-                r"\d{4}-\d{2}-\d{2}T\d{2}:\d{2}:\d{2}([+-]\d{2}:\d{2})?"
-            );
-            let re = Regex::new(&re).unwrap();
+    /// Drops the deletes of snapshots dated before `now` minus `since` or after `now` minus `until`, so that the
+    /// snapshots outside the window are neither pruned nor counted by retention and min. As in `min` timespans, months
+    /// and years are calendar months and years.
+    pub fn retain_window(
+        intents: &mut Vec<Rc<RefCell<Self>>>,
+        now: &DateTime<FixedOffset>,
+        since: Option<&str>,
+        until: Option<&str>,
+    ) -> Result<(), Box<dyn Error>> {
+        let since = since.map(|since| subtract_from(now, since)).transpose()?;
+        let until = until.map(|until| subtract_from(now, until)).transpose()?;
+        let before = intents.len();
+        intents.retain(|int| {
+            let int = int.borrow();
+            if int.intent != IntentType::Delete {
+                return true;
+            }
+            let timestamp = match int.timestamp() {
+                Ok(timestamp) => timestamp,
+                Err(_) => return false,
+            };
+            since.is_none_or(|since| timestamp >= since) && until.is_none_or(|until| timestamp <= until)
+        });
+        debug!(
+            "{} snapshots are outside the window and left alone",
+            before - intents.len()
+        );
+
+        Ok(())
+    }
+
+    /// Plans deleting each existing snapshot of the jobs. Also returns the targets that couldn't be read, whose jobs
+    /// are not pruned. With `show_usage`, the size of each snapshot is determined as well, which can take a while.
+    pub fn gather_delete_intents(
+        jobs: &[&'a Job],
+        show_usage: bool,
+    ) -> (Vec<Rc<RefCell<Self>>>, Vec<UnreadableTarget>) {
+        let mut delete_intents = Vec::new();
+        let mut unreadable = Vec::new();
+        for &job in jobs {
+            if job.excludes_subvolume() {
+                debug!("{} is excluded, not pruning its snapshots", &job.subvolume);
+                continue;
+            }
+            let scheme = job.name_scheme();
+            let base_name = job.snapshot_base_name();
+            let re = scheme.regex(&base_name);
+            let directory = job.snapshot_directory();
 
-            let paths = fs::read_dir(&job.target);
+            let paths = match job.snapshot_listing.unwrap_or(SnapshotListing::ReadDir) {
+                SnapshotListing::ReadDir => list_directories(Path::new(&directory)),
+                SnapshotListing::Subvolumes => list_subvolumes(Path::new(&directory)).or_else(|e| {
+                    warn!(
+                        "Unable to list the subvolumes in {}, reading its directories instead! Error: {}",
+                        &directory, e
+                    );
+                    list_directories(Path::new(&directory))
+                }),
+            };
             if let Ok(paths) = paths {
                 for path in paths {
-                    if let Ok(path) = path {
-                        if let Ok(metadata) = path.metadata() {
-                            if metadata.is_dir() {
-                                if let Some(filename) = path.file_name().to_str() {
-                                    if re.is_match(filename) {
-                                        delete_intents.push(Rc::new(RefCell::new(Intent {
-                                            intent: IntentType::Delete,
-                                            subvolume: job.subvolume.clone(),
-                                            target: path.path().to_str().unwrap().to_string(),
-                                            name: path.file_name().to_str().unwrap().to_string(),
-                                            job,
-                                        })));
-                                    }
-                                } else {
-                                    error!("Unable to parse Unicode from path {:?}!", path.path())
-                                }
-                            }
-                        } else if let Err(e) = path.metadata() {
-                            error!("Unable to read metadata of {:?}! Error: {}", path.path(), e)
+                    if let (Some(filename), Some(target)) = (path.file_name().and_then(|f| f.to_str()), path.to_str()) {
+                        if job.excludes_snapshot(filename) {
+                            debug!("{} is excluded, not pruning it", target);
+                            continue;
                         }
-                    } else if let Err(e) = path {
-                        error!("IO error occured when accessing {}! Error: {}", &job.target, e)
+                        // names whose timestamp can't be parsed are never considered for deletion
+                        if re.is_match(filename) && scheme.parse_timestamp(&base_name, filename).is_some() {
+                            delete_intents.push(Rc::new(RefCell::new(Intent {
+                                intent: IntentType::Delete,
+                                subvolume: job.subvolume.clone(),
+                                target: target.to_string(),
+                                name: filename.to_string(),
+                                notes: snapshot_note(&path),
+                                keep_reason: None,
+                                size: if show_usage { exclusive_size(&path) } else { None },
+                                displaced: false,
+                                job,
+                            })));
+                        }
+                    } else {
+                        error!("Unable to parse Unicode from path {:?}!", path)
                     }
                 }
             } else if let Err(e) = paths {
-                error!("Unable to read directory {}! Error: {}", &job.target, e)
+                // the subdirectory of a job, and with `create_target` its target, is only created along with its first
+                // snapshot
+                let not_created_yet = e.kind() == ErrorKind::NotFound
+                    && ((directory != job.target && Path::new(&job.target).is_dir()) || job.creates_target());
+                if not_created_yet {
+                    debug!("{} doesn't exist yet, there is nothing to prune", &directory);
+                } else {
+                    error!("Unable to read directory {}! Error: {}", &directory, e);
+                    unreadable.push(UnreadableTarget {
+                        subvolume: job.subvolume.clone(),
+                        directory,
+                        error: e.to_string(),
+                    });
+                }
             }
         }
 
-        delete_intents
+        (delete_intents, unreadable)
     }
 
-    pub fn delete_to_keep_intents(intents: &mut [Rc<RefCell<Self>>], jobs: &[Job]) {
-        for job in jobs {
+    /// Reconstructs the intents that failed in a previous execution, as long as they still apply. Failed creates are
+    /// planned anew with the current time, failed deletes only if the snapshot still exists.
+    pub fn gather_retry_intents(
+        failed: &[ExecutedIntent],
+        jobs: &[&'a Job],
+        now: &DateTime<FixedOffset>,
+    ) -> Vec<Rc<RefCell<Self>>> {
+        let mut retry_intents = Vec::new();
+        for executed in failed {
+            match executed.intent {
+                IntentType::Create => {
+                    let job = jobs
+                        .iter()
+                        .find(|j| j.subvolume == executed.subvolume && j.target == executed.target);
+                    if let Some(job) = job {
+                        retry_intents.append(&mut Self::gather_create_intents(std::slice::from_ref(job), now, None).0);
+                    }
+                }
+                IntentType::Delete => {
+                    let snapshot = Path::new(&executed.target);
+                    let job = jobs.iter().copied().find(|j| {
+                        j.subvolume == executed.subvolume
+                            && snapshot.parent() == Some(Path::new(&j.snapshot_directory()))
+                            && j.name_scheme().regex(&j.snapshot_base_name()).is_match(&executed.name)
+                    });
+                    match job {
+                        Some(job) if snapshot.exists() => retry_intents.push(Rc::new(RefCell::new(Intent {
+                            intent: IntentType::Delete,
+                            subvolume: executed.subvolume.clone(),
+                            target: executed.target.clone(),
+                            name: executed.name.clone(),
+                            notes: snapshot_note(snapshot),
+                            keep_reason: None,
+                            size: None,
+                            displaced: false,
+                            job,
+                        }))),
+                        Some(_) => info!("{} has already been deleted, not retrying", &executed.target),
+                        None => {}
+                    }
+                }
+                IntentType::Send => {
+                    let job = jobs.iter().copied().find(|j| {
+                        j.subvolume == executed.subvolume
+                            && j.replicate.as_ref().map(|r| r.destination.as_str()) == Some(executed.target.as_str())
+                    });
+                    if let Some(job) = job {
+                        let send = Intent {
+                            intent: IntentType::Send,
+                            subvolume: executed.subvolume.clone(),
+                            target: executed.target.clone(),
+                            name: executed.name.clone(),
+                            notes: String::new(),
+                            keep_reason: None,
+                            size: None,
+                            displaced: false,
+                            job,
+                        };
+                        if Path::new(&send.snapshot_path()).exists() {
+                            retry_intents.push(Rc::new(RefCell::new(send)));
+                        } else {
+                            info!("{} no longer exists, not retrying to send it", send.snapshot_path());
+                        }
+                    }
+                }
+                IntentType::Keep => {}
+            }
+        }
+
+        retry_intents
+    }
+
+    /// Reconstructs the intents of a plan, exactly as they were planned. Entries whose job is no longer configured, or
+    /// whose snapshot doesn't exist anymore for a delete or a send or already exists for a create, are skipped with a
+    /// warning. Keeps are left out, as there is nothing to execute for them.
+    pub fn gather_plan_intents(plan: &[PlanEntry], jobs: &[&'a Job]) -> Vec<Rc<RefCell<Self>>> {
+        let mut plan_intents = Vec::new();
+        for entry in plan.iter().filter(|e| e.intent != IntentType::Keep) {
+            let job = jobs.iter().copied().find(|j| {
+                j.subvolume == entry.subvolume
+                    && match entry.intent {
+                        IntentType::Create => j.target == entry.target,
+                        IntentType::Send => j.replicate.as_ref().is_some_and(|r| r.destination == entry.target),
+                        IntentType::Keep | IntentType::Delete => {
+                            Path::new(&entry.target).parent() == Some(Path::new(&j.snapshot_directory()))
+                        }
+                    }
+            });
+            let job = match job {
+                Some(job) => job,
+                None => {
+                    warn!(
+                        "no configured job of {} matches the planned {} of {}, skipping it!",
+                        &entry.subvolume, entry.intent, &entry.target
+                    );
+                    continue;
+                }
+            };
+            let mut intent = Intent {
+                intent: entry.intent.clone(),
+                subvolume: entry.subvolume.clone(),
+                target: entry.target.clone(),
+                name: entry.name.clone(),
+                notes: String::new(),
+                keep_reason: None,
+                size: None,
+                displaced: entry.displaced,
+                job,
+            };
+
+            let exists = Path::new(&intent.snapshot_path()).exists();
+            let expected = match intent.intent {
+                IntentType::Create => !exists,
+                IntentType::Send => exists,
+                IntentType::Delete => exists && intent.is_own_snapshot(),
+                IntentType::Keep => false,
+            };
+            if !expected {
+                warn!(
+                    "snapshot {} no longer matches the planned {}, skipping it!",
+                    intent.snapshot_path(),
+                    intent.intent
+                );
+                continue;
+            }
+            if intent.intent == IntentType::Delete {
+                intent.notes = snapshot_note(Path::new(&intent.target));
+            }
+            plan_intents.push(Rc::new(RefCell::new(intent)));
+        }
+
+        plan_intents
+    }
+
+    /// Passes each job's planned intents as a JSON array on stdin to its `plan_hook` command, which prints the names
+    /// of the snapshots whose deletion it vetoes, one per line. If the command fails, none of the job's snapshots are
+    /// deleted. With `dryrun`, the command is only logged.
+    pub fn apply_plan_hooks(intents: &[Rc<RefCell<Self>>], jobs: &[&Job], dryrun: bool) {
+        for &job in jobs {
+            let command = match &job.plan_hook {
+                Some(command) => command,
+                None => continue,
+            };
+            if dryrun {
+                info!("would pass the plan of {} to `{}`", &job.subvolume, command);
+                continue;
+            }
+
+            let job_intents = intents.iter().filter(|int| int.borrow().job == job).collect::<Vec<_>>();
+            let vetoed = match Self::run_plan_hook(command, &job_intents) {
+                Ok(vetoed) => vetoed,
+                Err(e) => {
+                    warn!(
+                        "`{}` failed for {}, NOT DELETING ANY OF ITS SNAPSHOTS! Error: {}",
+                        command, &job.subvolume, e
+                    );
+                    for int in &job_intents {
+                        let mut int = int.borrow_mut();
+                        if int.intent == IntentType::Delete {
+                            int.keep(KeepReason::Safety);
+                        }
+                    }
+                    continue;
+                }
+            };
+            for int in &job_intents {
+                let mut int = int.borrow_mut();
+                if int.intent == IntentType::Delete && vetoed.contains(&int.name) {
+                    info!("`{}` vetoed deleting {}", command, &int.name);
+                    int.keep(KeepReason::Veto);
+                }
+            }
+        }
+    }
+
+    /// Runs a `plan_hook` command with the intents as JSON on stdin, and returns the names it prints
+    fn run_plan_hook(command: &str, intents: &[&Rc<RefCell<Self>>]) -> Result<Vec<String>, Box<dyn Error>> {
+        let planned = intents.iter().map(|int| int.borrow()).collect::<Vec<_>>();
+        let planned = planned.iter().map(|int| int.planned()).collect::<Vec<_>>();
+        let json = serde_json::to_vec(&planned)?;
+
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        if let Some(mut stdin) = child.stdin.take() {
+            // a hook that doesn't read its input closes the pipe early, which is fine
+            match stdin.write_all(&json) {
+                Err(e) if e.kind() != ErrorKind::BrokenPipe => return Err(Box::new(e)),
+                _ => {}
+            }
+        }
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            warn!("`{}` exited with {}", command, output.status);
+            return Err(Box::new(PlanHookError));
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(|line| line.trim().to_string())
+            .filter(|line| !line.is_empty())
+            .collect())
+    }
+
+    /// Keeps all snapshots that would be deleted while `hold_file` exists
+    pub fn hold_deletes(intents: &[Rc<RefCell<Self>>], hold_file: &Path) {
+        if !hold_file.exists() {
+            return;
+        }
+
+        warn!(
+            "{:?} exists, NOT DELETING ANY SNAPSHOTS! Remove it to resume pruning.",
+            hold_file
+        );
+        for int in intents {
+            let mut int = int.borrow_mut();
+            if int.intent == IntentType::Delete {
+                int.keep(KeepReason::Hold);
+            }
+        }
+    }
+
+    /// Keeps all snapshots that would be deleted if an existing snapshot is dated more than
+    /// `CLOCK_SKEW_TOLERANCE_HOURS` after `now`. The clock has then most likely gone backwards, and retention based on
+    /// it can't be trusted.
+    pub fn hold_deletes_on_clock_skew(intents: &[Rc<RefCell<Self>>], now: &DateTime<FixedOffset>) {
+        let newest = intents
+            .iter()
+            .filter(|int| matches!(int.borrow().intent, IntentType::Keep | IntentType::Delete))
+            .filter_map(|int| int.borrow().timestamp().ok())
+            .max();
+        let newest = match newest {
+            Some(newest) if newest > *now + Duration::hours(CLOCK_SKEW_TOLERANCE_HOURS) => newest,
+            _ => return,
+        };
+
+        error!(
+            "the newest snapshot is dated {}, but the clock says it is {}! The clock seems to have gone backwards, NOT DELETING ANY SNAPSHOTS!",
+            newest, now
+        );
+        for int in intents {
+            let mut int = int.borrow_mut();
+            if int.intent == IntentType::Delete {
+                int.keep(KeepReason::Safety);
+            }
+        }
+    }
+
+    /// Keeps all snapshots that would be deleted from a filesystem that reports errors, as deleting snapshots from a
+    /// failing filesystem could make its data harder to recover. `errors` lists the errors of the filesystem a
+    /// directory is on. Filesystems whose errors can't be determined are pruned as usual.
+    pub fn hold_deletes_on_filesystem_errors(
+        intents: &[Rc<RefCell<Self>>],
+        errors: impl Fn(&Path) -> Result<Vec<String>, Box<dyn Error>>,
+    ) {
+        let mut checked: HashMap<String, bool> = HashMap::new();
+        for int in intents {
+            let mut int = int.borrow_mut();
+            if int.intent != IntentType::Delete {
+                continue;
+            }
+
+            let directory = int.target_directory().to_string_lossy().to_string();
+            let failing = *checked.entry(directory).or_insert_with_key(|directory| match errors(Path::new(directory)) {
+                Ok(errors) if errors.is_empty() => false,
+                Ok(errors) => {
+                    error!(
+                        "the filesystem of {} reports errors: {}! NOT DELETING ITS SNAPSHOTS, pass --ignore-filesystem-errors to delete them anyway.",
+                        directory,
+                        errors.join(", ")
+                    );
+                    true
+                }
+                Err(e) => {
+                    warn!("Unable to check the filesystem of {} for errors! Error: {}", directory, e);
+                    false
+                }
+            });
+            if failing {
+                int.keep(KeepReason::Safety);
+            }
+        }
+    }
+
+    /// Moves the creates of jobs with `prune_before_create` behind all deletes, so the deletes free space first. Their
+    /// new snapshots take no bins, so nothing more is deleted if creating them fails. The creates of other jobs go
+    /// first, so that the snapshots they displaced from their bins can be kept if they fail.
+    /// The existing snapshots of each job are ordered newest first, or oldest first with `oldest_first`.
+    pub fn sort_for_execution(intents: &mut [Rc<RefCell<Self>>], oldest_first: bool) {
+        let mut jobs: Vec<&Job> = Vec::new();
+        for int in intents.iter().filter(|i| i.borrow().intent != IntentType::Create) {
+            let job = int.borrow().job;
+            if !jobs.contains(&job) {
+                jobs.push(job);
+            }
+        }
+
+        intents.sort_by_cached_key(|i| {
+            let i = i.borrow();
+            // snapshots are sent once all of them are created
+            let send = i.intent == IntentType::Send;
+            let after_deletes = i.intent == IntentType::Create && i.job.prune_before_create.unwrap_or(false);
+            let existing = matches!(i.intent, IntentType::Keep | IntentType::Delete);
+            let job = jobs.iter().position(|j| *j == i.job);
+            let age = match i
+                .job
+                .name_scheme()
+                .parse_timestamp(&i.job.snapshot_base_name(), &i.name)
+            {
+                Some(ts) if existing && oldest_first => ts.timestamp(),
+                Some(ts) if existing => -ts.timestamp(),
+                _ => 0,
+            };
+            (send, after_deletes, existing, job, age)
+        });
+    }
+
+    /// Sorts snapshots by their timestamp, newest first. Snapshots with equal timestamps are ordered by name, so which
+    /// of them are kept doesn't change between runs.
+    fn sort_newest_first(snapshots: &mut [(DateTime<FixedOffset>, Rc<RefCell<Self>>)]) {
+        snapshots.sort_by_cached_key(|(ts, int)| (Reverse(*ts), Reverse(int.borrow().name.clone())));
+    }
+
+    /// Decides which snapshots to keep, with the retention counting back from `now`. Returns how full the retention
+    /// bins of each job are.
+    pub fn delete_to_keep_intents(
+        intents: &mut [Rc<RefCell<Self>>],
+        jobs: &[&Job],
+        now: &DateTime<FixedOffset>,
+    ) -> Vec<BinUsage> {
+        let mut usages = Vec::new();
+        // snapshots that can't be dated can't be placed in the retention, so they are never deleted
+        for int in intents.iter() {
+            let mut int = int.borrow_mut();
+            if int.intent != IntentType::Delete {
+                continue;
+            }
+            if let Err(e) = int.timestamp() {
+                warn!("skipping snapshot {}, keeping it! Error: {}", &int.target, e);
+                int.keep(KeepReason::Safety);
+            }
+        }
+
+        for &job in jobs {
             let delete_intents = intents
                 .iter_mut()
                 .filter(|int| int.borrow().intent == IntentType::Delete)
-                .map(|int| (int.borrow().timestamp(), Rc::clone(int)));
+                .filter_map(|int| Some((int.borrow().timestamp().ok()?, Rc::clone(int))));
 
             let mut job_intents = delete_intents
                 .filter(|(_ts, int)| int.borrow().job == job)
                 .collect::<Vec<_>>();
-            job_intents.sort_by_key(|t| Reverse(t.0));
+            Self::sort_newest_first(&mut job_intents);
             let job_intents = job_intents.into_iter();
 
-            match &job.preserve.min {
-                PreservePolicyMin::Variant(PreservePolicyMinVariants::All) => {
-                    job_intents.for_each(|(_ts, int)| (*int).borrow_mut().intent = IntentType::Keep);
-                }
-                PreservePolicyMin::Variant(PreservePolicyMinVariants::Latest) => {
-                    job_intents
-                        .take(1)
-                        .for_each(|(_ts, int)| (*int).borrow_mut().intent = IntentType::Keep);
-                }
-                PreservePolicyMin::Timespan(ts) => {
-                    let d = duration_from_str(ts);
-                    match d {
-                        Err(e) => {
-                            warn!("error while handling preserve min for job: {}\nerror: {}\nfor safety, will not delete any snapshots from this job!", &job.subvolume, e);
-                            job_intents.for_each(|(_ts, int)| (*int).borrow_mut().intent = IntentType::Keep);
-                        }
-                        Ok(d) => {
-                            debug!("parsed duration for preserve min: {:?}", d);
-                            job_intents
-                                .take_while(|(ts, _int)| ts > &Local::now().sub(d))
-                                .for_each(|(_ts, int)| (*int).borrow_mut().intent = IntentType::Keep)
-                        }
-                    };
+            // min and retention are applied independently, and a malformed one of them keeps the rest for safety
+            let timestamps = job_intents.clone().map(|(ts, _int)| ts).collect::<Vec<_>>();
+            let min_valid = match job.preserve.min.keep_count(&timestamps, now) {
+                Err(e) => {
+                    warn!("error while handling preserve min for job: {}\nerror: {}\nretention is still applied, but for safety, will not delete any snapshots from this job!", &job.subvolume, e);
+                    false
                 }
-                PreservePolicyMin::Count(n) => {
+                Ok(n) => {
                     job_intents
-                        .take(*n)
-                        .for_each(|(_ts, int)| (*int).borrow_mut().intent = IntentType::Keep);
+                        .take(n)
+                        .for_each(|(_ts, int)| (*int).borrow_mut().keep(KeepReason::Min));
+                    true
                 }
             };
 
@@ -216,34 +1662,1797 @@ impl<'a> Intent<'a> {
             let delete_intents = intents
                 .iter_mut()
                 .filter(|int| int.borrow().intent == IntentType::Delete)
-                .map(|int| (int.borrow().timestamp(), Rc::clone(int)));
+                .filter_map(|int| Some((int.borrow().timestamp().ok()?, Rc::clone(int))));
 
             let mut job_intents = delete_intents
                 .filter(|(_ts, int)| int.borrow().job == job)
                 .collect::<Vec<_>>();
-            job_intents.sort_by_key(|t| Reverse(t.0));
+            Self::sort_newest_first(&mut job_intents);
+            let now = match job.preserve.retention_anchor {
+                Some(RetentionAnchor::Newest) => job_intents.first().map_or(*now, |(ts, _int)| *ts),
+                Some(RetentionAnchor::Now) | None => *now,
+            };
+            // bins follow the calendar of the job's time zone, so snapshots written in other zones are binned by the
+            // same point in time in it
+            let now = job.in_timezone(&now);
             let job_intents = job_intents.into_iter();
 
-            let retention = Retention::from_str_option(&job.preserve.retention);
+            let retention = job.retention();
             match retention {
+                Err(e) if min_valid => {
+                    warn!("error while handling preserve retention for job: {}\nerror: {}\nmin is still applied, but for safety, will not delete any other snapshots from this job!", &job.subvolume, e);
+                    job_intents.for_each(|(_ts, int)| (*int).borrow_mut().keep(KeepReason::Safety));
+                }
                 Err(e) => {
                     warn!("error while handling preserve retention for job: {}\nerror: {}\nfor safety, will not delete any snapshots from this job!", &job.subvolume, e);
-                    job_intents.for_each(|(_ts, int)| (*int).borrow_mut().intent = IntentType::Keep);
+                    job_intents.for_each(|(_ts, int)| (*int).borrow_mut().keep(KeepReason::Safety));
+                }
+                Ok(retention) if retention.is_zero() => {
+                    // the bins of every tier start with the current period, so binning would still keep a snapshot
+                    debug!(
+                        "no retention for job {}, keeping snapshots by min alone",
+                        &job.subvolume
+                    );
                 }
                 Ok(retention) => {
-                    let mut timebins = TimeBins::new(&retention);
+                    let anchor = job.preserve.effective_bin_anchor().unwrap_or_else(|e| {
+                        warn!(
+                            "invalid bin_time of job {}, using bin_anchor instead! error: {}",
+                            &job.subvolume, e
+                        );
+                        job.preserve.bin_anchor.unwrap_or(BinAnchor::First)
+                    });
+                    let mut timebins = TimeBins::covering(&retention, &now, anchor, timestamps.last());
+                    timebins.keep_beyond_window = job.preserve.keep_beyond_window.unwrap_or(false);
+                    if let Ok(Some(tz)) = job.timezone() {
+                        timebins.localize(tz);
+                    }
 
                     debug!("timebins before filling: {:?}", timebins);
 
                     for (timestamp, intent) in job_intents {
-                        timebins.store(&timestamp, Rc::clone(&intent));
+                        timebins.store(&timestamp.with_timezone(now.offset()), Rc::clone(&intent));
+                    }
+                    // the snapshots created in this run are binned as well, so that the retention decides about the
+                    // snapshots there will be after the run. Not with `prune_before_create`, as the deletes then run
+                    // before it is known whether creating them succeeds.
+                    if !job.prune_before_create.unwrap_or(false) {
+                        timebins.prefer_created = job.preserve.prefer_created.unwrap_or(false);
+                        let created = intents
+                            .iter()
+                            .filter(|int| int.borrow().intent == IntentType::Create && int.borrow().job == job);
+                        for intent in created {
+                            if let Ok(timestamp) = intent.borrow().timestamp() {
+                                timebins.store(&timestamp.with_timezone(now.offset()), Rc::clone(intent));
+                            }
+                        }
                     }
 
                     debug!("timebins after filling: {:?}", timebins);
 
                     timebins.set_keep();
+                    usages.extend(timebins.usage().into_iter().map(|(tier, filled, capacity)| BinUsage {
+                        job: job.subvolume.clone(),
+                        tier,
+                        filled,
+                        capacity,
+                    }));
                 }
             };
+
+            if !min_valid {
+                for int in intents.iter().filter(|int| int.borrow().job == job) {
+                    let mut int = int.borrow_mut();
+                    if int.intent == IntentType::Delete {
+                        int.keep(KeepReason::Safety);
+                    }
+                }
+                // deduplicating and freeing space would delete snapshots kept by retention
+                continue;
+            }
+
+            if job.deduplicate.unwrap_or(false) {
+                let job_intents = intents
+                    .iter()
+                    .filter(|int| int.borrow().job == job)
+                    .cloned()
+                    .collect::<Vec<_>>();
+                Self::deduplicate_unchanged(&job_intents, |int| change_transid(Path::new(&int.target)));
+            }
+
+            if let Some(min_free) = &job.preserve.min_free {
+                match FreeSpace::parse(min_free) {
+                    Ok(min_free) => {
+                        let job_intents = intents
+                            .iter()
+                            .filter(|int| int.borrow().job == job)
+                            .cloned()
+                            .collect::<Vec<_>>();
+                        Self::delete_for_free_space(&job_intents, min_free, free_space(Path::new(&job.target)));
+                    }
+                    Err(e) => warn!(
+                        "invalid min_free {} of job {}, not deleting for free space! error: {}",
+                        min_free, &job.subvolume, e
+                    ),
+                }
+            }
+        }
+
+        usages
+    }
+
+    /// If less than `min_free` of the `free` (available, total) bytes are available, deletes the oldest snapshot kept
+    /// by retention. btrfs frees the space of deleted snapshots later and how much is only known then, so one snapshot
+    /// is deleted per run until enough space is free. Snapshots kept by min or for safety are never deleted. Nothing is
+    /// deleted for a job with `trash`, since a trashed snapshot frees no space until the trash is emptied.
+    pub fn delete_for_free_space(intents: &[Rc<RefCell<Self>>], min_free: FreeSpace, free: Option<(u64, u64)>) {
+        let (available, total) = match free {
+            Some(free) => free,
+            None => return,
+        };
+        if min_free.is_satisfied(available, total) {
+            return;
+        }
+        if let Some(trashing) = intents.iter().find(|int| int.borrow().job.trash.unwrap_or(false)) {
+            let trashing = trashing.borrow();
+            warn!(
+                "only {} of {} bytes are free on the filesystem of {}, less than {:?}! Trashing frees no space",
+                available, total, &trashing.target, min_free
+            );
+            return;
+        }
+
+        let mut kept = intents
+            .iter()
+            .filter(|int| {
+                let int = int.borrow();
+                int.intent == IntentType::Keep && !matches!(int.keep_reason, Some(KeepReason::Min | KeepReason::Safety))
+            })
+            .filter_map(|int| Some((int.borrow().timestamp().ok()?, Rc::clone(int))))
+            .collect::<Vec<_>>();
+        Self::sort_newest_first(&mut kept);
+        if let Some((_ts, oldest)) = kept.last() {
+            let mut oldest = oldest.borrow_mut();
+            info!(
+                "only {} of {} bytes are free on the filesystem of {}, less than {:?}! Deleting {}",
+                available, total, &oldest.target, min_free, &oldest.name
+            );
+            oldest.intent = IntentType::Delete;
+            oldest.keep_reason = None;
+        }
+    }
+
+    /// Of consecutive snapshots that were taken without the subvolume changing in between, only one of those kept by
+    /// retention is kept. Snapshots kept by min or for safety are never deleted.
+    pub fn deduplicate_unchanged(intents: &[Rc<RefCell<Self>>], transid: impl Fn(&Self) -> Option<u64>) {
+        let mut snapshots = intents
+            .iter()
+            .filter(|int| matches!(int.borrow().intent, IntentType::Keep | IntentType::Delete))
+            .filter_map(|int| Some((int.borrow().timestamp().ok()?, Rc::clone(int))))
+            .collect::<Vec<_>>();
+        Self::sort_newest_first(&mut snapshots);
+        let transids = snapshots
+            .iter()
+            .map(|(_ts, int)| transid(&int.borrow()))
+            .collect::<Vec<_>>();
+
+        let mut start = 0;
+        while start < snapshots.len() {
+            let len = match transids[start] {
+                Some(t) => transids[start..].iter().take_while(|o| **o == Some(t)).count(),
+                None => 1,
+            };
+            let run = &snapshots[start..start + len];
+            start += len;
+
+            let mut keep_one = !run
+                .iter()
+                .any(|(_ts, int)| int.borrow().keep_reason == Some(KeepReason::Min));
+            for (_ts, int) in run {
+                let mut int = int.borrow_mut();
+                if int.intent != IntentType::Keep
+                    || matches!(int.keep_reason, Some(KeepReason::Min | KeepReason::Safety))
+                {
+                    continue;
+                }
+                if keep_one {
+                    keep_one = false;
+                    continue;
+                }
+
+                debug!("{} is identical to a newer snapshot, deleting it", &int.name);
+                int.intent = IntentType::Delete;
+                int.keep_reason = None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+    use std::collections::HashMap;
+    use std::error::Error;
+    use std::fs;
+    use std::path::Path;
+    use std::rc::Rc;
+    use std::time::Instant;
+
+    use chrono::{DateTime, Datelike, FixedOffset, Local, TimeZone};
+
+    use crate::error::{ConsistencyGroupError, HookError, SourceLockedError, UnexpectedDeleteError};
+    use crate::executed_intent::ExecutedIntent;
+    use crate::filesystem::{try_lock, FreeSpace};
+    use crate::intent::{CountViolation, Intent, IntentType, KeepReason, PlanEntry};
+    use crate::job::{Job, TimestampSource};
+    use crate::output::TableStyle;
+    use crate::policies::BinAnchor;
+    use crate::replicate::Replicate;
+    use crate::skipped::{SkipReason, SkippedJob};
+
+    fn job(subvolume: &str, prune_before_create: bool) -> Job {
+        serde_yaml::from_str(&format!(
+            "{{subvolume: {}, target: /mnt/btrfs/@/gheesnaps, preserve: {{min: all}}, prune_before_create: {}}}",
+            subvolume, prune_before_create
+        ))
+        .unwrap()
+    }
+
+    fn intent(job: &Job, intent: IntentType) -> Rc<RefCell<Intent<'_>>> {
+        Rc::new(RefCell::new(Intent {
+            intent,
+            subvolume: job.subvolume.clone(),
+            target: job.target.clone(),
+            name: job.subvolume_name().unwrap().to_string(),
+            notes: String::new(),
+            keep_reason: None,
+            size: None,
+            displaced: false,
+            job,
+        }))
+    }
+
+    #[test]
+    fn prune_before_create_order() {
+        let home = job("/home", true);
+        let etc = job("/etc", false);
+        let mut intents = vec![
+            intent(&home, IntentType::Create),
+            intent(&etc, IntentType::Create),
+            intent(&home, IntentType::Delete),
+            intent(&etc, IntentType::Delete),
+        ];
+
+        Intent::sort_for_execution(&mut intents, false);
+        let order = intents
+            .iter()
+            .map(|i| (i.borrow().subvolume.clone(), i.borrow().intent.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            order,
+            vec![
+                ("/etc".to_string(), IntentType::Create),
+                ("/home".to_string(), IntentType::Delete),
+                ("/etc".to_string(), IntentType::Delete),
+                ("/home".to_string(), IntentType::Create),
+            ]
+        );
+    }
+
+    #[test]
+    fn window_leaves_other_snapshots_alone() {
+        let home = job("/home", false);
+        let now = FixedOffset::east_opt(0)
+            .unwrap()
+            .with_ymd_and_hms(2022, 1, 31, 0, 0, 0)
+            .unwrap();
+        let mut intents = [1, 10, 20, 30]
+            .into_iter()
+            .map(|day| {
+                let ts = FixedOffset::east_opt(0)
+                    .unwrap()
+                    .with_ymd_and_hms(2022, 1, day, 0, 0, 0)
+                    .unwrap();
+                let int = intent(&home, IntentType::Delete);
+                int.borrow_mut().name = home.name_scheme().format(home.subvolume_name().unwrap(), &ts);
+                int
+            })
+            .collect::<Vec<_>>();
+        intents.push(intent(&home, IntentType::Create));
+
+        Intent::retain_window(&mut intents, &now, Some("25d"), Some("5d")).unwrap();
+        let kept = intents
+            .iter()
+            .map(|i| i.borrow().timestamp().map(|ts| ts.day()).ok())
+            .collect::<Vec<_>>();
+        assert_eq!(kept, vec![Some(10), Some(20), None]);
+
+        // three calendar months before May 15th reach back to February 15th, not just 84 days to February 20th
+        let now = FixedOffset::east_opt(0)
+            .unwrap()
+            .with_ymd_and_hms(2022, 5, 15, 0, 0, 0)
+            .unwrap();
+        let mut intents = [14, 16]
+            .into_iter()
+            .map(|day| {
+                let ts = FixedOffset::east_opt(0)
+                    .unwrap()
+                    .with_ymd_and_hms(2022, 2, day, 0, 0, 0)
+                    .unwrap();
+                let int = intent(&home, IntentType::Delete);
+                int.borrow_mut().name = home.name_scheme().format(home.subvolume_name().unwrap(), &ts);
+                int
+            })
+            .collect::<Vec<_>>();
+        Intent::retain_window(&mut intents, &now, Some("3m"), None).unwrap();
+        assert_eq!(intents.len(), 1);
+        assert_eq!(intents[0].borrow().timestamp().unwrap().day(), 16);
+    }
+
+    #[test]
+    fn reverse_order() {
+        fn snapshot(job: &Job, day: u32) -> Rc<RefCell<Intent<'_>>> {
+            let ts = FixedOffset::east_opt(0)
+                .unwrap()
+                .with_ymd_and_hms(2022, 1, day, 0, 0, 0)
+                .unwrap();
+            let int = intent(job, IntentType::Delete);
+            int.borrow_mut().name = job.name_scheme().format(job.subvolume_name().unwrap(), &ts);
+            int
+        }
+        fn order(intents: &[Rc<RefCell<Intent>>]) -> Vec<(String, u32)> {
+            intents
+                .iter()
+                .map(|i| (i.borrow().subvolume.clone(), i.borrow().timestamp().unwrap().day()))
+                .collect()
+        }
+        let home = job("/home", false);
+        let etc = job("/etc", false);
+
+        let mut intents = vec![
+            snapshot(&home, 2),
+            snapshot(&etc, 1),
+            snapshot(&home, 3),
+            snapshot(&home, 1),
+            snapshot(&etc, 2),
+            intent(&etc, IntentType::Create),
+        ];
+        Intent::sort_for_execution(&mut intents, false);
+        assert_eq!(intents[0].borrow().intent, IntentType::Create);
+        assert_eq!(
+            order(&intents[1..]),
+            vec![
+                ("/home".to_string(), 3),
+                ("/home".to_string(), 2),
+                ("/home".to_string(), 1),
+                ("/etc".to_string(), 2),
+                ("/etc".to_string(), 1),
+            ]
+        );
+
+        Intent::sort_for_execution(&mut intents, true);
+        assert_eq!(intents[0].borrow().intent, IntentType::Create);
+        assert_eq!(
+            order(&intents[1..]),
+            vec![
+                ("/home".to_string(), 1),
+                ("/home".to_string(), 2),
+                ("/home".to_string(), 3),
+                ("/etc".to_string(), 1),
+                ("/etc".to_string(), 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn delete_oldest_for_free_space() {
+        let home = job("/home", false);
+        let tz = FixedOffset::east_opt(0).unwrap();
+        let snapshots = |reasons: &[(u32, Option<KeepReason>)]| {
+            reasons
+                .iter()
+                .map(|&(day, reason)| {
+                    let int = intent(&home, IntentType::Delete);
+                    int.borrow_mut().name = home.name_scheme().format(
+                        home.subvolume_name().unwrap(),
+                        &tz.with_ymd_and_hms(2022, 1, day, 12, 0, 0).unwrap(),
+                    );
+                    if let Some(reason) = reason {
+                        int.borrow_mut().keep(reason);
+                    }
+                    int
+                })
+                .collect::<Vec<_>>()
+        };
+        let kept = |intents: &[Rc<RefCell<Intent>>]| {
+            intents
+                .iter()
+                .map(|i| i.borrow().intent == IntentType::Keep)
+                .collect::<Vec<_>>()
+        };
+        let gib = 1024 * 1024 * 1024;
+        let ten_percent = FreeSpace::parse("10%").unwrap();
+
+        let intents = snapshots(&[
+            (20, Some(KeepReason::Min)),
+            (19, Some(KeepReason::Daily)),
+            (18, Some(KeepReason::Daily)),
+            (17, None),
+            (16, Some(KeepReason::Safety)),
+        ]);
+        Intent::delete_for_free_space(&intents, ten_percent, Some((5 * gib, 100 * gib)));
+        assert_eq!(kept(&intents), vec![true, true, false, false, true]);
+
+        // enough free space, or unknown free space, deletes nothing more
+        let intents = snapshots(&[(20, Some(KeepReason::Daily)), (19, Some(KeepReason::Daily))]);
+        Intent::delete_for_free_space(&intents, ten_percent, Some((10 * gib, 100 * gib)));
+        Intent::delete_for_free_space(&intents, ten_percent, None);
+        assert_eq!(kept(&intents), vec![true, true]);
+
+        // min protects its snapshots even when the filesystem is full
+        let intents = snapshots(&[(20, Some(KeepReason::Min)), (19, Some(KeepReason::Min))]);
+        Intent::delete_for_free_space(&intents, ten_percent, Some((0, 100 * gib)));
+        assert_eq!(kept(&intents), vec![true, true]);
+
+        // trashing a snapshot would free no space, so a job with trash deletes nothing for it
+        let trashing: Job = serde_yaml::from_str(
+            "{subvolume: /home, target: /mnt/btrfs/@/gheesnaps, preserve: {min: all}, trash: true}",
+        )
+        .unwrap();
+        let intents = snapshots(&[(20, Some(KeepReason::Daily)), (19, Some(KeepReason::Daily))]);
+        for int in &intents {
+            int.borrow_mut().job = &trashing;
+        }
+        Intent::delete_for_free_space(&intents, ten_percent, Some((0, 100 * gib)));
+        assert_eq!(kept(&intents), vec![true, true]);
+    }
+
+    #[test]
+    fn deduplicate_equal_generations() {
+        let home = job("/home", false);
+        let tz = FixedOffset::east_opt(0).unwrap();
+        let snapshots = [
+            (20, Some(KeepReason::Hourly), 7),
+            (19, Some(KeepReason::Daily), 7),
+            (18, Some(KeepReason::Daily), 7),
+            (17, Some(KeepReason::Min), 5),
+            (16, Some(KeepReason::Daily), 5),
+            (15, None, 3),
+            (14, Some(KeepReason::Daily), 3),
+        ];
+
+        let mut transids = HashMap::new();
+        let intents = snapshots
+            .iter()
+            .map(|&(day, reason, transid)| {
+                let int = intent(&home, IntentType::Delete);
+                let name = home.name_scheme().format(
+                    home.subvolume_name().unwrap(),
+                    &tz.with_ymd_and_hms(2022, 1, day, 12, 0, 0).unwrap(),
+                );
+                transids.insert(name.clone(), transid);
+                int.borrow_mut().name = name;
+                if let Some(reason) = reason {
+                    int.borrow_mut().keep(reason);
+                }
+                int
+            })
+            .collect::<Vec<_>>();
+
+        Intent::deduplicate_unchanged(&intents, |int| transids.get(&int.name).copied());
+        let kept = intents
+            .iter()
+            .map(|i| i.borrow().intent == IntentType::Keep)
+            .collect::<Vec<_>>();
+        assert_eq!(kept, vec![true, false, false, true, false, false, true]);
+    }
+
+    #[test]
+    fn layouts_discover_same_snapshots() {
+        let dir = std::env::temp_dir().join(format!("ghee-layout-test-{}", std::process::id()));
+        let flat: Job = serde_yaml::from_str(&format!(
+            "{{subvolume: /home, target: {}, preserve: {{min: all}}}}",
+            dir.join("flat").display()
+        ))
+        .unwrap();
+        let nested: Job = serde_yaml::from_str(&format!(
+            "{{subvolume: /home, target: {}, preserve: {{min: all}}, layout: per_subvolume}}",
+            dir.join("nested").display()
+        ))
+        .unwrap();
+        assert_eq!(Path::new(&nested.snapshot_directory()), dir.join("nested").join("home"));
+
+        let tz = FixedOffset::east_opt(0).unwrap();
+        for day in [1, 2, 3] {
+            let name = flat.name_scheme().format(
+                flat.subvolume_name().unwrap(),
+                &tz.with_ymd_and_hms(2022, 1, day, 12, 0, 0).unwrap(),
+            );
+            fs::create_dir_all(Path::new(&flat.snapshot_directory()).join(&name)).unwrap();
+            fs::create_dir_all(Path::new(&nested.snapshot_directory()).join(&name)).unwrap();
         }
+        fs::create_dir_all(dir.join("nested").join("etc.2022-01-01T12:00:00Z")).unwrap();
+
+        let names = |job: &Job| {
+            let mut names = Intent::gather_delete_intents(&[job], false)
+                .0
+                .iter()
+                .map(|i| i.borrow().name.clone())
+                .collect::<Vec<_>>();
+            names.sort();
+            names
+        };
+        assert_eq!(names(&flat).len(), 3);
+        assert_eq!(names(&flat), names(&nested));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn hold_file_suppresses_deletes() {
+        let home = job("/home", false);
+        let intents = vec![
+            intent(&home, IntentType::Create),
+            intent(&home, IntentType::Delete),
+            intent(&home, IntentType::Keep),
+        ];
+        let hold_file = std::env::temp_dir().join(format!("ghee-hold-test-{}", std::process::id()));
+
+        Intent::hold_deletes(&intents, &hold_file);
+        assert_eq!(intents[1].borrow().intent, IntentType::Delete);
+
+        fs::write(&hold_file, "").unwrap();
+        Intent::hold_deletes(&intents, &hold_file);
+        let kinds = intents.iter().map(|i| i.borrow().intent.clone()).collect::<Vec<_>>();
+        assert_eq!(kinds, vec![IntentType::Create, IntentType::Keep, IntentType::Keep]);
+        assert_eq!(intents[1].borrow().keep_reason, Some(KeepReason::Hold));
+
+        fs::remove_file(&hold_file).unwrap();
+    }
+
+    #[test]
+    fn operations() {
+        let home = job("/home", false);
+        let create = intent(&home, IntentType::Create);
+        assert_eq!(
+            create.borrow().operation().unwrap(),
+            r#"create_snapshot("/home", "/mnt/btrfs/@/gheesnaps/home", READ_ONLY, qgroup: none)"#
+        );
+        assert_eq!(intent(&home, IntentType::Keep).borrow().operation(), None);
+
+        let mut writable = job("/home", false);
+        writable.read_only = Some(false);
+        assert_eq!(
+            intent(&writable, IntentType::Create).borrow().operation().unwrap(),
+            r#"create_snapshot("/home", "/mnt/btrfs/@/gheesnaps/home", empty, qgroup: none)"#
+        );
+        writable.before_seal = Some("rm -rf tmp".to_string());
+        assert_eq!(
+            intent(&writable, IntentType::Create).borrow().operation().unwrap(),
+            r#"create_snapshot("/home", "/mnt/btrfs/@/gheesnaps/home", empty, qgroup: none); sh -c "rm -rf tmp""#
+        );
+    }
+
+    #[test]
+    fn pre_and_post_hooks() {
+        let dir = std::env::temp_dir().join(format!("ghee-hook-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let marker = dir.join("thawed");
+
+        // a failing pre_hook skips the snapshot, and there is nothing for the post_hook to undo
+        let mut frozen = job(dir.to_str().unwrap(), false);
+        frozen.pre_hook = Some("exit 1".to_string());
+        frozen.post_hook = Some(format!("touch {}", marker.display()));
+        let executed = intent(&frozen, IntentType::Create).borrow().execute();
+        assert!(!executed.success);
+        assert_eq!(executed.error, Some(HookError.to_string()));
+        assert!(!marker.exists());
+
+        // the post_hook runs even though the directory can't be snapshotted
+        let mut thawing = job(dir.to_str().unwrap(), false);
+        thawing.pre_hook = Some(format!(r#"test "$GHEE_SUBVOLUME" = {}"#, dir.display()));
+        thawing.post_hook = Some(format!(r#"echo "$GHEE_SNAPSHOT" > {}"#, marker.display()));
+        let create = intent(&thawing, IntentType::Create);
+        let executed = create.borrow().execute();
+        assert!(!executed.success);
+        assert_ne!(executed.error, Some(HookError.to_string()));
+        assert_eq!(
+            fs::read_to_string(&marker).unwrap().trim_end(),
+            create.borrow().snapshot_path()
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn undatable_snapshots_kept() {
+        let mut home = job("/home", false);
+        home.preserve.min = serde_yaml::from_str("0").unwrap();
+        let now = FixedOffset::east_opt(0)
+            .unwrap()
+            .with_ymd_and_hms(2022, 1, 20, 12, 0, 0)
+            .unwrap();
+        let mut intents = vec![intent(&home, IntentType::Delete), intent(&home, IntentType::Delete)];
+        intents[0].borrow_mut().name = "home.2022-01-10T12:00:00Z".to_string();
+        intents[1].borrow_mut().name = "home.renamed-by-hand".to_string();
+
+        assert_eq!(
+            intents[0].borrow().timestamp().unwrap(),
+            FixedOffset::east_opt(0)
+                .unwrap()
+                .with_ymd_and_hms(2022, 1, 10, 12, 0, 0)
+                .unwrap()
+        );
+        assert!(intents[1].borrow().timestamp().is_err());
+
+        Intent::delete_to_keep_intents(&mut intents, &[&home], &now);
+        assert_eq!(intents[0].borrow().intent, IntentType::Delete);
+        assert_eq!(intents[1].borrow().keep_reason, Some(KeepReason::Safety));
+
+        // without btrfs metadata to read, the name is used
+        let mut metadata = job("/home", false);
+        metadata.timestamp_source = Some(TimestampSource::Metadata);
+        let snapshot = intent(&metadata, IntentType::Delete);
+        snapshot.borrow_mut().name = "home.2022-01-10T12:00:00Z".to_string();
+        snapshot.borrow_mut().target = "/nonexistent/home.2022-01-10T12:00:00Z".to_string();
+        assert_eq!(
+            snapshot.borrow().timestamp().unwrap(),
+            intents[0].borrow().timestamp().unwrap()
+        );
+    }
+
+    #[test]
+    fn deleting_vanished_snapshot_succeeds() {
+        let dir = std::env::temp_dir().join(format!("ghee-vanished-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let home: Job = serde_yaml::from_str(&format!(
+            "{{subvolume: /home, target: {}, preserve: {{min: all}}}}",
+            dir.display()
+        ))
+        .unwrap();
+        let snapshot = intent(&home, IntentType::Delete);
+        snapshot.borrow_mut().name = "home.2022-01-10T12:00:00Z".to_string();
+        snapshot.borrow_mut().target = dir.join("home.2022-01-10T12:00:00Z").to_str().unwrap().to_string();
+
+        let executed = snapshot.borrow().execute();
+        assert!(executed.success);
+        assert_eq!(executed.error, None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn excluded_neither_created_nor_pruned() {
+        let dir = std::env::temp_dir().join(format!("ghee-exclude-test-{}", std::process::id()));
+        for name in ["home.2022-01-10T12:00:00Z", "home.2022-01-11T12:00:00Z"] {
+            fs::create_dir_all(dir.join(name)).unwrap();
+        }
+        let now = FixedOffset::east_opt(0)
+            .unwrap()
+            .with_ymd_and_hms(2022, 1, 20, 12, 0, 0)
+            .unwrap();
+        let home = |exclude: &str| -> Job {
+            serde_yaml::from_str(&format!(
+                "{{subvolume: /home, target: {}, exclude: [\"{}\"], preserve: {{min: all}}}}",
+                dir.display(),
+                exclude
+            ))
+            .unwrap()
+        };
+
+        // a pattern matching the subvolume excludes the whole job
+        let excluded = home("/h*");
+        let (intents, skipped) = Intent::gather_create_intents(&[&excluded], &now, None);
+        assert!(intents.is_empty());
+        assert!(skipped.is_empty());
+        assert!(Intent::gather_delete_intents(&[&excluded], false).0.is_empty());
+
+        // a pattern matching a snapshot name leaves that snapshot alone
+        let one_excluded = home("*-10T*");
+        let names = Intent::gather_delete_intents(&[&one_excluded], false)
+            .0
+            .iter()
+            .map(|i| i.borrow().name.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["home.2022-01-11T12:00:00Z"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn usage_only_when_requested() {
+        let dir = std::env::temp_dir().join(format!("ghee-usage-test-{}", std::process::id()));
+        let snapshot = dir.join("home.2022-01-10T12:00:00Z");
+        fs::create_dir_all(snapshot.join("nested")).unwrap();
+        fs::write(snapshot.join("a"), [0; 1000]).unwrap();
+        fs::write(snapshot.join("nested/b"), [0; 24]).unwrap();
+        let home: Job = serde_yaml::from_str(&format!(
+            "{{subvolume: /home, target: {}, preserve: {{min: all}}}}",
+            dir.display()
+        ))
+        .unwrap();
+
+        let (intents, _unreadable) = Intent::gather_delete_intents(&[&home], false);
+        assert_eq!(intents[0].borrow().size, None);
+        // without quotas on a temporary directory, the file sizes are summed
+        let (intents, _unreadable) = Intent::gather_delete_intents(&[&home], true);
+        assert_eq!(intents[0].borrow().size, Some(1024));
+        Intent::print_tabled(&intents, TableStyle::Modern);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn plan_skips_what_changed() {
+        let dir = std::env::temp_dir().join(format!("ghee-plan-test-{}", std::process::id()));
+        fs::create_dir_all(dir.join("home.2022-01-10T12:00:00Z")).unwrap();
+        let home: Job = serde_yaml::from_str(&format!(
+            "{{subvolume: /home, target: {}, preserve: {{min: all}}}}",
+            dir.display()
+        ))
+        .unwrap();
+        let entry = |intent: &str, name: &str| -> PlanEntry {
+            serde_json::from_value(serde_json::json!({
+                "intent": intent,
+                "subvolume": "/home",
+                "target": if intent == "create" { home.target.clone() } else { format!("{}/{}", home.target, name) },
+                "name": name,
+                "keep_reason": null,
+            }))
+            .unwrap()
+        };
+        let plan = vec![
+            entry("create", "home.2022-01-20T12:00:00Z"),
+            entry("create", "home.2022-01-10T12:00:00Z"),
+            entry("delete", "home.2022-01-10T12:00:00Z"),
+            entry("delete", "home.2022-01-05T12:00:00Z"),
+            entry("keep", "home.2022-01-10T12:00:00Z"),
+        ];
+
+        let intents = Intent::gather_plan_intents(&plan, &[&home]);
+        let planned = intents
+            .iter()
+            .map(|i| (i.borrow().intent.clone(), i.borrow().name.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            planned,
+            vec![
+                (IntentType::Create, "home.2022-01-20T12:00:00Z".to_string()),
+                (IntentType::Delete, "home.2022-01-10T12:00:00Z".to_string()),
+            ]
+        );
+        assert!(Intent::gather_plan_intents(&plan, &[]).is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn retention_none_keeps_only_min() {
+        let now = FixedOffset::east_opt(0)
+            .unwrap()
+            .with_ymd_and_hms(2022, 1, 20, 12, 0, 0)
+            .unwrap();
+        let home: Job = serde_yaml::from_str(
+            "{subvolume: /home, target: /mnt/btrfs/@/gheesnaps, preserve: {retention: none, min: 5}}",
+        )
+        .unwrap();
+        let mut intents = (12..20)
+            .rev()
+            .map(|day| {
+                let int = intent(&home, IntentType::Delete);
+                int.borrow_mut().name = home.name_scheme().format(
+                    home.subvolume_name().unwrap(),
+                    &FixedOffset::east_opt(0)
+                        .unwrap()
+                        .with_ymd_and_hms(2022, 1, day, 0, 0, 0)
+                        .unwrap(),
+                );
+                int
+            })
+            .collect::<Vec<_>>();
+
+        Intent::delete_to_keep_intents(&mut intents, &[&home], &now);
+        let decided = intents
+            .iter()
+            .map(|i| (i.borrow().intent.clone(), i.borrow().keep_reason))
+            .collect::<Vec<_>>();
+        let min = (IntentType::Keep, Some(KeepReason::Min));
+        let delete = (IntentType::Delete, None);
+        assert_eq!(
+            decided,
+            vec![
+                min.clone(),
+                min.clone(),
+                min.clone(),
+                min.clone(),
+                min,
+                delete.clone(),
+                delete.clone(),
+                delete
+            ]
+        );
+    }
+
+    #[test]
+    fn min_and_retention_independent() {
+        let now = FixedOffset::east_opt(0)
+            .unwrap()
+            .with_ymd_and_hms(2022, 1, 20, 12, 0, 0)
+            .unwrap();
+        let decide = |min: &str, retention: Option<&str>| {
+            let mut home = job("/home", false);
+            home.preserve.min = serde_yaml::from_str(min).unwrap();
+            home.preserve.retention = retention.map(str::to_string);
+            let mut intents = (15..20)
+                .rev()
+                .map(|day| {
+                    let int = intent(&home, IntentType::Delete);
+                    int.borrow_mut().name = home.name_scheme().format(
+                        home.subvolume_name().unwrap(),
+                        &FixedOffset::east_opt(0)
+                            .unwrap()
+                            .with_ymd_and_hms(2022, 1, day, 0, 0, 0)
+                            .unwrap(),
+                    );
+                    int
+                })
+                .collect::<Vec<_>>();
+            Intent::delete_to_keep_intents(&mut intents, &[&home], &now);
+            intents.iter().map(|i| i.borrow().keep_reason).collect::<Vec<_>>()
+        };
+
+        // min applies without a retention
+        let min = Some(KeepReason::Min);
+        assert_eq!(decide("3", None), vec![min, min, min, None, None]);
+        // a malformed retention still keeps what min keeps, and the rest for safety
+        let safety = Some(KeepReason::Safety);
+        assert_eq!(decide("3", Some("3x")), vec![min, min, min, safety, safety]);
+        // a malformed min still keeps what retention keeps, including the current week's and month's
+        // representatives, and the rest for safety
+        let daily = Some(KeepReason::Daily);
+        let weekly = Some(KeepReason::Weekly);
+        let monthly = Some(KeepReason::Monthly);
+        assert_eq!(decide("3x", Some("2d")), vec![daily, daily, weekly, safety, monthly]);
+    }
+
+    #[test]
+    fn malformed_snapshot_names_skipped() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = std::env::temp_dir().join(format!("ghee-malformed-test-{}", std::process::id()));
+        for name in [
+            "home.2022-01-10T12:00:00Z",
+            "home.2022-13-45T99:00:00Z",
+            "home.bogus",
+            "etc.2022-01-10T12:00:00Z",
+        ] {
+            fs::create_dir_all(dir.join(name)).unwrap();
+        }
+        fs::create_dir_all(dir.join(OsStr::from_bytes(b"home.\xff"))).unwrap();
+        let job_of = |subvolume: &str| -> Job {
+            serde_yaml::from_str(&format!(
+                "{{subvolume: {}, target: {}, preserve: {{min: 0}}}}",
+                subvolume,
+                dir.display()
+            ))
+            .unwrap()
+        };
+        let home = job_of("/home");
+        let etc = job_of("/etc");
+        let now = FixedOffset::east_opt(0)
+            .unwrap()
+            .with_ymd_and_hms(2022, 1, 20, 12, 0, 0)
+            .unwrap();
+
+        let (mut intents, unreadable) = Intent::gather_delete_intents(&[&home, &etc], false);
+        assert!(unreadable.is_empty());
+        let mut names = intents.iter().map(|i| i.borrow().name.clone()).collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, vec!["etc.2022-01-10T12:00:00Z", "home.2022-01-10T12:00:00Z"]);
+
+        Intent::delete_to_keep_intents(&mut intents, &[&home, &etc], &now);
+        assert!(intents.iter().all(|i| i.borrow().intent == IntentType::Delete));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn sync_before_snapshot() {
+        let source = std::env::temp_dir().to_str().unwrap().to_string();
+        let unsynced = job(&source, false);
+        assert_eq!(intent(&unsynced, IntentType::Create).borrow().sync_source(), None);
+
+        let mut synced = job(&source, false);
+        synced.sync_before = Some(true);
+        let create = intent(&synced, IntentType::Create);
+        assert_eq!(create.borrow().sync_source(), Some(true));
+        assert!(create.borrow().operation().unwrap().starts_with("syncfs("));
+    }
+
+    #[test]
+    fn time_budget_defers_intents() {
+        let home = job("/home", false);
+        let intents = vec![
+            intent(&home, IntentType::Keep),
+            intent(&home, IntentType::Delete),
+            intent(&home, IntentType::Delete),
+            intent(&home, IntentType::Create),
+        ];
+
+        for parallel_targets in [None, Some(1), Some(4)] {
+            let (executed, deferred) = Intent::execute_all(&intents, parallel_targets, Some(Instant::now()));
+            assert_eq!(executed.len(), 1);
+            assert_eq!(executed[0].intent, IntentType::Keep);
+            let deferred = deferred.iter().map(|i| i.borrow().intent.clone()).collect::<Vec<_>>();
+            assert_eq!(
+                deferred,
+                vec![IntentType::Delete, IntentType::Delete, IntentType::Create]
+            );
+        }
+    }
+
+    #[test]
+    fn displaced_kept_when_create_fails() {
+        let dir = std::env::temp_dir().join(format!("ghee-displaced-test-{}", std::process::id()));
+        let existing = dir.join("home.2022-01-20T08:00:00Z");
+        fs::create_dir_all(&existing).unwrap();
+        let mut home = job("/home", false);
+        home.target = dir.to_str().unwrap().to_string();
+        home.pre_hook = Some("exit 1".to_string());
+        let create = intent(&home, IntentType::Create);
+        create.borrow_mut().name = "home.2022-01-20T12:00:00Z".to_string();
+        let displaced = intent(&home, IntentType::Delete);
+        displaced.borrow_mut().name = "home.2022-01-20T08:00:00Z".to_string();
+        displaced.borrow_mut().target = existing.to_str().unwrap().to_string();
+        displaced.borrow_mut().displaced = true;
+
+        for parallel_targets in [None, Some(2)] {
+            let (executed, _deferred) =
+                Intent::execute_all(&[create.clone(), displaced.clone()], parallel_targets, None);
+            assert!(!executed[0].success);
+            assert_eq!(executed[1].intent, IntentType::Keep);
+            assert!(executed[1].success);
+        }
+        assert!(existing.exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn locked_source_skipped() {
+        let source = std::env::temp_dir().join(format!("ghee-lock-test-{}", std::process::id()));
+        fs::create_dir_all(&source).unwrap();
+        let unlocking = job(source.to_str().unwrap(), false);
+        assert!(intent(&unlocking, IntentType::Create)
+            .borrow()
+            .lock_source()
+            .unwrap()
+            .is_none());
+
+        let mut locking = job(source.to_str().unwrap(), false);
+        locking.skip_if_locked = Some(true);
+        let create = intent(&locking, IntentType::Create);
+
+        let held = try_lock(&source).unwrap().unwrap();
+        assert!(create.borrow().lock_source().is_err());
+        let executed = create.borrow().execute();
+        assert!(!executed.success);
+        assert_eq!(executed.error, Some(SourceLockedError.to_string()));
+
+        drop(held);
+        assert!(create.borrow().lock_source().unwrap().is_some());
+
+        fs::remove_dir_all(&source).unwrap();
+    }
+
+    #[test]
+    fn verify_sample() {
+        let snapshot = std::env::temp_dir().join(format!("ghee-verify-test-{}", std::process::id()));
+        fs::create_dir_all(&snapshot).unwrap();
+        let mut home = job("/home", false);
+        assert!(intent(&home, IntentType::Create).borrow().verify(&snapshot).is_ok());
+
+        home.verify_sample = Some(2);
+        assert!(intent(&home, IntentType::Create).borrow().verify(&snapshot).is_err());
+        fs::write(snapshot.join(".bashrc"), "export EDITOR=vi\n").unwrap();
+        assert!(intent(&home, IntentType::Create).borrow().verify(&snapshot).is_ok());
+
+        fs::remove_dir_all(&snapshot).unwrap();
+    }
+
+    #[test]
+    fn seal_failure_deletes_snapshot() {
+        let path = std::env::temp_dir().join(format!("ghee-seal-test-{}", std::process::id()));
+        let sealed = Cell::new(false);
+        let deleted = Cell::new(false);
+        let seal = |_: &Path| -> Result<(), Box<dyn Error>> {
+            sealed.set(true);
+            Ok(())
+        };
+        let fail_seal = |_: &Path| -> Result<(), Box<dyn Error>> { Err("read-only property not set".into()) };
+        let delete = |_: &Path| -> Result<(), Box<dyn Error>> {
+            deleted.set(true);
+            Ok(())
+        };
+
+        let mut home = job("/home", false);
+        home.before_seal = Some("test -n \"$GHEE_SNAPSHOT\"".to_string());
+        let create = intent(&home, IntentType::Create);
+        assert!(create.borrow().seal(&path, seal, delete).is_ok());
+        assert!(sealed.get() && !deleted.get());
+
+        assert!(create.borrow().seal(&path, fail_seal, delete).is_err());
+        assert!(deleted.get());
+
+        sealed.set(false);
+        deleted.set(false);
+        let mut failing = job("/home", false);
+        failing.before_seal = Some("exit 1".to_string());
+        let create = intent(&failing, IntentType::Create);
+        assert!(create.borrow().seal(&path, seal, delete).is_err());
+        assert!(!sealed.get() && deleted.get());
+    }
+
+    #[test]
+    fn retention_at_point_in_time() {
+        let home: Job = serde_yaml::from_str(
+            "{subvolume: /home, target: /mnt/btrfs/@/gheesnaps, preserve: {retention: 3d, min: none}}",
+        )
+        .unwrap();
+        let tz = FixedOffset::east_opt(0).unwrap();
+        let timestamps = [
+            tz.with_ymd_and_hms(2022, 1, 20, 12, 0, 0).unwrap(),
+            tz.with_ymd_and_hms(2022, 1, 19, 12, 0, 0).unwrap(),
+            tz.with_ymd_and_hms(2021, 12, 1, 12, 0, 0).unwrap(),
+        ];
+        let plan = |now| {
+            let mut intents = timestamps
+                .iter()
+                .map(|ts| {
+                    let int = intent(&home, IntentType::Delete);
+                    int.borrow_mut().name = home.name_scheme().format(home.subvolume_name().unwrap(), ts);
+                    int
+                })
+                .collect::<Vec<_>>();
+            Intent::delete_to_keep_intents(&mut intents, &[&home], &now);
+            intents.iter().map(|i| i.borrow().intent.clone()).collect::<Vec<_>>()
+        };
+
+        let at = plan(tz.with_ymd_and_hms(2022, 1, 21, 12, 0, 0).unwrap());
+        assert_eq!(at, vec![IntentType::Keep, IntentType::Keep, IntentType::Delete]);
+
+        let local_now = Local::now();
+        let real = plan(local_now.with_timezone(local_now.offset()));
+        assert_eq!(real, vec![IntentType::Delete; 3]);
+    }
+
+    #[test]
+    fn equal_timestamps_ordered_by_name() {
+        let home: Job =
+            serde_yaml::from_str("{subvolume: /home, target: /mnt/btrfs/@/gheesnaps, preserve: {min: latest}}")
+                .unwrap();
+        let local_now = Local::now();
+        let now = local_now.with_timezone(local_now.offset());
+        // the same instant, written with different offsets
+        let names = ["home.2022-01-20T12:00:00+00:00", "home.2022-01-20T13:00:00+01:00"];
+
+        for order in [[0, 1], [1, 0]] {
+            let mut intents = order
+                .iter()
+                .map(|&i| {
+                    let int = intent(&home, IntentType::Delete);
+                    int.borrow_mut().name = names[i].to_string();
+                    int
+                })
+                .collect::<Vec<_>>();
+            Intent::delete_to_keep_intents(&mut intents, &[&home], &now);
+
+            let kept = intents
+                .iter()
+                .filter(|i| i.borrow().intent == IntentType::Keep)
+                .map(|i| i.borrow().name.clone())
+                .collect::<Vec<_>>();
+            assert_eq!(kept, vec![names[1]]);
+        }
+    }
+
+    #[test]
+    fn colon_free_timestamps_binned() {
+        let home: Job = serde_yaml::from_str(
+            "{subvolume: /home, target: /mnt/btrfs/@/gheesnaps, timestamp_format: rfc3339-dashes, \
+             preserve: {retention: 2d, min: none}}",
+        )
+        .unwrap();
+        let now = FixedOffset::east_opt(0)
+            .unwrap()
+            .with_ymd_and_hms(2022, 1, 21, 12, 0, 0)
+            .unwrap();
+        // two snapshots on the 20th, one on the 19th and an old one
+        let names = [
+            "home.2022-01-20T18-00-00Z",
+            "home.2022-01-20T06-00-00Z",
+            "home.2022-01-19T12-00-00+00-00",
+            "home.2021-12-01T12-00-00Z",
+        ];
+        let mut intents = names
+            .iter()
+            .map(|name| {
+                let int = intent(&home, IntentType::Delete);
+                int.borrow_mut().name = name.to_string();
+                int
+            })
+            .collect::<Vec<_>>();
+        Intent::delete_to_keep_intents(&mut intents, &[&home], &now);
+
+        let kept = intents.iter().map(|i| i.borrow().intent.clone()).collect::<Vec<_>>();
+        assert_eq!(
+            kept,
+            vec![
+                IntentType::Delete,
+                IntentType::Keep,
+                IntentType::Keep,
+                IntentType::Delete
+            ]
+        );
+    }
+
+    #[test]
+    fn counts_intents_by_type() {
+        let home = job("/home", false);
+        let mut intents = vec![
+            intent(&home, IntentType::Create),
+            intent(&home, IntentType::Keep),
+            intent(&home, IntentType::Keep),
+            intent(&home, IntentType::Delete),
+        ];
+        assert_eq!(Intent::summary(&intents), "create: 1, keep: 2, delete: 1");
+
+        intents.push(intent(&home, IntentType::Send));
+        assert_eq!(Intent::summary(&intents), "create: 1, keep: 2, delete: 1, send: 1");
+        assert_eq!(Intent::summary(&[]), "create: 0, keep: 0, delete: 0");
+    }
+
+    #[test]
+    fn failure_records_error() {
+        let home = job("/home", false);
+        let keep = intent(&home, IntentType::Keep).borrow().execute();
+        assert!(keep.success && keep.error.is_none());
+
+        // a plain directory in place of the snapshot, which isn't deleted as it isn't a subvolume
+        let dir = std::env::temp_dir().join(format!("ghee-failure-test-{}", std::process::id()));
+        fs::create_dir_all(dir.join("home.2022-01-20T12:00:00Z")).unwrap();
+        let mut home = job("/home", false);
+        home.target = dir.to_str().unwrap().to_string();
+        let delete = intent(&home, IntentType::Delete);
+        delete.borrow_mut().name = "home.2022-01-20T12:00:00Z".to_string();
+        delete.borrow_mut().target = dir.join("home.2022-01-20T12:00:00Z").to_str().unwrap().to_string();
+        let executed = delete.borrow().execute();
+        assert!(!executed.success);
+        assert!(executed.error.is_some());
+        assert!(dir.join("home.2022-01-20T12:00:00Z").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn same_named_subvolumes_share_target() {
+        let dir = std::env::temp_dir().join(format!("ghee-source-tag-test-{}", std::process::id()));
+        let job_of = |subvolume: &str| -> Job {
+            serde_yaml::from_str(&format!(
+                "{{subvolume: {}, target: {}, preserve: {{min: all}}}}",
+                subvolume,
+                dir.display()
+            ))
+            .unwrap()
+        };
+        let mut jobs = vec![job_of("/srv/a/data"), job_of("/srv/b/data"), job_of("/home")];
+        Job::tag_colliding_names(&mut jobs);
+        let (a, b, home) = (&jobs[0], &jobs[1], &jobs[2]);
+
+        assert!(a.source_tag.is_some() && b.source_tag.is_some());
+        assert_ne!(a.snapshot_base_name(), b.snapshot_base_name());
+        assert_eq!(home.snapshot_base_name(), "home");
+        // the hash only depends on the subvolume, so names stay the same across runs
+        let mut again = vec![job_of("/srv/b/data"), job_of("/srv/a/data")];
+        Job::tag_colliding_names(&mut again);
+        assert_eq!(again[0].source_tag, b.source_tag);
+
+        let ts = FixedOffset::east_opt(0)
+            .unwrap()
+            .with_ymd_and_hms(2022, 1, 20, 12, 0, 0)
+            .unwrap();
+        let a_name = a.name_scheme().format(&a.snapshot_base_name(), &ts);
+        let b_name = b.name_scheme().format(&b.snapshot_base_name(), &ts);
+        for name in [&a_name, &b_name] {
+            fs::create_dir_all(dir.join(name)).unwrap();
+        }
+
+        for (job, own) in [(a, &a_name), (b, &b_name)] {
+            let (intents, _unreadable) = Intent::gather_delete_intents(&[job], false);
+            let names = intents.iter().map(|i| i.borrow().name.clone()).collect::<Vec<_>>();
+            assert_eq!(names, vec![own.clone()]);
+            assert!(intents[0].borrow().is_own_snapshot());
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn filesystem_errors_hold_deletes() {
+        let home = job("/home", false);
+        let failing: Job =
+            serde_yaml::from_str("{subvolume: /srv, target: /mnt/failing/gheesnaps, preserve: {min: all}}").unwrap();
+        fn snapshot<'a>(job: &'a Job, intent_type: IntentType, name: &str) -> Rc<RefCell<Intent<'a>>> {
+            let int = intent(job, intent_type);
+            int.borrow_mut().target = format!("{}/{}", &job.target, name);
+            int
+        }
+        let intents = vec![
+            intent(&failing, IntentType::Create),
+            snapshot(&failing, IntentType::Delete, "srv.2022-01-19T12:00:00Z"),
+            snapshot(&failing, IntentType::Delete, "srv.2022-01-20T12:00:00Z"),
+            snapshot(&home, IntentType::Delete, "home.2022-01-20T12:00:00Z"),
+        ];
+
+        let checked = RefCell::new(Vec::new());
+        Intent::hold_deletes_on_filesystem_errors(&intents, |directory| {
+            checked.borrow_mut().push(directory.to_path_buf());
+            match directory.starts_with("/mnt/failing") {
+                true => Ok(vec!["[/dev/sdb1].corruption_errs 17".to_string()]),
+                false => Ok(Vec::new()),
+            }
+        });
+
+        // each directory is checked once
+        assert_eq!(checked.borrow().len(), 2);
+        let outcome = intents
+            .iter()
+            .map(|i| (i.borrow().intent.clone(), i.borrow().keep_reason))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            outcome,
+            vec![
+                (IntentType::Create, None),
+                (IntentType::Keep, Some(KeepReason::Safety)),
+                (IntentType::Keep, Some(KeepReason::Safety)),
+                (IntentType::Delete, None),
+            ]
+        );
+
+        // filesystems that can't be checked are pruned as usual
+        let unchecked = vec![snapshot(&home, IntentType::Delete, "home.2022-01-20T12:00:00Z")];
+        Intent::hold_deletes_on_filesystem_errors(&unchecked, |_| Err("btrfs not found".into()));
+        assert_eq!(unchecked[0].borrow().intent, IntentType::Delete);
+    }
+
+    #[test]
+    fn intent_type_round_trips() {
+        for (intent, name) in [
+            (IntentType::Create, "create"),
+            (IntentType::Keep, "keep"),
+            (IntentType::Delete, "delete"),
+            (IntentType::Send, "send"),
+        ] {
+            assert_eq!(intent.to_string(), name);
+            assert_eq!(name.parse::<IntentType>().unwrap(), intent);
+
+            let json = serde_json::to_string(&intent).unwrap();
+            assert_eq!(json, format!("\"{}\"", name));
+            assert_eq!(serde_json::from_str::<IntentType>(&json).unwrap(), intent);
+            assert_eq!(serde_yaml::from_str::<IntentType>(name).unwrap(), intent);
+        }
+
+        assert!("Create".parse::<IntentType>().is_err());
+        assert!("------".parse::<IntentType>().is_err());
+        assert!(serde_json::from_str::<IntentType>("\"Delete\"").is_err());
+    }
+
+    #[test]
+    fn expected_counts_violated() {
+        let expecting = |subvolume: &str, expected_count: &str| -> Job {
+            serde_yaml::from_str(&format!(
+                "{{subvolume: {}, target: /mnt/btrfs/@/gheesnaps, preserve: {{min: all, expected_count: {}}}}}",
+                subvolume, expected_count
+            ))
+            .unwrap()
+        };
+        let (home, srv, var, etc) = (
+            expecting("/home", "[2, 3]"),
+            expecting("/srv", "[2, 3]"),
+            expecting("/var", "[1, 2]"),
+            job("/etc", false),
+        );
+        let intents = vec![
+            // too few: the only snapshot is deleted and one is created
+            intent(&home, IntentType::Create),
+            intent(&home, IntentType::Delete),
+            // within range
+            intent(&srv, IntentType::Create),
+            intent(&srv, IntentType::Keep),
+            intent(&srv, IntentType::Delete),
+            // too many: pruning keeps everything
+            intent(&var, IntentType::Create),
+            intent(&var, IntentType::Keep),
+            intent(&var, IntentType::Keep),
+            // no expectation
+            intent(&etc, IntentType::Keep),
+        ];
+
+        let violations = CountViolation::check(&intents, &[&home, &srv, &var, &etc]);
+        assert_eq!(
+            violations,
+            vec![
+                CountViolation {
+                    subvolume: "/home".to_string(),
+                    expected: "2-3".to_string(),
+                    actual: 1,
+                },
+                CountViolation {
+                    subvolume: "/var".to_string(),
+                    expected: "1-2".to_string(),
+                    actual: 3,
+                },
+            ]
+        );
+        assert!(CountViolation::check(&intents, &[&srv, &etc]).is_empty());
+    }
+
+    #[test]
+    fn consistency_group_rolled_back() {
+        let grouped = |subvolume: &str| {
+            let mut job = job(subvolume, false);
+            job.consistency_group = Some("database".to_string());
+            job
+        };
+        let (data, wal, logs, other) = (grouped("/data"), grouped("/wal"), grouped("/logs"), job("/home", false));
+        let intents = [
+            intent(&data, IntentType::Create),
+            intent(&other, IntentType::Create),
+            intent(&data, IntentType::Delete),
+            intent(&wal, IntentType::Create),
+            intent(&logs, IntentType::Create),
+        ];
+        let owned = intents.iter().map(|i| i.borrow().clone()).collect::<Vec<_>>();
+        assert_eq!(Intent::execution_units(&owned), vec![vec![0, 3, 4], vec![1], vec![2]]);
+
+        let created = RefCell::new(Vec::new());
+        let deleted = RefCell::new(Vec::new());
+        let members = [&owned[0], &owned[3], &owned[4]];
+        let executed = Intent::execute_consistency_group(
+            &members,
+            |intent| {
+                created.borrow_mut().push(intent.subvolume.clone());
+                match intent.subvolume.as_str() {
+                    "/wal" => ExecutedIntent::failed(intent, "no space left"),
+                    _ => ExecutedIntent::new(intent, true),
+                }
+            },
+            |path| {
+                deleted.borrow_mut().push(path.to_path_buf());
+                Ok(())
+            },
+        );
+
+        // /logs is not created anymore once /wal failed, /data is deleted again
+        assert_eq!(*created.borrow(), vec!["/data", "/wal"]);
+        assert_eq!(
+            *deleted.borrow(),
+            vec![Path::new(&owned[0].snapshot_path()).to_path_buf()]
+        );
+        let rolled_back = ConsistencyGroupError.to_string();
+        let errors = executed
+            .iter()
+            .map(|e| (e.success, e.error.as_deref()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            vec![
+                (false, Some(rolled_back.as_str())),
+                (false, Some("no space left")),
+                (false, Some(rolled_back.as_str())),
+            ]
+        );
+
+        let executed = Intent::execute_consistency_group(
+            &members,
+            |intent| ExecutedIntent::new(intent, true),
+            |_| panic!("nothing to roll back"),
+        );
+        assert!(executed.iter().all(|e| e.success));
+    }
+
+    #[test]
+    fn refuses_unexpected_deletes() {
+        let home = job("/home", false);
+        let delete = |target: &str, name: &str| {
+            let int = intent(&home, IntentType::Delete);
+            int.borrow_mut().target = target.to_string();
+            int.borrow_mut().name = name.to_string();
+            let executed = int.borrow().execute();
+            executed
+        };
+        let refused = UnexpectedDeleteError.to_string();
+
+        for (target, name) in [
+            // outside the target directory
+            ("/home", "home"),
+            ("/mnt/btrfs/@/home.2022-01-20T12:00:00Z", "home.2022-01-20T12:00:00Z"),
+            (
+                "/mnt/btrfs/@/gheesnaps/../home.2022-01-20T12:00:00Z",
+                "home.2022-01-20T12:00:00Z",
+            ),
+            // not named like a snapshot of the job
+            (
+                "/mnt/btrfs/@/gheesnaps/etc.2022-01-20T12:00:00Z",
+                "etc.2022-01-20T12:00:00Z",
+            ),
+            ("/mnt/btrfs/@/gheesnaps/home.backup", "home.backup"),
+            // a name that doesn't match the target
+            ("/mnt/btrfs/@/gheesnaps/data", "home.2022-01-20T12:00:00Z"),
+        ] {
+            let executed = delete(target, name);
+            assert!(!executed.success, "{} was not refused", target);
+            assert_eq!(executed.error.as_ref(), Some(&refused), "{} was not refused", target);
+        }
+
+        let executed = delete(
+            "/mnt/btrfs/@/gheesnaps/home.2022-01-20T12:00:00Z",
+            "home.2022-01-20T12:00:00Z",
+        );
+        assert_ne!(executed.error, Some(refused));
+    }
+
+    #[test]
+    fn backwards_clock_holds_deletes() {
+        let home: Job = serde_yaml::from_str(
+            "{subvolume: /home, target: /mnt/btrfs/@/gheesnaps, preserve: {retention: 1d, min: none}}",
+        )
+        .unwrap();
+        let tz = FixedOffset::east_opt(0).unwrap();
+        let plan = |now| {
+            let mut intents = [
+                "home.2022-01-20T12:00:00Z",
+                "home.2022-01-19T12:00:00Z",
+                "home.2021-12-01T12:00:00Z",
+            ]
+            .iter()
+            .map(|name| {
+                let int = intent(&home, IntentType::Delete);
+                int.borrow_mut().name = name.to_string();
+                int
+            })
+            .collect::<Vec<_>>();
+            Intent::delete_to_keep_intents(&mut intents, &[&home], &now);
+            Intent::hold_deletes_on_clock_skew(&intents, &now);
+            intents.iter().map(|i| i.borrow().keep_reason).collect::<Vec<_>>()
+        };
+
+        let in_time = plan(tz.with_ymd_and_hms(2022, 1, 20, 12, 30, 0).unwrap());
+        assert_eq!(in_time[2], None);
+        // within the tolerance, e.g. a snapshot taken by a host whose clock runs slightly ahead
+        let slightly_behind = plan(tz.with_ymd_and_hms(2022, 1, 20, 11, 30, 0).unwrap());
+        assert_eq!(slightly_behind[2], None);
+        assert!(!slightly_behind.contains(&Some(KeepReason::Safety)));
+
+        // reset to the epoch, every snapshot is in the future and would fall out of the retention
+        let epoch = plan(tz.with_ymd_and_hms(1970, 1, 1, 0, 0, 0).unwrap());
+        assert_eq!(epoch, vec![Some(KeepReason::Safety); 3]);
+    }
+
+    #[test]
+    fn unreadable_targets_reported() {
+        let dir = std::env::temp_dir().join(format!("ghee-unreadable-test-{}", std::process::id()));
+        fs::create_dir_all(dir.join("empty")).unwrap();
+        fs::write(dir.join("file"), "").unwrap();
+        let job_at = |target: &Path, layout: &str| -> Job {
+            serde_yaml::from_str(&format!(
+                "{{subvolume: /home, target: {}, preserve: {{min: all}}, layout: {}}}",
+                target.display(),
+                layout
+            ))
+            .unwrap()
+        };
+
+        // nothing to prune, in an empty target or a subdirectory that no snapshot was created in yet
+        let empty = job_at(&dir.join("empty"), "flat");
+        let not_created_yet = job_at(&dir.join("empty"), "per_subvolume");
+        let (intents, unreadable) = Intent::gather_delete_intents(&[&empty, &not_created_yet], false);
+        assert!(intents.is_empty() && unreadable.is_empty());
+
+        // couldn't check, because the target is missing, e.g. not mounted, or isn't a directory
+        let unmounted = job_at(&dir.join("unmounted"), "flat");
+        let file = job_at(&dir.join("file"), "flat");
+        let (intents, unreadable) = Intent::gather_delete_intents(&[&empty, &unmounted, &file], false);
+        assert!(intents.is_empty());
+        let directories = unreadable.iter().map(|u| u.directory.as_str()).collect::<Vec<_>>();
+        assert_eq!(directories, vec![unmounted.target.as_str(), file.target.as_str()]);
+        assert!(unreadable.iter().all(|u| u.subvolume == "/home" && !u.error.is_empty()));
+
+        // a missing target that is created along with the first snapshot
+        let mut created = job_at(&dir.join("new"), "flat");
+        created.create_target = Some(true);
+        assert!(created.creates_target());
+        let (intents, unreadable) = Intent::gather_delete_intents(&[&created], false);
+        assert!(intents.is_empty() && unreadable.is_empty());
+        fs::create_dir_all(dir.join("new")).unwrap();
+        assert!(!created.creates_target());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn jobs_in_different_timezones() {
+        let job_in = |subvolume: &str, tz: &str| -> Job {
+            serde_yaml::from_str(&format!(
+                "{{subvolume: {}, target: /mnt/btrfs/@/gheesnaps, timestamp_tz: {}, \
+                 preserve: {{retention: 1d, min: none}}}}",
+                subvolume, tz
+            ))
+            .unwrap()
+        };
+        let tokyo = job_in("/home", "Asia/Tokyo");
+        let new_york = job_in("/srv", "America/New_York");
+        let utc = FixedOffset::east_opt(0).unwrap();
+        // 21:00 in Tokyo, 07:00 in New York
+        let now = utc.with_ymd_and_hms(2022, 1, 20, 12, 0, 0).unwrap();
+
+        fn snapshot(job: &Job, name: String) -> Rc<RefCell<Intent<'_>>> {
+            let int = intent(job, IntentType::Delete);
+            int.borrow_mut().name = name;
+            int
+        }
+        let evening = utc.with_ymd_and_hms(2022, 1, 18, 23, 30, 0).unwrap();
+        let tokyo_name = tokyo.name_scheme().format(tokyo.subvolume_name().unwrap(), &evening);
+        let new_york_name = new_york
+            .name_scheme()
+            .format(new_york.subvolume_name().unwrap(), &evening);
+        assert_eq!(tokyo_name, "home.2022-01-19T08:30:00+09:00");
+        assert_eq!(new_york_name, "srv.2022-01-18T18:30:00-05:00");
+
+        let mut intents = vec![
+            // the 19th in Tokyo, within yesterday's bin
+            snapshot(&tokyo, tokyo_name),
+            // the 18th in New York, only within this week's bin
+            snapshot(&new_york, new_york_name),
+            // written in UTC, but the 18th and the 19th in New York
+            snapshot(&new_york, "srv.2022-01-19T03:00:00Z".to_string()),
+            snapshot(&new_york, "srv.2022-01-19T06:00:00Z".to_string()),
+        ];
+        Intent::delete_to_keep_intents(&mut intents, &[&tokyo, &new_york], &now);
+
+        let reasons = intents.iter().map(|i| i.borrow().keep_reason).collect::<Vec<_>>();
+        assert_eq!(
+            reasons,
+            vec![
+                Some(KeepReason::Daily),
+                Some(KeepReason::Weekly),
+                None,
+                Some(KeepReason::Daily)
+            ]
+        );
+    }
+
+    #[test]
+    fn created_snapshot_wins_its_bin() {
+        let job_with = |prefer_created: bool| -> Job {
+            serde_yaml::from_str(&format!(
+                "{{subvolume: /home, target: /mnt/btrfs/@/gheesnaps, \
+                 preserve: {{retention: 48h, min: none, prefer_created: {}}}}}",
+                prefer_created
+            ))
+            .unwrap()
+        };
+        let now = FixedOffset::east_opt(0)
+            .unwrap()
+            .with_ymd_and_hms(2022, 1, 20, 11, 45, 0)
+            .unwrap();
+
+        fn plan<'a>(job: &'a Job, now: &DateTime<FixedOffset>) -> Vec<Rc<RefCell<Intent<'a>>>> {
+            let create = intent(job, IntentType::Create);
+            create.borrow_mut().name = job.name_scheme().format(job.subvolume_name().unwrap(), now);
+            let mut intents = vec![create];
+            for name in ["home.2022-01-20T11:10:00Z", "home.2022-01-20T10:10:00Z"] {
+                let existing = intent(job, IntentType::Delete);
+                existing.borrow_mut().name = name.to_string();
+                intents.push(existing);
+            }
+            Intent::delete_to_keep_intents(&mut intents, &[job], now);
+            intents
+        }
+        let outcome = |intents: &[Rc<RefCell<Intent>>]| {
+            intents
+                .iter()
+                .map(|i| (i.borrow().intent.clone(), i.borrow().keep_reason))
+                .collect::<Vec<_>>()
+        };
+
+        let default = job_with(false);
+        assert_eq!(
+            outcome(&plan(&default, &now)),
+            vec![
+                (IntentType::Create, None),
+                (IntentType::Keep, Some(KeepReason::Hourly)),
+                (IntentType::Keep, Some(KeepReason::Hourly)),
+            ]
+        );
+
+        let preferring = job_with(true);
+        let intents = plan(&preferring, &now);
+        assert_eq!(
+            outcome(&intents),
+            vec![
+                (IntentType::Create, None),
+                (IntentType::Delete, None),
+                (IntentType::Keep, Some(KeepReason::Hourly)),
+            ]
+        );
+        // the snapshot that lost its bin is only deleted once the new one exists
+        assert!(intents[1].borrow().displaced);
+    }
+
+    #[test]
+    fn pending_create_shifts_pruned_snapshot() {
+        let now = FixedOffset::east_opt(0)
+            .unwrap()
+            .with_ymd_and_hms(2022, 1, 20, 12, 30, 0)
+            .unwrap();
+        let plan = |anchor: BinAnchor, prune_before_create: bool| {
+            let mut home = job("/home", prune_before_create);
+            home.preserve.min = serde_yaml::from_str("0").unwrap();
+            home.preserve.retention = Some("2d".to_string());
+            home.preserve.bin_anchor = Some(anchor);
+            let create = intent(&home, IntentType::Create);
+            create.borrow_mut().name = home.name_scheme().format(home.subvolume_name().unwrap(), &now);
+            let mut intents = vec![create];
+            for name in ["home.2022-01-20T12:10:00Z", "home.2022-01-19T12:00:00Z"] {
+                let existing = intent(&home, IntentType::Delete);
+                existing.borrow_mut().name = name.to_string();
+                intents.push(existing);
+            }
+            Intent::delete_to_keep_intents(&mut intents, &[&home], &now);
+            intents
+                .iter()
+                .map(|i| (i.borrow().intent.clone(), i.borrow().keep_reason))
+                .collect::<Vec<_>>()
+        };
+
+        // the snapshot about to be created is the last one of this hour, so the one of ten past is pruned
+        assert_eq!(
+            plan(BinAnchor::Last, false),
+            vec![
+                (IntentType::Create, None),
+                (IntentType::Delete, None),
+                (IntentType::Keep, Some(KeepReason::Daily)),
+            ]
+        );
+        let kept = vec![
+            (IntentType::Create, None),
+            (IntentType::Keep, Some(KeepReason::Hourly)),
+            (IntentType::Keep, Some(KeepReason::Daily)),
+        ];
+        assert_eq!(plan(BinAnchor::First, false), kept);
+        // the deletes run first, before the new snapshot exists
+        assert_eq!(plan(BinAnchor::Last, true), kept);
+    }
+
+    #[test]
+    fn plan_hook_vetoes_delete() {
+        let mut home = job("/home", false);
+        // the hook finds the snapshot in the JSON it is passed
+        home.plan_hook = Some(r#"grep -o '"name":"home.2022-01-10T12:00:00Z"' | cut -d'"' -f4"#.to_string());
+        let mut failing = job("/etc", false);
+        failing.plan_hook = Some("exit 1".to_string());
+        let unhooked = job("/var", false);
+
+        let intents = vec![
+            intent(&home, IntentType::Delete),
+            intent(&home, IntentType::Delete),
+            intent(&failing, IntentType::Delete),
+            intent(&unhooked, IntentType::Delete),
+        ];
+        intents[0].borrow_mut().name = "home.2022-01-10T12:00:00Z".to_string();
+        intents[1].borrow_mut().name = "home.2022-01-11T12:00:00Z".to_string();
+
+        Intent::apply_plan_hooks(&intents, &[&home, &failing, &unhooked], true);
+        assert!(intents.iter().all(|int| int.borrow().intent == IntentType::Delete));
+
+        Intent::apply_plan_hooks(&intents, &[&home, &failing, &unhooked], false);
+        let decisions = intents
+            .iter()
+            .map(|int| (int.borrow().intent.clone(), int.borrow().keep_reason))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            decisions,
+            vec![
+                (IntentType::Keep, Some(KeepReason::Veto)),
+                (IntentType::Delete, None),
+                (IntentType::Keep, Some(KeepReason::Safety)),
+                (IntentType::Delete, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn json_output() {
+        let home = job("/home", false);
+        let kept = intent(&home, IntentType::Keep);
+        kept.borrow_mut().keep(KeepReason::Daily);
+        let json: serde_json::Value =
+            serde_json::from_str(&Intent::to_json(&[intent(&home, IntentType::Create), kept]).unwrap()).unwrap();
+        assert_eq!(json[0]["intent"], "create");
+        assert_eq!(json[0]["subvolume"], "/home");
+        assert_eq!(json[0]["target"], "/mnt/btrfs/@/gheesnaps");
+        assert_eq!(json[0]["name"], "home");
+        assert_eq!(json[1]["intent"], "keep");
+        assert_eq!(json[1]["keep_reason"], KeepReason::Daily.to_string());
+    }
+
+    #[test]
+    fn json_report() {
+        let home = job("/home", false);
+        let intents = vec![intent(&home, IntentType::Create)];
+        let planned: serde_json::Value =
+            serde_json::from_str(&Intent::report_json(&intents, None, None).unwrap()).unwrap();
+        assert_eq!(planned["intents"][0]["name"], "home");
+        assert!(planned.get("executed").is_none());
+        assert!(planned.get("skipped").is_none());
+
+        let executed = vec![ExecutedIntent::new(&intents[0].borrow(), true)];
+        let skipped = vec![SkippedJob::new(&home, IntentType::Delete, SkipReason::Hold)];
+        let report: serde_json::Value =
+            serde_json::from_str(&Intent::report_json(&intents, Some(&executed), Some(&skipped)).unwrap()).unwrap();
+        assert_eq!(report["intents"].as_array().unwrap().len(), 1);
+        assert_eq!(report["executed"][0]["name"], "home");
+        assert_eq!(report["executed"][0]["success"], true);
+        assert_eq!(report["skipped"][0]["subvolume"], "/home");
+        assert_eq!(report["skipped"][0]["reason"], "hold");
+    }
+
+    #[test]
+    fn sends_follow_creates() {
+        let mut home = job("/home", false);
+        home.replicate = Some(Replicate {
+            destination: "/mnt/backup".to_string(),
+            ssh: None,
+        });
+        let etc = job("/etc", false);
+        let mut intents = vec![
+            intent(&home, IntentType::Create),
+            intent(&etc, IntentType::Create),
+            intent(&home, IntentType::Delete),
+        ];
+        Intent::gather_send_intents(&mut intents);
+        Intent::sort_for_execution(&mut intents, false);
+        let planned = intents
+            .iter()
+            .map(|i| (i.borrow().intent.clone(), i.borrow().target.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            planned,
+            vec![
+                (IntentType::Create, "/mnt/btrfs/@/gheesnaps".to_string()),
+                (IntentType::Create, "/mnt/btrfs/@/gheesnaps".to_string()),
+                (IntentType::Delete, "/mnt/btrfs/@/gheesnaps".to_string()),
+                (IntentType::Send, "/mnt/backup".to_string()),
+            ]
+        );
+        let send = intents[3].borrow();
+        assert_eq!(send.target_directory(), Path::new("/mnt/btrfs/@/gheesnaps"));
+
+        let tz = FixedOffset::east_opt(0).unwrap();
+        let name = |day: u32| {
+            home.name_scheme().format(
+                home.subvolume_name().unwrap(),
+                &tz.with_ymd_and_hms(2022, 1, day, 12, 0, 0).unwrap(),
+            )
+        };
+        let mut sent = intent(&home, IntentType::Send).borrow().clone();
+        sent.name = name(20);
+        let local = [name(17), name(18), name(19), name(20), "unrelated".to_string()];
+        let received = [name(17), name(18), "unrelated".to_string()];
+        assert_eq!(sent.common_parent(&local, &received), Some(name(18)));
+        assert_eq!(sent.common_parent(&local, &[name(20)]), None);
+        assert_eq!(sent.common_parent(&local, &[]), None);
     }
 }
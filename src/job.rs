@@ -1,27 +1,1288 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use chrono::{DateTime, FixedOffset, Local, SecondsFormat, TimeZone};
+use chrono_tz::Tz;
+use log::{debug, error, info, warn};
+use regex::Regex;
 use serde::Deserialize;
+use tabled::{Style, Table, Tabled};
 
-use crate::policies::PreservePolicy;
+use crate::error::{NoJobsError, NotBtrfsError, TimezoneParseError, UnsetVariableError};
+use crate::filesystem::{filesystem_device, is_btrfs, list_directories, parse_mode, resolve_bind_mount, FreeSpace};
+use crate::naming::{source_hash, to_timezone, NameScheme};
+use crate::policies::{parse_time_of_day, PreservePolicy};
+use crate::replicate::Replicate;
+use crate::retention::{Retention, DEFAULT_MAX_TIER_BINS};
 
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
 pub struct Job {
+    #[serde(default)]
     pub subvolume: String,
+    /// Command printing the subvolumes this job is a template for, one per line
+    pub subvolume_command: Option<String>,
+    /// Glob patterns of subvolumes printed by the `subvolume_command` that get no job
+    pub exclude_subvolumes: Option<Vec<String>>,
+    /// Glob patterns matched against the subvolume, which is then neither snapshotted nor pruned, and against the name
+    /// of each snapshot, which is then left out of pruning
+    pub exclude: Option<Vec<String>>,
     pub target: String,
     pub groups: Option<Vec<String>>,
+    /// Disabled jobs neither create nor prune snapshots, but keep their configuration
+    pub enabled: Option<bool>,
     pub preserve: PreservePolicy,
+    pub stale_after: Option<String>,
+    pub follow_symlinks: Option<bool>,
+    pub target_mode: Option<String>,
+    /// Create the target directory along with the first snapshot if it doesn't exist yet
+    pub create_target: Option<bool>,
+    pub prune_before_create: Option<bool>,
+    pub deduplicate: Option<bool>,
+    pub timestamp_format: Option<String>,
+    /// Fractional digits of the seconds in RFC3339 snapshot names, for taking more than one snapshot a second
+    pub timestamp_precision: Option<TimestampPrecision>,
+    /// How snapshot names are built, e.g. `snap_{subvol}_{timestamp:%Y-%m-%d_%H%M%S}`; overrides `timestamp_format`
+    pub name_template: Option<String>,
+    pub timestamp_source: Option<TimestampSource>,
+    /// Time zone snapshot names are written in, e.g. Europe/Berlin, instead of the local one
+    pub timestamp_tz: Option<String>,
+    /// Appended to the subvolume name in snapshot names, to tell them apart from those of other subvolumes with the
+    /// same directory name in the target
+    pub source_tag: Option<String>,
+    pub when: Option<String>,
+    pub layout: Option<TargetLayout>,
+    /// How the existing snapshots in the target directory are found
+    pub snapshot_listing: Option<SnapshotListing>,
+    pub min_free_space: Option<String>,
+    pub sync_before: Option<bool>,
+    /// Commands run right before and after the snapshot is created, e.g. to freeze and thaw a database
+    pub pre_hook: Option<String>,
+    pub post_hook: Option<String>,
+    /// Command run on the still writable snapshot before it is made read-only
+    pub before_seal: Option<String>,
+    /// Create the snapshots read-only, which is the default, or writable so that they can be modified
+    pub read_only: Option<bool>,
+    /// Where created snapshots are copied to with btrfs send and receive
+    pub replicate: Option<Replicate>,
+    /// Command that receives the job's planned intents as JSON on stdin and prints the snapshots it vetoes deleting
+    pub plan_hook: Option<String>,
+    pub skip_if_locked: Option<bool>,
+    /// Jobs sharing a consistency group have their snapshots created together, and all of them or none
+    pub consistency_group: Option<String>,
+    /// Number of files picked at random that are read from each new snapshot to check it
+    pub verify_sample: Option<usize>,
+    /// Move deleted snapshots to the trash of the snapshot directory instead of deleting them right away
+    pub trash: Option<bool>,
+    /// What a failed create or delete of this job means for the exit status of the run
+    pub on_failure: Option<FailurePolicy>,
+    /// Write a manifest of the snapshots in the target after each run
+    pub write_manifest: Option<bool>,
+    /// Where the settings that weren't written in the job itself came from, by setting name
+    #[serde(skip)]
+    pub origins: BTreeMap<String, String>,
+    /// The most bins a tier of the retention may have, from the configuration's `max_retention_bins`
+    #[serde(skip)]
+    pub max_retention_bins: Option<usize>,
+}
+
+/// The `target` of a job as configured: a single directory, or several with their own retention each
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum JobTargets {
+    Single(String),
+    Multiple(Vec<TargetEntry>),
+}
+
+/// One of several targets of a job
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+pub struct TargetEntry {
+    pub path: String,
+    /// Preserve policy of the snapshots in this target instead of the job's, kept unparsed until the job is
+    pub preserve: Option<serde_json::Value>,
+}
+
+/// How snapshots are arranged in the target directory
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub enum TargetLayout {
+    /// Directly in the target directory
+    #[serde(alias = "flat")]
+    Flat,
+    /// In a subdirectory of the target directory named after the subvolume
+    #[serde(alias = "per_subvolume")]
+    PerSubvolume,
+}
+
+/// What a failed intent of a job means for the run
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub enum FailurePolicy {
+    /// Shown in the table and warned about, but the run still succeeds
+    #[serde(alias = "warn")]
+    Warn,
+    /// The run exits with an error
+    #[serde(alias = "fail_run")]
+    FailRun,
+    /// Only shown in the table
+    #[serde(alias = "ignore")]
+    Ignore,
+}
+
+/// Where the time a snapshot was taken is read from
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub enum TimestampSource {
+    /// The timestamp in the snapshot's name
+    #[serde(alias = "name")]
+    Name,
+    /// The creation time btrfs recorded for the snapshot, falling back to the name if it can't be read
+    #[serde(alias = "metadata")]
+    Metadata,
+}
+
+/// How precisely the seconds of RFC3339 timestamps in snapshot names are written
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub enum TimestampPrecision {
+    /// Whole seconds
+    #[serde(alias = "seconds")]
+    Seconds,
+    /// Three fractional digits
+    #[serde(alias = "millis")]
+    Millis,
+    /// Nine fractional digits
+    #[serde(alias = "nanos")]
+    Nanos,
+}
+
+impl TimestampPrecision {
+    pub fn seconds_format(self) -> SecondsFormat {
+        match self {
+            TimestampPrecision::Seconds => SecondsFormat::Secs,
+            TimestampPrecision::Millis => SecondsFormat::Millis,
+            TimestampPrecision::Nanos => SecondsFormat::Nanos,
+        }
+    }
+}
+
+/// How the existing snapshots of a job are found
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub enum SnapshotListing {
+    /// The directories in the snapshot directory
+    #[serde(alias = "read_dir")]
+    ReadDir,
+    /// The subvolumes btrfs lists in the snapshot directory, falling back to its directories if they can't be listed
+    #[serde(alias = "subvolumes")]
+    Subvolumes,
 }
 
 impl Job {
-    pub fn filter_active_groups(jobs: &[Self], groups: &[String]) -> Vec<Self> {
+    /// The directory name of the subvolume, used as the base of its snapshot names. None for a subvolume without one,
+    /// like `/`, which `load_config` rejects.
+    pub fn subvolume_name(&self) -> Option<&str> {
+        Path::new(&self.subvolume).file_name().and_then(|name| name.to_str())
+    }
+
+    /// The base of the job's snapshot names: the directory name of the subvolume, followed by its `source_tag` if it
+    /// has one
+    pub fn snapshot_base_name(&self) -> Cow<'_, str> {
+        let name = self.subvolume_name().unwrap_or_default();
+        match &self.source_tag {
+            Some(tag) => Cow::Owned(format!("{}~{}", name, tag)),
+            None => Cow::Borrowed(name),
+        }
+    }
+
+    /// Gives the jobs whose snapshots would be named alike in the same directory, because their subvolumes have the
+    /// same directory name, a `source_tag` of a hash of their subvolume path. Jobs with their own tag are left as is.
+    pub fn tag_colliding_names(jobs: &mut [Job]) {
+        let untagged = jobs.iter().filter(|j| j.source_tag.is_none());
+        let mut sources: BTreeMap<(String, String), BTreeSet<String>> = BTreeMap::new();
+        for job in untagged {
+            match job.subvolume_name() {
+                Some(name) => {
+                    let key = (job.snapshot_directory(), name.to_string());
+                    sources.entry(key).or_default().insert(job.subvolume.clone());
+                }
+                None => warn!(
+                    "subvolume {} has no directory name to name its snapshots after",
+                    &job.subvolume
+                ),
+            }
+        }
+
+        for job in jobs.iter_mut().filter(|j| j.source_tag.is_none()) {
+            let Some(name) = job.subvolume_name().map(str::to_string) else {
+                continue;
+            };
+            let directory = job.snapshot_directory();
+            let key = (directory.clone(), name);
+            if sources.get(&key).map_or(0, |s| s.len()) < 2 {
+                continue;
+            }
+
+            job.source_tag = Some(source_hash(&job.subvolume));
+            warn!(
+                "subvolumes named {} share the snapshot directory {}, naming the snapshots of {} {}.* instead",
+                &key.1,
+                &directory,
+                &job.subvolume,
+                job.snapshot_base_name()
+            );
+            job.origins.insert(
+                "source_tag".to_string(),
+                format!("hash of the subvolume, its name is shared in {}", directory),
+            );
+        }
+    }
+
+    /// The job's retention, with at most `max_retention_bins` bins in each tier
+    pub fn retention(&self) -> Result<Retention, Box<dyn Error>> {
+        let max_tier_bins = self.max_retention_bins.unwrap_or(DEFAULT_MAX_TIER_BINS);
+        Retention::from_str_option(&self.preserve.retention, max_tier_bins)
+    }
+
+    /// The directory the job's snapshots are placed in according to its layout
+    pub fn snapshot_directory(&self) -> String {
+        match self.layout.unwrap_or(TargetLayout::Flat) {
+            TargetLayout::Flat => self.target.clone(),
+            TargetLayout::PerSubvolume => Path::new(&self.target)
+                .join(self.subvolume_name().unwrap_or_default())
+                .to_string_lossy()
+                .to_string(),
+        }
+    }
+
+    /// Whether a snapshot named `name` already exists in the job's snapshot directory
+    pub fn has_snapshot(&self, name: &str) -> bool {
+        Path::new(&self.snapshot_directory()).join(name).exists()
+    }
+
+    /// Whether the job's subvolume path matches one of its `exclude` patterns
+    pub fn excludes_subvolume(&self) -> bool {
+        is_excluded(&self.subvolume, self.exclude.as_deref().unwrap_or_default())
+    }
+
+    /// Whether the snapshot name `name` matches one of the job's `exclude` patterns
+    pub fn excludes_snapshot(&self, name: &str) -> bool {
+        is_excluded(name, self.exclude.as_deref().unwrap_or_default())
+    }
+
+    /// How the job's snapshots are named: by its `name_template`, or as `{subvol}.{timestamp}` with an RFC3339
+    /// timestamp unless the job sets a `timestamp_format`. An invalid template is ignored; `validate` warns about it.
+    pub fn name_scheme(&self) -> NameScheme {
+        let template = self
+            .name_template
+            .as_deref()
+            .and_then(|t| NameScheme::from_template(t).ok());
+        let scheme = match (template, &self.timestamp_format) {
+            (Some(template), _) => template,
+            (None, Some(format)) => NameScheme::with_timestamp_format(format),
+            (None, None) => NameScheme::default(),
+        };
+        let precision = self.timestamp_precision.unwrap_or(TimestampPrecision::Seconds);
+        scheme
+            .in_timezone(self.timezone().ok().flatten())
+            .with_precision(precision.seconds_format())
+    }
+
+    /// Checks that a snapshot created at `now`, as well as at a fixed reference time, is named so that pruning finds it
+    /// and dates it to the time it was created
+    pub fn check_naming(&self, now: &DateTime<Local>) -> Result<(), String> {
+        let reference = Local.with_ymd_and_hms(2022, 10, 22, 13, 37, 42).unwrap();
+        let scheme = self.name_scheme();
+        let base_name = self.snapshot_base_name();
+
+        for time in [reference, Local.timestamp_opt(now.timestamp(), 0).unwrap()] {
+            scheme.check_round_trip(&base_name, &time.with_timezone(time.offset()))?;
+        }
+
+        Ok(())
+    }
+
+    /// The time zone of the job's `timestamp_tz`, if it sets one
+    pub fn timezone(&self) -> Result<Option<Tz>, Box<dyn Error>> {
+        match &self.timestamp_tz {
+            Some(tz) => Ok(Some(tz.parse::<Tz>().map_err(|_| TimezoneParseError)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// The same point in time as `timestamp`, in the job's time zone if it sets one
+    pub fn in_timezone(&self, timestamp: &DateTime<FixedOffset>) -> DateTime<FixedOffset> {
+        match self.timezone() {
+            Ok(Some(tz)) => to_timezone(timestamp, &tz),
+            _ => *timestamp,
+        }
+    }
+
+    /// Expands `~` and environment variables in the subvolume and target paths
+    pub fn expand_variables(&mut self) -> Result<(), Box<dyn Error>> {
+        let var = |name: &str| env::var(name).ok();
+        self.subvolume = expand_path(&self.subvolume, var)?;
+        self.target = expand_path(&self.target, var)?;
+
+        Ok(())
+    }
+
+    /// Expands a job with a `subvolume_command` into a job for each subvolume the command prints, one per line, and a
+    /// job whose `subvolume` is a glob pattern into a job for each directory it matches. Paths matching
+    /// `exclude_subvolumes` are skipped, those that `is_subvolume` rejects with a warning. `{name}` in the target is
+    /// replaced with the last component of each path. Other jobs stay as they are.
+    pub fn expand(self, is_subvolume: impl Fn(&Path) -> bool) -> Vec<Job> {
+        let command = match &self.subvolume_command {
+            Some(command) => command.clone(),
+            None if self.subvolume.is_empty() => {
+                error!("skipping job with target {} because it has no subvolume!", &self.target);
+                return Vec::new();
+            }
+            None if is_glob(&self.subvolume) => {
+                let paths = glob_directories(&self.subvolume);
+                if paths.is_empty() {
+                    warn!("subvolume pattern {} matches no directories", &self.subvolume);
+                }
+                let source = format!("subvolume pattern `{}`", &self.subvolume);
+                return self.expand_paths(&source, paths, is_subvolume);
+            }
+            None => return vec![self],
+        };
+        if !self.subvolume.is_empty() {
+            warn!(
+                "job with subvolume_command `{}` also sets subvolume {}, which is ignored",
+                command, &self.subvolume
+            );
+        }
+
+        let output = match Command::new("sh").arg("-c").arg(&command).output() {
+            Ok(output) if output.status.success() => output,
+            Ok(output) => {
+                error!(
+                    "`{}` exited with {}, no jobs are created from it!",
+                    command, output.status
+                );
+                return Vec::new();
+            }
+            Err(e) => {
+                error!(
+                    "running `{}` failed, no jobs are created from it! error: {}",
+                    command, e
+                );
+                return Vec::new();
+            }
+        };
+
+        let paths = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(str::trim)
+            .filter(|path| !path.is_empty())
+            .map(str::to_string)
+            .collect();
+        let source = format!("subvolume_command `{}`", command);
+        self.expand_paths(&source, paths, is_subvolume)
+    }
+
+    /// Creates a job for each of the `paths` that `source` gave, see `expand`
+    fn expand_paths(&self, source: &str, paths: Vec<String>, is_subvolume: impl Fn(&Path) -> bool) -> Vec<Job> {
+        let excluded = self.exclude_subvolumes.as_deref().unwrap_or_default();
+        paths
+            .into_iter()
+            .filter(|path| {
+                let exclude = is_excluded(path, excluded);
+                if exclude {
+                    debug!("{} gave {}, which is excluded", source, path);
+                }
+                !exclude
+            })
+            .filter(|path| {
+                let subvolume = is_subvolume(Path::new(path));
+                if !subvolume {
+                    warn!("{} gave {}, which is not a subvolume, skipping it", source, path);
+                }
+                subvolume
+            })
+            .map(|path| {
+                let name = Path::new(&path)
+                    .file_name()
+                    .map_or(String::new(), |n| n.to_string_lossy().to_string());
+                let mut job = Job {
+                    subvolume: path,
+                    subvolume_command: None,
+                    target: self.target.replace("{name}", &name),
+                    ..self.clone()
+                };
+                job.origins.insert("subvolume".to_string(), source.to_string());
+                if job.target != self.target {
+                    job.origins.insert("target".to_string(), source.to_string());
+                }
+                job
+            })
+            .collect()
+    }
+
+    /// Runs the job's `when` command, if it has one, and reports whether a snapshot should be created. With `dryrun`,
+    /// the command is only logged.
+    pub fn when_satisfied(&self, dryrun: bool) -> bool {
+        let command = match &self.when {
+            Some(command) => command,
+            None => return true,
+        };
+        if dryrun {
+            info!(
+                "would run `{}` to check whether to snapshot {}",
+                command, &self.subvolume
+            );
+            return true;
+        }
+
+        match Command::new("sh").arg("-c").arg(command).status() {
+            Ok(status) if status.success() => true,
+            Ok(status) => {
+                info!(
+                    "`{}` exited with {}, not snapshotting {}",
+                    command, status, &self.subvolume
+                );
+                false
+            }
+            Err(e) => {
+                warn!("Unable to run `{}` for {}! Error: {}", command, &self.subvolume, e);
+                false
+            }
+        }
+    }
+
+    /// Resolves the subvolume and target paths relative to `root` instead of the filesystem root
+    pub fn rebase(&mut self, root: &Path) {
+        for setting in ["subvolume", "target"] {
+            let origin = self.origins.get(setting).map_or("job", String::as_str);
+            let origin = format!("{} below --root {}", origin, root.display());
+            self.origins.insert(setting.to_string(), origin);
+        }
+        self.subvolume = root
+            .join(self.subvolume.trim_start_matches('/'))
+            .to_string_lossy()
+            .to_string();
+        self.target = root
+            .join(self.target.trim_start_matches('/'))
+            .to_string_lossy()
+            .to_string();
+    }
+
+    /// Resolves symlinks in the subvolume and target paths, so that subvolume detection and snapshot names are based on
+    /// the actual locations. Does nothing but warn about symlinks if the job doesn't follow symlinks. A subvolume path
+    /// that is a bind mount of a directory is resolved to the subvolume containing that directory.
+    pub fn canonicalize_paths(&mut self) {
+        let follow_symlinks = self.follow_symlinks.unwrap_or(true);
+
+        for path in [&mut self.subvolume, &mut self.target] {
+            let is_symlink = fs::symlink_metadata(&path).is_ok_and(|m| m.file_type().is_symlink());
+            if !follow_symlinks {
+                if is_symlink {
+                    warn!("{} is a symlink, but will not be followed!", path);
+                }
+                continue;
+            }
+
+            if let Ok(canonical) = fs::canonicalize(&path) {
+                let canonical = canonical.to_string_lossy().to_string();
+                if canonical != *path {
+                    if is_symlink {
+                        warn!("{} is a symlink to {}, using the resolved path", path, canonical);
+                    } else {
+                        debug!("resolved {} to {}", path, canonical);
+                    }
+                    *path = canonical;
+                }
+            }
+        }
+
+        match resolve_bind_mount(Path::new(&self.subvolume)) {
+            None => {}
+            Some(Ok(subvolume)) => {
+                let subvolume = subvolume.to_string_lossy().to_string();
+                warn!(
+                    "{} is a bind mount of a directory, snapshotting the subvolume containing it at {}",
+                    &self.subvolume, subvolume
+                );
+                self.subvolume = subvolume;
+            }
+            Some(Err(subvol)) => warn!(
+                "{} is a bind mount of a directory in subvolume {:?}, which is not mounted anywhere!",
+                &self.subvolume, subvol
+            ),
+        }
+    }
+
+    /// Checks the job for settings that are almost certainly mistakes and describes each of them
+    pub fn validate(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+
+        if self.preserve.retains_nothing() {
+            warnings.push(format!(
+                "job {} has neither a retention nor a min setting that keeps snapshots, all of them may be deleted! Set `min: none` if this is intended.",
+                &self.subvolume
+            ));
+        }
+        if let Some(mode) = &self.target_mode {
+            if let Err(e) = parse_mode(mode) {
+                warnings.push(format!(
+                    "target_mode {} of job {} is invalid: {}",
+                    mode, &self.subvolume, e
+                ));
+            }
+        }
+        if let Some(min_free_space) = &self.min_free_space {
+            if let Err(e) = FreeSpace::parse(min_free_space) {
+                warnings.push(format!(
+                    "min_free_space {} of job {} is invalid: {}",
+                    min_free_space, &self.subvolume, e
+                ));
+            }
+        }
+        if self.replicate.is_some() && self.read_only == Some(false) {
+            warnings.push(format!(
+                "job {} replicates its snapshots, but btrfs can only send read-only snapshots",
+                &self.subvolume
+            ));
+        }
+        if let Some(min_free) = &self.preserve.min_free {
+            if let Err(e) = FreeSpace::parse(min_free) {
+                warnings.push(format!(
+                    "min_free {} of job {} is invalid: {}",
+                    min_free, &self.subvolume, e
+                ));
+            }
+        }
+        if let Some(bin_time) = &self.preserve.bin_time {
+            if let Err(e) = parse_time_of_day(bin_time) {
+                warnings.push(format!(
+                    "bin_time {} of job {} is invalid: {}",
+                    bin_time, &self.subvolume, e
+                ));
+            }
+        }
+        if let Some((lowest, highest)) = self.preserve.expected_count {
+            if lowest > highest {
+                warnings.push(format!(
+                    "expected_count [{}, {}] of job {} is empty, its lowest count is above its highest",
+                    lowest, highest, &self.subvolume
+                ));
+            }
+        }
+        if let (Some(tz), Err(e)) = (&self.timestamp_tz, self.timezone()) {
+            warnings.push(format!(
+                "timestamp_tz {} of job {} is invalid, using the local time zone: {}",
+                tz, &self.subvolume, e
+            ));
+        }
+        if let Some(tag) = &self.source_tag {
+            if tag.is_empty() || !tag.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+                warnings.push(format!(
+                    "source_tag {} of job {} may only contain letters, digits, _ and -",
+                    tag, &self.subvolume
+                ));
+            }
+        }
+        if let Some(template) = &self.name_template {
+            if let Err(e) = NameScheme::from_template(template) {
+                warnings.push(format!(
+                    "name_template {} of job {} is invalid, its snapshots are named by the default scheme! {}",
+                    template, &self.subvolume, e
+                ));
+            } else if let Some(format) = &self.timestamp_format {
+                warnings.push(format!(
+                    "job {} sets both name_template and timestamp_format {}, which is ignored",
+                    &self.subvolume, format
+                ));
+            }
+        }
+        if self.timestamp_precision.is_some() && !self.name_scheme().writes_rfc3339() {
+            warnings.push(format!(
+                "job {} sets timestamp_precision, which is ignored, as its snapshot names don't use RFC3339",
+                &self.subvolume
+            ));
+        }
+        if let Err(e) = self.check_naming(&Local::now()) {
+            warnings.push(format!(
+                "naming of job {} is inconsistent, its snapshots would not be recognized for pruning! {}",
+                &self.subvolume, e
+            ));
+        }
+        if is_btrfs(Path::new(&self.target)) == Some(false) {
+            warnings.push(format!(
+                "target {} of job {} is not on a btrfs filesystem! {}",
+                &self.target, &self.subvolume, NotBtrfsError
+            ));
+        }
+        let source_device = filesystem_device(Path::new(&self.subvolume));
+        let target_device = filesystem_device(Path::new(&self.target));
+        if let (Some(source), Some(target)) = (source_device, target_device) {
+            if source != target {
+                warnings.push(format!(
+                    "subvolume {} (device {}) and target {} (device {}) of job {} are on different filesystems, snapshots can't be created!",
+                    &self.subvolume, source, &self.target, target, &self.subvolume
+                ));
+            }
+        }
+        if self.target_inside_subvolume() {
+            warnings.push(format!(
+                "target {} of job {} is inside the subvolume, snapshots would contain previous snapshots!",
+                &self.target, &self.subvolume
+            ));
+        }
+
+        warnings
+    }
+
+    /// Whether the target directory doesn't exist yet and is to be created before snapshotting into it
+    pub fn creates_target(&self) -> bool {
+        self.create_target.unwrap_or(false) && !Path::new(&self.target).exists()
+    }
+
+    pub fn target_inside_subvolume(&self) -> bool {
+        Path::new(&self.target).starts_with(&self.subvolume)
+    }
+
+    /// Selects the jobs in any of `groups`, which may be glob patterns using `*` and `?`
+    pub fn filter_active_groups<'a>(jobs: &'a [Self], groups: &[String]) -> Vec<&'a Self> {
+        let patterns = groups.iter().map(|g| glob_regex(g)).collect::<Vec<_>>();
         let filtered_jobs = if !groups.is_empty() {
             jobs.iter()
                 .filter(|j| j.groups.is_some())
-                .filter(|j| j.groups.as_ref().unwrap().iter().any(|jg| groups.contains(jg)))
-                .cloned()
+                .filter(|j| {
+                    j.groups
+                        .as_ref()
+                        .unwrap()
+                        .iter()
+                        .any(|jg| patterns.iter().any(|p| p.is_match(jg)))
+                })
                 .collect::<Vec<_>>()
         } else {
-            jobs.to_vec()
+            jobs.iter().collect()
         };
 
         filtered_jobs
     }
+
+    /// Selects the jobs of `subvolume` if given, otherwise those in any of `groups`. Warns if no job is selected,
+    /// which is an error under `strict`.
+    pub fn select<'a>(
+        jobs: &'a [Self],
+        groups: &[String],
+        subvolume: &Option<String>,
+        target: &Option<String>,
+        strict: bool,
+    ) -> Result<Vec<&'a Self>, Box<dyn Error>> {
+        let mut selected = match subvolume {
+            Some(subvolume) => Self::filter_subvolume(jobs, subvolume),
+            None => Self::filter_active_groups(jobs, groups),
+        };
+        if let Some(target) = target {
+            selected = Self::filter_target(&selected, target);
+        }
+
+        if selected.is_empty() && !jobs.is_empty() {
+            warn!(
+                "no jobs match the groups {:?} or subvolume {:?} and target {:?}",
+                groups, subvolume, target
+            );
+            if strict {
+                return Err(Box::new(NoJobsError));
+            }
+        }
+
+        Ok(selected)
+    }
+
+    /// Drops the jobs that are disabled, logging each of them
+    pub fn filter_enabled(jobs: Vec<&Self>) -> Vec<&Self> {
+        jobs.into_iter()
+            .filter(|j| {
+                let enabled = j.enabled.unwrap_or(true);
+                if !enabled {
+                    info!("Skipping job of {} to {}, it is disabled.", &j.subvolume, &j.target);
+                }
+                enabled
+            })
+            .collect()
+    }
+
+    pub fn filter_subvolume<'a>(jobs: &'a [Self], subvolume: &str) -> Vec<&'a Self> {
+        jobs.iter().filter(|j| j.subvolume == subvolume).collect()
+    }
+
+    /// Keeps the jobs whose target is `target` or a directory below it
+    pub fn filter_target<'a>(jobs: &[&'a Self], target: &str) -> Vec<&'a Self> {
+        jobs.iter()
+            .copied()
+            .filter(|j| Path::new(&j.target).starts_with(target))
+            .collect()
+    }
+}
+
+/// The outcome of checking one job's subvolume, target and preserve settings
+#[derive(Debug, Tabled)]
+pub struct JobCheck {
+    pub subvolume: String,
+    pub target: String,
+    pub status: String,
+    pub error: String,
+}
+
+impl JobCheck {
+    /// Checks that each job's subvolume is a btrfs subvolume, that its target is a directory and that its retention
+    /// and min settings parse. Nothing is gathered or changed.
+    pub fn gather(jobs: &[&Job], now: &DateTime<FixedOffset>, is_subvolume: impl Fn(&Path) -> bool) -> Vec<Self> {
+        jobs.iter()
+            .map(|job| {
+                let mut errors = Vec::new();
+                if !is_subvolume(Path::new(&job.subvolume)) {
+                    errors.push("subvolume is not a btrfs subvolume".to_string());
+                }
+                if job.subvolume_name().is_none() {
+                    errors.push("subvolume has no directory name to name its snapshots after".to_string());
+                }
+                let target = Path::new(&job.target);
+                if !target.exists() && !job.create_target.unwrap_or(false) {
+                    errors.push("target does not exist".to_string());
+                } else if target.exists() && !target.is_dir() {
+                    errors.push("target is not a directory".to_string());
+                }
+                if let Err(e) = job.retention() {
+                    errors.push(format!("retention: {}", e));
+                }
+                if let Err(e) = job.preserve.min.keep_count(&[], now) {
+                    errors.push(format!("min: {}", e));
+                }
+
+                Self {
+                    subvolume: job.subvolume.clone(),
+                    target: job.target.clone(),
+                    status: if errors.is_empty() { "ok" } else { "error" }.to_string(),
+                    error: errors.join("; "),
+                }
+            })
+            .collect()
+    }
+
+    pub fn failed(&self) -> bool {
+        self.status != "ok"
+    }
+
+    pub fn print_tabled(checks: &[Self]) {
+        let table = Table::new(checks).with(Style::modern()).to_string();
+        info!("{}", table);
+    }
+}
+
+/// Replaces a leading `~` with `$HOME`, and `$VAR` and `${VAR}` with the value `var` gives for them. Fails on
+/// variables that aren't set instead of leaving an empty string in their place.
+pub fn expand_path(path: &str, var: impl Fn(&str) -> Option<String>) -> Result<String, Box<dyn Error>> {
+    let home = match path.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => format!("${{HOME}}{}", rest),
+        _ => path.to_string(),
+    };
+
+    let re = Regex::new(r"\$(?:\{(\w+)\}|(\w+))").expect("variable regex is valid");
+    let mut expanded = String::new();
+    let mut last = 0;
+    for captures in re.captures_iter(&home) {
+        let whole = captures.get(0).expect("a match has a whole");
+        let name = captures
+            .get(1)
+            .or_else(|| captures.get(2))
+            .expect("one of the alternatives matched")
+            .as_str();
+        let value = var(name).ok_or_else(|| {
+            error!("variable {} in path {} is not set!", name, path);
+            UnsetVariableError
+        })?;
+        expanded.push_str(&home[last..whole.start()]);
+        expanded.push_str(&value);
+        last = whole.end();
+    }
+    expanded.push_str(&home[last..]);
+
+    Ok(expanded)
+}
+
+/// Whether `path` matches any of the glob `patterns`
+pub fn is_excluded(path: &str, patterns: &[String]) -> bool {
+    patterns.iter().any(|p| glob_regex(p).is_match(path))
+}
+
+/// Whether `pattern` contains any of the glob wildcards `*` and `?`
+fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?'])
+}
+
+/// The directories matching the glob `pattern`, sorted. Unlike in `exclude_subvolumes`, wildcards only match within
+/// one component of the path.
+fn glob_directories(pattern: &str) -> Vec<String> {
+    let mut matches = vec![if pattern.starts_with('/') {
+        PathBuf::from("/")
+    } else {
+        PathBuf::new()
+    }];
+    for component in pattern.split('/').filter(|c| !c.is_empty()) {
+        if !is_glob(component) {
+            matches = matches.into_iter().map(|m| m.join(component)).collect();
+            continue;
+        }
+
+        let re = glob_regex(component);
+        let mut expanded = Vec::new();
+        for directory in &matches {
+            let read = if directory.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                directory.as_path()
+            };
+            if let Ok(entries) = list_directories(read) {
+                expanded.extend(entries.into_iter().filter_map(|entry| {
+                    let name = entry.file_name()?.to_str()?;
+                    re.is_match(name).then(|| directory.join(name))
+                }));
+            }
+        }
+        matches = expanded;
+    }
+
+    let mut directories = matches
+        .into_iter()
+        .filter(|m| m.is_dir())
+        .filter_map(|m| m.to_str().map(str::to_string))
+        .collect::<Vec<_>>();
+    directories.sort();
+    directories
+}
+
+/// Builds a regex matching the whole of what the glob `pattern` matches
+fn glob_regex(pattern: &str) -> Regex {
+    let mut re = String::from("^");
+    for c in pattern.chars() {
+        match c {
+            '*' => re.push_str(".*"),
+            '?' => re.push('.'),
+            c => re.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    re.push('$');
+
+    Regex::new(&re).expect("glob regex is built from escaped characters")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::os::unix::fs::symlink;
+    use std::path::Path;
+
+    use chrono::{FixedOffset, Local, TimeZone};
+
+    use crate::error::NotBtrfsError;
+    use crate::job::{expand_path, glob_regex, is_excluded, Job, JobCheck};
+
+    fn job(subvolume: &str, target: &str) -> Job {
+        serde_yaml::from_str(&format!(
+            "{{subvolume: {}, target: {}, preserve: {{min: all}}}}",
+            subvolume, target
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn subvolume_command_expands_jobs() {
+        let template: Job = serde_yaml::from_str(
+            r#"{subvolume_command: "printf '/srv/a\n\n/srv/b\n/srv/plain\n'", target: /snaps, preserve: {min: 5}}"#,
+        )
+        .unwrap();
+        let is_subvolume = |path: &Path| path != Path::new("/srv/plain");
+
+        let jobs = template.clone().expand(is_subvolume);
+        let subvolumes = jobs.iter().map(|j| j.subvolume.as_str()).collect::<Vec<_>>();
+        assert_eq!(subvolumes, vec!["/srv/a", "/srv/b"]);
+        assert!(jobs.iter().all(|j| j.subvolume_command.is_none()));
+        assert!(jobs
+            .iter()
+            .all(|j| j.target == "/snaps" && j.preserve == template.preserve));
+
+        let failing = Job {
+            subvolume_command: Some("echo /srv/a; exit 1".to_string()),
+            ..template.clone()
+        };
+        assert!(failing.expand(is_subvolume).is_empty());
+
+        let plain = job("/home", "/snaps");
+        assert_eq!(plain.clone().expand(is_subvolume), vec![plain]);
+
+        let without_subvolume = Job {
+            subvolume_command: None,
+            ..template
+        };
+        assert!(without_subvolume.expand(is_subvolume).is_empty());
+    }
+
+    #[test]
+    fn subvolume_pattern_expands_jobs() {
+        let dir = std::env::temp_dir().join(format!("ghee-glob-test-{}", std::process::id()));
+        for machine in ["web", "db", "db-tmp", "plain"] {
+            fs::create_dir_all(dir.join("machines").join(machine)).unwrap();
+        }
+        fs::write(dir.join("machines").join("file"), "").unwrap();
+        let template: Job = serde_yaml::from_str(&format!(
+            "{{subvolume: {}/machines/*, target: \"/snaps/{{name}}\", exclude_subvolumes: [\"*-tmp\"], \
+             preserve: {{min: 5}}}}",
+            dir.display()
+        ))
+        .unwrap();
+        let is_subvolume = |path: &Path| !path.ends_with("plain");
+
+        let jobs = template.expand(is_subvolume);
+        let expanded = jobs
+            .iter()
+            .map(|j| {
+                (
+                    j.subvolume.strip_prefix(dir.to_str().unwrap()).unwrap(),
+                    j.target.as_str(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            expanded,
+            vec![("/machines/db", "/snaps/db"), ("/machines/web", "/snaps/web")]
+        );
+        assert!(jobs[0].origins["target"].starts_with("subvolume pattern"));
+
+        let nested: Job = serde_yaml::from_str(&format!(
+            "{{subvolume: {}/*/w?b, target: /snaps, preserve: {{min: 5}}}}",
+            dir.display()
+        ))
+        .unwrap();
+        let jobs = nested.expand(is_subvolume);
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].subvolume, format!("{}/machines/web", dir.display()));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn excluded_subvolumes_not_expanded() {
+        let template: Job = serde_yaml::from_str(
+            r#"{subvolume_command: "printf '/srv/a\n/srv/b\n/srv/snaps\n/srv/snaps/x\n/var/lib/docker/btrfs/1\n'",
+                target: /srv/snaps, exclude_subvolumes: ["/srv/snaps*", "/var/lib/docker/*"], preserve: {min: 5}}"#,
+        )
+        .unwrap();
+
+        let jobs = template.expand(|_path: &Path| true);
+        let subvolumes = jobs.iter().map(|j| j.subvolume.as_str()).collect::<Vec<_>>();
+        assert_eq!(subvolumes, vec!["/srv/a", "/srv/b"]);
+
+        assert!(is_excluded("/srv/snaps", &["/srv/snaps".to_string()]));
+        assert!(!is_excluded("/srv/snapshot", &["/srv/snaps".to_string()]));
+        assert!(!is_excluded("/srv/a", &[]));
+    }
+
+    #[test]
+    fn target_inside_subvolume() {
+        let nested = job("/home", "/home/.snapshots");
+        assert!(nested.target_inside_subvolume());
+        assert_eq!(nested.validate().len(), 1);
+
+        let separate = job("/home", "/mnt/btrfs/@/gheesnaps");
+        assert!(!separate.target_inside_subvolume());
+        assert!(separate.validate().is_empty());
+
+        let similar_prefix = job("/home", "/homesnaps");
+        assert!(!similar_prefix.target_inside_subvolume());
+    }
+
+    #[test]
+    fn naming_round_trip() {
+        let now = Local.with_ymd_and_hms(2022, 1, 17, 12, 34, 56).unwrap();
+        let mut consistent = job("/home", "/mnt/btrfs/@/gheesnaps");
+        assert_eq!(consistent.check_naming(&now), Ok(()));
+        consistent.timestamp_format = Some("rfc3339-dashes".to_string());
+        consistent.timestamp_tz = Some("Asia/Kolkata".to_string());
+        consistent.source_tag = Some("nvme".to_string());
+        assert_eq!(consistent.check_naming(&now), Ok(()));
+        assert!(consistent.validate().is_empty());
+
+        let mut broken = job("/home", "/mnt/btrfs/@/gheesnaps");
+        broken.timestamp_format = Some("%Y%m%d".to_string());
+        assert!(broken.check_naming(&now).is_err());
+        let warnings = broken.validate();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with("naming of job /home is inconsistent"));
+    }
+
+    #[test]
+    fn name_template() {
+        let mut templated = job("/home", "/mnt/btrfs/@/gheesnaps");
+        templated.name_template = Some("snap_{subvol}_{timestamp:%Y-%m-%d_%H%M%S%z}".to_string());
+        let ts = FixedOffset::east_opt(0)
+            .unwrap()
+            .with_ymd_and_hms(2023, 1, 2, 15, 0, 0)
+            .unwrap();
+        let name = templated.name_scheme().format("home", &ts);
+        assert_eq!(name, "snap_home_2023-01-02_150000+0000");
+        assert_eq!(templated.name_scheme().parse_timestamp("home", &name), Some(ts));
+        assert!(templated.validate().is_empty());
+
+        let default = job("/home", "/mnt/btrfs/@/gheesnaps");
+        assert_eq!(default.name_scheme().format("home", &ts), "home.2023-01-02T15:00:00Z");
+
+        let mut invalid = job("/home", "/mnt/btrfs/@/gheesnaps");
+        invalid.name_template = Some("snap_{subvol}".to_string());
+        assert_eq!(invalid.name_scheme(), default.name_scheme());
+        assert_eq!(invalid.validate().len(), 1);
+    }
+
+    #[test]
+    fn target_not_on_btrfs() {
+        let tmpfs = job("/home", "/dev/shm");
+        let expected = format!(
+            "target /dev/shm of job /home is not on a btrfs filesystem! {}",
+            NotBtrfsError
+        );
+        assert!(tmpfs.validate().contains(&expected));
+    }
+
+    #[test]
+    fn canonicalize_symlinked_subvolume() {
+        let dir = std::env::temp_dir().join(format!("ghee-symlink-test-{}", std::process::id()));
+        fs::create_dir_all(dir.join("data")).unwrap();
+        symlink(dir.join("data"), dir.join("link")).unwrap();
+        let dir = fs::canonicalize(&dir).unwrap();
+
+        let mut followed = job(dir.join("link").to_str().unwrap(), "/mnt/btrfs/@/gheesnaps");
+        followed.canonicalize_paths();
+        assert_eq!(followed.subvolume, dir.join("data").to_str().unwrap());
+        assert_eq!(followed.subvolume_name(), Some("data"));
+
+        let mut unfollowed = job(dir.join("link").to_str().unwrap(), "/mnt/btrfs/@/gheesnaps");
+        unfollowed.follow_symlinks = Some(false);
+        unfollowed.canonicalize_paths();
+        assert_eq!(unfollowed.subvolume, dir.join("link").to_str().unwrap());
+        assert_eq!(unfollowed.subvolume_name(), Some("link"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn filter_subvolume() {
+        let jobs = vec![
+            job("/home", "/mnt/btrfs/@/gheesnaps"),
+            job("/etc", "/mnt/btrfs/@/gheesnaps"),
+            job("/var/lib/postgres", "/mnt/btrfs/@/gheesnaps"),
+        ];
+
+        let filtered = Job::filter_subvolume(&jobs, "/etc");
+        assert_eq!(filtered, vec![&jobs[1]]);
+        assert!(Job::filter_subvolume(&jobs, "/et").is_empty());
+    }
+
+    #[test]
+    fn filter_active_groups() {
+        let mut jobs = vec![
+            job("/home", "/mnt/btrfs/@/gheesnaps"),
+            job("/etc", "/mnt/btrfs/@/gheesnaps"),
+            job("/var/lib/postgres", "/mnt/btrfs/@/gheesnaps"),
+        ];
+        jobs[0].groups = Some(vec!["daily".to_string()]);
+        jobs[2].groups = Some(vec!["daily".to_string(), "db".to_string()]);
+        let original = jobs.clone();
+
+        let filtered = Job::filter_active_groups(&jobs, &["daily".to_string()]);
+        assert_eq!(filtered, vec![&jobs[0], &jobs[2]]);
+        assert!(std::ptr::eq(filtered[1], &jobs[2]));
+        assert_eq!(Job::filter_active_groups(&jobs, &["db".to_string()]), vec![&jobs[2]]);
+        assert_eq!(Job::filter_active_groups(&jobs, &[]).len(), 3);
+        assert_eq!(jobs, original);
+    }
+
+    #[test]
+    fn finds_existing_snapshot() {
+        let dir = std::env::temp_dir().join(format!("ghee-existing-test-{}", std::process::id()));
+        fs::create_dir_all(dir.join("home.2022-01-20T12:00:00Z")).unwrap();
+        let job = job("/home", dir.to_str().unwrap());
+
+        assert!(job.has_snapshot("home.2022-01-20T12:00:00Z"));
+        assert!(!job.has_snapshot("home.2022-01-20T12:00:01Z"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn exclude_patterns() {
+        let mut home = job("/srv/home-tmp", "/snaps");
+        assert!(!home.excludes_subvolume());
+        assert!(!home.excludes_snapshot("home-tmp.2022-01-10T12:00:00Z"));
+
+        home.exclude = Some(vec!["*-tmp".to_string(), "*.2022-01-1?T*".to_string()]);
+        // the subvolume is matched by its whole path, snapshots by their name
+        assert!(home.excludes_subvolume());
+        assert!(home.excludes_snapshot("home-tmp.2022-01-10T12:00:00Z"));
+        assert!(!home.excludes_snapshot("home-tmp.2022-01-20T12:00:00Z"));
+        assert!(!home.excludes_snapshot("home.2022-01-20T12:00:00Z"));
+
+        home.subvolume = "/srv/home".to_string();
+        assert!(!home.excludes_subvolume());
+    }
+
+    #[test]
+    fn filter_enabled() {
+        let mut jobs = vec![
+            job("/home", "/mnt/btrfs/@/gheesnaps"),
+            job("/etc", "/mnt/btrfs/@/gheesnaps"),
+            job("/var/lib/postgres", "/mnt/btrfs/@/gheesnaps"),
+        ];
+        jobs[1].enabled = Some(false);
+        jobs[2].enabled = Some(true);
+
+        let selected = Job::filter_active_groups(&jobs, &[]);
+        assert_eq!(Job::filter_enabled(selected), vec![&jobs[0], &jobs[2]]);
+    }
+
+    #[test]
+    fn when_commands() {
+        let mut job = job("/home", "/mnt/btrfs/@/gheesnaps");
+        assert!(job.when_satisfied(false));
+
+        job.when = Some("true".to_string());
+        assert!(job.when_satisfied(false));
+
+        job.when = Some("exit 3".to_string());
+        assert!(!job.when_satisfied(false));
+        assert!(job.when_satisfied(true));
+    }
+
+    #[test]
+    fn checks_jobs() {
+        let dir = std::env::temp_dir().join(format!("ghee-check-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("file");
+        fs::write(&file, "").unwrap();
+        let dir = dir.to_str().unwrap();
+        let file = file.to_str().unwrap();
+        let now = FixedOffset::east_opt(0)
+            .unwrap()
+            .with_ymd_and_hms(2022, 5, 1, 12, 0, 0)
+            .unwrap();
+
+        let valid = job("/home", dir);
+        let missing = job("/srv", "/nonexistent/gheesnaps");
+        let mut created = job("/home", "/nonexistent/gheesnaps");
+        created.create_target = Some(true);
+        let not_directory = job("/home", file);
+        let nameless = job("/", dir);
+        let mut bad_preserve = job("/home", dir);
+        // malformed policies are rejected when the configuration is loaded, so they're set directly
+        bad_preserve.preserve.retention = Some("2x".to_string());
+        bad_preserve.preserve.min = serde_yaml::from_str("3q").unwrap();
+        let jobs = vec![&valid, &missing, &not_directory, &bad_preserve, &created, &nameless];
+
+        let checks = JobCheck::gather(&jobs, &now, |path| path == Path::new("/home"));
+        assert!(!checks[0].failed());
+        assert_eq!(checks[0].error, "");
+        assert!(checks[1].failed());
+        assert_eq!(
+            checks[1].error,
+            "subvolume is not a btrfs subvolume; target does not exist"
+        );
+        assert_eq!(checks[2].error, "target is not a directory");
+        assert!(checks[3].error.starts_with("retention: "));
+        assert!(checks[3].error.contains("; min: "));
+        assert!(!checks[4].failed());
+        assert_eq!(
+            checks[5].error,
+            "subvolume is not a btrfs subvolume; subvolume has no directory name to name its snapshots after"
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn select_nothing() {
+        let jobs = vec![job("/home", "/mnt/btrfs/@/gheesnaps")];
+        let groups = vec!["nonexistent".to_string()];
+
+        assert!(Job::select(&jobs, &groups, &None, &None, false).unwrap().is_empty());
+        assert!(Job::select(&jobs, &groups, &None, &None, true).is_err());
+        assert!(Job::select(&jobs, &[], &Some("/etc".to_string()), &None, true).is_err());
+        assert!(Job::select(&jobs, &[], &None, &Some("/backups".to_string()), true).is_err());
+        assert_eq!(Job::select(&jobs, &[], &None, &None, true).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn filter_target() {
+        let mut jobs = vec![
+            job("/home", "/backups/home"),
+            job("/etc", "/backups"),
+            job("/var/lib/postgres", "/backups2"),
+            job("/srv", "/mnt/btrfs/@/gheesnaps"),
+        ];
+        jobs[0].groups = Some(vec!["daily".to_string()]);
+        jobs[3].groups = Some(vec!["daily".to_string()]);
+
+        let target = Some("/backups".to_string());
+        let selected = Job::select(&jobs, &[], &None, &target, false).unwrap();
+        assert_eq!(selected, vec![&jobs[0], &jobs[1]]);
+
+        let selected = Job::select(&jobs, &["daily".to_string()], &None, &target, false).unwrap();
+        assert_eq!(selected, vec![&jobs[0]]);
+
+        let selected = Job::select(
+            &jobs,
+            &[],
+            &Some("/etc".to_string()),
+            &Some("/backups/".to_string()),
+            false,
+        );
+        assert_eq!(selected.unwrap(), vec![&jobs[1]]);
+    }
+
+    #[test]
+    fn glob_groups() {
+        let db = glob_regex("db-*");
+        assert!(db.is_match("db-postgres"));
+        assert!(db.is_match("db-"));
+        assert!(!db.is_match("mydb-postgres"));
+        assert!(!db.is_match("db"));
+
+        let single = glob_regex("host?.daily");
+        assert!(single.is_match("host1.daily"));
+        assert!(!single.is_match("host12.daily"));
+        assert!(!single.is_match("host1xdaily"));
+
+        let mut jobs = vec![
+            job("/var/lib/postgres", "/mnt/btrfs/@/gheesnaps"),
+            job("/var/lib/mongodb", "/mnt/btrfs/@/gheesnaps"),
+            job("/home", "/mnt/btrfs/@/gheesnaps"),
+        ];
+        jobs[0].groups = Some(vec!["db-postgres".to_string()]);
+        jobs[1].groups = Some(vec!["db-mongo".to_string()]);
+        jobs[2].groups = Some(vec!["home".to_string()]);
+
+        assert_eq!(Job::filter_active_groups(&jobs, &["db-*".to_string()]).len(), 2);
+        assert_eq!(Job::filter_active_groups(&jobs, &["home".to_string()]), vec![&jobs[2]]);
+        assert!(Job::filter_active_groups(&jobs, &["ho".to_string()]).is_empty());
+    }
+
+    #[test]
+    fn expands_variables_in_paths() {
+        let var = |name: &str| match name {
+            "HOME" => Some("/home/alice".to_string()),
+            "SNAPS" => Some("/mnt/snaps".to_string()),
+            _ => None,
+        };
+        assert_eq!(expand_path("~/snapshots", var).unwrap(), "/home/alice/snapshots");
+        assert_eq!(expand_path("~", var).unwrap(), "/home/alice");
+        assert_eq!(expand_path("$HOME/snapshots", var).unwrap(), "/home/alice/snapshots");
+        assert_eq!(expand_path("${SNAPS}-old/home", var).unwrap(), "/mnt/snaps-old/home");
+        assert_eq!(expand_path("/data~1/~", var).unwrap(), "/data~1/~");
+        assert_eq!(expand_path("/srv", var).unwrap(), "/srv");
+        assert!(expand_path("$UNDEFINED/snapshots", var).is_err());
+        assert_eq!(expand_path("~bob/snapshots", var).unwrap(), "~bob/snapshots");
+    }
 }
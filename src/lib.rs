@@ -0,0 +1,140 @@
+//! Automated btrfs snapshots. The `ghee` binary is a command line interface to this library, which plans which
+//! snapshots to create, keep and delete for the configured jobs and executes those plans.
+
+use std::error::Error;
+use std::path::Path;
+
+use log::debug;
+
+pub mod audit;
+pub mod config;
+pub mod discover;
+pub mod duration;
+pub mod error;
+pub mod executed_intent;
+pub mod explain;
+pub mod filesystem;
+pub mod groups;
+pub mod intent;
+pub mod job;
+pub mod list;
+pub mod manifest;
+pub mod metrics;
+pub mod migrate;
+pub mod naming;
+pub mod notify;
+pub mod orphans;
+pub mod output;
+pub mod policies;
+pub mod preview;
+pub mod replicate;
+pub mod retention;
+pub mod scheduling;
+pub mod skipped;
+pub mod state;
+pub mod status;
+pub mod timebins;
+pub mod trash;
+
+pub use crate::config::{Config, ConfigFormat};
+pub use crate::executed_intent::ExecutedIntent;
+pub use crate::intent::{Intent, IntentType, KeepReason};
+pub use crate::job::Job;
+pub use crate::retention::Retention;
+
+/// Loads the configuration at `path` the way the `ghee` binary does: in the given format, else the one of its
+/// extension, with all files it includes. The jobs are rebased onto `root` if one is given, their paths are
+/// canonicalized and jobs whose snapshot names would collide are told apart. The configuration is not validated; see
+/// `Config::validate`.
+pub fn load_config(path: &Path, format: Option<ConfigFormat>, root: Option<&Path>) -> Result<Config, Box<dyn Error>> {
+    let mut config = Config::load(path, format)?;
+    debug!("parsed configuration: {:?}", config);
+
+    if let Some(root) = root {
+        for job in &mut config.jobs {
+            job.rebase(root);
+        }
+        debug!("jobs rebased onto {:?}: {:?}", root, config.jobs);
+    }
+
+    for job in &mut config.jobs {
+        job.canonicalize_paths();
+    }
+    config.set_apart_invalid_jobs(|job| match job.subvolume_name() {
+        Some(_) => Ok(()),
+        None => Err("the subvolume has no directory name to name its snapshots after".to_string()),
+    });
+    config.check_invalid_jobs()?;
+    Job::tag_colliding_names(&mut config.jobs);
+
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use crate::load_config;
+
+    #[test]
+    fn loads_rebased_config() {
+        let dir = std::env::temp_dir().join(format!("ghee-lib-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("ghee.yaml"),
+            "jobs:\n  - {subvolume: /nonexistent/home, target: /nonexistent/snaps, preserve: {min: all}}\n",
+        )
+        .unwrap();
+
+        let config = load_config(&dir.join("ghee.yaml"), None, None).unwrap();
+        assert_eq!(config.jobs[0].subvolume, "/nonexistent/home");
+        let config = load_config(&dir.join("ghee.yaml"), None, Some(Path::new("/mnt/root"))).unwrap();
+        assert_eq!(config.jobs[0].subvolume, "/mnt/root/nonexistent/home");
+        assert_eq!(config.jobs[0].target, "/mnt/root/nonexistent/snaps");
+        assert!(load_config(&dir.join("missing.yaml"), None, None).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_malformed_policy() {
+        let dir = std::env::temp_dir().join(format!("ghee-lib-policy-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let jobs = "jobs:
+  - {subvolume: /nonexistent/home, target: /nonexistent/snaps, preserve: {min: all}}
+  - {subvolume: /nonexistent/srv, target: /nonexistent/snaps, preserve: {retention: 7dd, min: 3}}
+";
+        fs::write(dir.join("strict.yaml"), jobs).unwrap();
+        fs::write(dir.join("lenient.yaml"), format!("skip_invalid_jobs: true\n{}", jobs)).unwrap();
+
+        assert!(load_config(&dir.join("strict.yaml"), None, None).is_err());
+        let config = load_config(&dir.join("lenient.yaml"), None, None).unwrap();
+        assert_eq!(config.jobs.len(), 1);
+        assert_eq!(config.invalid_jobs[0].subvolume, "/nonexistent/srv");
+        let error = &config.invalid_jobs[0].error;
+        assert!(error.contains("invalid preserve retention \"7dd\""));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_nameless_subvolume() {
+        let dir = std::env::temp_dir().join(format!("ghee-lib-nameless-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let jobs = "jobs:
+  - {subvolume: /, target: /nonexistent/snaps, preserve: {min: all}}
+  - {subvolume: /nonexistent/root, target: /nonexistent/snaps, preserve: {min: all}}
+";
+        fs::write(dir.join("strict.yaml"), jobs).unwrap();
+        fs::write(dir.join("lenient.yaml"), format!("skip_invalid_jobs: true\n{}", jobs)).unwrap();
+
+        assert!(load_config(&dir.join("strict.yaml"), None, None).is_err());
+        let config = load_config(&dir.join("lenient.yaml"), None, None).unwrap();
+        assert_eq!(config.jobs.len(), 1);
+        assert_eq!(config.jobs[0].subvolume, "/nonexistent/root");
+        assert_eq!(config.invalid_jobs[0].subvolume, "/");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,127 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use chrono::{DateTime, FixedOffset};
+use log::info;
+use tabled::Tabled;
+
+use crate::filesystem::format_size;
+use crate::intent::Intent;
+use crate::output::TableStyle;
+
+/// An existing snapshot of a job
+#[derive(Debug, PartialEq, Eq, Tabled)]
+pub struct ListedSnapshot {
+    pub subvolume: String,
+    pub name: String,
+    pub timestamp: DateTime<FixedOffset>,
+    #[tabled(skip)]
+    pub size: Option<u64>,
+}
+
+/// An existing snapshot printed along with its size
+#[derive(Tabled)]
+struct SizedSnapshot<'a> {
+    #[tabled(inline)]
+    snapshot: &'a ListedSnapshot,
+    #[tabled(display_with("Self::display_size", args))]
+    size: Option<u64>,
+}
+
+impl SizedSnapshot<'_> {
+    fn display_size(&self) -> String {
+        self.size.map_or_else(|| "n/a".to_string(), format_size)
+    }
+}
+
+impl ListedSnapshot {
+    /// Lists the snapshots found for pruning, newest first
+    pub fn gather(intents: &[Rc<RefCell<Intent>>]) -> Vec<Self> {
+        let mut snapshots = intents
+            .iter()
+            .filter_map(|int| {
+                let int = int.borrow();
+                Some(Self {
+                    subvolume: int.subvolume.clone(),
+                    name: int.name.clone(),
+                    timestamp: int.timestamp().ok()?,
+                    size: int.size,
+                })
+            })
+            .collect::<Vec<_>>();
+        snapshots.sort_by(|a, b| {
+            b.timestamp
+                .cmp(&a.timestamp)
+                .then_with(|| a.subvolume.cmp(&b.subvolume))
+        });
+
+        snapshots
+    }
+
+    /// Prints the snapshots, with `show_usage` including their sizes or `n/a` where they couldn't be determined
+    pub fn print_tabled(snapshots: &[Self], show_usage: bool, style: TableStyle) {
+        if snapshots.is_empty() {
+            info!("No snapshots exist.");
+            return;
+        }
+
+        let table = if show_usage {
+            let sized = snapshots.iter().map(|snapshot| SizedSnapshot {
+                snapshot,
+                size: snapshot.size,
+            });
+            style.render(sized)
+        } else {
+            style.render(snapshots)
+        };
+        info!("{}", table);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use crate::intent::{Intent, IntentType};
+    use crate::job::Job;
+    use crate::list::ListedSnapshot;
+
+    #[test]
+    fn newest_first() {
+        let job: Job =
+            serde_yaml::from_str("{subvolume: /home, target: /mnt/btrfs/@/gheesnaps, preserve: {min: all}}").unwrap();
+        let intents = [
+            "home.2022-01-10T12:00:00Z",
+            "home.2022-01-12T12:00:00Z",
+            "home.2022-01-11T12:00:00+01:00",
+        ]
+        .iter()
+        .map(|name| {
+            Rc::new(RefCell::new(Intent {
+                intent: IntentType::Delete,
+                subvolume: job.subvolume.clone(),
+                target: format!("{}/{}", &job.target, name),
+                name: name.to_string(),
+                notes: String::new(),
+                keep_reason: None,
+                size: None,
+                displaced: false,
+                job: &job,
+            }))
+        })
+        .collect::<Vec<_>>();
+
+        let listed = ListedSnapshot::gather(&intents);
+        let names = listed.iter().map(|s| s.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                "home.2022-01-12T12:00:00Z",
+                "home.2022-01-11T12:00:00+01:00",
+                "home.2022-01-10T12:00:00Z"
+            ]
+        );
+        assert_eq!(listed[1].timestamp.to_rfc3339(), "2022-01-11T12:00:00+01:00");
+    }
+}
@@ -1,36 +1,123 @@
-use std::fs;
-use std::io::Write;
-use std::path::PathBuf;
+use std::cell::RefCell;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 
-use clap::{Parser, Subcommand};
+use chrono::{DateTime, FixedOffset, Local};
+use clap::{CommandFactory, Parser, Subcommand};
+use clap_complete::Shell;
 use clap_verbosity_flag::InfoLevel;
-use log::{debug, info};
-use serde::Deserialize;
-
-use crate::error::ConfigfileExtensionError;
-use crate::executed_intent::ExecutedIntent;
-use crate::intent::Intent;
-use crate::job::Job;
-
-mod duration;
-mod error;
-mod executed_intent;
-mod intent;
-mod job;
-mod policies;
-mod retention;
-mod timebins;
+use libbtrfsutil as btrfs;
+use log::{debug, info, warn};
+
+use ghee::audit::CreationTiming;
+use ghee::config::{Config, ConfigCheck, ConfigFormat};
+use ghee::duration::duration_from_str;
+use ghee::error::{
+    ConfigTestError, EmptyTrashError, ExpectedCountError, FailedIntentsError, FailedMigrationsError,
+    InvalidJobConfigError, InvalidJobsError, RunLockedError, StaleJobsError, StateIssuesError, UnreadableTargetsError,
+};
+use ghee::executed_intent::ExecutedIntent;
+use ghee::explain::Setting;
+use ghee::filesystem::{btrfs_subvolumes, filesystem_errors, is_subvolume, try_lock_file};
+use ghee::groups::GroupSummary;
+use ghee::intent::{CountViolation, Intent, IntentType, PlanEntry, UnreadableTarget};
+use ghee::job::{Job, JobCheck};
+use ghee::list::ListedSnapshot;
+use ghee::manifest::Manifest;
+use ghee::metrics::BinUsage;
+use ghee::migrate::{ExecutedMigration, Migration};
+use ghee::naming::NameScheme;
+use ghee::orphans::Orphan;
+use ghee::output::{OutputFormat, TableStyle};
+use ghee::preview::DecisionChange;
+use ghee::retention::Retention;
+use ghee::skipped::SkippedJob;
+use ghee::state::{StateIssue, StateStore};
+use ghee::status::JobStatus;
+use ghee::trash::TrashedSnapshot;
+use ghee::{discover, load_config, metrics, orphans};
 
 /// Automated btrfs snapshots
 #[derive(Debug, Parser)]
 #[clap(name = "ghee")]
 #[clap(about = "Automated btrfs snapshots", long_about = None)]
 struct Cli {
+    /// Configuration file or directory, or `-` to read the configuration from stdin
     #[clap(short, long, default_value = "/etc/ghee/ghee.yaml")]
     config: String,
+    /// Format of the configuration instead of the one of its file extension, required when reading from stdin
+    #[clap(long, value_enum)]
+    config_format: Option<ConfigFormat>,
     /// Dry run, don't perform any actions
     #[clap(short = 'n', long, default_value = "false")]
     dryrun: bool,
+    /// Treat configuration warnings as errors
+    #[clap(long, default_value = "false")]
+    strict: bool,
+    /// File that keeps information across runs
+    #[clap(long, default_value = "/var/lib/ghee/state")]
+    state_file: PathBuf,
+    /// Resolve the subvolume and target paths of all jobs relative to this directory
+    #[clap(long)]
+    root: Option<PathBuf>,
+    /// Execute the intents of different filesystems concurrently
+    #[clap(long, default_value = "false")]
+    parallel_targets: bool,
+    /// Execute the intents of at most this many filesystems at once, implies `--parallel-targets`
+    #[clap(long)]
+    jobs: Option<usize>,
+    /// While this file exists, no snapshots are deleted
+    #[clap(long, default_value = "/etc/ghee/HOLD")]
+    hold_file: PathBuf,
+    /// File locked while intents are executed, so that runs don't overlap
+    #[clap(long, default_value = "/run/ghee.lock")]
+    lock_file: PathBuf,
+    /// Don't delete any snapshots if the newest one is dated more than an hour after now
+    #[clap(long, default_value = "false")]
+    detect_clock_skew: bool,
+    /// Delete snapshots even from filesystems that report device errors or were remounted read-only
+    #[clap(long, default_value = "false")]
+    ignore_filesystem_errors: bool,
+    /// Also print the exact btrfs operation of each planned intent
+    #[clap(long, default_value = "false")]
+    verbose_plan: bool,
+    /// Print the intents and executed intents as tables in the log or as JSON on stdout
+    #[clap(long, value_enum, default_value = "table")]
+    output: OutputFormat,
+    /// How the lines of the intent tables are drawn
+    #[clap(long, value_enum, default_value = "modern")]
+    table_style: TableStyle,
+    /// Group the printed intents by the retention tier that keeps them
+    #[clap(long, default_value = "false")]
+    group_by_tier: bool,
+    /// Process the snapshots of each job oldest first instead of newest first
+    #[clap(long, default_value = "false")]
+    reverse: bool,
+    /// Stop executing intents after this many seconds and defer the rest to `ghee retry`
+    #[clap(long)]
+    time_budget: Option<u64>,
+    /// Plan as if run at this RFC3339 timestamp instead of now
+    #[clap(long, value_parser = parse_at)]
+    at: Option<DateTime<FixedOffset>>,
+    /// Print durations in ISO 8601, e.g. PT1H or P7D
+    #[clap(long, default_value = "false")]
+    iso_durations: bool,
+    /// Append this label to the names of the snapshots created by this run
+    #[clap(long, value_parser = parse_label)]
+    label: Option<String>,
+    /// Write OpenMetrics gauges of how full the retention bins are and counts of the executed intents to this file
+    #[clap(long)]
+    metrics_file: Option<PathBuf>,
+    /// Only operate on the job of this subvolume, regardless of groups
+    #[clap(long, global = true)]
+    subvolume: Option<String>,
+    /// Only operate on the jobs whose target is this directory or below it
+    #[clap(long, global = true)]
+    target: Option<String>,
     #[clap(subcommand)]
     command: Commands,
     #[clap(flatten)]
@@ -49,6 +136,18 @@ enum Commands {
     /// Prints the actions that would be taken
     #[clap(arg_required_else_help = false)]
     Dryrun {
+        /// Show how much space each snapshot occupies exclusively, from btrfs quotas or by summing its file sizes
+        #[clap(long, default_value = "false")]
+        show_usage: bool,
+        /// Write the planned intents as JSON to this file, to be executed later with `apply`
+        #[clap(long)]
+        plan_out: Option<PathBuf>,
+        /// Only consider the snapshots taken within this duration before now, e.g. "90d"
+        #[clap(long, value_parser = parse_duration)]
+        since: Option<String>,
+        /// Only consider the snapshots taken at least this duration before now, e.g. "30d"
+        #[clap(long, value_parser = parse_duration)]
+        until: Option<String>,
         /// Group filter for configured jobs
         #[clap(value_parser)]
         groups: Vec<String>,
@@ -56,19 +155,371 @@ enum Commands {
     /// Prunes snapshots
     #[clap(arg_required_else_help = false)]
     Prune {
+        /// Only consider the snapshots taken within this duration before now, e.g. "90d"
+        #[clap(long, value_parser = parse_duration)]
+        since: Option<String>,
+        /// Only consider the snapshots taken at least this duration before now, e.g. "30d"
+        #[clap(long, value_parser = parse_duration)]
+        until: Option<String>,
+        /// Group filter for configured jobs
+        #[clap(value_parser)]
+        groups: Vec<String>,
+    },
+    /// Retries the intents that failed in the last run
+    #[clap(arg_required_else_help = false)]
+    Retry {
+        /// Group filter for configured jobs
+        #[clap(value_parser)]
+        groups: Vec<String>,
+    },
+    /// Executes a plan written by `dryrun --plan-out`
+    Apply {
+        /// Plan file to execute
+        #[clap(value_parser)]
+        plan: PathBuf,
+    },
+    /// Reports the age of each job's last successful snapshot
+    #[clap(arg_required_else_help = false)]
+    Status {
+        /// Group filter for configured jobs
+        #[clap(value_parser)]
+        groups: Vec<String>,
+    },
+    /// Inspects and maintains the state file
+    State {
+        #[clap(subcommand)]
+        action: StateAction,
+    },
+    /// Shows what a different retention would decide about the existing snapshots, without executing anything
+    #[clap(arg_required_else_help = true)]
+    Preview {
+        /// Proposed retention, e.g. "48h 14d 8w"
+        #[clap(long, value_parser = parse_retention)]
+        retention: String,
+        /// Group filter for configured jobs
+        #[clap(value_parser)]
+        groups: Vec<String>,
+    },
+    /// Deletes the snapshots that have been in the trash for longer than a grace period
+    #[clap(arg_required_else_help = false)]
+    EmptyTrash {
+        /// Grace period, e.g. "7d"
+        #[clap(long, default_value = "7d", value_parser = parse_duration)]
+        older_than: String,
+        /// Group filter for configured jobs
+        #[clap(value_parser)]
+        groups: Vec<String>,
+    },
+    /// Lists the existing snapshots of each job, newest first
+    #[clap(arg_required_else_help = false)]
+    List {
+        /// Show how much space each snapshot occupies exclusively, from btrfs quotas or by summing its file sizes
+        #[clap(long, default_value = "false")]
+        show_usage: bool,
+        /// Group filter for configured jobs
+        #[clap(value_parser)]
+        groups: Vec<String>,
+    },
+    /// Reports when in the hour the snapshots of each job were created
+    #[clap(arg_required_else_help = false)]
+    Audit {
+        /// Group filter for configured jobs
+        #[clap(value_parser)]
+        groups: Vec<String>,
+    },
+    /// Lists the snapshots in the jobs' snapshot directories that no job prunes anymore
+    #[clap(arg_required_else_help = false)]
+    Orphans {
+        /// Delete the orphaned snapshots
+        #[clap(long, default_value = "false")]
+        delete: bool,
+        /// Group filter for configured jobs
+        #[clap(value_parser)]
+        groups: Vec<String>,
+    },
+    /// Lists the configured groups and how many jobs are in each
+    Groups,
+    /// Loads every configuration file in a directory and reports which fail
+    #[clap(arg_required_else_help = true)]
+    ConfigTest {
+        /// Directory of configuration files
+        #[clap(value_parser)]
+        directory: PathBuf,
+    },
+    /// Checks each job's subvolume, target and preserve settings without changing anything
+    #[clap(arg_required_else_help = false)]
+    Validate {
+        /// Group filter for configured jobs
+        #[clap(value_parser)]
+        groups: Vec<String>,
+    },
+    /// Prints each setting of the selected jobs along with where its value came from
+    #[clap(arg_required_else_help = false)]
+    ExplainConfig {
+        /// Group filter for configured jobs
+        #[clap(value_parser)]
+        groups: Vec<String>,
+    },
+    /// Prints a starter configuration with a job for each subvolume of the mounted btrfs filesystems
+    Discover {
+        /// Glob pattern of subvolumes to leave out, matched against their mount path and their path in the filesystem
+        #[clap(long)]
+        exclude_subvolumes: Vec<String>,
+    },
+    /// Prints a completion script for the shell
+    #[clap(hide = true)]
+    Completions {
+        #[clap(value_enum)]
+        shell: Shell,
+    },
+    /// Renames existing snapshots from one naming scheme to another
+    #[clap(arg_required_else_help = true)]
+    MigrateNames {
+        /// Current name template, e.g. "{subvol}.{timestamp}"
+        #[clap(long)]
+        from: String,
+        /// New name template, e.g. "{subvol}-{timestamp:%Y%m%d-%H%M%S%z}"
+        #[clap(long)]
+        to: String,
         /// Group filter for configured jobs
         #[clap(value_parser)]
         groups: Vec<String>,
     },
 }
 
-#[derive(Debug, Deserialize)]
-struct Config {
-    jobs: Vec<Job>,
+#[derive(Debug, Subcommand)]
+enum StateAction {
+    /// Prints the recorded state as JSON
+    Show,
+    /// Reports entries of jobs that are no longer configured
+    Verify,
+    /// Removes the entries of jobs that are no longer configured
+    Compact,
+}
+
+fn parse_at(s: &str) -> Result<DateTime<FixedOffset>, String> {
+    DateTime::parse_from_rfc3339(s).map_err(|e| format!("expected an RFC3339 timestamp: {}", e))
+}
+
+fn parse_retention(s: &str) -> Result<String, String> {
+    s.parse::<Retention>().map(|_| s.to_string()).map_err(|e| e.to_string())
+}
+
+fn parse_duration(s: &str) -> Result<String, String> {
+    duration_from_str(s).map(|_| s.to_string()).map_err(|e| e.to_string())
+}
+
+fn parse_label(s: &str) -> Result<String, String> {
+    let valid = !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if valid {
+        Ok(s.to_string())
+    } else {
+        Err("labels may only contain letters, digits, - and _".to_string())
+    }
+}
+
+fn print_intents(
+    intents: &[Rc<RefCell<Intent>>],
+    output: OutputFormat,
+    style: TableStyle,
+    group_by_tier: bool,
+    verbose_plan: bool,
+) {
+    match output {
+        OutputFormat::Json => (),
+        OutputFormat::Table if group_by_tier => Intent::print_grouped(intents, style),
+        OutputFormat::Table => Intent::print_tabled(intents, style),
+    }
+    if verbose_plan {
+        Intent::print_operations(intents);
+    }
+}
+
+fn print_executed(executed_intents: &[ExecutedIntent], output: OutputFormat, style: TableStyle) {
+    if output == OutputFormat::Table {
+        ExecutedIntent::print_tabled(executed_intents, style);
+    }
+}
+
+/// With `--output json`, prints the intents, the executed intents if they were executed, and the skipped jobs as a
+/// single JSON object on stdout
+fn print_json(
+    intents: &[Rc<RefCell<Intent>>],
+    executed_intents: Option<&[ExecutedIntent]>,
+    skipped: Option<&[SkippedJob]>,
+    output: OutputFormat,
+) -> Result<(), Box<dyn Error>> {
+    if output == OutputFormat::Json {
+        println!("{}", Intent::report_json(intents, executed_intents, skipped)?);
+    }
+
+    Ok(())
+}
+
+/// Writes the bin usages and the counts of the executed intents to the metrics file, if one is configured. Failing to
+/// do so doesn't fail the run.
+fn write_metrics(metrics_file: &Option<PathBuf>, usages: &[BinUsage], executed_intents: &[ExecutedIntent]) {
+    if let Some(metrics_file) = metrics_file {
+        let local_now = Local::now();
+        let finished = local_now.with_timezone(local_now.offset());
+        if let Err(e) = metrics::write(metrics_file, usages, executed_intents, &finished) {
+            warn!("Unable to write metrics file {:?}! Error: {}", metrics_file, e);
+        }
+    }
+}
+
+/// Persists the outcome of executed intents, and the deferred intents for a later retry. Failing to do so doesn't
+/// fail the run.
+fn record_state(
+    state_file: &Path,
+    executed_intents: &mut [ExecutedIntent],
+    deferred: &[Rc<RefCell<Intent>>],
+    jobs: &[&Job],
+) {
+    let mut state = StateStore::load(state_file);
+    state.record_executed(executed_intents, jobs);
+    state.record_deferred(deferred.iter().map(|i| ExecutedIntent::new(&i.borrow(), false)));
+    if let Err(e) = state.save() {
+        warn!("Unable to save state file {:?}! Error: {}", state_file, e);
+    }
+}
+
+/// Takes the lock that keeps runs from executing intents at the same time. Dry runs don't need it.
+fn lock_run(lock_file: &Path, dryrun: bool) -> Result<Option<File>, Box<dyn Error>> {
+    if dryrun {
+        return Ok(None);
+    }
+
+    match try_lock_file(lock_file)? {
+        Some(lock) => Ok(Some(lock)),
+        None => Err(Box::new(RunLockedError)),
+    }
+}
+
+/// All jobs that didn't create a snapshot or weren't pruned, along with the reason
+fn gather_skipped(
+    mut skipped: Vec<SkippedJob>,
+    unreadable: &[UnreadableTarget],
+    intents: &[Rc<RefCell<Intent>>],
+) -> Vec<SkippedJob> {
+    skipped.extend(SkippedJob::unreadable(unreadable));
+    skipped.extend(SkippedJob::held(intents));
+    skipped
+}
+
+/// Reports the targets that couldn't be read for pruning. With `strict`, they fail the run.
+fn check_unreadable(unreadable: &[UnreadableTarget], strict: bool, style: TableStyle) -> Result<(), Box<dyn Error>> {
+    UnreadableTarget::report(unreadable, style);
+    if strict && !unreadable.is_empty() {
+        return Err(Box::new(UnreadableTargetsError));
+    }
+
+    Ok(())
+}
+
+/// Reports the jobs whose plan leaves them outside their `expected_count`. With `strict`, they fail the run.
+fn check_expected_counts(
+    intents: &[Rc<RefCell<Intent>>],
+    jobs: &[&Job],
+    strict: bool,
+    style: TableStyle,
+) -> Result<(), Box<dyn Error>> {
+    let violations = CountViolation::check(intents, jobs);
+    CountViolation::report(&violations, style);
+    if strict && !violations.is_empty() {
+        return Err(Box::new(ExpectedCountError));
+    }
+
+    Ok(())
+}
+
+/// Reports the intents that were not executed because the time budget ran out
+fn report_deferred(deferred: &[Rc<RefCell<Intent>>], style: TableStyle) {
+    if deferred.is_empty() {
+        return;
+    }
+
+    warn!(
+        "The time budget ran out, {} intents are deferred until `ghee retry`:",
+        deferred.len()
+    );
+    Intent::print_tabled(deferred, style);
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// The settings shared by the subcommands that execute intents
+#[derive(Clone, Copy)]
+struct IntentRun<'a> {
+    config: &'a Config,
+    now: &'a DateTime<FixedOffset>,
+    dryrun: bool,
+    hold_file: &'a Path,
+    detect_clock_skew: bool,
+    ignore_filesystem_errors: bool,
+    state_file: &'a Path,
+    metrics_file: &'a Option<PathBuf>,
+    output: OutputFormat,
+    table_style: TableStyle,
+    group_by_tier: bool,
+    verbose_plan: bool,
+    parallel_targets: Option<usize>,
+    deadline: Option<Instant>,
+}
+
+impl IntentRun<'_> {
+    /// Holds the deletions that must not happen, prints the gathered intents and, unless it's a dry run, executes them,
+    /// records their outcome for the jobs and notifies about failures. Given the bin usages, the metrics and the
+    /// manifests are written as well. Returns the executed intents, or None for a dry run.
+    fn run_intents(
+        &self,
+        intents: &[Rc<RefCell<Intent>>],
+        jobs: &[&Job],
+        usages: Option<&[BinUsage]>,
+    ) -> Option<Vec<ExecutedIntent>> {
+        Intent::hold_deletes(intents, self.hold_file);
+        if self.detect_clock_skew {
+            Intent::hold_deletes_on_clock_skew(intents, self.now);
+        }
+        if !self.ignore_filesystem_errors {
+            Intent::hold_deletes_on_filesystem_errors(intents, filesystem_errors);
+        }
+
+        debug!("raw intents: {:?}", intents);
+        print_intents(
+            intents,
+            self.output,
+            self.table_style,
+            self.group_by_tier,
+            self.verbose_plan,
+        );
+        Intent::print_summary(intents);
+
+        if self.dryrun {
+            Intent::print_created_targets(intents);
+            return None;
+        }
+
+        let (mut executed_intents, deferred) = Intent::execute_all(intents, self.parallel_targets, self.deadline);
+        record_state(self.state_file, &mut executed_intents, &deferred, jobs);
+        if let Some(usages) = usages {
+            write_metrics(self.metrics_file, usages, &executed_intents);
+            Manifest::write_all(&self.config.jobs, jobs, self.now);
+        }
+        print_executed(&executed_intents, self.output, self.table_style);
+        info!("{}", ExecutedIntent::summary(&executed_intents));
+        if let Some(notify) = &self.config.notify {
+            notify.failures(&executed_intents);
+        }
+        report_deferred(&deferred, self.table_style);
+
+        Some(executed_intents)
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
     let args: Cli = Cli::parse();
+    let deadline = args
+        .time_budget
+        .map(|seconds| Instant::now() + Duration::from_secs(seconds));
     debug!("program arguments: {:?}", args);
 
     env_logger::Builder::new()
@@ -82,73 +533,391 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .filter_level(args.verbose.log_level_filter())
         .init();
 
-    let config = fs::read_to_string(&args.config)?;
-    debug!("configuration content:\n{}", config);
-
-    let filepath = PathBuf::from(&args.config);
-    let fileextension = filepath.extension().ok_or(ConfigfileExtensionError)?;
-    let filetype = fileextension.to_str().ok_or(ConfigfileExtensionError)?;
-    let config: Config = match filetype {
-        "yaml" | "yml" => serde_yaml::from_str(&config)?,
-        "json" => serde_json::from_str(&config)?,
-        "toml" => toml::from_str(&config)?,
-        &_ => return Err(Box::new(ConfigfileExtensionError)),
+    if let Commands::Completions { shell } = args.command {
+        clap_complete::generate(shell, &mut Cli::command(), "ghee", &mut io::stdout());
+        return Ok(());
+    }
+
+    if let Commands::ConfigTest { directory } = &args.command {
+        let checks = ConfigCheck::gather(directory)?;
+        ConfigCheck::print_tabled(&checks);
+        if checks.iter().any(|c| c.failed()) {
+            return Err(Box::new(ConfigTestError));
+        }
+        return Ok(());
+    }
+
+    if let Commands::Discover { exclude_subvolumes } = &args.command {
+        let found = discover::without_excluded(btrfs_subvolumes(), exclude_subvolumes);
+        print!("{}", discover::render(&found)?);
+        return Ok(());
+    }
+
+    let config = load_config(Path::new(&args.config), args.config_format, args.root.as_deref())?;
+
+    if let Some(scheduling) = &config.scheduling {
+        if let Err(e) = scheduling.apply() {
+            warn!("Unable to apply scheduling {:?}! Error: {}", scheduling, e);
+        }
+    }
+
+    config.validate(args.strict)?;
+
+    let now = args.at.unwrap_or_else(|| {
+        let local_now = Local::now();
+        local_now.with_timezone(local_now.offset())
+    });
+    if let Some(at) = &args.at {
+        info!("Planning as of {}.", at);
+    }
+    let parallel_targets = (args.parallel_targets || args.jobs.is_some()).then(|| config.max_parallel(args.jobs));
+    let intent_run = IntentRun {
+        config: &config,
+        now: &now,
+        dryrun: args.dryrun,
+        hold_file: &args.hold_file,
+        detect_clock_skew: args.detect_clock_skew,
+        ignore_filesystem_errors: args.ignore_filesystem_errors,
+        state_file: &args.state_file,
+        metrics_file: &args.metrics_file,
+        output: args.output,
+        table_style: args.table_style,
+        group_by_tier: args.group_by_tier,
+        verbose_plan: args.verbose_plan,
+        parallel_targets,
+        deadline,
     };
 
-    debug!("parsed configuration: {:?}", config);
+    // only the commands that would have acted on the jobs left out with `skip_invalid_jobs` fail because of them
+    let acts_on_jobs = matches!(
+        args.command,
+        Commands::Run { .. }
+            | Commands::Prune { .. }
+            | Commands::Dryrun { .. }
+            | Commands::Retry { .. }
+            | Commands::Apply { .. }
+    );
 
     match args.command {
-        Commands::Dryrun { groups } => {
+        Commands::Dryrun {
+            show_usage,
+            plan_out,
+            since,
+            until,
+            groups,
+        } => {
             info!("Will perform a dry run without executing the intents.");
             debug!("Will dry run with groups: {:?}", groups);
 
-            let filtered_jobs = Job::filter_active_groups(&config.jobs, &groups);
-            debug!("jobs filtered using active groups: {:?}", filtered_jobs);
-
-            let mut intents = Intent::gather_create_intents(&filtered_jobs[..]);
-            intents.append(Intent::gather_delete_intents(&filtered_jobs[..]).as_mut());
-            Intent::delete_to_keep_intents(&mut intents, &filtered_jobs[..]);
+            let filtered_jobs = Job::select(&config.jobs, &groups, &args.subvolume, &args.target, args.strict)?;
+            let mut skipped = SkippedJob::invalid(&config.invalid_jobs, &[IntentType::Create, IntentType::Delete]);
+            skipped.extend(SkippedJob::disabled(
+                &filtered_jobs,
+                &[IntentType::Create, IntentType::Delete],
+            ));
+            let filtered_jobs = Job::filter_enabled(filtered_jobs);
+            debug!("jobs filtered using active groups or subvolume: {:?}", filtered_jobs);
 
-            debug!("raw intents: {:?}", intents);
-            Intent::print_tabled(&intents);
+            let (create_jobs, mut when) = SkippedJob::partition_when(&filtered_jobs, true);
+            skipped.append(&mut when);
+            let (mut intents, mut not_created) =
+                Intent::gather_create_intents(&create_jobs[..], &now, args.label.as_deref());
+            skipped.append(&mut not_created);
+            Intent::gather_send_intents(&mut intents);
+            let (mut delete_intents, unreadable) = Intent::gather_delete_intents(&filtered_jobs[..], show_usage);
+            Intent::retain_window(&mut delete_intents, &now, since.as_deref(), until.as_deref())?;
+            intents.append(&mut delete_intents);
+            Intent::delete_to_keep_intents(&mut intents, &filtered_jobs[..], &now);
+            Intent::sort_for_execution(&mut intents, args.reverse);
+            Intent::apply_plan_hooks(&intents, &filtered_jobs, true);
+            let dryrun = IntentRun {
+                dryrun: true,
+                ..intent_run
+            };
+            dryrun.run_intents(&intents, &filtered_jobs, None);
+            let skipped = gather_skipped(skipped, &unreadable, &intents);
+            print_json(&intents, None, Some(&skipped), args.output)?;
+            SkippedJob::report(&skipped, args.table_style);
+            if let Some(plan_out) = plan_out {
+                fs::write(&plan_out, Intent::to_json(&intents)?)?;
+                info!("Wrote the plan to {}.", plan_out.display());
+            }
+            check_unreadable(&unreadable, args.strict, args.table_style)?;
+            check_expected_counts(&intents, &filtered_jobs, args.strict, args.table_style)?;
         }
-        Commands::Prune { groups } => {
+        Commands::Prune { since, until, groups } => {
             debug!("Will prune with groups: {:?}", groups);
+            let _lock = lock_run(&args.lock_file, args.dryrun)?;
             info!("Actions that will be performed:");
 
-            let filtered_jobs = Job::filter_active_groups(&config.jobs, &groups);
-            debug!("jobs filtered using active groups: {:?}", filtered_jobs);
+            let filtered_jobs = Job::select(&config.jobs, &groups, &args.subvolume, &args.target, args.strict)?;
+            let mut skipped = SkippedJob::invalid(&config.invalid_jobs, &[IntentType::Delete]);
+            skipped.extend(SkippedJob::disabled(&filtered_jobs, &[IntentType::Delete]));
+            let filtered_jobs = Job::filter_enabled(filtered_jobs);
+            debug!("jobs filtered using active groups or subvolume: {:?}", filtered_jobs);
 
-            let mut intents = Intent::gather_delete_intents(&filtered_jobs[..]);
-            Intent::delete_to_keep_intents(&mut intents, &filtered_jobs[..]);
-
-            debug!("raw intents: {:?}", intents);
-            Intent::print_tabled(&intents);
-
-            if !args.dryrun {
-                let executed_intents = intents.into_iter().map(|i| i.borrow().execute()).collect::<Vec<_>>();
-                ExecutedIntent::print_tabled(&executed_intents);
+            let (mut intents, unreadable) = Intent::gather_delete_intents(&filtered_jobs[..], false);
+            Intent::retain_window(&mut intents, &now, since.as_deref(), until.as_deref())?;
+            let usages = Intent::delete_to_keep_intents(&mut intents, &filtered_jobs[..], &now);
+            Intent::sort_for_execution(&mut intents, args.reverse);
+            Intent::apply_plan_hooks(&intents, &filtered_jobs, args.dryrun);
+            let executed = intent_run.run_intents(&intents, &filtered_jobs, Some(&usages));
+            let skipped = gather_skipped(skipped, &unreadable, &intents);
+            print_json(&intents, executed.as_deref(), Some(&skipped), args.output)?;
+            SkippedJob::report(&skipped, args.table_style);
+            let fails_run = executed.is_some_and(|executed| ExecutedIntent::fails_run(&executed, &filtered_jobs));
+            check_unreadable(&unreadable, args.strict, args.table_style)?;
+            check_expected_counts(&intents, &filtered_jobs, args.strict, args.table_style)?;
+            if fails_run {
+                return Err(Box::new(FailedIntentsError));
             }
         }
         Commands::Run { groups } => {
             debug!("Will run with groups: {:?}", groups);
+            let _lock = lock_run(&args.lock_file, args.dryrun)?;
             info!("Actions that will be performed:");
 
-            let filtered_jobs = Job::filter_active_groups(&config.jobs, &groups);
-            debug!("jobs filtered using active groups: {:?}", filtered_jobs);
+            let filtered_jobs = Job::select(&config.jobs, &groups, &args.subvolume, &args.target, args.strict)?;
+            let mut skipped = SkippedJob::invalid(&config.invalid_jobs, &[IntentType::Create, IntentType::Delete]);
+            skipped.extend(SkippedJob::disabled(
+                &filtered_jobs,
+                &[IntentType::Create, IntentType::Delete],
+            ));
+            let filtered_jobs = Job::filter_enabled(filtered_jobs);
+            debug!("jobs filtered using active groups or subvolume: {:?}", filtered_jobs);
+
+            let (create_jobs, mut when) = SkippedJob::partition_when(&filtered_jobs, args.dryrun);
+            skipped.append(&mut when);
+            let (mut intents, mut not_created) =
+                Intent::gather_create_intents(&create_jobs[..], &now, args.label.as_deref());
+            skipped.append(&mut not_created);
+            Intent::gather_send_intents(&mut intents);
+            let (mut delete_intents, unreadable) = Intent::gather_delete_intents(&filtered_jobs[..], false);
+            intents.append(&mut delete_intents);
+            let usages = Intent::delete_to_keep_intents(&mut intents, &filtered_jobs[..], &now);
+            Intent::sort_for_execution(&mut intents, args.reverse);
+            Intent::apply_plan_hooks(&intents, &filtered_jobs, args.dryrun);
+            let executed = intent_run.run_intents(&intents, &filtered_jobs, Some(&usages));
+            let skipped = gather_skipped(skipped, &unreadable, &intents);
+            print_json(&intents, executed.as_deref(), Some(&skipped), args.output)?;
+            SkippedJob::report(&skipped, args.table_style);
+            let fails_run = executed.is_some_and(|executed| ExecutedIntent::fails_run(&executed, &filtered_jobs));
+            check_unreadable(&unreadable, args.strict, args.table_style)?;
+            check_expected_counts(&intents, &filtered_jobs, args.strict, args.table_style)?;
+            if fails_run {
+                return Err(Box::new(FailedIntentsError));
+            }
+        }
+        Commands::Retry { groups } => {
+            debug!("Will retry failed intents with groups: {:?}", groups);
+            let _lock = lock_run(&args.lock_file, args.dryrun)?;
+            info!("Actions that will be retried:");
+
+            let filtered_jobs = Job::select(&config.jobs, &groups, &args.subvolume, &args.target, args.strict)?;
+            let filtered_jobs = Job::filter_enabled(filtered_jobs);
+            debug!("jobs filtered using active groups or subvolume: {:?}", filtered_jobs);
+
+            let state = StateStore::load(&args.state_file);
+            let intents = Intent::gather_retry_intents(state.failed(), &filtered_jobs[..], &now);
+            let executed = intent_run.run_intents(&intents, &filtered_jobs, None);
+            print_json(&intents, executed.as_deref(), None, args.output)?;
+            if executed.is_some_and(|executed| ExecutedIntent::fails_run(&executed, &filtered_jobs)) {
+                return Err(Box::new(FailedIntentsError));
+            }
+        }
+        Commands::Apply { plan } => {
+            debug!("Will apply the plan in {}", plan.display());
+            let _lock = lock_run(&args.lock_file, args.dryrun)?;
+            let entries: Vec<PlanEntry> = serde_json::from_str(&fs::read_to_string(&plan)?)?;
+            info!("Actions of the plan that will be performed:");
+
+            let jobs = Job::filter_enabled(config.jobs.iter().collect());
+            let intents = Intent::gather_plan_intents(&entries, &jobs[..]);
+            let executed = intent_run.run_intents(&intents, &jobs, None);
+            print_json(&intents, executed.as_deref(), None, args.output)?;
+            if executed.is_some_and(|executed| ExecutedIntent::fails_run(&executed, &jobs)) {
+                return Err(Box::new(FailedIntentsError));
+            }
+        }
+        Commands::Status { groups } => {
+            debug!("Will report status with groups: {:?}", groups);
+
+            let filtered_jobs = Job::select(&config.jobs, &groups, &args.subvolume, &args.target, args.strict)?;
+            debug!("jobs filtered using active groups or subvolume: {:?}", filtered_jobs);
+
+            let state = StateStore::load(&args.state_file);
+            let statuses = JobStatus::gather(&filtered_jobs, &state, args.iso_durations);
+            JobStatus::print_tabled(&statuses, args.table_style);
+
+            if statuses.iter().any(|s| s.stale) {
+                return Err(Box::new(StaleJobsError));
+            }
+        }
+        Commands::State { action } => {
+            let mut state = StateStore::load(&args.state_file);
+            match action {
+                StateAction::Show => println!("{}", state.to_json()?),
+                StateAction::Verify => {
+                    let issues = state.verify(&config.jobs);
+                    StateIssue::print_tabled(&issues);
+                    if !issues.is_empty() {
+                        return Err(Box::new(StateIssuesError));
+                    }
+                }
+                StateAction::Compact => {
+                    let removed = state.compact(&config.jobs);
+                    info!("Removing {} entries of jobs that are no longer configured.", removed);
+                    if !args.dryrun && removed > 0 {
+                        state.save()?;
+                    }
+                }
+            }
+        }
+        Commands::Preview { retention, groups } => {
+            debug!("Will preview retention {} with groups: {:?}", retention, groups);
+
+            let filtered_jobs = Job::select(&config.jobs, &groups, &args.subvolume, &args.target, args.strict)?;
+            debug!("jobs filtered using active groups or subvolume: {:?}", filtered_jobs);
+
+            let (changes, total, unreadable) = DecisionChange::gather(&filtered_jobs, &retention, &now);
+            DecisionChange::print_tabled(&changes, total);
+            check_unreadable(&unreadable, args.strict, args.table_style)?;
+        }
+        Commands::EmptyTrash { older_than, groups } => {
+            debug!(
+                "Will empty the trash of snapshots older than {} with groups: {:?}",
+                older_than, groups
+            );
+            let _lock = lock_run(&args.lock_file, args.dryrun)?;
+            info!("Trashed snapshots that will be deleted:");
 
-            let mut intents = Intent::gather_create_intents(&filtered_jobs[..]);
-            intents.append(Intent::gather_delete_intents(&filtered_jobs[..]).as_mut());
-            Intent::delete_to_keep_intents(&mut intents, &filtered_jobs[..]);
+            let filtered_jobs = Job::select(&config.jobs, &groups, &args.subvolume, &args.target, args.strict)?;
+            debug!("jobs filtered using active groups or subvolume: {:?}", filtered_jobs);
 
-            debug!("raw intents: {:?}", intents);
-            Intent::print_tabled(&intents);
+            let trashed = TrashedSnapshot::gather(&filtered_jobs);
+            let expired = TrashedSnapshot::expired(trashed, duration_from_str(&older_than)?, &now);
+            TrashedSnapshot::print_tabled(&expired);
 
             if !args.dryrun {
-                let executed_intents = intents.into_iter().map(|i| i.borrow().execute()).collect::<Vec<_>>();
-                ExecutedIntent::print_tabled(&executed_intents);
+                let failed = TrashedSnapshot::empty(&expired, |path| {
+                    Ok(btrfs::delete_subvolume(path, btrfs::DeleteSubvolumeFlags::empty())?)
+                });
+                info!(
+                    "Deleted {} of {} trashed snapshots.",
+                    expired.len() - failed,
+                    expired.len()
+                );
+                if failed > 0 {
+                    return Err(Box::new(EmptyTrashError));
+                }
             }
         }
+        Commands::List { show_usage, groups } => {
+            debug!("Will list snapshots with groups: {:?}", groups);
+
+            let filtered_jobs = Job::select(&config.jobs, &groups, &args.subvolume, &args.target, args.strict)?;
+            debug!("jobs filtered using active groups or subvolume: {:?}", filtered_jobs);
+
+            let (intents, unreadable) = Intent::gather_delete_intents(&filtered_jobs[..], show_usage);
+            ListedSnapshot::print_tabled(&ListedSnapshot::gather(&intents), show_usage, args.table_style);
+            check_unreadable(&unreadable, args.strict, args.table_style)?;
+        }
+        Commands::Audit { groups } => {
+            debug!("Will audit with groups: {:?}", groups);
+
+            let filtered_jobs = Job::select(&config.jobs, &groups, &args.subvolume, &args.target, args.strict)?;
+            debug!("jobs filtered using active groups or subvolume: {:?}", filtered_jobs);
+
+            let (intents, unreadable) = Intent::gather_delete_intents(&filtered_jobs[..], false);
+            let timings = CreationTiming::gather(&filtered_jobs, &intents);
+            CreationTiming::print_tabled(&timings);
+
+            for timing in timings.iter().filter(|t| !t.aligned) {
+                warn!(
+                    "snapshots of {} were created from {} to {} past the hour, across the full hour! Snapshots of the same schedule fall into different hourly and daily bins, so the snapshot kept for a bin may change unexpectedly",
+                    &timing.subvolume, &timing.earliest, &timing.latest
+                );
+            }
+            check_unreadable(&unreadable, args.strict, args.table_style)?;
+        }
+        Commands::ConfigTest { .. } => unreachable!("configuration tests don't load the configuration"),
+        Commands::Completions { .. } => unreachable!("completions don't load the configuration"),
+        Commands::Discover { .. } => unreachable!("discovery doesn't load the configuration"),
+        Commands::ExplainConfig { groups } => {
+            let filtered_jobs = Job::select(&config.jobs, &groups, &args.subvolume, &args.target, args.strict)?;
+            debug!("jobs filtered using active groups or subvolume: {:?}", filtered_jobs);
+
+            for job in filtered_jobs {
+                Setting::print_tabled(job, &Setting::gather(job));
+            }
+        }
+        Commands::Validate { groups } => {
+            let filtered_jobs = Job::select(&config.jobs, &groups, &args.subvolume, &args.target, args.strict)?;
+            debug!("jobs filtered using active groups or subvolume: {:?}", filtered_jobs);
+
+            let checks = JobCheck::gather(&filtered_jobs, &now, is_subvolume);
+            JobCheck::print_tabled(&checks);
+            if checks.iter().any(|c| c.failed()) {
+                return Err(Box::new(InvalidJobsError));
+            }
+        }
+        Commands::Orphans { delete, groups } => {
+            debug!("Will look for orphaned snapshots with groups: {:?}", groups);
+
+            let filtered_jobs = Job::select(&config.jobs, &groups, &args.subvolume, &args.target, args.strict)?;
+            debug!("jobs filtered using active groups or subvolume: {:?}", filtered_jobs);
+
+            let unloaded = config
+                .invalid_jobs
+                .iter()
+                .chain(&config.unexpanded_jobs)
+                .collect::<Vec<_>>();
+            let orphans = Orphan::gather(&filtered_jobs, &config.jobs, &unloaded, orphans::subvolume_exists);
+            Orphan::print_tabled(&orphans);
+
+            if delete && !orphans.is_empty() {
+                let _lock = lock_run(&args.lock_file, args.dryrun)?;
+                info!("Actions that will be performed:");
+
+                let intents = Orphan::delete_intents(&orphans);
+                let orphan_jobs = orphans.iter().map(|o| &o.job).collect::<Vec<_>>();
+                let executed = intent_run.run_intents(&intents, &orphan_jobs, None);
+                print_json(&intents, executed.as_deref(), None, args.output)?;
+                if executed.is_some_and(|executed| executed.iter().any(|e| !e.success)) {
+                    return Err(Box::new(FailedIntentsError));
+                }
+            }
+        }
+        Commands::Groups => {
+            let summaries = GroupSummary::gather(&config.jobs);
+            GroupSummary::print_tabled(&summaries);
+        }
+        Commands::MigrateNames { from, to, groups } => {
+            debug!("Will migrate names from {} to {} with groups: {:?}", from, to, groups);
+            let from = NameScheme::from_template(&from)?;
+            let to = NameScheme::from_template(&to)?;
+            let _lock = lock_run(&args.lock_file, args.dryrun)?;
+            info!("Renames that will be performed:");
+
+            let filtered_jobs = Job::select(&config.jobs, &groups, &args.subvolume, &args.target, args.strict)?;
+            debug!("jobs filtered using active groups or subvolume: {:?}", filtered_jobs);
+
+            let migrations = Migration::gather(&filtered_jobs, &from, &to);
+            Migration::print_tabled(&migrations);
+
+            if !args.dryrun {
+                let executed_migrations = migrations.iter().map(|m| m.execute()).collect::<Vec<_>>();
+                ExecutedMigration::print_tabled(&executed_migrations);
+                if executed_migrations.iter().any(|m| !m.success) {
+                    return Err(Box::new(FailedMigrationsError));
+                }
+            }
+        }
+    }
+
+    // the jobs left out with `skip_invalid_jobs` still fail the run, after the others have run
+    if acts_on_jobs && !config.invalid_jobs.is_empty() {
+        return Err(Box::new(InvalidJobConfigError));
     }
 
     Ok(())
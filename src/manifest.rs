@@ -0,0 +1,200 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use chrono::{DateTime, FixedOffset};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::filesystem::directory_size;
+use crate::intent::Intent;
+use crate::job::Job;
+
+/// Version of the manifest format, increased whenever its layout changes
+const MANIFEST_VERSION: u32 = 1;
+
+/// Name of the manifest file in each target directory
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// An index of the snapshots in a target, readable without the configuration that created them
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: u32,
+    pub generated: DateTime<FixedOffset>,
+    pub snapshots: Vec<ManifestEntry>,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub name: String,
+    /// Location of the snapshot relative to the target directory
+    pub path: String,
+    pub timestamp: DateTime<FixedOffset>,
+    /// The subvolume the snapshot was taken of
+    pub source: String,
+    /// Apparent size of the files in the snapshot in bytes, counting data shared with other snapshots in each of them
+    pub size: Option<u64>,
+}
+
+impl Manifest {
+    /// Lists the existing snapshots of the jobs in their manifests, one per target directory, oldest first
+    pub fn gather(jobs: &[&Job], now: &DateTime<FixedOffset>) -> BTreeMap<String, Self> {
+        let mut manifests: BTreeMap<String, Self> = BTreeMap::new();
+        for &job in jobs {
+            let (intents, _unreadable) = Intent::gather_delete_intents(&[job], false);
+            let manifest = manifests.entry(job.target.clone()).or_insert_with(|| Self {
+                version: MANIFEST_VERSION,
+                generated: *now,
+                snapshots: Vec::new(),
+            });
+
+            for int in intents {
+                let int = int.borrow();
+                let timestamp = match int.timestamp() {
+                    Ok(timestamp) => timestamp,
+                    Err(e) => {
+                        warn!("Leaving {} out of the manifest! Error: {}", &int.target, e);
+                        continue;
+                    }
+                };
+                let path = Path::new(&int.target);
+                let size = match directory_size(path) {
+                    Ok(size) => Some(size),
+                    Err(e) => {
+                        warn!("Unable to determine the size of {}! Error: {}", &int.target, e);
+                        None
+                    }
+                };
+                manifest.snapshots.push(ManifestEntry {
+                    name: int.name.clone(),
+                    path: path
+                        .strip_prefix(&job.target)
+                        .unwrap_or(path)
+                        .to_string_lossy()
+                        .to_string(),
+                    timestamp,
+                    source: int.subvolume.clone(),
+                    size,
+                });
+            }
+        }
+        for manifest in manifests.values_mut() {
+            manifest
+                .snapshots
+                .sort_by(|a, b| (a.timestamp, &a.path).cmp(&(b.timestamp, &b.path)));
+        }
+
+        manifests
+    }
+
+    /// Writes the manifest into the target directory, replacing it at once so it is never found half written
+    pub fn write(&self, target: &Path) -> Result<(), Box<dyn Error>> {
+        let path = target.join(MANIFEST_FILE);
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(self)?)?;
+        fs::rename(&tmp, &path)?;
+
+        Ok(())
+    }
+
+    /// Writes the manifests of the targets of `run_jobs` that have a job with `write_manifest`. All jobs of `jobs`
+    /// sharing such a target are listed, not just those that ran. Failing to do so doesn't fail the run.
+    pub fn write_all(jobs: &[Job], run_jobs: &[&Job], now: &DateTime<FixedOffset>) {
+        let manifest_jobs = jobs
+            .iter()
+            .filter(|j| j.write_manifest.unwrap_or(false))
+            .filter(|j| run_jobs.iter().any(|r| r.target == j.target))
+            .collect::<Vec<_>>();
+
+        for (target, manifest) in Self::gather(&manifest_jobs, now) {
+            if let Err(e) = manifest.write(Path::new(&target)) {
+                warn!("Unable to write manifest of {}! Error: {}", target, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use chrono::{FixedOffset, TimeZone};
+
+    use crate::job::Job;
+    use crate::manifest::{Manifest, ManifestEntry, MANIFEST_FILE, MANIFEST_VERSION};
+
+    #[test]
+    fn lists_snapshots() {
+        let dir = std::env::temp_dir().join(format!("ghee-manifest-test-{}", std::process::id()));
+        for (snapshot, content) in [
+            ("home.2022-01-20T12:00:00Z", "hello"),
+            ("home.2022-01-19T12:00:00Z", "hi"),
+            ("srv/srv.2022-01-19T18:00:00Z", ""),
+        ] {
+            fs::create_dir_all(dir.join(snapshot).join("sub")).unwrap();
+            fs::write(dir.join(snapshot).join("sub/file"), content).unwrap();
+        }
+        fs::create_dir_all(dir.join("unrelated")).unwrap();
+
+        let job_of = |subvolume: &str, layout: &str| -> Job {
+            serde_yaml::from_str(&format!(
+                "{{subvolume: {}, target: {}, layout: {}, write_manifest: true, preserve: {{min: all}}}}",
+                subvolume,
+                dir.display(),
+                layout
+            ))
+            .unwrap()
+        };
+        let home = job_of("/home", "flat");
+        let srv = job_of("/srv", "per_subvolume");
+        let now = FixedOffset::east_opt(0)
+            .unwrap()
+            .with_ymd_and_hms(2022, 1, 21, 0, 0, 0)
+            .unwrap();
+
+        Manifest::write_all(&[home.clone(), srv], &[&home], &now);
+
+        let written: Manifest = serde_json::from_str(&fs::read_to_string(dir.join(MANIFEST_FILE)).unwrap()).unwrap();
+        let entry = |name: &str, path: &str, timestamp, source: &str, size| ManifestEntry {
+            name: name.to_string(),
+            path: path.to_string(),
+            timestamp,
+            source: source.to_string(),
+            size: Some(size),
+        };
+        let utc = FixedOffset::east_opt(0).unwrap();
+        assert_eq!(
+            written,
+            Manifest {
+                version: MANIFEST_VERSION,
+                generated: now,
+                snapshots: vec![
+                    entry(
+                        "home.2022-01-19T12:00:00Z",
+                        "home.2022-01-19T12:00:00Z",
+                        utc.with_ymd_and_hms(2022, 1, 19, 12, 0, 0).unwrap(),
+                        "/home",
+                        2
+                    ),
+                    entry(
+                        "srv.2022-01-19T18:00:00Z",
+                        "srv/srv.2022-01-19T18:00:00Z",
+                        utc.with_ymd_and_hms(2022, 1, 19, 18, 0, 0).unwrap(),
+                        "/srv",
+                        0
+                    ),
+                    entry(
+                        "home.2022-01-20T12:00:00Z",
+                        "home.2022-01-20T12:00:00Z",
+                        utc.with_ymd_and_hms(2022, 1, 20, 12, 0, 0).unwrap(),
+                        "/home",
+                        5
+                    ),
+                ],
+            }
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
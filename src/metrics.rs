@@ -0,0 +1,201 @@
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+use chrono::{DateTime, FixedOffset};
+
+use crate::executed_intent::ExecutedIntent;
+use crate::intent::{IntentType, KeepReason};
+
+/// How many of the retention bins of one tier of a job hold a snapshot
+#[derive(Debug, PartialEq, Eq)]
+pub struct BinUsage {
+    pub job: String,
+    pub tier: KeepReason,
+    pub filled: usize,
+    pub capacity: usize,
+}
+
+/// The name and help text of a metric, and how its value is taken from `T`
+type Metric<T, V> = (&'static str, &'static str, fn(&T) -> V);
+
+/// Escapes a label value of the OpenMetrics text format
+fn escape_label(value: &str) -> String {
+    value.replace('\\', r"\\").replace('"', r#"\""#).replace('\n', r"\n")
+}
+
+/// Renders the bin usages as gauges in the OpenMetrics text format
+fn bin_gauges(usages: &[BinUsage]) -> String {
+    let mut metrics = String::new();
+    let gauges: [Metric<BinUsage, usize>; 2] = [
+        ("ghee_bin_filled", "Retention bins holding a snapshot", |u| u.filled),
+        ("ghee_bin_capacity", "Retention bins of the tier", |u| u.capacity),
+    ];
+
+    for (name, help, value) in gauges {
+        let _ = writeln!(metrics, "# HELP {} {}", name, help);
+        let _ = writeln!(metrics, "# TYPE {} gauge", name);
+        for usage in usages {
+            let _ = writeln!(
+                metrics,
+                "{}{{job=\"{}\",tier=\"{}\"}} {}",
+                name,
+                escape_label(&usage.job),
+                usage.tier.to_string().to_lowercase(),
+                value(usage)
+            );
+        }
+    }
+
+    metrics
+}
+
+/// Renders how many snapshots of each subvolume the run created and deleted, and how many of its intents failed, as
+/// counters in the OpenMetrics text format, followed by the time the run `finished`
+fn run_counters(executed: &[ExecutedIntent], finished: &DateTime<FixedOffset>) -> String {
+    let mut metrics = String::new();
+    let subvolumes = executed.iter().map(|e| e.subvolume.as_str()).collect::<BTreeSet<_>>();
+    let counters: [Metric<ExecutedIntent, bool>; 3] = [
+        ("ghee_snapshots_created", "Snapshots created in the last run", |e| {
+            e.success && e.intent == IntentType::Create
+        }),
+        ("ghee_snapshots_deleted", "Snapshots deleted in the last run", |e| {
+            e.success && e.intent == IntentType::Delete
+        }),
+        ("ghee_snapshots_failed", "Intents that failed in the last run", |e| {
+            !e.success
+        }),
+    ];
+
+    for (name, help, counted) in counters {
+        let _ = writeln!(metrics, "# HELP {} {}", name, help);
+        let _ = writeln!(metrics, "# TYPE {} counter", name);
+        for subvolume in &subvolumes {
+            let count = executed
+                .iter()
+                .filter(|e| e.subvolume == *subvolume && counted(e))
+                .count();
+            let _ = writeln!(
+                metrics,
+                "{}_total{{subvolume=\"{}\"}} {}",
+                name,
+                escape_label(subvolume),
+                count
+            );
+        }
+    }
+    let _ = writeln!(
+        metrics,
+        "# HELP ghee_last_run_timestamp_seconds When the last run finished"
+    );
+    let _ = writeln!(metrics, "# TYPE ghee_last_run_timestamp_seconds gauge");
+    let _ = writeln!(metrics, "ghee_last_run_timestamp_seconds {}", finished.timestamp());
+
+    metrics
+}
+
+/// Renders the bin usages and the counts of the executed intents in the OpenMetrics text format
+pub fn render(usages: &[BinUsage], executed: &[ExecutedIntent], finished: &DateTime<FixedOffset>) -> String {
+    format!("{}{}# EOF\n", bin_gauges(usages), run_counters(executed, finished))
+}
+
+/// Writes the metrics file, replacing it at once so a collector never reads it half written
+pub fn write(
+    path: &Path,
+    usages: &[BinUsage],
+    executed: &[ExecutedIntent],
+    finished: &DateTime<FixedOffset>,
+) -> Result<(), Box<dyn Error>> {
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, render(usages, executed, finished))?;
+    fs::rename(&tmp, path)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{FixedOffset, TimeZone};
+
+    use crate::executed_intent::ExecutedIntent;
+    use crate::intent::{IntentType, KeepReason};
+    use crate::metrics::{bin_gauges, render, run_counters, BinUsage};
+
+    #[test]
+    fn renders_gauges() {
+        let usages = [
+            BinUsage {
+                job: "/home".to_string(),
+                tier: KeepReason::Daily,
+                filled: 3,
+                capacity: 15,
+            },
+            BinUsage {
+                job: "/srv/\"a\"".to_string(),
+                tier: KeepReason::Hourly,
+                filled: 0,
+                capacity: 1,
+            },
+        ];
+
+        assert_eq!(
+            bin_gauges(&usages),
+            r#"# HELP ghee_bin_filled Retention bins holding a snapshot
+# TYPE ghee_bin_filled gauge
+ghee_bin_filled{job="/home",tier="daily"} 3
+ghee_bin_filled{job="/srv/\"a\"",tier="hourly"} 0
+# HELP ghee_bin_capacity Retention bins of the tier
+# TYPE ghee_bin_capacity gauge
+ghee_bin_capacity{job="/home",tier="daily"} 15
+ghee_bin_capacity{job="/srv/\"a\"",tier="hourly"} 1
+"#
+        );
+    }
+
+    #[test]
+    fn renders_run_counters() {
+        let executed = |intent: IntentType, subvolume: &str, success: bool| ExecutedIntent {
+            intent,
+            subvolume: subvolume.to_string(),
+            target: String::new(),
+            name: String::new(),
+            success,
+            changes: None,
+            error: None,
+        };
+        let intents = [
+            executed(IntentType::Create, "/srv", true),
+            executed(IntentType::Create, "/home", true),
+            executed(IntentType::Delete, "/home", true),
+            executed(IntentType::Delete, "/home", false),
+            executed(IntentType::Keep, "/home", true),
+        ];
+        let finished = FixedOffset::east_opt(0)
+            .unwrap()
+            .with_ymd_and_hms(2022, 1, 20, 12, 0, 0)
+            .unwrap();
+
+        assert_eq!(
+            run_counters(&intents, &finished),
+            r#"# HELP ghee_snapshots_created Snapshots created in the last run
+# TYPE ghee_snapshots_created counter
+ghee_snapshots_created_total{subvolume="/home"} 1
+ghee_snapshots_created_total{subvolume="/srv"} 1
+# HELP ghee_snapshots_deleted Snapshots deleted in the last run
+# TYPE ghee_snapshots_deleted counter
+ghee_snapshots_deleted_total{subvolume="/home"} 1
+ghee_snapshots_deleted_total{subvolume="/srv"} 0
+# HELP ghee_snapshots_failed Intents that failed in the last run
+# TYPE ghee_snapshots_failed counter
+ghee_snapshots_failed_total{subvolume="/home"} 1
+ghee_snapshots_failed_total{subvolume="/srv"} 0
+# HELP ghee_last_run_timestamp_seconds When the last run finished
+# TYPE ghee_last_run_timestamp_seconds gauge
+ghee_last_run_timestamp_seconds 1642680000
+"#
+        );
+        assert!(render(&[], &intents, &finished).ends_with("1642680000\n# EOF\n"));
+    }
+}
@@ -0,0 +1,110 @@
+use std::fs;
+use std::path::Path;
+
+use log::{error, info, warn};
+use tabled::{Style, Table, Tabled};
+
+use crate::job::Job;
+use crate::naming::NameScheme;
+
+#[derive(Debug, Tabled, Clone)]
+pub struct Migration {
+    pub subvolume: String,
+    pub target: String,
+    pub from: String,
+    pub to: String,
+}
+
+#[derive(Tabled)]
+pub struct ExecutedMigration {
+    pub subvolume: String,
+    pub target: String,
+    pub from: String,
+    pub to: String,
+    pub success: bool,
+}
+
+impl Migration {
+    /// Finds the snapshots of each job named according to `from` and plans renaming them according to `to`.
+    pub fn gather(jobs: &[&Job], from: &NameScheme, to: &NameScheme) -> Vec<Self> {
+        let mut migrations = Vec::new();
+        for job in jobs {
+            let subvol = &job.snapshot_base_name();
+            let re = from.regex(subvol);
+
+            let directory = job.snapshot_directory();
+            let paths = match fs::read_dir(&directory) {
+                Ok(paths) => paths,
+                Err(e) => {
+                    error!("Unable to read directory {}! Error: {}", &directory, e);
+                    continue;
+                }
+            };
+
+            for path in paths.flatten() {
+                if let Some(filename) = path.file_name().to_str() {
+                    if !re.is_match(filename) {
+                        continue;
+                    }
+                    if let Some(timestamp) = from.parse_timestamp(subvol, filename) {
+                        let label = from.parse_label(subvol, filename);
+                        let new_name = to.format_labeled(subvol, &timestamp, label.as_deref());
+                        if new_name != filename {
+                            migrations.push(Migration {
+                                subvolume: job.subvolume.clone(),
+                                target: directory.clone(),
+                                from: filename.to_string(),
+                                to: new_name,
+                            });
+                        }
+                    } else {
+                        warn!("Unable to parse timestamp of snapshot {:?}!", path.path());
+                    }
+                } else {
+                    error!("Unable to parse Unicode from path {:?}!", path.path())
+                }
+            }
+        }
+
+        migrations
+    }
+
+    pub fn print_tabled(migrations: &[Self]) {
+        let table = Table::new(migrations).with(Style::modern()).to_string();
+        info!("{}", table);
+    }
+
+    /// Renames the snapshot. A rename within the same btrfs filesystem also moves subvolumes.
+    pub fn execute(&self) -> ExecutedMigration {
+        let from = Path::new(&self.target).join(&self.from);
+        let to = Path::new(&self.target).join(&self.to);
+
+        let success = if to.exists() {
+            warn!("not renaming {:?}, {:?} already exists!", from, to);
+            false
+        } else {
+            match fs::rename(&from, &to) {
+                Ok(_) => true,
+                Err(e) => {
+                    warn!("renaming snapshot failed! error: {}", e);
+                    false
+                }
+            }
+        };
+
+        ExecutedMigration {
+            subvolume: self.subvolume.clone(),
+            target: self.target.clone(),
+            from: self.from.clone(),
+            to: self.to.clone(),
+            success,
+        }
+    }
+}
+
+impl ExecutedMigration {
+    pub fn print_tabled(migrations: &[Self]) {
+        let table = Table::new(migrations).with(Style::modern()).to_string();
+        info!("{}", table);
+    }
+}
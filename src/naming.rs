@@ -0,0 +1,587 @@
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::error::Error;
+
+use chrono::{DateTime, FixedOffset, Local, NaiveDateTime, Offset, SecondsFormat, TimeZone};
+use chrono_tz::Tz;
+use regex::Regex;
+
+use crate::error::NameTemplateError;
+
+/// Maximum length of a file name in bytes on btrfs
+pub const NAME_MAX: usize = 255;
+
+const RFC3339_RE: &str = r"\d{4}-\d{2}-\d{2}T\d{2}:\d{2}:\d{2}(?:\.\d+)?(?:Z|[+-]\d{2}:\d{2})?";
+const RFC3339_DASHES_RE: &str = r"\d{4}-\d{2}-\d{2}T\d{2}-\d{2}-\d{2}(?:\.\d+)?(?:Z|[+-]\d{2}-\d{2})";
+
+/// Optional label of a snapshot, appended to its name after an `@`
+const LABEL_RE: &str = r"(?:@(?P<label>[A-Za-z0-9_-]+))?";
+
+/// Name of the timestamp format that writes RFC3339 with dashes instead of colons
+const RFC3339_DASHES: &str = "rfc3339-dashes";
+
+thread_local! {
+    /// Compiled snapshot name regexes by their pattern. Planning parses the name of every snapshot several times, and
+    /// compiling the regex each time dominates pruning targets with many snapshots.
+    static REGEXES: RefCell<HashMap<String, Regex>> = RefCell::new(HashMap::new());
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Subvol,
+    Timestamp,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum TimestampFormat {
+    Rfc3339,
+    /// RFC3339 with dashes in place of the colons, for filesystems that don't allow colons in names
+    Rfc3339Dashes,
+    Strftime(String),
+}
+
+impl TimestampFormat {
+    fn from_format(format: &str) -> Self {
+        match format {
+            RFC3339_DASHES => TimestampFormat::Rfc3339Dashes,
+            _ => TimestampFormat::Strftime(format.to_string()),
+        }
+    }
+}
+
+/// Describes how snapshot names are built from the subvolume name and the creation time.
+///
+/// Templates contain a `{subvol}` placeholder for the subvolume's directory name and exactly one `{timestamp}`
+/// placeholder, which is rendered as RFC3339 or, when written as `{timestamp:FORMAT}`, using a strftime format.
+/// The format `rfc3339-dashes` renders RFC3339 with dashes instead of colons.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NameScheme {
+    segments: Vec<Segment>,
+    timestamp: TimestampFormat,
+    /// Time zone timestamps are written in, instead of the offset of the time they are given in
+    timezone: Option<Tz>,
+    /// Fractional digits of the seconds of RFC3339 timestamps
+    precision: SecondsFormat,
+}
+
+impl Default for NameScheme {
+    fn default() -> Self {
+        Self {
+            segments: vec![Segment::Subvol, Segment::Literal(".".to_string()), Segment::Timestamp],
+            timestamp: TimestampFormat::Rfc3339,
+            timezone: None,
+            precision: SecondsFormat::Secs,
+        }
+    }
+}
+
+/// The same point in time, with the offset `tz` has at that time
+pub fn to_timezone(timestamp: &DateTime<FixedOffset>, tz: &Tz) -> DateTime<FixedOffset> {
+    let offset = tz.offset_from_utc_datetime(&timestamp.naive_utc()).fix();
+    timestamp.with_timezone(&offset)
+}
+
+impl NameScheme {
+    pub fn from_template(template: &str) -> Result<Self, Box<dyn Error>> {
+        let mut segments = Vec::new();
+        let mut timestamp = None;
+        let mut rest = template;
+
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                segments.push(Segment::Literal(rest[..start].to_string()));
+            }
+            let end = rest[start..].find('}').ok_or(NameTemplateError)? + start;
+            let placeholder = &rest[start + 1..end];
+
+            match placeholder.split_once(':') {
+                None if placeholder == "subvol" => segments.push(Segment::Subvol),
+                None if placeholder == "timestamp" && timestamp.is_none() => {
+                    segments.push(Segment::Timestamp);
+                    timestamp = Some(TimestampFormat::Rfc3339);
+                }
+                Some(("timestamp", format)) if timestamp.is_none() && !format.is_empty() => {
+                    segments.push(Segment::Timestamp);
+                    timestamp = Some(TimestampFormat::from_format(format));
+                }
+                _ => return Err(Box::new(NameTemplateError)),
+            }
+
+            rest = &rest[end + 1..];
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Literal(rest.to_string()));
+        }
+
+        let timestamp = timestamp.ok_or(NameTemplateError)?;
+
+        Ok(Self {
+            segments,
+            timestamp,
+            timezone: None,
+            precision: SecondsFormat::Secs,
+        })
+    }
+
+    /// The default scheme, but with the timestamp written using the strftime `format` or `rfc3339-dashes`
+    pub fn with_timestamp_format(format: &str) -> Self {
+        Self {
+            timestamp: TimestampFormat::from_format(format),
+            ..Self::default()
+        }
+    }
+
+    /// The scheme, but writing timestamps in the time zone `tz`, and reading those without an offset in it
+    pub fn in_timezone(self, timezone: Option<Tz>) -> Self {
+        Self { timezone, ..self }
+    }
+
+    /// The scheme, but writing RFC3339 timestamps with fractional seconds of the given `precision`
+    pub fn with_precision(self, precision: SecondsFormat) -> Self {
+        Self { precision, ..self }
+    }
+
+    /// Whether the timestamps are written as RFC3339, with colons or dashes, rather than with a strftime format
+    pub fn writes_rfc3339(&self) -> bool {
+        matches!(
+            self.timestamp,
+            TimestampFormat::Rfc3339 | TimestampFormat::Rfc3339Dashes
+        )
+    }
+
+    /// Whether a timestamp written by this scheme is parsed back to the same point in time
+    pub fn round_trips(&self) -> bool {
+        let local = Local.with_ymd_and_hms(2022, 10, 22, 13, 37, 42).unwrap();
+        let timestamp = local.with_timezone(local.offset());
+
+        self.check_round_trip("subvol", &timestamp).is_ok()
+    }
+
+    /// Checks that the snapshot of `subvol` named after `timestamp` matches the regex its snapshots are found with, and
+    /// that its timestamp is parsed back to the same point in time. Returns what breaks otherwise.
+    pub fn check_round_trip(&self, subvol: &str, timestamp: &DateTime<FixedOffset>) -> Result<(), String> {
+        let name = self.format(subvol, timestamp);
+        if !self.regex(subvol).is_match(&name) {
+            return Err(format!(
+                "snapshot name {} doesn't match the pattern of its own snapshots",
+                name
+            ));
+        }
+
+        match self.parse_timestamp(subvol, &name) {
+            Some(parsed) if parsed == *timestamp => Ok(()),
+            Some(parsed) => Err(format!(
+                "snapshot name {} is parsed back to {} instead of {}",
+                name, parsed, timestamp
+            )),
+            None => Err(format!("the timestamp of snapshot name {} can't be parsed", name)),
+        }
+    }
+
+    /// Returns the subvolume name as it appears in snapshot names. Names that would make snapshot names exceed
+    /// `NAME_MAX` are shortened and suffixed with a hash of the full name, so they stay unique and stable across runs.
+    pub fn subvol_component<'s>(&self, subvol: &'s str) -> Cow<'s, str> {
+        // widest rendering of the timestamp, so that the shortened name doesn't depend on the time of creation
+        let widest = FixedOffset::west_opt(11 * 3600 + 59 * 60)
+            .unwrap()
+            .with_ymd_and_hms(2000, 12, 31, 23, 59, 59)
+            .unwrap();
+        let other_len = self.render("", &widest).len();
+
+        if other_len + subvol.len() <= NAME_MAX {
+            return Cow::Borrowed(subvol);
+        }
+
+        let suffix = format!("~{:08x}", fnv1a(subvol));
+        let mut keep = NAME_MAX.saturating_sub(other_len + suffix.len());
+        while !subvol.is_char_boundary(keep) {
+            keep -= 1;
+        }
+
+        Cow::Owned(format!("{}{}", &subvol[..keep], suffix))
+    }
+
+    pub fn format(&self, subvol: &str, timestamp: &DateTime<FixedOffset>) -> String {
+        self.render(&self.subvol_component(subvol), timestamp)
+    }
+
+    /// Formats the name with the `label` appended after an `@`, if there is one
+    pub fn format_labeled(&self, subvol: &str, timestamp: &DateTime<FixedOffset>, label: Option<&str>) -> String {
+        match label {
+            Some(label) => format!("{}@{}", self.format(subvol, timestamp), label),
+            None => self.format(subvol, timestamp),
+        }
+    }
+
+    fn render(&self, subvol: &str, timestamp: &DateTime<FixedOffset>) -> String {
+        let timestamp = &match &self.timezone {
+            Some(tz) => to_timezone(timestamp, tz),
+            None => *timestamp,
+        };
+        let mut name = String::new();
+
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(l) => name.push_str(l),
+                Segment::Subvol => name.push_str(subvol),
+                Segment::Timestamp => match &self.timestamp {
+                    TimestampFormat::Rfc3339 => name.push_str(&timestamp.to_rfc3339_opts(self.precision, true)),
+                    TimestampFormat::Rfc3339Dashes => {
+                        name.push_str(&timestamp.to_rfc3339_opts(self.precision, true).replace(':', "-"))
+                    }
+                    TimestampFormat::Strftime(f) => name.push_str(&timestamp.format(f).to_string()),
+                },
+            }
+        }
+
+        name
+    }
+
+    /// Builds a regex matching the complete names of snapshots of `subvol`, capturing the timestamp as `timestamp` and
+    /// an optional label as `label`.
+    pub fn regex(&self, subvol: &str) -> Regex {
+        self.build_regex(Some(&self.subvol_component(subvol)))
+    }
+
+    /// Builds a regex matching the complete names of snapshots of any subvolume, capturing the subvolume component of
+    /// the name as `subvol`
+    pub fn any_subvolume_regex(&self) -> Regex {
+        self.build_regex(None)
+    }
+
+    fn build_regex(&self, subvol: Option<&str>) -> Regex {
+        let mut re = String::from("^");
+        let mut captured = false;
+
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(l) => re.push_str(&regex::escape(l)),
+                Segment::Subvol => match subvol {
+                    Some(subvol) => re.push_str(&regex::escape(subvol)),
+                    // a template may contain the subvolume more than once, but a group can only be named once
+                    None if captured => re.push_str(".+?"),
+                    None => {
+                        re.push_str("(?P<subvol>.+?)");
+                        captured = true;
+                    }
+                },
+                Segment::Timestamp => {
+                    let timestamp_re = match &self.timestamp {
+                        TimestampFormat::Rfc3339 => RFC3339_RE.to_string(),
+                        TimestampFormat::Rfc3339Dashes => RFC3339_DASHES_RE.to_string(),
+                        TimestampFormat::Strftime(f) => strftime_regex(f),
+                    };
+                    re.push_str(&format!("(?P<timestamp>{})", timestamp_re));
+                }
+            }
+        }
+        re.push_str(LABEL_RE);
+        re.push('$');
+
+        REGEXES.with(|regexes| {
+            regexes
+                .borrow_mut()
+                .entry(re)
+                .or_insert_with_key(|re| {
+                    Regex::new(re).expect("snapshot name regex is derived from escaped template parts")
+                })
+                .clone()
+        })
+    }
+
+    /// The label of the snapshot named `name`, if it has one
+    pub fn parse_label(&self, subvol: &str, name: &str) -> Option<String> {
+        let captures = self.regex(subvol).captures(name)?;
+        captures.name("label").map(|l| l.as_str().to_string())
+    }
+
+    pub fn parse_timestamp(&self, subvol: &str, name: &str) -> Option<DateTime<FixedOffset>> {
+        let captures = self.regex(subvol).captures(name)?;
+        let timestamp = captures.name("timestamp")?.as_str();
+
+        match &self.timestamp {
+            TimestampFormat::Rfc3339 => DateTime::parse_from_rfc3339(timestamp).ok(),
+            TimestampFormat::Rfc3339Dashes => {
+                // the regex matched, so the time starts at byte 11 and the fractional seconds or the offset at byte 19
+                let offset = timestamp[19..].find(['Z', '+', '-'])? + 19;
+                let rfc3339 = format!(
+                    "{}{}{}{}{}",
+                    &timestamp[..11],
+                    timestamp[11..19].replace('-', ":"),
+                    &timestamp[19..offset],
+                    &timestamp[offset..offset + 1],
+                    timestamp[offset + 1..].replace('-', ":")
+                );
+                DateTime::parse_from_rfc3339(&rfc3339).ok()
+            }
+            TimestampFormat::Strftime(f) => match DateTime::parse_from_str(timestamp, f) {
+                Ok(ts) => Some(ts),
+                Err(_) => {
+                    // formats without an offset are interpreted in the scheme's time zone, or else as local time
+                    let naive = NaiveDateTime::parse_from_str(timestamp, f).ok()?;
+                    match &self.timezone {
+                        Some(tz) => {
+                            let zoned = tz.from_local_datetime(&naive).earliest()?;
+                            Some(zoned.with_timezone(&zoned.offset().fix()))
+                        }
+                        None => {
+                            let local = Local.from_local_datetime(&naive).earliest()?;
+                            Some(local.with_timezone(local.offset()))
+                        }
+                    }
+                }
+            },
+        }
+    }
+}
+
+/// Short hash of a subvolume path, distinguishing the snapshot names of subvolumes with the same directory name
+pub fn source_hash(subvolume: &str) -> String {
+    format!("{:08x}", fnv1a(subvolume))
+}
+
+/// 32 bit FNV-1a, used instead of the std hasher because its output must not change between Rust versions
+fn fnv1a(s: &str) -> u32 {
+    s.bytes()
+        .fold(0x811c9dc5, |hash, b| (hash ^ b as u32).wrapping_mul(0x01000193))
+}
+
+fn strftime_regex(format: &str) -> String {
+    let mut re = String::new();
+    let mut chars = format.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            re.push_str(&regex::escape(&c.to_string()));
+            continue;
+        }
+
+        match chars.next() {
+            Some('Y') => re.push_str(r"\d{4}"),
+            Some('C' | 'y' | 'm' | 'd' | 'H' | 'I' | 'M' | 'S' | 'U' | 'W' | 'V') => re.push_str(r"\d{2}"),
+            Some('j') => re.push_str(r"\d{3}"),
+            Some('u' | 'w') => re.push_str(r"\d"),
+            Some('s') => re.push_str(r"\d+"),
+            Some('F') => re.push_str(r"\d{4}-\d{2}-\d{2}"),
+            Some('T') => re.push_str(r"\d{2}:\d{2}:\d{2}"),
+            Some('z') => re.push_str(r"[+-]\d{4}"),
+            Some(':') if chars.next() == Some('z') => re.push_str(r"[+-]\d{2}:\d{2}"),
+            Some('%') => re.push('%'),
+            _ => re.push_str(".+?"),
+        }
+    }
+
+    re
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, FixedOffset, Local, SecondsFormat, TimeZone, Timelike};
+
+    use crate::naming::{NameScheme, NAME_MAX, REGEXES};
+
+    #[test]
+    fn regexes_compiled_once() {
+        let scheme = NameScheme::default();
+        let ts = FixedOffset::east_opt(0)
+            .unwrap()
+            .with_ymd_and_hms(2022, 10, 22, 13, 37, 0)
+            .unwrap();
+        let cached = || REGEXES.with(|regexes| regexes.borrow().len());
+        let before = cached();
+
+        for minute in 0..60 {
+            let ts = ts + chrono::Duration::minutes(minute);
+            assert_eq!(scheme.parse_timestamp("home", &scheme.format("home", &ts)), Some(ts));
+        }
+        assert_eq!(cached(), before + 1);
+
+        // each subvolume and scheme has its own regex
+        assert_eq!(scheme.parse_timestamp("srv", &scheme.format("home", &ts)), None);
+        let dashes = NameScheme::with_timestamp_format("rfc3339-dashes");
+        assert_eq!(dashes.parse_timestamp("home", &dashes.format("home", &ts)), Some(ts));
+        assert_eq!(cached(), before + 3);
+    }
+
+    #[test]
+    fn default_scheme() {
+        let scheme = NameScheme::default();
+        let ts = FixedOffset::east_opt(2 * 3600)
+            .unwrap()
+            .with_ymd_and_hms(2022, 10, 22, 13, 37, 0)
+            .unwrap();
+
+        let name = scheme.format("home", &ts);
+        assert_eq!(name, "home.2022-10-22T13:37:00+02:00");
+        assert_eq!(scheme.parse_timestamp("home", &name), Some(ts));
+        assert_eq!(scheme.parse_timestamp("myhome", &name), None);
+        assert_eq!(scheme.parse_timestamp("home", "home.backup"), None);
+    }
+
+    #[test]
+    fn template_scheme() {
+        let scheme = NameScheme::from_template("snap_{subvol}_{timestamp:%Y-%m-%d_%H%M%S%z}").unwrap();
+        let ts = FixedOffset::east_opt(3600)
+            .unwrap()
+            .with_ymd_and_hms(2023, 1, 2, 15, 0, 0)
+            .unwrap();
+
+        let name = scheme.format("home", &ts);
+        assert_eq!(name, "snap_home_2023-01-02_150000+0100");
+        assert_eq!(scheme.parse_timestamp("home", &name), Some(ts));
+        assert_eq!(
+            NameScheme::from_template("{subvol}.{timestamp}").unwrap(),
+            NameScheme::default()
+        );
+    }
+
+    #[test]
+    fn invalid_templates() {
+        assert!(NameScheme::from_template("{subvol}").is_err());
+        assert!(NameScheme::from_template("{subvol}.{timestamp}.{timestamp}").is_err());
+        assert!(NameScheme::from_template("{subvol}.{time}").is_err());
+        assert!(NameScheme::from_template("{subvol}.{timestamp").is_err());
+    }
+
+    #[test]
+    fn migrate_between_schemes() {
+        let from = NameScheme::default();
+        let to = NameScheme::from_template("{subvol}-{timestamp:%Y%m%d-%H%M%S%z}").unwrap();
+
+        let ts = from.parse_timestamp("etc", "etc.2022-01-01T00:00:00+01:00").unwrap();
+        assert_eq!(to.format("etc", &ts), "etc-20220101-000000+0100");
+    }
+
+    #[test]
+    fn overlong_subvolume_names() {
+        let scheme = NameScheme::default();
+        let ts = FixedOffset::east_opt(3600)
+            .unwrap()
+            .with_ymd_and_hms(2023, 1, 2, 15, 0, 0)
+            .unwrap();
+        let long = "ä".repeat(150);
+
+        let name = scheme.format(&long, &ts);
+        assert!(name.len() <= NAME_MAX);
+        assert_eq!(name, scheme.format(&long, &ts));
+        assert_eq!(scheme.parse_timestamp(&long, &name), Some(ts));
+
+        let other = format!("{}b", "ä".repeat(149));
+        assert_ne!(scheme.subvol_component(&long), scheme.subvol_component(&other));
+        assert_eq!(scheme.subvol_component("home"), "home");
+    }
+
+    #[test]
+    fn timestamp_formats() {
+        let compact = NameScheme::with_timestamp_format("%Y%m%d-%H%M%S");
+        let ts = FixedOffset::east_opt(0)
+            .unwrap()
+            .with_ymd_and_hms(2022, 1, 1, 0, 0, 0)
+            .unwrap();
+        assert!(compact.format("home", &ts).starts_with("home.2022"));
+        assert!(compact.round_trips());
+        assert!(NameScheme::default().round_trips());
+
+        assert!(!NameScheme::with_timestamp_format("%Y%m%d-%H%M").round_trips());
+        assert!(!NameScheme::with_timestamp_format("%Y%m%d").round_trips());
+
+        // formats without an offset are parsed back as local time
+        let local = Local.with_ymd_and_hms(2022, 1, 1, 12, 34, 56).unwrap();
+        let ts = local.with_timezone(local.offset());
+        assert_eq!(compact.check_round_trip("home", &ts), Ok(()));
+        assert_eq!(
+            NameScheme::with_timestamp_format("%Y%m%d-%H%M").check_round_trip("home", &ts),
+            Err(format!(
+                "snapshot name home.20220101-1234 is parsed back to {} instead of {}",
+                ts.with_second(0).unwrap(),
+                ts
+            ))
+        );
+    }
+
+    #[test]
+    fn fractional_seconds() {
+        let ts = FixedOffset::east_opt(3600)
+            .unwrap()
+            .with_ymd_and_hms(2022, 1, 1, 12, 0, 0)
+            .unwrap()
+            + Duration::nanoseconds(123_456_789);
+        let millis = NameScheme::default().with_precision(SecondsFormat::Millis);
+        let name = millis.format("home", &ts);
+        assert_eq!(name, "home.2022-01-01T12:00:00.123+01:00");
+        assert_eq!(
+            millis.parse_timestamp("home", &name),
+            Some(ts - Duration::nanoseconds(456_789))
+        );
+
+        let nanos = NameScheme::with_timestamp_format("rfc3339-dashes").with_precision(SecondsFormat::Nanos);
+        let name = nanos.format("home", &ts);
+        assert_eq!(name, "home.2022-01-01T12-00-00.123456789+01-00");
+        assert_eq!(nanos.parse_timestamp("home", &name), Some(ts));
+
+        // snapshots taken before the precision was raised are still found
+        assert!(millis
+            .parse_timestamp("home", "home.2022-01-01T12:00:00+01:00")
+            .is_some());
+        assert!(NameScheme::default()
+            .parse_timestamp("home", &millis.format("home", &ts))
+            .is_some());
+    }
+
+    #[test]
+    fn colon_free_rfc3339() {
+        let scheme = NameScheme::with_timestamp_format("rfc3339-dashes");
+        let ts = FixedOffset::east_opt(-(5 * 3600 + 30 * 60))
+            .unwrap()
+            .with_ymd_and_hms(2022, 1, 1, 0, 0, 0)
+            .unwrap();
+        let utc = FixedOffset::east_opt(0)
+            .unwrap()
+            .with_ymd_and_hms(2022, 1, 1, 0, 0, 0)
+            .unwrap();
+
+        let name = scheme.format("home", &ts);
+        assert_eq!(name, "home.2022-01-01T00-00-00-05-30");
+        assert!(!name.contains(':'));
+        assert_eq!(scheme.parse_timestamp("home", &name), Some(ts));
+        assert_eq!(scheme.format("home", &utc), "home.2022-01-01T00-00-00Z");
+        assert_eq!(scheme.parse_timestamp("home", "home.2022-01-01T00-00-00Z"), Some(utc));
+        assert_eq!(scheme.parse_timestamp("home", "home.2022-01-01T00:00:00Z"), None);
+        assert!(scheme.round_trips());
+        assert_eq!(
+            NameScheme::from_template("{subvol}.{timestamp:rfc3339-dashes}").unwrap(),
+            scheme
+        );
+    }
+
+    #[test]
+    fn labeled_names() {
+        let scheme = NameScheme::default();
+        let ts = FixedOffset::east_opt(3600)
+            .unwrap()
+            .with_ymd_and_hms(2023, 1, 2, 15, 0, 0)
+            .unwrap();
+
+        let name = scheme.format_labeled("home", &ts, Some("pre-upgrade"));
+        assert_eq!(name, "home.2023-01-02T15:00:00+01:00@pre-upgrade");
+        assert_eq!(scheme.parse_timestamp("home", &name), Some(ts));
+        assert_eq!(scheme.parse_label("home", &name), Some("pre-upgrade".to_string()));
+        assert_eq!(scheme.format_labeled("home", &ts, None), scheme.format("home", &ts));
+        assert_eq!(scheme.parse_label("home", &scheme.format("home", &ts)), None);
+        assert_eq!(
+            scheme.parse_timestamp("home", "home.2023-01-02T15:00:00+01:00@pre upgrade"),
+            None
+        );
+        assert_eq!(
+            scheme.parse_timestamp("home", "home.2023-01-02T15:00:00+01:00.bak"),
+            None
+        );
+
+        let compact = NameScheme::with_timestamp_format("%Y%m%d-%H%M%S%z");
+        let name = compact.format_labeled("home", &ts, Some("manual_1"));
+        assert_eq!(name, "home.20230102-150000+0100@manual_1");
+        assert_eq!(compact.parse_timestamp("home", &name), Some(ts));
+    }
+}
@@ -0,0 +1,99 @@
+use std::error::Error;
+use std::time::Duration;
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::executed_intent::ExecutedIntent;
+
+/// How long connecting to the webhook, and the whole request, may take before it is given up on, so that an
+/// unresponsive webhook doesn't keep the run from finishing
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where failed intents are reported to
+#[derive(Debug, PartialEq, Eq, Deserialize)]
+pub struct Notify {
+    /// URL a JSON summary of the failed intents of a run is POSTed to
+    pub webhook: String,
+}
+
+/// The JSON body POSTed to the webhook
+#[derive(Serialize)]
+struct FailureNotification<'a> {
+    summary: String,
+    failed: Vec<&'a ExecutedIntent>,
+}
+
+impl Notify {
+    /// The JSON summary of the failed intents, or `None` if all of them succeeded
+    fn body(executed_intents: &[ExecutedIntent]) -> Result<Option<String>, Box<dyn Error>> {
+        let summary = match ExecutedIntent::failure_summary(executed_intents) {
+            Some(summary) => summary,
+            None => return Ok(None),
+        };
+        let notification = FailureNotification {
+            summary,
+            failed: executed_intents.iter().filter(|i| !i.success).collect(),
+        };
+
+        Ok(Some(serde_json::to_string(&notification)?))
+    }
+
+    /// POSTs the failed intents to the webhook. Failing to do so is warned about, but doesn't fail the run.
+    pub fn failures(&self, executed_intents: &[ExecutedIntent]) {
+        let result = Self::body(executed_intents).and_then(|body| match body {
+            Some(body) => {
+                let agent = ureq::AgentBuilder::new()
+                    .timeout_connect(WEBHOOK_TIMEOUT)
+                    .timeout_read(WEBHOOK_TIMEOUT)
+                    .timeout(WEBHOOK_TIMEOUT)
+                    .build();
+                agent
+                    .post(&self.webhook)
+                    .set("Content-Type", "application/json")
+                    .send_string(&body)?;
+                info!("Reported the failed intents to {}", &self.webhook);
+                Ok(())
+            }
+            None => Ok(()),
+        });
+        if let Err(e) = result {
+            warn!("Unable to notify {} of the failed intents! Error: {}", &self.webhook, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::executed_intent::ExecutedIntent;
+    use crate::intent::IntentType;
+    use crate::notify::Notify;
+
+    fn executed(name: &str, success: bool) -> ExecutedIntent {
+        ExecutedIntent {
+            intent: IntentType::Create,
+            subvolume: "/home".to_string(),
+            target: "/snaps".to_string(),
+            name: name.to_string(),
+            success,
+            changes: None,
+            error: (!success).then(|| "No space left on device".to_string()),
+        }
+    }
+
+    #[test]
+    fn only_failures_are_sent() {
+        assert_eq!(Notify::body(&[executed("home.1", true)]).unwrap(), None);
+        assert_eq!(Notify::body(&[]).unwrap(), None);
+
+        let body = Notify::body(&[executed("home.1", true), executed("home.2", false)])
+            .unwrap()
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["summary"], "1 of 2 intents failed");
+        let failed = body["failed"].as_array().unwrap();
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0]["name"], "home.2");
+        assert_eq!(failed[0]["error"], "No space left on device");
+    }
+}
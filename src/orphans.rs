@@ -0,0 +1,300 @@
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::rc::Rc;
+
+use libbtrfsutil as btrfs;
+use log::{info, warn};
+use tabled::{Style, Table, Tabled};
+
+use crate::config::InvalidJob;
+use crate::filesystem::list_directories;
+use crate::intent::{Intent, IntentType};
+use crate::job::{Job, TargetLayout};
+
+/// Whether `path` is still a subvolume. Paths that can't be checked but exist are taken as subvolumes, so that a
+/// failing check never makes their snapshots orphans.
+pub fn subvolume_exists(path: &Path) -> bool {
+    btrfs::is_subvolume(path).unwrap_or_else(|_| path.exists())
+}
+
+/// A snapshot in a snapshot directory that no configured job prunes
+#[derive(Debug, Tabled)]
+pub struct Orphan {
+    pub directory: String,
+    pub name: String,
+    pub reason: String,
+    /// Whether `--delete` deletes the snapshot. Snapshots of a configured job whose subvolume path still exists are
+    /// only listed, as the subvolume's filesystem may just not be mounted.
+    #[tabled(skip)]
+    pub deletable: bool,
+    /// Job whose naming scheme the snapshot follows, for the subvolume in its name, so that deleting it is checked
+    /// like any other delete
+    #[tabled(skip)]
+    pub job: Job,
+}
+
+impl Orphan {
+    /// Scans the snapshot directories of `jobs` for snapshots of subvolumes that none of `all_jobs` snapshots into
+    /// the directory, and for snapshots of jobs whose subvolume `is_subvolume` rejects. Entries not named like a
+    /// snapshot of any job are left alone. Orphans in a directory that one of the `unloaded` jobs, which are invalid or
+    /// expanded to no jobs, may snapshot into are only listed, as they may be that job's.
+    pub fn gather(
+        jobs: &[&Job],
+        all_jobs: &[Job],
+        unloaded: &[&InvalidJob],
+        is_subvolume: impl Fn(&Path) -> bool,
+    ) -> Vec<Self> {
+        let directories = jobs.iter().map(|j| j.snapshot_directory()).collect::<BTreeSet<_>>();
+
+        let mut orphans = Vec::new();
+        for directory in directories {
+            let owners = all_jobs
+                .iter()
+                .filter(|j| j.snapshot_directory() == directory)
+                .collect::<Vec<_>>();
+            let unloaded_owner = unloaded.iter().find(|u| Self::may_snapshot_into(u, &directory));
+            let paths = match list_directories(Path::new(&directory)) {
+                Ok(paths) => paths,
+                Err(e) => {
+                    warn!("Unable to read directory {}! Error: {}", &directory, e);
+                    continue;
+                }
+            };
+            let mut names = paths
+                .iter()
+                .filter_map(|p| p.file_name().and_then(|f| f.to_str()))
+                .collect::<Vec<_>>();
+            names.sort_unstable();
+            orphans.extend(
+                names
+                    .into_iter()
+                    .filter_map(|name| Self::classify(&owners, &directory, name, &is_subvolume))
+                    .map(|mut orphan| {
+                        if let Some(unloaded) = unloaded_owner.filter(|_| orphan.deletable) {
+                            orphan.reason = format!(
+                                "{}, but may be of the job of {}, which wasn't loaded",
+                                orphan.reason, &unloaded.subvolume
+                            );
+                            orphan.deletable = false;
+                        }
+                        orphan
+                    }),
+            );
+        }
+
+        orphans
+    }
+
+    /// Whether `unloaded` may snapshot into `directory`. Its target may be unknown, or contain the `{name}` of the
+    /// subvolumes it would have expanded to, so any directory starting like its target may be its.
+    fn may_snapshot_into(unloaded: &InvalidJob, directory: &str) -> bool {
+        let prefix = unloaded.target.split("{name}").next().unwrap_or_default();
+        unloaded.target == "?" || directory.starts_with(prefix)
+    }
+
+    fn classify(owners: &[&Job], directory: &str, name: &str, is_subvolume: &impl Fn(&Path) -> bool) -> Option<Self> {
+        let owner = owners
+            .iter()
+            .find(|j| j.name_scheme().parse_timestamp(&j.snapshot_base_name(), name).is_some());
+        if let Some(owner) = owner {
+            let source = Path::new(&owner.subvolume);
+            if is_subvolume(source) {
+                return None;
+            }
+            let (reason, deletable) = if source.exists() {
+                (format!("{} exists but is not a subvolume", &owner.subvolume), false)
+            } else {
+                (format!("subvolume {} no longer exists", &owner.subvolume), true)
+            };
+            return Some(Self {
+                directory: directory.to_string(),
+                name: name.to_string(),
+                reason,
+                deletable,
+                job: (*owner).clone(),
+            });
+        }
+
+        owners.iter().find_map(|j| {
+            let captures = j.name_scheme().any_subvolume_regex().captures(name)?;
+            let subvol = captures.name("subvol")?.as_str();
+            let job = Job {
+                subvolume: subvol.to_string(),
+                subvolume_command: None,
+                target: directory.to_string(),
+                layout: Some(TargetLayout::Flat),
+                source_tag: None,
+                ..(*j).clone()
+            };
+            job.name_scheme().parse_timestamp(&job.snapshot_base_name(), name)?;
+
+            Some(Self {
+                directory: directory.to_string(),
+                name: name.to_string(),
+                reason: format!("no job snapshots {}", subvol),
+                deletable: true,
+                job,
+            })
+        })
+    }
+
+    /// Plans deleting each of the orphans that may be deleted
+    pub fn delete_intents(orphans: &[Self]) -> Vec<Rc<RefCell<Intent<'_>>>> {
+        orphans
+            .iter()
+            .filter(|orphan| {
+                if !orphan.deletable {
+                    warn!(
+                        "not deleting {} of {}, {}!",
+                        &orphan.name, &orphan.directory, &orphan.reason
+                    );
+                }
+                orphan.deletable
+            })
+            .map(|orphan| {
+                Rc::new(RefCell::new(Intent {
+                    intent: IntentType::Delete,
+                    subvolume: orphan.job.subvolume.clone(),
+                    target: Path::new(&orphan.directory)
+                        .join(&orphan.name)
+                        .to_string_lossy()
+                        .to_string(),
+                    name: orphan.name.clone(),
+                    notes: orphan.reason.clone(),
+                    keep_reason: None,
+                    size: None,
+                    displaced: false,
+                    job: &orphan.job,
+                }))
+            })
+            .collect()
+    }
+
+    pub fn print_tabled(orphans: &[Self]) {
+        if orphans.is_empty() {
+            info!("No orphaned snapshots found.");
+            return;
+        }
+
+        let table = Table::new(orphans).with(Style::modern()).to_string();
+        info!("{}", table);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use crate::config::InvalidJob;
+    use crate::job::Job;
+    use crate::orphans::Orphan;
+
+    #[test]
+    fn finds_snapshots_of_unknown_and_missing_subvolumes() {
+        let dir = std::env::temp_dir().join(format!("ghee-orphans-test-{}", std::process::id()));
+        for name in [
+            "home.2022-01-20T12:00:00Z",
+            "srv.2022-01-20T12:00:00Z",
+            "old.2022-01-19T12:00:00Z",
+            "old.not-a-timestamp",
+            "lost+found",
+        ] {
+            fs::create_dir_all(dir.join(name)).unwrap();
+        }
+        let job_of = |subvolume: &str| -> Job {
+            serde_yaml::from_str(&format!(
+                "{{subvolume: {}, target: {}, preserve: {{min: all}}}}",
+                subvolume,
+                dir.display()
+            ))
+            .unwrap()
+        };
+        // the srv subvolume is gone, while the path of home still exists, as if its filesystem weren't mounted
+        let sources = dir.join("sources");
+        fs::create_dir_all(sources.join("home")).unwrap();
+        let home = sources.join("home").to_str().unwrap().to_string();
+        let srv = sources.join("srv").to_str().unwrap().to_string();
+        let jobs = vec![job_of(&home), job_of(&srv)];
+        let selected = vec![&jobs[0]];
+
+        let orphans = Orphan::gather(&selected, &jobs, &[], |_path| false);
+        let found = orphans
+            .iter()
+            .map(|o| (o.name.as_str(), o.reason.clone(), o.deletable))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            vec![
+                (
+                    "home.2022-01-20T12:00:00Z",
+                    format!("{} exists but is not a subvolume", home),
+                    false
+                ),
+                ("old.2022-01-19T12:00:00Z", "no job snapshots old".to_string(), true),
+                (
+                    "srv.2022-01-20T12:00:00Z",
+                    format!("subvolume {} no longer exists", srv),
+                    true
+                ),
+            ]
+        );
+
+        // the snapshots of the configured home job are only listed
+        let intents = Orphan::delete_intents(&orphans);
+        assert_eq!(intents.len(), 2);
+        let expected = dir.join("old.2022-01-19T12:00:00Z");
+        assert_eq!(intents[0].borrow().target, expected.to_str().unwrap());
+        assert_eq!(intents[0].borrow().job.snapshot_base_name(), "old");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn snapshots_of_unloaded_jobs_not_deleted() {
+        let dir = std::env::temp_dir().join(format!("ghee-orphans-unloaded-{}", std::process::id()));
+        let other = std::env::temp_dir().join(format!("ghee-orphans-unloaded-other-{}", std::process::id()));
+        for directory in [&dir, &other] {
+            fs::create_dir_all(directory.join("srv.2022-01-20T12:00:00Z")).unwrap();
+        }
+        let job_of = |target: &Path| -> Job {
+            serde_yaml::from_str(&format!(
+                "{{subvolume: /home, target: {}, preserve: {{min: all}}}}",
+                target.display()
+            ))
+            .unwrap()
+        };
+        let jobs = vec![job_of(&dir), job_of(&other)];
+        let selected = jobs.iter().collect::<Vec<_>>();
+        // the job of srv was skipped with `skip_invalid_jobs`, so its snapshots aren't orphans of the other target
+        let invalid = InvalidJob {
+            subvolume: "/srv".to_string(),
+            target: dir.to_str().unwrap().to_string(),
+            error: "invalid preserve retention".to_string(),
+        };
+
+        let orphans = Orphan::gather(&selected, &jobs, &[&invalid], |_path| true);
+        let found = orphans
+            .iter()
+            .map(|o| (o.directory.as_str(), o.deletable))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            vec![(dir.to_str().unwrap(), false), (other.to_str().unwrap(), true)]
+        );
+        assert_eq!(Orphan::delete_intents(&orphans).len(), 1);
+
+        // a template whose target is unknown until it is expanded may snapshot into any directory below its prefix
+        let template = InvalidJob {
+            subvolume: "btrfs-list-data".to_string(),
+            target: format!("{}/{{name}}", std::env::temp_dir().display()),
+            error: "expanded to no jobs".to_string(),
+        };
+        let orphans = Orphan::gather(&selected, &jobs, &[&template], |_path| true);
+        assert!(orphans.iter().all(|o| !o.deletable));
+
+        fs::remove_dir_all(&dir).unwrap();
+        fs::remove_dir_all(&other).unwrap();
+    }
+}
@@ -0,0 +1,159 @@
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use clap::ValueEnum;
+use log::{info, log_enabled, Level};
+use tabled::{Style, Table, Tabled};
+use terminal_size::{terminal_size_using_fd, Width};
+
+const MIN_ELIDED_WIDTH: usize = 12;
+/// Executions of fewer intents finish quickly enough to not report their progress
+const MIN_PROGRESS_TOTAL: usize = 20;
+
+/// How intents and executed intents are printed
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Tables in the log
+    Table,
+    /// A single JSON object on stdout
+    Json,
+}
+
+/// How the lines of the intent tables are drawn
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum TableStyle {
+    /// Box-drawing characters
+    Modern,
+    /// ASCII characters
+    Ascii,
+    /// Only a line below the header and between the columns
+    Plain,
+    /// Columns aligned with whitespace, without any lines
+    None,
+}
+
+impl TableStyle {
+    pub fn render<T: Tabled>(self, rows: impl IntoIterator<Item = T>) -> String {
+        let mut table = Table::new(rows);
+        match self {
+            Self::Modern => table.with(Style::modern()).to_string(),
+            Self::Ascii => table.with(Style::ascii()).to_string(),
+            Self::Plain => table.with(Style::psql()).to_string(),
+            Self::None => table.with(Style::blank()).to_string(),
+        }
+    }
+}
+
+/// The width of the terminal the tables are logged to, or `None` when not logging to a terminal
+fn terminal_width() -> Option<usize> {
+    terminal_size_using_fd(io::stderr().as_raw_fd()).map(|(Width(w), _)| w as usize)
+}
+
+/// The width of a table column holding the given header and values
+pub fn column_width<'a>(header: &str, values: impl Iterator<Item = &'a str>) -> usize {
+    values
+        .map(|v| v.chars().count())
+        .chain([header.len()])
+        .max()
+        .unwrap_or(0)
+}
+
+/// Computes how wide each of `elidable_columns` columns may become for a table with the given fixed column widths to
+/// fit into the terminal. Returns `None` when not logging to a terminal, so nothing should be elided.
+pub fn elided_column_width(fixed_columns: &[usize], elidable_columns: usize) -> Option<usize> {
+    let width = terminal_width()?;
+    let borders = 1 + 3 * (fixed_columns.len() + elidable_columns);
+    let fixed = borders + fixed_columns.iter().sum::<usize>();
+
+    Some((width.saturating_sub(fixed) / elidable_columns.max(1)).max(MIN_ELIDED_WIDTH))
+}
+
+/// Shortens `s` to at most `max` characters by replacing its middle with an ellipsis
+pub fn elide_middle(s: &str, max: usize) -> String {
+    let len = s.chars().count();
+    if len <= max {
+        return s.to_string();
+    }
+
+    let keep = max.saturating_sub(1);
+    let head = keep - keep / 2;
+    let tail = keep / 2;
+
+    let mut elided = s.chars().take(head).collect::<String>();
+    elided.push('…');
+    elided.extend(s.chars().skip(len - tail));
+    elided
+}
+
+/// Reports how many of a number of intents have been executed. On a terminal, a single line is updated in place,
+/// otherwise a line is logged every tenth of the way. Nothing is reported when info messages are disabled.
+pub struct Progress {
+    total: usize,
+    done: AtomicUsize,
+    enabled: bool,
+    in_place: bool,
+}
+
+impl Progress {
+    pub fn new(total: usize) -> Self {
+        Self {
+            total,
+            done: AtomicUsize::new(0),
+            enabled: total >= MIN_PROGRESS_TOTAL && log_enabled!(Level::Info),
+            in_place: terminal_width().is_some(),
+        }
+    }
+
+    pub fn step(&self) {
+        if !self.enabled {
+            return;
+        }
+
+        let done = self.done.fetch_add(1, Ordering::Relaxed) + 1;
+        if self.in_place {
+            eprint!("\rexecuted {}/{}", done, self.total);
+            if done == self.total {
+                eprintln!();
+            }
+        } else if done.is_multiple_of((self.total / 10).max(1)) || done == self.total {
+            info!("executed {}/{}", done, self.total);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tabled::Tabled;
+
+    use crate::output::{elide_middle, TableStyle};
+
+    #[test]
+    fn elides_middle() {
+        assert_eq!(elide_middle("/mnt/btrfs/@/gheesnaps", 30), "/mnt/btrfs/@/gheesnaps");
+        assert_eq!(elide_middle("/mnt/btrfs/@/gheesnaps", 11), "/mnt/…snaps");
+        assert_eq!(elide_middle("/mnt/btrfs/@/gheesnaps", 10), "/mnt/…naps");
+        assert_eq!(elide_middle("/mnt/btrfs/@/gheesnaps", 10).chars().count(), 10);
+    }
+
+    #[test]
+    fn table_styles() {
+        #[derive(Tabled)]
+        struct Row {
+            subvolume: &'static str,
+            name: &'static str,
+        }
+        let rows = || {
+            [Row {
+                subvolume: "/home",
+                name: "home.2022-01-20T12:00:00Z",
+            }]
+        };
+
+        assert!(TableStyle::Modern.render(rows()).contains('│'));
+        assert!(TableStyle::Ascii.render(rows()).starts_with("+-"));
+        let plain = TableStyle::None.render(rows());
+        assert!(!plain.contains(['│', '─', '|', '+']));
+        assert!(plain.contains("/home"));
+    }
+}
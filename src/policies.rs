@@ -1,15 +1,108 @@
-use serde::Deserialize;
+use std::error::Error;
+
+use chrono::{DateTime, FixedOffset, NaiveTime};
+use log::debug;
+use serde::{de, Deserialize, Deserializer};
+
+use crate::duration::{duration_from_str, subtract_from};
+use crate::error::TimeOfDayParseError;
+use crate::retention::Retention;
 
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
 pub struct PreservePolicy {
+    #[serde(default, deserialize_with = "deserialize_retention")]
     pub retention: Option<String>,
+    #[serde(deserialize_with = "deserialize_min")]
     pub min: PreservePolicyMin,
+    pub retention_anchor: Option<RetentionAnchor>,
+    pub bin_anchor: Option<BinAnchor>,
+    /// Time of day, e.g. 12:00, the representatives of the retention bins should be taken closest to
+    pub bin_time: Option<String>,
+    /// Keep snapshots older than the oldest retention bin instead of deleting them
+    pub keep_beyond_window: Option<bool>,
+    /// Let the snapshot created in a run represent its bin instead of an older snapshot in the same bin
+    pub prefer_created: Option<bool>,
+    /// Lowest and highest number of snapshots the job is expected to have once a run's plan is executed
+    pub expected_count: Option<(usize, usize)>,
+    /// Free space, e.g. 10G or 15%, below which the oldest snapshots kept by retention are deleted
+    pub min_free: Option<String>,
+}
+
+/// Rejects a malformed retention when the configuration is loaded, instead of only noticing it during a run. How many
+/// bins a tier may have is only checked once the configuration's `max_retention_bins` is known.
+fn deserialize_retention<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    let retention = Option::<String>::deserialize(deserializer)?;
+    if let Err(e) = Retention::from_str_option(&retention, usize::MAX) {
+        return Err(de::Error::custom(format!(
+            "invalid preserve retention {:?}: {}",
+            retention.unwrap_or_default(),
+            e
+        )));
+    }
+
+    Ok(retention)
+}
+
+/// Rejects a malformed min when the configuration is loaded, instead of only noticing it during a run
+fn deserialize_min<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PreservePolicyMin, D::Error> {
+    let min = PreservePolicyMin::deserialize(deserializer)?;
+    if let Some((timespan, e)) = min.malformed_timespan() {
+        return Err(de::Error::custom(format!("invalid preserve min {:?}: {}", timespan, e)));
+    }
+
+    Ok(min)
+}
+
+/// The point in time the retention bins count back from
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub enum RetentionAnchor {
+    /// The current time
+    #[serde(alias = "now")]
+    Now,
+    /// The timestamp of the job's newest existing snapshot, so that retention is relative to the data
+    #[serde(alias = "newest")]
+    Newest,
 }
 
-#[derive(Debug, Deserialize)]
-pub enum RetentionPolicy {
-    No,
-    Policy(String),
+impl PreservePolicy {
+    /// Whether neither the retention nor the min setting asks to keep any snapshots.
+    /// An explicit `min: none` is taken as confirmation and never reported.
+    pub fn retains_nothing(&self) -> bool {
+        let retention_empty = match Retention::from_str_option(&self.retention, usize::MAX) {
+            Ok(retention) => retention.is_zero(),
+            Err(_) => false,
+        };
+        retention_empty && self.min.keeps_nothing()
+    }
+
+    /// The anchor of the retention bins. A `bin_time` takes precedence over `bin_anchor`.
+    pub fn effective_bin_anchor(&self) -> Result<BinAnchor, Box<dyn Error>> {
+        match &self.bin_time {
+            Some(time) => Ok(BinAnchor::Nearest(parse_time_of_day(time)?)),
+            None => Ok(self.bin_anchor.unwrap_or(BinAnchor::First)),
+        }
+    }
+}
+
+/// Parses a time of day like 12:00 or 12:00:00
+pub fn parse_time_of_day(time: &str) -> Result<NaiveTime, Box<dyn Error>> {
+    NaiveTime::parse_from_str(time, "%H:%M")
+        .or_else(|_| NaiveTime::parse_from_str(time, "%H:%M:%S"))
+        .map_err(|_| Box::new(TimeOfDayParseError) as Box<dyn Error>)
+}
+
+/// Which snapshot represents a retention bin when several snapshots fall into the same period
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub enum BinAnchor {
+    /// The earliest snapshot of the period
+    #[serde(alias = "first")]
+    First,
+    /// The latest snapshot of the period
+    #[serde(alias = "last")]
+    Last,
+    /// The snapshot taken at the time of day closest to the given one, set through `bin_time`
+    #[serde(skip)]
+    Nearest(NaiveTime),
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
@@ -18,6 +111,90 @@ pub enum PreservePolicyMin {
     Variant(PreservePolicyMinVariants),
     Timespan(String),
     Count(usize),
+    /// Keeps this percentage of the existing snapshots, but at least one
+    Percent {
+        percent: usize,
+    },
+    /// Keeps the snapshots that every one of the rules keeps
+    AllOf {
+        all_of: Vec<PreservePolicyMin>,
+    },
+    /// Keeps the snapshots that any one of the rules keeps
+    AnyOf {
+        any_of: Vec<PreservePolicyMin>,
+    },
+}
+
+impl PreservePolicyMin {
+    /// How many of the newest snapshots the rule keeps, given the timestamps of all snapshots, newest first
+    pub fn keep_count(
+        &self,
+        timestamps: &[DateTime<FixedOffset>],
+        now: &DateTime<FixedOffset>,
+    ) -> Result<usize, Box<dyn Error>> {
+        let total = timestamps.len();
+        let count = match self {
+            PreservePolicyMin::Variant(PreservePolicyMinVariants::All) => total,
+            PreservePolicyMin::Variant(PreservePolicyMinVariants::None) => 0,
+            PreservePolicyMin::Variant(PreservePolicyMinVariants::Latest) => total.min(1),
+            PreservePolicyMin::Timespan(ts) => {
+                let since = subtract_from(now, ts)?;
+                debug!("preserve min keeps the snapshots since: {}", since);
+                timestamps.iter().take_while(|ts| **ts > since).count()
+            }
+            PreservePolicyMin::Count(n) => total.min(*n),
+            PreservePolicyMin::Percent { percent } => Self::percent_count(*percent, total),
+            // every rule keeps a number of the newest snapshots, so the snapshots all of them keep are the newest of
+            // the smallest number, and those any of them keeps the newest of the largest
+            PreservePolicyMin::AllOf { all_of } => all_of
+                .iter()
+                .map(|rule| rule.keep_count(timestamps, now))
+                .collect::<Result<Vec<_>, _>>()?
+                .into_iter()
+                .min()
+                .unwrap_or(total),
+            PreservePolicyMin::AnyOf { any_of } => any_of
+                .iter()
+                .map(|rule| rule.keep_count(timestamps, now))
+                .collect::<Result<Vec<_>, _>>()?
+                .into_iter()
+                .max()
+                .unwrap_or(0),
+        };
+
+        Ok(count)
+    }
+
+    /// The first timespan of the rule, including those of combined rules, that can't be parsed, and why
+    pub fn malformed_timespan(&self) -> Option<(&str, Box<dyn Error>)> {
+        match self {
+            PreservePolicyMin::Timespan(ts) => duration_from_str(ts).err().map(|e| (ts.as_str(), e)),
+            PreservePolicyMin::AllOf { all_of: rules } | PreservePolicyMin::AnyOf { any_of: rules } => {
+                rules.iter().find_map(|rule| rule.malformed_timespan())
+            }
+            PreservePolicyMin::Variant(_) | PreservePolicyMin::Count(_) | PreservePolicyMin::Percent { .. } => None,
+        }
+    }
+
+    /// Whether the rule keeps no snapshots. An explicit `none` is taken as confirmation and doesn't count.
+    pub fn keeps_nothing(&self) -> bool {
+        match self {
+            PreservePolicyMin::Count(n) => *n == 0,
+            PreservePolicyMin::Timespan(ts) => matches!(duration_from_str(ts), Ok(d) if d.is_zero()),
+            PreservePolicyMin::Variant(_) | PreservePolicyMin::Percent { .. } => false,
+            PreservePolicyMin::AllOf { all_of } => all_of.iter().any(|rule| rule.keeps_nothing()),
+            PreservePolicyMin::AnyOf { any_of } => any_of.iter().all(|rule| rule.keeps_nothing()),
+        }
+    }
+
+    /// The number of snapshots `min: {percent: N}` keeps out of `total`, rounded up
+    pub fn percent_count(percent: usize, total: usize) -> usize {
+        if total == 0 {
+            return 0;
+        }
+
+        (total * percent).div_ceil(100).clamp(1, total)
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
@@ -26,4 +203,110 @@ pub enum PreservePolicyMinVariants {
     All,
     #[serde(alias = "latest")]
     Latest,
+    #[serde(alias = "none")]
+    None,
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, FixedOffset, NaiveTime, TimeZone};
+
+    use crate::policies::{parse_time_of_day, BinAnchor, PreservePolicy, PreservePolicyMin};
+    use crate::retention::Retention;
+
+    #[test]
+    fn percent_counts() {
+        assert_eq!(PreservePolicyMin::percent_count(20, 0), 0);
+        assert_eq!(PreservePolicyMin::percent_count(20, 1), 1);
+        assert_eq!(PreservePolicyMin::percent_count(20, 4), 1);
+        assert_eq!(PreservePolicyMin::percent_count(20, 10), 2);
+        assert_eq!(PreservePolicyMin::percent_count(20, 11), 3);
+        assert_eq!(PreservePolicyMin::percent_count(0, 50), 1);
+        assert_eq!(PreservePolicyMin::percent_count(100, 7), 7);
+        assert_eq!(PreservePolicyMin::percent_count(150, 7), 7);
+
+        let min: PreservePolicyMin = serde_yaml::from_str("{percent: 20}").unwrap();
+        assert_eq!(min, PreservePolicyMin::Percent { percent: 20 });
+    }
+
+    #[test]
+    fn malformed_policies_rejected() {
+        let valid = "{retention: 2h-all 48h 14d, min: {any_of: [10, 5d, latest]}}";
+        let policy: PreservePolicy = serde_yaml::from_str(valid).unwrap();
+        assert_eq!(policy.retention.as_deref(), Some("2h-all 48h 14d"));
+        let policy: PreservePolicy = serde_yaml::from_str("{min: 3}").unwrap();
+        assert_eq!(policy.retention, None);
+
+        let error = serde_yaml::from_str::<PreservePolicy>("{retention: 7dd, min: 3}").unwrap_err();
+        assert!(error.to_string().contains("invalid preserve retention \"7dd\""));
+        let error = serde_yaml::from_str::<PreservePolicy>("{min: {all_of: [10, 5x]}}").unwrap_err();
+        assert!(error.to_string().contains("invalid preserve min \"5x\""));
+        // a min on its own is only checked as part of a policy
+        assert!(serde_yaml::from_str::<PreservePolicyMin>("5x").is_ok());
+    }
+
+    #[test]
+    fn retention_none() {
+        for retention in ["none", "no"] {
+            let policy: PreservePolicy =
+                serde_yaml::from_str(&format!("{{retention: {}, min: 5}}", retention)).unwrap();
+            assert!(Retention::from_str_option(&policy.retention, usize::MAX)
+                .unwrap()
+                .is_zero());
+            assert!(!policy.retains_nothing());
+        }
+        let policy: PreservePolicy = serde_yaml::from_str("{retention: none, min: 0}").unwrap();
+        assert!(policy.retains_nothing());
+    }
+
+    #[test]
+    fn bin_times() {
+        assert_eq!(
+            parse_time_of_day("12:00").unwrap(),
+            NaiveTime::from_hms_opt(12, 0, 0).unwrap()
+        );
+        assert_eq!(
+            parse_time_of_day("06:30:15").unwrap(),
+            NaiveTime::from_hms_opt(6, 30, 15).unwrap()
+        );
+        assert!(parse_time_of_day("noon").is_err());
+        assert!(parse_time_of_day("25:00").is_err());
+
+        let preserve: PreservePolicy = serde_yaml::from_str("{min: 0, bin_anchor: last, bin_time: \"12:00\"}").unwrap();
+        assert_eq!(
+            preserve.effective_bin_anchor().unwrap(),
+            BinAnchor::Nearest(NaiveTime::from_hms_opt(12, 0, 0).unwrap())
+        );
+        let preserve: PreservePolicy = serde_yaml::from_str("{min: 0, bin_anchor: last}").unwrap();
+        assert_eq!(preserve.effective_bin_anchor().unwrap(), BinAnchor::Last);
+    }
+
+    #[test]
+    fn combined_min_rules() {
+        let now = FixedOffset::east_opt(0)
+            .unwrap()
+            .with_ymd_and_hms(2022, 1, 20, 12, 0, 0)
+            .unwrap();
+        // a snapshot every 12 hours, newest first
+        let timestamps = (0..10).map(|i| now - Duration::hours(12 * i)).collect::<Vec<_>>();
+        let count = |min: &str| {
+            let min: PreservePolicyMin = serde_yaml::from_str(min).unwrap();
+            min.keep_count(&timestamps, &now)
+        };
+
+        assert_eq!(count("5").unwrap(), 5);
+        assert_eq!(count("48h").unwrap(), 4);
+        assert_eq!(count("{all_of: [5, 48h]}").unwrap(), 4);
+        assert_eq!(count("{any_of: [5, 48h]}").unwrap(), 5);
+        assert_eq!(count("{all_of: [latest, {any_of: [3, {percent: 50}]}]}").unwrap(), 1);
+        assert_eq!(count("{any_of: [latest, {all_of: [3, {percent: 50}]}]}").unwrap(), 3);
+        assert_eq!(count("{all_of: []}").unwrap(), 10);
+        assert_eq!(count("{any_of: []}").unwrap(), 0);
+        assert!(count("{any_of: [5, 48x]}").is_err());
+
+        let min: PreservePolicyMin = serde_yaml::from_str("{all_of: [5, 0]}").unwrap();
+        assert!(min.keeps_nothing());
+        let min: PreservePolicyMin = serde_yaml::from_str("{any_of: [5, 0]}").unwrap();
+        assert!(!min.keeps_nothing());
+    }
 }
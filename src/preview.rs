@@ -0,0 +1,138 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, FixedOffset};
+use log::info;
+use tabled::{Style, Table, Tabled};
+
+use crate::intent::{Intent, IntentType, UnreadableTarget};
+use crate::job::Job;
+
+/// A snapshot that a proposed retention decides about differently than the current one
+#[derive(Debug, PartialEq, Eq, Tabled)]
+pub struct DecisionChange {
+    pub subvolume: String,
+    pub name: String,
+    pub current: String,
+    pub proposed: String,
+}
+
+/// What planning decides for each existing snapshot of the jobs, by subvolume and name
+fn decisions(
+    jobs: &[&Job],
+    now: &DateTime<FixedOffset>,
+) -> (BTreeMap<(String, String), String>, Vec<UnreadableTarget>) {
+    let (mut intents, unreadable) = Intent::gather_delete_intents(jobs, false);
+    Intent::delete_to_keep_intents(&mut intents, jobs, now);
+
+    let decisions = intents
+        .iter()
+        .map(|int| {
+            let int = int.borrow();
+            let decision = match (&int.intent, int.keep_reason) {
+                (IntentType::Keep, Some(reason)) => format!("keep ({})", reason),
+                (intent, _) => intent.to_string(),
+            };
+            ((int.subvolume.clone(), int.name.clone()), decision)
+        })
+        .collect();
+
+    (decisions, unreadable)
+}
+
+impl DecisionChange {
+    /// Plans the existing snapshots of the jobs once with their retention and once with `retention` instead, and lists
+    /// the snapshots whose decision differs. Nothing is executed.
+    pub fn gather(
+        jobs: &[&Job],
+        retention: &str,
+        now: &DateTime<FixedOffset>,
+    ) -> (Vec<Self>, usize, Vec<UnreadableTarget>) {
+        let proposed_jobs = jobs
+            .iter()
+            .map(|&job| {
+                let mut job = job.clone();
+                job.preserve.retention = Some(retention.to_string());
+                job
+            })
+            .collect::<Vec<_>>();
+
+        let (current, unreadable) = decisions(jobs, now);
+        let (proposed, _unreadable) = decisions(&proposed_jobs.iter().collect::<Vec<_>>(), now);
+
+        let changes = current
+            .iter()
+            .filter_map(|((subvolume, name), current)| {
+                let proposed = proposed.get(&(subvolume.clone(), name.clone()))?;
+                (proposed != current).then(|| Self {
+                    subvolume: subvolume.clone(),
+                    name: name.clone(),
+                    current: current.clone(),
+                    proposed: proposed.clone(),
+                })
+            })
+            .collect();
+
+        (changes, current.len(), unreadable)
+    }
+
+    pub fn print_tabled(changes: &[Self], total: usize) {
+        if changes.is_empty() {
+            info!("The proposed retention decides the same for all {} snapshots.", total);
+            return;
+        }
+
+        let table = Table::new(changes).with(Style::modern()).to_string();
+        info!("{}", table);
+        info!(
+            "The proposed retention decides differently for {} of {} snapshots.",
+            changes.len(),
+            total
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use chrono::{FixedOffset, TimeZone};
+
+    use crate::job::Job;
+    use crate::preview::DecisionChange;
+
+    #[test]
+    fn differing_decisions() {
+        let dir = std::env::temp_dir().join(format!("ghee-preview-test-{}", std::process::id()));
+        for day in 17..=20 {
+            fs::create_dir_all(dir.join(format!("home.2022-01-{}T12:00:00Z", day))).unwrap();
+        }
+        let home: Job = serde_yaml::from_str(&format!(
+            "{{subvolume: /home, target: {}, preserve: {{retention: 1d, min: none}}}}",
+            dir.display()
+        ))
+        .unwrap();
+        let now = FixedOffset::east_opt(0)
+            .unwrap()
+            .with_ymd_and_hms(2022, 1, 21, 0, 0, 0)
+            .unwrap();
+
+        let (changes, total, unreadable) = DecisionChange::gather(&[&home], "3d", &now);
+        assert_eq!(total, 4);
+        assert!(unreadable.is_empty());
+        let change = |name: &str| DecisionChange {
+            subvolume: "/home".to_string(),
+            name: name.to_string(),
+            current: "delete".to_string(),
+            proposed: "keep (Daily)".to_string(),
+        };
+        assert_eq!(
+            changes,
+            vec![change("home.2022-01-18T12:00:00Z"), change("home.2022-01-19T12:00:00Z")]
+        );
+
+        let (unchanged, _total, _unreadable) = DecisionChange::gather(&[&home], "1d", &now);
+        assert!(unchanged.is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
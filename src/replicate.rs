@@ -0,0 +1,108 @@
+use std::error::Error;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use serde::Deserialize;
+
+use crate::error::ReplicateError;
+
+/// Where the snapshots of a job are copied to with `btrfs send` and `btrfs receive`
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+pub struct Replicate {
+    /// Directory on the receiving btrfs filesystem
+    pub destination: String,
+    /// Command the receiving side is run through, e.g. `ssh backup@nas`
+    pub ssh: Option<String>,
+}
+
+impl Replicate {
+    /// The shell command line running `command` on the receiving side
+    fn receiving(&self, command: &str) -> String {
+        match &self.ssh {
+            Some(ssh) => format!("{} {}", ssh, quote(command)),
+            None => command.to_string(),
+        }
+    }
+
+    fn receive_command(&self) -> String {
+        self.receiving(&format!("btrfs receive {}", quote(&self.destination)))
+    }
+
+    /// The names of the subvolumes the destination already has
+    pub fn received(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(self.receiving(&format!("ls -1 {}", quote(&self.destination))))
+            .output()?;
+        if !output.status.success() {
+            return Err(Box::new(ReplicateError));
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(str::to_string)
+            .collect())
+    }
+
+    /// Pipes `btrfs send` of the read-only `snapshot` into `btrfs receive` at the destination, incrementally against
+    /// `parent` if given
+    pub fn send(&self, snapshot: &Path, parent: Option<&Path>) -> Result<(), Box<dyn Error>> {
+        let mut send = Command::new("btrfs");
+        send.arg("send");
+        if let Some(parent) = parent {
+            send.arg("-p").arg(parent);
+        }
+        let mut send = send.arg(snapshot).stdout(Stdio::piped()).spawn()?;
+        let stream = send.stdout.take().ok_or(ReplicateError)?;
+
+        let received = Command::new("sh")
+            .arg("-c")
+            .arg(self.receive_command())
+            .stdin(stream)
+            .status();
+        let sent = send.wait()?;
+        if !sent.success() || !received?.success() {
+            return Err(Box::new(ReplicateError));
+        }
+
+        Ok(())
+    }
+
+    /// Describes the commands `send` runs for `snapshot`, with the parent only known once the destination is read
+    pub fn operation(&self, snapshot: &str) -> String {
+        format!(
+            "btrfs send [-p <newest snapshot the destination has>] {} | {}",
+            quote(snapshot),
+            self.receive_command()
+        )
+    }
+}
+
+/// Quotes `s` as a single word for `sh`
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::replicate::{quote, Replicate};
+
+    #[test]
+    fn receive_commands() {
+        let local = Replicate {
+            destination: "/mnt/backup/home snaps".to_string(),
+            ssh: None,
+        };
+        assert_eq!(local.receive_command(), "btrfs receive '/mnt/backup/home snaps'");
+
+        let remote = Replicate {
+            ssh: Some("ssh backup@nas".to_string()),
+            ..local
+        };
+        assert_eq!(
+            remote.receive_command(),
+            r#"ssh backup@nas 'btrfs receive '\''/mnt/backup/home snaps'\'''"#
+        );
+        assert_eq!(quote("it's"), r"'it'\''s'");
+    }
+}
@@ -1,10 +1,20 @@
 use std::error::Error;
+use std::str::FromStr;
 
 use regex::Regex;
 
-use crate::error::DurationParseError;
+use crate::duration::duration_parse_error;
+use crate::error::{DurationParseError, RetentionTooLongError};
+
+/// The most bins a tier of a retention may have, e.g. `10000h`, unless the configuration sets `max_retention_bins`.
+/// Larger values are almost certainly typos, and binning them would take a long time.
+pub const DEFAULT_MAX_TIER_BINS: usize = 10_000;
 
 pub struct Retention {
+    /// Hours back from now within which every snapshot is kept, written as e.g. `2h-all`
+    pub all_h: usize,
+    /// Minutes, written with a capital `M` to tell them from months
+    pub n: usize,
     pub h: usize,
     pub d: usize,
     pub w: usize,
@@ -15,6 +25,8 @@ pub struct Retention {
 impl Retention {
     pub fn zero() -> Self {
         Retention {
+            all_h: 0,
+            n: 0,
             h: 0,
             d: 0,
             w: 0,
@@ -23,30 +35,50 @@ impl Retention {
         }
     }
 
-    pub fn from_str_option(o: &Option<String>) -> Result<Self, Box<dyn Error>> {
+    pub fn is_zero(&self) -> bool {
+        self.all_h == 0 && self.n == 0 && self.h == 0 && self.d == 0 && self.w == 0 && self.m == 0 && self.y == 0
+    }
+
+    pub fn from_str_option(o: &Option<String>, max_tier_bins: usize) -> Result<Self, Box<dyn Error>> {
         match o {
             None => Ok(Self::zero()),
-            Some(s) => Self::from_str(&s[..]),
+            Some(s) => Self::parse_limited(s, max_tier_bins),
         }
     }
 
-    pub fn from_str(s: &str) -> Result<Self, Box<dyn Error>> {
-        let re = Regex::new(r"^(?:(\d+)h)?\s*(?:(\d+)d)?\s*(?:(\d+)w)?\s*(?:(\d+)m)?\s*(?:(\d+)y)?$")?;
+    /// Parses a retention such as `2h-all 30M 48h 14d 4w 6m 2y`. `M` are minutes and `m` are months. `none` or `no`
+    /// keeps nothing by retention, leaving only `min`. Tiers of more than `max_tier_bins` bins are rejected.
+    pub fn parse_limited(s: &str, max_tier_bins: usize) -> Result<Self, Box<dyn Error>> {
+        if matches!(s.trim(), "none" | "no") {
+            return Ok(Self::zero());
+        }
+
+        let re = Regex::new(
+            r"^(?:(\d+)h-all)?\s*(?:(\d+)M)?\s*(?:(\d+)h)?\s*(?:(\d+)d)?\s*(?:(\d+)w)?\s*(?:(\d+)m)?\s*(?:(\d+)y)?$",
+        )?;
 
         if !re.is_match(s) {
-            return Err(Box::new(DurationParseError));
+            return Err(duration_parse_error(s));
         };
 
         let capture = re.captures(s).ok_or(Box::new(DurationParseError))?;
 
-        let hours = capture.get(1);
-        let days = capture.get(2);
-        let weeks = capture.get(3);
-        let months = capture.get(4);
-        let years = capture.get(5);
+        let all_hours = capture.get(1);
+        let minutes = capture.get(2);
+        let hours = capture.get(3);
+        let days = capture.get(4);
+        let weeks = capture.get(5);
+        let months = capture.get(6);
+        let years = capture.get(7);
 
         let mut r = Retention::zero();
 
+        if let Some(all_h) = all_hours {
+            r.all_h = all_h.as_str().parse()?
+        }
+        if let Some(n) = minutes {
+            r.n = n.as_str().parse()?
+        }
         if let Some(h) = hours {
             r.h = h.as_str().parse()?
         }
@@ -63,6 +95,38 @@ impl Retention {
             r.y = y.as_str().parse()?
         }
 
+        let tiers = [r.all_h, r.n, r.h, r.d, r.w, r.m, r.y];
+        if tiers.iter().any(|&tier| tier > max_tier_bins) {
+            return Err(Box::new(RetentionTooLongError(max_tier_bins)));
+        }
+
         Ok(r)
     }
 }
+
+impl FromStr for Retention {
+    type Err = Box<dyn Error>;
+
+    /// Parses a retention with at most `DEFAULT_MAX_TIER_BINS` bins in each tier, see `Retention::parse_limited`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse_limited(s, DEFAULT_MAX_TIER_BINS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::retention::Retention;
+
+    #[test]
+    fn absurd_retention_rejected() {
+        let error = "1000000h".parse::<Retention>().err().unwrap();
+        assert!(error.to_string().contains("at most 10000"));
+        assert!("14d 1000000y".parse::<Retention>().is_err());
+        assert!("10000h".parse::<Retention>().is_ok());
+
+        assert!(Retention::parse_limited("1000000h", 1_000_000).is_ok());
+        let error = Retention::parse_limited("48h", 24).err().unwrap();
+        assert!(error.to_string().contains("at most 24"));
+        assert!(Retention::from_str_option(&Some("1000000h".to_string()), 10_000).is_err());
+    }
+}
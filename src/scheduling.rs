@@ -0,0 +1,87 @@
+use std::error::Error;
+
+use nix::errno::Errno;
+use nix::libc;
+use serde::Deserialize;
+
+/// `IOPRIO_WHO_PROCESS` of ioprio_set(2), which with id 0 targets the calling thread
+const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+/// The IO priority class is stored above this many bits of priority data
+const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+/// Priority within the realtime and best-effort classes, the middle of 0 (highest) to 7
+const IOPRIO_DEFAULT_LEVEL: libc::c_int = 4;
+
+/// How the CPU and IO scheduling of ghee and the commands it runs is lowered
+#[derive(Debug, Default, PartialEq, Eq, Deserialize)]
+pub struct Scheduling {
+    /// Niceness from -20 (highest priority) to 19 (lowest)
+    pub nice: Option<i32>,
+    pub ionice_class: Option<IoniceClass>,
+}
+
+/// IO scheduling classes of ioprio_set(2)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub enum IoniceClass {
+    #[serde(alias = "realtime")]
+    Realtime = 1,
+    #[serde(alias = "best_effort", alias = "best-effort")]
+    BestEffort = 2,
+    /// Only gets disk time when no other process needs it
+    #[serde(alias = "idle")]
+    Idle = 3,
+}
+
+impl Scheduling {
+    /// The IO priority passed to ioprio_set(2)
+    fn ioprio(class: IoniceClass) -> libc::c_int {
+        let level = match class {
+            IoniceClass::Idle => 0,
+            IoniceClass::Realtime | IoniceClass::BestEffort => IOPRIO_DEFAULT_LEVEL,
+        };
+        ((class as libc::c_int) << IOPRIO_CLASS_SHIFT) | level
+    }
+
+    /// Applies the scheduling to the calling thread. Threads and processes started by it afterwards inherit it, so
+    /// this is done before any jobs are worked on.
+    pub fn apply(&self) -> Result<(), Box<dyn Error>> {
+        if let Some(nice) = self.nice {
+            // with who 0, this only changes the calling thread on Linux
+            Errno::result(unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) })?;
+        }
+        if let Some(class) = self.ionice_class {
+            Errno::result(unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, Self::ioprio(class)) })?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::scheduling::{IoniceClass, Scheduling};
+
+    #[test]
+    fn parses_and_encodes() {
+        let scheduling: Scheduling = serde_yaml::from_str("{nice: 10, ionice_class: idle}").unwrap();
+        assert_eq!(
+            scheduling,
+            Scheduling {
+                nice: Some(10),
+                ionice_class: Some(IoniceClass::Idle),
+            }
+        );
+        assert!(serde_yaml::from_str::<Scheduling>("{ionice_class: fast}").is_err());
+
+        assert_eq!(Scheduling::ioprio(IoniceClass::Idle), 3 << 13);
+        assert_eq!(Scheduling::ioprio(IoniceClass::BestEffort), (2 << 13) | 4);
+        assert_eq!(Scheduling::ioprio(IoniceClass::Realtime), (1 << 13) | 4);
+
+        // lowering the priority of the test thread is always allowed
+        assert!(Scheduling::default().apply().is_ok());
+        let lower = Scheduling {
+            nice: Some(19),
+            ionice_class: Some(IoniceClass::BestEffort),
+        };
+        assert!(lower.apply().is_ok());
+    }
+}
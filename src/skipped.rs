@@ -0,0 +1,309 @@
+use std::cell::RefCell;
+use std::fmt::{Display, Formatter};
+use std::rc::Rc;
+
+use log::warn;
+use serde::Serialize;
+use tabled::Tabled;
+
+use crate::config::InvalidJob;
+use crate::intent::{Intent, IntentType, KeepReason, UnreadableTarget};
+use crate::job::Job;
+use crate::output::TableStyle;
+
+/// Why a job was left out of creating or pruning snapshots. Serialized as the same code it is displayed as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    /// The job's configuration is invalid, and it was left out with `skip_invalid_jobs`
+    InvalidConfig,
+    /// The job is disabled with `enabled: false`
+    Disabled,
+    /// The job's `when` command didn't succeed
+    When,
+    /// The job's subvolume is not a btrfs subvolume
+    NotSubvolume,
+    /// The snapshot name would be too long for the filesystem
+    NameTooLong,
+    /// A snapshot with the name already exists in the target
+    AlreadyExists,
+    /// The snapshots in the job's target couldn't be listed
+    UnreadableTarget,
+    /// The hold file exists
+    Hold,
+    /// Deleting was unsafe, e.g. because of clock skew, filesystem errors or an invalid retention
+    Safety,
+}
+
+impl Display for SkipReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let reason = match self {
+            SkipReason::InvalidConfig => "invalid_config",
+            SkipReason::Disabled => "disabled",
+            SkipReason::When => "when",
+            SkipReason::NotSubvolume => "not_subvolume",
+            SkipReason::NameTooLong => "name_too_long",
+            SkipReason::AlreadyExists => "already_exists",
+            SkipReason::UnreadableTarget => "unreadable_target",
+            SkipReason::Hold => "hold",
+            SkipReason::Safety => "safety",
+        };
+        write!(f, "{}", reason)
+    }
+}
+
+/// A job that didn't create a snapshot (`create`) or wasn't pruned (`delete`) in a run
+#[derive(Debug, PartialEq, Eq, Tabled, Serialize)]
+pub struct SkippedJob {
+    pub subvolume: String,
+    pub target: String,
+    pub skipped: IntentType,
+    pub reason: SkipReason,
+}
+
+impl SkippedJob {
+    pub fn new(job: &Job, skipped: IntentType, reason: SkipReason) -> Self {
+        Self {
+            subvolume: job.subvolume.clone(),
+            target: job.target.clone(),
+            skipped,
+            reason,
+        }
+    }
+
+    /// The jobs that are disabled, skipping each of the given intent types
+    pub fn disabled(jobs: &[&Job], skipped: &[IntentType]) -> Vec<Self> {
+        jobs.iter()
+            .filter(|j| !j.enabled.unwrap_or(true))
+            .flat_map(|j| skipped.iter().map(|s| Self::new(j, s.clone(), SkipReason::Disabled)))
+            .collect()
+    }
+
+    /// The jobs left out of the configuration because it is invalid, skipping each of the given intent types
+    pub fn invalid(invalid_jobs: &[InvalidJob], skipped: &[IntentType]) -> Vec<Self> {
+        invalid_jobs
+            .iter()
+            .flat_map(|j| {
+                skipped.iter().map(|s| Self {
+                    subvolume: j.subvolume.clone(),
+                    target: j.target.clone(),
+                    skipped: s.clone(),
+                    reason: SkipReason::InvalidConfig,
+                })
+            })
+            .collect()
+    }
+
+    /// Splits the jobs into those whose `when` command allows a snapshot, and the skipped rest
+    pub fn partition_when<'a>(jobs: &[&'a Job], dryrun: bool) -> (Vec<&'a Job>, Vec<Self>) {
+        let (create, skipped): (Vec<&Job>, Vec<&Job>) = jobs.iter().partition(|j| j.when_satisfied(dryrun));
+        let skipped = skipped
+            .into_iter()
+            .map(|j| Self::new(j, IntentType::Create, SkipReason::When))
+            .collect();
+
+        (create, skipped)
+    }
+
+    /// The jobs whose targets couldn't be read for pruning
+    pub fn unreadable(unreadable: &[UnreadableTarget]) -> Vec<Self> {
+        unreadable
+            .iter()
+            .map(|u| Self {
+                subvolume: u.subvolume.clone(),
+                target: u.directory.clone(),
+                skipped: IntentType::Delete,
+                reason: SkipReason::UnreadableTarget,
+            })
+            .collect()
+    }
+
+    /// The jobs that had snapshots to delete, but kept them because of the hold file or for safety
+    pub fn held(intents: &[Rc<RefCell<Intent>>]) -> Vec<Self> {
+        let mut held: Vec<Self> = Vec::new();
+        for int in intents {
+            let int = int.borrow();
+            let reason = match int.keep_reason {
+                Some(KeepReason::Hold) => SkipReason::Hold,
+                Some(KeepReason::Safety) => SkipReason::Safety,
+                _ => continue,
+            };
+            if !held
+                .iter()
+                .any(|h| h.subvolume == int.job.subvolume && h.target == int.job.target)
+            {
+                held.push(Self::new(int.job, IntentType::Delete, reason));
+            }
+        }
+
+        held
+    }
+
+    /// Warns about the jobs that were skipped, at the end of a run
+    pub fn report(skipped: &[Self], style: TableStyle) {
+        if skipped.is_empty() {
+            return;
+        }
+
+        warn!("{} jobs were skipped:", skipped.len());
+        warn!("{}", style.render(skipped));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use chrono::{FixedOffset, TimeZone};
+
+    use crate::config::InvalidJob;
+    use crate::intent::{Intent, IntentType, KeepReason, UnreadableTarget};
+    use crate::job::Job;
+    use crate::skipped::{SkipReason, SkippedJob};
+
+    fn job(subvolume: &str) -> Job {
+        serde_yaml::from_str(&format!(
+            "{{subvolume: {}, target: /mnt/btrfs/@/gheesnaps, preserve: {{min: all}}}}",
+            subvolume
+        ))
+        .unwrap()
+    }
+
+    fn delete<'a>(job: &'a Job, keep_reason: Option<KeepReason>) -> Rc<RefCell<Intent<'a>>> {
+        Rc::new(RefCell::new(Intent {
+            intent: match keep_reason {
+                Some(_) => IntentType::Keep,
+                None => IntentType::Delete,
+            },
+            subvolume: job.subvolume.clone(),
+            target: format!("{}/{}.2022-01-10T12:00:00Z", job.target, &job.subvolume[1..]),
+            name: format!("{}.2022-01-10T12:00:00Z", &job.subvolume[1..]),
+            notes: String::new(),
+            keep_reason,
+            size: None,
+            displaced: false,
+            job,
+        }))
+    }
+
+    #[test]
+    fn each_skip_reason_reported() {
+        let home = job("/home");
+        let mut waiting = job("/waiting");
+        waiting.when = Some("exit 1".to_string());
+        let missing = job("/nonexistent-ghee-subvolume");
+        let held = job("/held");
+        let unsafe_job = job("/unsafe");
+        let pruned = job("/pruned");
+
+        let mut disabled = job("/disabled");
+        disabled.enabled = Some(false);
+        assert_eq!(
+            SkippedJob::disabled(&[&home, &disabled], &[IntentType::Create, IntentType::Delete]),
+            vec![
+                SkippedJob::new(&disabled, IntentType::Create, SkipReason::Disabled),
+                SkippedJob::new(&disabled, IntentType::Delete, SkipReason::Disabled),
+            ]
+        );
+
+        let invalid = SkippedJob::invalid(
+            &[InvalidJob {
+                subvolume: "/srv".to_string(),
+                target: "/snaps".to_string(),
+                error: "invalid preserve retention".to_string(),
+            }],
+            &[IntentType::Delete],
+        );
+        assert_eq!(invalid.len(), 1);
+        assert_eq!(invalid[0].reason, SkipReason::InvalidConfig);
+        assert_eq!(invalid[0].target, "/snaps");
+
+        let (create, when) = SkippedJob::partition_when(&[&home, &waiting], false);
+        assert_eq!(create, vec![&home]);
+        assert_eq!(
+            when,
+            vec![SkippedJob::new(&waiting, IntentType::Create, SkipReason::When)]
+        );
+
+        let now = FixedOffset::east_opt(0)
+            .unwrap()
+            .with_ymd_and_hms(2022, 1, 17, 12, 0, 0)
+            .unwrap();
+        let (intents, not_subvolume) = Intent::gather_create_intents(&[&missing], &now, None);
+        assert!(intents.is_empty());
+        assert_eq!(
+            not_subvolume,
+            vec![SkippedJob::new(&missing, IntentType::Create, SkipReason::NotSubvolume)]
+        );
+
+        let unreadable = SkippedJob::unreadable(&[UnreadableTarget {
+            subvolume: "/etc".to_string(),
+            directory: "/mnt/btrfs/@/gheesnaps/etc".to_string(),
+            error: "Permission denied".to_string(),
+        }]);
+        assert_eq!(unreadable[0].reason, SkipReason::UnreadableTarget);
+        assert_eq!(unreadable[0].skipped, IntentType::Delete);
+
+        let intents = vec![
+            delete(&held, Some(KeepReason::Hold)),
+            delete(&held, Some(KeepReason::Hold)),
+            delete(&unsafe_job, Some(KeepReason::Safety)),
+            delete(&pruned, None),
+            delete(&pruned, Some(KeepReason::Daily)),
+        ];
+        assert_eq!(
+            SkippedJob::held(&intents),
+            vec![
+                SkippedJob::new(&held, IntentType::Delete, SkipReason::Hold),
+                SkippedJob::new(&unsafe_job, IntentType::Delete, SkipReason::Safety),
+            ]
+        );
+
+        let reasons = [
+            SkipReason::InvalidConfig,
+            SkipReason::Disabled,
+            SkipReason::When,
+            SkipReason::NotSubvolume,
+            SkipReason::NameTooLong,
+            SkipReason::AlreadyExists,
+            SkipReason::UnreadableTarget,
+            SkipReason::Hold,
+            SkipReason::Safety,
+        ];
+        let codes = reasons.map(|r| r.to_string());
+        assert_eq!(
+            codes,
+            [
+                "invalid_config",
+                "disabled",
+                "when",
+                "not_subvolume",
+                "name_too_long",
+                "already_exists",
+                "unreadable_target",
+                "hold",
+                "safety"
+            ]
+        );
+        assert_eq!(
+            reasons.map(|r| serde_json::to_value(r).unwrap()),
+            codes.map(serde_json::Value::from)
+        );
+    }
+
+    #[test]
+    fn json_output() {
+        let home = job("/home");
+        let json = serde_json::to_value(SkippedJob::new(&home, IntentType::Create, SkipReason::When)).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "subvolume": "/home",
+                "target": "/mnt/btrfs/@/gheesnaps",
+                "skipped": "create",
+                "reason": "when",
+            })
+        );
+    }
+}
@@ -0,0 +1,386 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, FixedOffset, Local};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use tabled::{Style, Table, Tabled};
+
+use crate::executed_intent::ExecutedIntent;
+use crate::filesystem::change_transid;
+use crate::intent::IntentType;
+use crate::job::Job;
+
+/// Version of the state file format, increased whenever the layout of the persisted state changes
+const STATE_VERSION: u32 = 4;
+
+/// Information persisted across runs, keyed by job
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct StateFile {
+    version: u32,
+    jobs: HashMap<String, JobState>,
+    failed: Vec<ExecutedIntent>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct JobState {
+    pub last_success: Option<DateTime<FixedOffset>>,
+    /// Change transaction id of the job's newest snapshot
+    pub last_transid: Option<u64>,
+}
+
+/// The state file as shown by `ghee state show`, with the jobs sorted
+#[derive(Serialize)]
+struct StateView<'s> {
+    version: u32,
+    jobs: BTreeMap<&'s String, &'s JobState>,
+    failed: &'s [ExecutedIntent],
+}
+
+/// An entry of the state file that doesn't belong to any configured job
+#[derive(Debug, PartialEq, Eq, Tabled)]
+pub struct StateIssue {
+    pub entry: String,
+    pub issue: String,
+}
+
+impl StateIssue {
+    pub fn print_tabled(issues: &[Self]) {
+        if issues.is_empty() {
+            info!("The state file is consistent with the configuration.");
+            return;
+        }
+
+        let table = Table::new(issues).with(Style::modern()).to_string();
+        warn!("{}", table);
+    }
+}
+
+/// Loads and saves the state file, which is the single place for everything ghee persists across runs
+#[derive(Debug)]
+pub struct StateStore {
+    path: PathBuf,
+    jobs: HashMap<String, JobState>,
+    failed: Vec<ExecutedIntent>,
+}
+
+impl StateStore {
+    pub fn job_key(subvolume: &str, target: &str) -> String {
+        format!("{}:{}", subvolume, target)
+    }
+
+    /// Loads the state file. A missing, unreadable, corrupt or outdated state file results in an empty state.
+    pub fn load(path: &Path) -> Self {
+        let empty = Self {
+            path: path.to_path_buf(),
+            jobs: HashMap::new(),
+            failed: Vec::new(),
+        };
+
+        let content = match fs::read(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => return empty,
+            Err(e) => {
+                warn!("Unable to read state file {:?}! Error: {}", path, e);
+                return empty;
+            }
+        };
+
+        match bincode::deserialize::<StateFile>(&content) {
+            Ok(state) if state.version == STATE_VERSION => Self {
+                path: path.to_path_buf(),
+                jobs: state.jobs,
+                failed: state.failed,
+            },
+            Ok(state) => {
+                warn!(
+                    "Ignoring state file {:?} of unsupported version {}!",
+                    path, state.version
+                );
+                empty
+            }
+            Err(e) => {
+                warn!("Unable to parse state file {:?}, ignoring it! Error: {}", path, e);
+                empty
+            }
+        }
+    }
+
+    /// Saves the state file atomically
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let state = StateFile {
+            version: STATE_VERSION,
+            jobs: self.jobs.clone(),
+            failed: self.failed.clone(),
+        };
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, bincode::serialize(&state)?)?;
+        fs::rename(&tmp, &self.path)?;
+
+        Ok(())
+    }
+
+    pub fn job(&self, job: &Job) -> Option<&JobState> {
+        self.jobs.get(&Self::job_key(&job.subvolume, &job.target))
+    }
+
+    /// The intents that failed when they were last executed
+    pub fn failed(&self) -> &[ExecutedIntent] {
+        &self.failed
+    }
+
+    /// Records the time of each successfully created snapshot as the last success of its job, and how many
+    /// transactions changed the subvolume since the job's previous snapshot. The failed intents of the given jobs are
+    /// replaced by the failures of this execution.
+    pub fn record_executed(&mut self, executed_intents: &mut [ExecutedIntent], jobs: &[&Job]) {
+        let local_now = Local::now();
+        let now = local_now.with_timezone(local_now.offset());
+
+        for executed in executed_intents.iter_mut() {
+            if executed.intent == IntentType::Create && executed.success {
+                let job_state = self
+                    .jobs
+                    .entry(Self::job_key(&executed.subvolume, &executed.target))
+                    .or_default();
+                job_state.last_success = Some(now);
+
+                let directory = jobs
+                    .iter()
+                    .find(|j| j.subvolume == executed.subvolume && j.target == executed.target)
+                    .map_or(executed.target.clone(), |j| j.snapshot_directory());
+                let snapshot = Path::new(&directory).join(&executed.name);
+                if let Some(transid) = change_transid(&snapshot) {
+                    executed.changes = job_state.last_transid.map(|last| transid.saturating_sub(last));
+                    job_state.last_transid = Some(transid);
+                }
+            }
+        }
+
+        self.failed.retain(|f| !jobs.iter().any(|j| f.belongs_to(j)));
+        self.failed
+            .extend(executed_intents.iter().filter(|e| !e.success).cloned());
+    }
+
+    /// The recorded state as pretty-printed JSON
+    pub fn to_json(&self) -> Result<String, Box<dyn Error>> {
+        let view = StateView {
+            version: STATE_VERSION,
+            jobs: self.jobs.iter().collect(),
+            failed: &self.failed,
+        };
+        Ok(serde_json::to_string_pretty(&view)?)
+    }
+
+    /// Lists the entries recorded for jobs that are not configured in `jobs`, e.g. because they were removed
+    pub fn verify(&self, jobs: &[Job]) -> Vec<StateIssue> {
+        let mut issues = self
+            .jobs
+            .keys()
+            .filter(|key| !jobs.iter().any(|j| Self::job_key(&j.subvolume, &j.target) == **key))
+            .map(|key| StateIssue {
+                entry: key.clone(),
+                issue: "state of a job that is not configured".to_string(),
+            })
+            .collect::<Vec<_>>();
+        issues.sort_by(|a, b| a.entry.cmp(&b.entry));
+
+        issues.extend(
+            self.failed
+                .iter()
+                .filter(|f| !jobs.iter().any(|j| j.subvolume == f.subvolume))
+                .map(|f| StateIssue {
+                    entry: format!("{} {}", f.intent, &f.target),
+                    issue: format!("failed intent of {}, which has no configured job", &f.subvolume),
+                }),
+        );
+
+        issues
+    }
+
+    /// Removes the entries recorded for jobs that are not configured in `jobs`, returning how many were removed
+    pub fn compact(&mut self, jobs: &[Job]) -> usize {
+        let before = self.jobs.len() + self.failed.len();
+        self.jobs
+            .retain(|key, _| jobs.iter().any(|j| Self::job_key(&j.subvolume, &j.target) == *key));
+        self.failed.retain(|f| jobs.iter().any(|j| j.subvolume == f.subvolume));
+
+        before - self.jobs.len() - self.failed.len()
+    }
+
+    /// Records intents that were not executed, so that `ghee retry` executes them
+    pub fn record_deferred(&mut self, deferred: impl IntoIterator<Item = ExecutedIntent>) {
+        self.failed.extend(deferred);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use chrono::{FixedOffset, TimeZone};
+
+    use crate::executed_intent::ExecutedIntent;
+    use crate::intent::IntentType;
+    use crate::job::Job;
+    use crate::state::{JobState, StateIssue, StateStore};
+
+    fn executed(intent: IntentType, subvolume: &str, target: &str, success: bool) -> ExecutedIntent {
+        ExecutedIntent {
+            intent,
+            subvolume: subvolume.to_string(),
+            target: target.to_string(),
+            name: String::new(),
+            success,
+            changes: None,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn round_trip_and_corruption() {
+        let path = std::env::temp_dir().join(format!("ghee-state-test-{}", std::process::id()));
+        let ts = FixedOffset::east_opt(3600)
+            .unwrap()
+            .with_ymd_and_hms(2022, 10, 22, 13, 37, 0)
+            .unwrap();
+
+        let mut store = StateStore::load(&path);
+        assert!(store.jobs.is_empty());
+        store.jobs.insert(
+            StateStore::job_key("/home", "/snaps"),
+            JobState {
+                last_success: Some(ts),
+                last_transid: Some(42),
+            },
+        );
+        store.save().unwrap();
+
+        let loaded = StateStore::load(&path);
+        assert_eq!(
+            loaded.jobs[&StateStore::job_key("/home", "/snaps")].last_success,
+            Some(ts)
+        );
+
+        fs::write(&path, b"not a state file").unwrap();
+        assert!(StateStore::load(&path).jobs.is_empty());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn failures_replaced_per_job() {
+        let path = std::env::temp_dir().join(format!("ghee-state-failures-{}", std::process::id()));
+        let home: Job = serde_yaml::from_str("{subvolume: /home, target: /snaps, preserve: {min: all}}").unwrap();
+
+        let mut store = StateStore::load(&path);
+        store.record_executed(
+            &mut [
+                executed(IntentType::Create, "/home", "/snaps", false),
+                executed(IntentType::Delete, "/root", "/snaps/root.old", false),
+            ],
+            &[&home],
+        );
+        assert_eq!(store.failed().len(), 2);
+
+        store.record_executed(&mut [executed(IntentType::Create, "/home", "/snaps", true)], &[&home]);
+        assert_eq!(store.failed().len(), 1);
+        assert_eq!(store.failed()[0].subvolume, "/root");
+        assert!(store.jobs[&StateStore::job_key("/home", "/snaps")]
+            .last_success
+            .is_some());
+
+        store.record_executed(&mut [], &[&home]);
+        store.record_deferred([executed(IntentType::Delete, "/home", "/snaps/home.old", false)]);
+        assert_eq!(store.failed().len(), 2);
+        assert_eq!(store.failed()[1].target, "/snaps/home.old");
+    }
+
+    #[test]
+    fn failures_of_unselected_jobs_kept() {
+        let path = std::env::temp_dir().join(format!("ghee-state-unselected-{}", std::process::id()));
+        let home: Job = serde_yaml::from_str("{subvolume: /home, target: /snaps, preserve: {min: all}}").unwrap();
+        let offsite: Job = serde_yaml::from_str("{subvolume: /home, target: /offsite, preserve: {min: all}}").unwrap();
+
+        let mut store = StateStore::load(&path);
+        store.record_executed(
+            &mut [
+                executed(IntentType::Create, "/home", "/snaps", false),
+                executed(IntentType::Create, "/home", "/offsite", false),
+                executed(IntentType::Delete, "/home", "/offsite/home.old", false),
+            ],
+            &[&home, &offsite],
+        );
+        assert_eq!(store.failed().len(), 3);
+
+        // a run narrowed to one target of the subvolume leaves the failures of the other target pending
+        store.record_executed(&mut [executed(IntentType::Create, "/home", "/snaps", true)], &[&home]);
+        assert_eq!(store.failed().len(), 2);
+        assert!(store.failed().iter().all(|f| f.target.starts_with("/offsite")));
+    }
+
+    #[test]
+    fn show_verify_compact() {
+        let path = std::env::temp_dir().join(format!("ghee-state-maintenance-{}", std::process::id()));
+        let ts = FixedOffset::east_opt(3600)
+            .unwrap()
+            .with_ymd_and_hms(2022, 10, 22, 13, 37, 0)
+            .unwrap();
+        let home: Job = serde_yaml::from_str("{subvolume: /home, target: /snaps, preserve: {min: all}}").unwrap();
+
+        let mut store = StateStore::load(&path);
+        for (subvolume, target) in [("/home", "/snaps"), ("/srv", "/snaps"), ("/home", "/old")] {
+            store.jobs.insert(
+                StateStore::job_key(subvolume, target),
+                JobState {
+                    last_success: Some(ts),
+                    last_transid: None,
+                },
+            );
+        }
+        store.record_deferred([
+            executed(IntentType::Delete, "/home", "/snaps/home.old", false),
+            executed(IntentType::Create, "/srv", "/snaps", false),
+        ]);
+        store.save().unwrap();
+
+        let store = StateStore::load(&path);
+        let shown: serde_json::Value = serde_json::from_str(&store.to_json().unwrap()).unwrap();
+        assert_eq!(
+            shown["jobs"]["/home:/snaps"]["last_success"],
+            "2022-10-22T13:37:00+01:00"
+        );
+        assert_eq!(shown["jobs"].as_object().unwrap().len(), 3);
+        assert_eq!(shown["failed"][1]["intent"], "create");
+
+        let issue = |entry: &str, issue: &str| StateIssue {
+            entry: entry.to_string(),
+            issue: issue.to_string(),
+        };
+        assert_eq!(
+            store.verify(std::slice::from_ref(&home)),
+            vec![
+                issue("/home:/old", "state of a job that is not configured"),
+                issue("/srv:/snaps", "state of a job that is not configured"),
+                issue("create /snaps", "failed intent of /srv, which has no configured job"),
+            ]
+        );
+
+        let mut store = store;
+        assert_eq!(store.compact(std::slice::from_ref(&home)), 3);
+        assert_eq!(store.compact(std::slice::from_ref(&home)), 0);
+        store.save().unwrap();
+        let compacted = StateStore::load(&path);
+        assert!(compacted.verify(std::slice::from_ref(&home)).is_empty());
+        assert!(compacted.job(&home).is_some());
+        assert_eq!(compacted.failed().len(), 1);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
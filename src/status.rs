@@ -0,0 +1,64 @@
+use chrono::Local;
+use log::{info, warn};
+use tabled::Tabled;
+
+use crate::duration::{duration_from_str, format_duration, format_duration_iso};
+use crate::job::Job;
+use crate::output::TableStyle;
+use crate::state::StateStore;
+
+#[derive(Tabled)]
+pub struct JobStatus {
+    pub subvolume: String,
+    pub target: String,
+    pub last_success: String,
+    pub age: String,
+    pub stale: bool,
+}
+
+impl JobStatus {
+    /// Gathers the status of each job, with ages in ISO 8601 if `iso_durations` is set
+    pub fn gather(jobs: &[&Job], state: &StateStore, iso_durations: bool) -> Vec<Self> {
+        let now = Local::now();
+        let format_age = if iso_durations {
+            format_duration_iso
+        } else {
+            format_duration
+        };
+
+        jobs.iter()
+            .map(|&job| {
+                let last_success = state.job(job).and_then(|s| s.last_success);
+                let age = last_success.map(|ts| now.signed_duration_since(ts));
+
+                let stale_after = job.stale_after.as_ref().and_then(|s| match duration_from_str(s) {
+                    Ok(d) => Some(d),
+                    Err(e) => {
+                        warn!(
+                            "error while parsing stale_after for job: {}\nerror: {}",
+                            &job.subvolume, e
+                        );
+                        None
+                    }
+                });
+                let stale = match (stale_after, age) {
+                    (Some(stale_after), Some(age)) => age > stale_after,
+                    (Some(_), None) => true,
+                    (None, _) => false,
+                };
+
+                JobStatus {
+                    subvolume: job.subvolume.clone(),
+                    target: job.target.clone(),
+                    last_success: last_success.map_or("never".to_string(), |ts| ts.to_rfc3339()),
+                    age: age.map_or("-".to_string(), format_age),
+                    stale,
+                }
+            })
+            .collect()
+    }
+
+    pub fn print_tabled(statuses: &[Self], style: TableStyle) {
+        info!("{}", style.render(statuses));
+    }
+}
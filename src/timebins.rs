@@ -3,77 +3,156 @@ use std::collections::HashMap;
 use std::ops::Sub;
 use std::rc::Rc;
 
-use chrono::{DateTime, Duration, FixedOffset, Local};
+use chrono::{DateTime, Duration, FixedOffset, NaiveTime, Offset, TimeZone, Timelike};
+use chrono_tz::Tz;
 use log::trace;
 
 use crate::duration::{
-    duration_trunc_day, duration_trunc_hour, duration_trunc_month, duration_trunc_week, duration_trunc_year,
+    duration_trunc_day, duration_trunc_hour, duration_trunc_minute, duration_trunc_month, duration_trunc_week,
+    duration_trunc_year, sub_months,
 };
-use crate::intent::{Intent, IntentType};
+use crate::intent::{Intent, IntentType, KeepReason};
+use crate::naming::to_timezone;
+use crate::policies::BinAnchor;
 use crate::retention::Retention;
 
+/// Maps the truncated timestamp of each bin to the timestamp and intent of its representative, and whether the
+/// representative only stands in for the bin because it falls into a bin of a finer tier
+pub type Bins<'a> = HashMap<DateTime<FixedOffset>, (DateTime<FixedOffset>, Rc<RefCell<Intent<'a>>>, bool)>;
+
 #[derive(Debug)]
 pub struct TimeBins<'a> {
-    pub h: HashMap<DateTime<FixedOffset>, Rc<RefCell<Intent<'a>>>>,
+    pub anchor: BinAnchor,
+    /// Snapshots taken at or after this time are all kept, without binning them
+    pub recent_start: Option<DateTime<FixedOffset>>,
+    pub recent: Vec<Rc<RefCell<Intent<'a>>>>,
+    pub n: Bins<'a>,
+    pub rn: Vec<DateTime<FixedOffset>>,
+    pub h: Bins<'a>,
     pub rh: Vec<DateTime<FixedOffset>>,
-    pub d: HashMap<DateTime<FixedOffset>, Rc<RefCell<Intent<'a>>>>,
+    pub d: Bins<'a>,
     pub rd: Vec<DateTime<FixedOffset>>,
-    pub w: HashMap<DateTime<FixedOffset>, Rc<RefCell<Intent<'a>>>>,
+    pub w: Bins<'a>,
     pub rw: Vec<DateTime<FixedOffset>>,
-    pub m: HashMap<DateTime<FixedOffset>, Rc<RefCell<Intent<'a>>>>,
+    pub m: Bins<'a>,
     pub rm: Vec<DateTime<FixedOffset>>,
-    pub y: HashMap<DateTime<FixedOffset>, Rc<RefCell<Intent<'a>>>>,
+    pub y: Bins<'a>,
     pub ry: Vec<DateTime<FixedOffset>>,
+    /// Whether snapshots older than all bins are kept
+    pub keep_beyond_window: bool,
+    /// Whether a snapshot created in this run represents its bins regardless of the anchor
+    pub prefer_created: bool,
+    pub beyond: Vec<Rc<RefCell<Intent<'a>>>>,
+    /// Representatives that lost their bin to a snapshot created in this run
+    pub displaced: Vec<Rc<RefCell<Intent<'a>>>>,
+    /// Time zone whose calendar the bins follow across changes of its offset, set through `localize`
+    pub timezone: Option<Tz>,
+    /// How many bins each tier has by the retention, from minutely to yearly, including those not created because
+    /// they predate the oldest snapshot
+    pub capacity: [usize; 6],
 }
 
 impl<'a> TimeBins<'a> {
-    pub fn new(retention: &Retention) -> Self {
-        let h: HashMap<DateTime<FixedOffset>, Rc<RefCell<Intent>>> = HashMap::new();
+    /// Creates the bins for the given retention, counting back from `now`
+    pub fn new(retention: &Retention, now: &DateTime<FixedOffset>, anchor: BinAnchor) -> Self {
+        Self::covering(retention, now, anchor, None)
+    }
+
+    /// Creates the bins for the given retention, counting back from `now`, but stops each tier shortly after its bins
+    /// predate `oldest`, since no snapshot could fall into the older ones
+    pub fn covering(
+        retention: &Retention,
+        now: &DateTime<FixedOffset>,
+        anchor: BinAnchor,
+        oldest: Option<&DateTime<FixedOffset>>,
+    ) -> Self {
+        let n: Bins = HashMap::new();
+        let mut rn: Vec<DateTime<FixedOffset>> = Vec::new();
+        let h: Bins = HashMap::new();
         let mut rh: Vec<DateTime<FixedOffset>> = Vec::new();
-        let d: HashMap<DateTime<FixedOffset>, Rc<RefCell<Intent>>> = HashMap::new();
+        let d: Bins = HashMap::new();
         let mut rd: Vec<DateTime<FixedOffset>> = Vec::new();
-        let w: HashMap<DateTime<FixedOffset>, Rc<RefCell<Intent>>> = HashMap::new();
+        let w: Bins = HashMap::new();
         let mut rw: Vec<DateTime<FixedOffset>> = Vec::new();
-        let m: HashMap<DateTime<FixedOffset>, Rc<RefCell<Intent>>> = HashMap::new();
+        let m: Bins = HashMap::new();
         let mut rm: Vec<DateTime<FixedOffset>> = Vec::new();
-        let y: HashMap<DateTime<FixedOffset>, Rc<RefCell<Intent>>> = HashMap::new();
+        let y: Bins = HashMap::new();
         let mut ry: Vec<DateTime<FixedOffset>> = Vec::new();
 
-        let local_now: DateTime<Local> = Local::now();
-        let now: DateTime<FixedOffset> = local_now.with_timezone(local_now.offset());
         trace!("timebin creation now: {:?}", now);
 
-        let this_hour = duration_trunc_hour(&now);
+        let recent_start = (retention.all_h > 0).then(|| now.sub(Duration::hours(retention.all_h as i64)));
+        // a bin or two before the oldest snapshot are still created, in case localizing the bins shifts them
+        let past_oldest = |bins: &[DateTime<FixedOffset>]| {
+            oldest.is_some_and(|oldest| bins.iter().rev().take(2).filter(|bin| *bin < oldest).count() == 2)
+        };
+
+        // unlike the other tiers, there is no bin for the current minute unless minutes are retained, so that snapshots
+        // are never kept for their minute otherwise
+        if retention.n > 0 {
+            let this_minute = duration_trunc_minute(now);
+            for i in 0..=retention.n {
+                rn.push(this_minute.sub(Duration::minutes(i as i64)));
+                if past_oldest(&rn) {
+                    break;
+                }
+            }
+        }
+
+        let this_hour = duration_trunc_hour(now);
         for i in 0..=retention.h {
             let bin_hour = this_hour.sub(Duration::hours(i as i64));
             rh.push(bin_hour);
+            if past_oldest(&rh) {
+                break;
+            }
         }
 
-        let this_day = duration_trunc_day(&now);
+        let this_day = duration_trunc_day(now);
         for i in 0..=retention.d {
             let bin_day = this_day.sub(Duration::days(i as i64));
             rd.push(bin_day);
+            if past_oldest(&rd) {
+                break;
+            }
         }
 
-        let this_week = duration_trunc_week(&now);
+        let this_week = duration_trunc_week(now);
         for i in 0..=retention.w {
             let bin_week = this_week.sub(Duration::weeks(i as i64));
             rw.push(bin_week);
+            if past_oldest(&rw) {
+                break;
+            }
         }
 
-        let this_month = duration_trunc_month(&now);
+        // months and years differ in length, so their bins step back by calendar months
+        let this_month = duration_trunc_month(now);
         for i in 0..=retention.m {
-            let bin_month = this_month.sub(Duration::weeks(4 * i as i64));
-            rm.push(bin_month);
+            if let Some(bin_month) = sub_months(&this_month, i as i64) {
+                rm.push(duration_trunc_month(&bin_month));
+            }
+            if past_oldest(&rm) {
+                break;
+            }
         }
 
-        let this_year = duration_trunc_year(&now);
+        let this_year = duration_trunc_year(now);
         for i in 0..=retention.y {
-            let bin_year = this_year.sub(Duration::days(365 * i as i64));
-            ry.push(bin_year);
+            if let Some(bin_year) = sub_months(&this_year, 12 * i as i64) {
+                ry.push(duration_trunc_year(&bin_year));
+            }
+            if past_oldest(&ry) {
+                break;
+            }
         }
 
         Self {
+            anchor,
+            recent_start,
+            recent: Vec::new(),
+            n,
+            rn,
             h,
             rh,
             d,
@@ -84,50 +163,735 @@ impl<'a> TimeBins<'a> {
             rm,
             y,
             ry,
+            keep_beyond_window: false,
+            prefer_created: false,
+            beyond: Vec::new(),
+            displaced: Vec::new(),
+            timezone: None,
+            capacity: [
+                if retention.n > 0 { retention.n + 1 } else { 0 },
+                retention.h + 1,
+                retention.d + 1,
+                retention.w + 1,
+                retention.m + 1,
+                retention.y + 1,
+            ],
         }
     }
 
+    /// Makes the day, week, month and year bins start at midnight in `tz`, with the offset `tz` has on that day rather
+    /// than the offset of now, so that bins stay on the calendar across daylight saving time. Snapshots are then binned
+    /// by their time in `tz`.
+    pub fn localize(&mut self, tz: Tz) {
+        self.timezone = Some(tz);
+        for bins in [&mut self.rd, &mut self.rw, &mut self.rm, &mut self.ry] {
+            for bin in bins.iter_mut() {
+                *bin = localize(bin, &tz);
+            }
+        }
+    }
+
+    /// The start of the oldest bin of any tier
+    fn window_start(&self) -> Option<DateTime<FixedOffset>> {
+        [&self.rn, &self.rh, &self.rd, &self.rw, &self.rm, &self.ry]
+            .iter()
+            .filter_map(|bins| bins.last())
+            .min()
+            .copied()
+    }
+
     pub fn store(&mut self, intent_timestamp: &DateTime<FixedOffset>, intent: Rc<RefCell<Intent<'a>>>) {
+        let timezone = self.timezone;
+        let in_zone = timezone.map_or(*intent_timestamp, |tz| to_timezone(intent_timestamp, &tz));
+        let on_calendar = |bin: DateTime<FixedOffset>| timezone.map_or(bin, |tz| localize(&bin, &tz));
+        let ts_minutely = duration_trunc_minute(intent_timestamp);
         let ts_hourly = duration_trunc_hour(intent_timestamp);
-        let ts_daily = duration_trunc_day(intent_timestamp);
-        let ts_weekly = duration_trunc_week(intent_timestamp);
-        let ts_monthly = duration_trunc_month(intent_timestamp);
-        let ts_yearly = duration_trunc_year(intent_timestamp);
+        let ts_daily = on_calendar(duration_trunc_day(&in_zone));
+        let ts_weekly = on_calendar(duration_trunc_week(&in_zone));
+        let ts_monthly = on_calendar(duration_trunc_month(&in_zone));
+        let ts_yearly = on_calendar(duration_trunc_year(&in_zone));
 
+        trace!("from ts: {:?} ts_minutely: {:?}", intent_timestamp, ts_minutely);
         trace!("from ts: {:?} ts_hourly: {:?}", intent_timestamp, ts_hourly);
         trace!("from ts: {:?} ts_daily: {:?}", intent_timestamp, ts_daily);
         trace!("from ts: {:?} ts_weekly: {:?}", intent_timestamp, ts_weekly);
         trace!("from ts: {:?} ts_monthly: {:?}", intent_timestamp, ts_monthly);
         trace!("from ts: {:?} ts_yearly: {:?}", intent_timestamp, ts_yearly);
 
-        if self.rh.contains(&ts_hourly) {
-            self.h.insert(ts_hourly, intent);
-        } else if self.rd.contains(&ts_daily) {
-            self.d.insert(ts_daily, intent);
-        } else if self.rw.contains(&ts_weekly) {
-            self.w.insert(ts_weekly, intent);
-        } else if self.rm.contains(&ts_monthly) {
-            self.m.insert(ts_monthly, intent);
-        } else if self.ry.contains(&ts_yearly) {
-            self.y.insert(ts_yearly, intent);
+        if self.recent_start.is_some_and(|start| *intent_timestamp >= start) {
+            self.recent.push(intent);
+            return;
+        }
+
+        // a snapshot competes for its bin in the finest tier that has one. It also stands in for its bins in the coarser
+        // tiers until a snapshot of those tiers takes them, so that those are only ever kept in addition
+        let tiers = [
+            (&self.rn, &mut self.n, ts_minutely),
+            (&self.rh, &mut self.h, ts_hourly),
+            (&self.rd, &mut self.d, ts_daily),
+            (&self.rw, &mut self.w, ts_weekly),
+            (&self.rm, &mut self.m, ts_monthly),
+            (&self.ry, &mut self.y, ts_yearly),
+        ];
+        let mut binned = false;
+        for (range, bins, bin) in tiers {
+            if range.contains(&bin) {
+                let replaced = Self::insert(
+                    bins,
+                    bin,
+                    intent_timestamp,
+                    Rc::clone(&intent),
+                    self.anchor,
+                    self.prefer_created,
+                    binned,
+                );
+                if let Some(replaced) = replaced.filter(|_| intent.borrow().intent == IntentType::Create) {
+                    self.displaced.push(replaced);
+                }
+                binned = true;
+            }
+        }
+
+        if !binned && self.keep_beyond_window && self.window_start().is_some_and(|start| *intent_timestamp < start) {
+            self.beyond.push(intent);
         }
     }
 
+    /// Stores the intent as the representative of its bin, unless the bin already holds one preferred by the anchor.
+    /// With `prefer_created`, a snapshot created in this run is preferred over all others, as it is the most current
+    /// representative. Otherwise it competes for its bin like the existing snapshots. A snapshot `standing_in` for the
+    /// bin only takes it from other stand-ins, and loses it to any snapshot of the bin's own tier. Returns the
+    /// representative the intent replaced, if any.
+    fn insert(
+        bins: &mut Bins<'a>,
+        bin: DateTime<FixedOffset>,
+        intent_timestamp: &DateTime<FixedOffset>,
+        intent: Rc<RefCell<Intent<'a>>>,
+        anchor: BinAnchor,
+        prefer_created: bool,
+        standing_in: bool,
+    ) -> Option<Rc<RefCell<Intent<'a>>>> {
+        let created = |int: &Rc<RefCell<Intent>>| prefer_created && int.borrow().intent == IntentType::Create;
+        let replace = match bins.get(&bin) {
+            None => true,
+            Some((_representative, _int, stand_in)) if *stand_in != standing_in => *stand_in,
+            Some((_representative, int, _stand_in)) if created(int) => false,
+            Some(_) if created(&intent) => true,
+            Some((representative, _int, _stand_in)) => match anchor {
+                BinAnchor::First => intent_timestamp <= representative,
+                BinAnchor::Last => intent_timestamp > representative,
+                BinAnchor::Nearest(time) => {
+                    let distance = time_of_day_distance(intent_timestamp, time);
+                    let representative_distance = time_of_day_distance(representative, time);
+                    distance < representative_distance
+                        || (distance == representative_distance && intent_timestamp <= representative)
+                }
+            },
+        };
+
+        if !replace {
+            return None;
+        }
+        bins.insert(bin, (*intent_timestamp, intent, standing_in))
+            .map(|(_representative, int, _stand_in)| int)
+    }
+
+    /// For each tier, how many bins hold a snapshot and how many bins there are
+    pub fn usage(&self) -> [(KeepReason, usize, usize); 6] {
+        [
+            (KeepReason::Minutely, self.n.len(), self.capacity[0]),
+            (KeepReason::Hourly, self.h.len(), self.capacity[1]),
+            (KeepReason::Daily, self.d.len(), self.capacity[2]),
+            (KeepReason::Weekly, self.w.len(), self.capacity[3]),
+            (KeepReason::Monthly, self.m.len(), self.capacity[4]),
+            (KeepReason::Yearly, self.y.len(), self.capacity[5]),
+        ]
+    }
+
+    /// Keeps the representatives of all bins. Coarser tiers go first, so that a snapshot representing bins of several
+    /// tiers is kept with the reason of the finest one. Representatives displaced by a snapshot created in this run
+    /// that no other bin keeps are marked as displaced.
     pub fn set_keep(&self) {
-        for int in self.h.values() {
-            (**int).borrow_mut().intent = IntentType::Keep;
+        for int in &self.beyond {
+            Self::keep(int, KeepReason::Beyond);
+        }
+        for (_ts, int, _stand_in) in self.y.values() {
+            Self::keep(int, KeepReason::Yearly);
+        }
+        for (_ts, int, _stand_in) in self.m.values() {
+            Self::keep(int, KeepReason::Monthly);
+        }
+        for (_ts, int, _stand_in) in self.w.values() {
+            Self::keep(int, KeepReason::Weekly);
+        }
+        for (_ts, int, _stand_in) in self.d.values() {
+            Self::keep(int, KeepReason::Daily);
+        }
+        for (_ts, int, _stand_in) in self.h.values() {
+            Self::keep(int, KeepReason::Hourly);
         }
-        for int in self.d.values() {
-            (**int).borrow_mut().intent = IntentType::Keep;
+        for (_ts, int, _stand_in) in self.n.values() {
+            Self::keep(int, KeepReason::Minutely);
         }
-        for int in self.w.values() {
-            (**int).borrow_mut().intent = IntentType::Keep;
+        for int in &self.recent {
+            Self::keep(int, KeepReason::Recent);
         }
-        for int in self.m.values() {
-            (**int).borrow_mut().intent = IntentType::Keep;
+        for int in &self.displaced {
+            let mut int = (**int).borrow_mut();
+            if int.intent == IntentType::Delete {
+                int.displaced = true;
+            }
         }
-        for int in self.y.values() {
-            (**int).borrow_mut().intent = IntentType::Keep;
+    }
+
+    /// Keeps the snapshot of the intent. Snapshots still to be created stay as they are.
+    fn keep(int: &Rc<RefCell<Intent<'a>>>, reason: KeepReason) {
+        let mut int = (**int).borrow_mut();
+        if int.intent != IntentType::Create {
+            int.keep(reason);
+        }
+    }
+}
+
+/// The same wall clock time as `ts` in `tz`, with the offset `tz` has then. Times skipped by `tz` stay as they are.
+fn localize(ts: &DateTime<FixedOffset>, tz: &Tz) -> DateTime<FixedOffset> {
+    match tz.from_local_datetime(&ts.naive_local()).earliest() {
+        Some(local) => local.with_timezone(&local.offset().fix()),
+        None => *ts,
+    }
+}
+
+/// Seconds between the time of day of `ts` and `time`, going around midnight if that is shorter
+fn time_of_day_distance(ts: &DateTime<FixedOffset>, time: NaiveTime) -> u32 {
+    const DAY: u32 = 24 * 60 * 60;
+    let distance = ts
+        .time()
+        .num_seconds_from_midnight()
+        .abs_diff(time.num_seconds_from_midnight());
+    distance.min(DAY - distance)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use chrono::{DateTime, Datelike, Duration, FixedOffset, Local, NaiveTime, TimeZone, Timelike};
+    use chrono_tz::Tz;
+
+    use crate::duration::{
+        duration_trunc_day, duration_trunc_hour, duration_trunc_minute, duration_trunc_month, duration_trunc_week,
+        duration_trunc_year,
+    };
+    use crate::intent::{Intent, IntentType, KeepReason};
+    use crate::job::Job;
+    use crate::policies::BinAnchor;
+    use crate::retention::Retention;
+    use crate::timebins::{Bins, TimeBins};
+
+    fn job(preserve: &str) -> Job {
+        serde_yaml::from_str(&format!(
+            "{{subvolume: /home, target: /mnt/btrfs/@/gheesnaps, preserve: {}}}",
+            preserve
+        ))
+        .unwrap()
+    }
+
+    fn intents<'a>(job: &'a Job, timestamps: &[DateTime<FixedOffset>]) -> Vec<Rc<RefCell<Intent<'a>>>> {
+        timestamps
+            .iter()
+            .map(|ts| {
+                Rc::new(RefCell::new(Intent {
+                    intent: IntentType::Delete,
+                    subvolume: job.subvolume.clone(),
+                    target: job.target.clone(),
+                    name: job.name_scheme().format(job.subvolume_name().unwrap(), ts),
+                    notes: String::new(),
+                    keep_reason: None,
+                    size: None,
+                    displaced: false,
+                    job,
+                }))
+            })
+            .collect()
+    }
+
+    fn keep_after_binning(
+        intents: &[Rc<RefCell<Intent>>],
+        retention: &Retention,
+        now: &DateTime<FixedOffset>,
+        anchor: BinAnchor,
+    ) -> Vec<IntentType> {
+        let mut timebins = TimeBins::new(retention, now, anchor);
+        for intent in intents {
+            let ts = intent.borrow().timestamp().unwrap();
+            timebins.store(&ts, Rc::clone(intent));
+        }
+        timebins.set_keep();
+
+        intents.iter().map(|i| i.borrow().intent.clone()).collect()
+    }
+
+    #[test]
+    fn calendar_bins() {
+        let tz = FixedOffset::east_opt(3600).unwrap();
+        let now = tz.with_ymd_and_hms(2024, 3, 31, 18, 45, 0).unwrap();
+        let bins = TimeBins::new(&"14m 5y".parse::<Retention>().unwrap(), &now, BinAnchor::First);
+
+        assert_eq!(bins.ry.len(), 6);
+        for (i, year) in bins.ry.iter().enumerate() {
+            assert_eq!(*year, tz.with_ymd_and_hms(2024 - i as i32, 1, 1, 0, 0, 0).unwrap());
+        }
+        assert_eq!(bins.rm.len(), 15);
+        assert!(bins.rm.iter().all(|month| month.day() == 1 && month.hour() == 0));
+        assert_eq!(bins.rm[1], tz.with_ymd_and_hms(2024, 2, 1, 0, 0, 0).unwrap());
+        assert_eq!(bins.rm[14], tz.with_ymd_and_hms(2023, 1, 1, 0, 0, 0).unwrap());
+
+        // a snapshot from New Year's several years back still lands in its yearly bin
+        let job = job("{retention: 5y, min: 0}");
+        let timestamps = [tz.with_ymd_and_hms(2020, 1, 1, 0, 5, 0).unwrap()];
+        let kept = keep_after_binning(
+            &intents(&job, &timestamps),
+            &"5y".parse::<Retention>().unwrap(),
+            &now,
+            BinAnchor::First,
+        );
+        assert_eq!(kept, vec![IntentType::Keep]);
+    }
+
+    #[test]
+    fn bins_follow_daylight_saving_time() {
+        let berlin: Tz = "Europe/Berlin".parse().unwrap();
+        let summer = FixedOffset::east_opt(7200).unwrap();
+        let winter = FixedOffset::east_opt(3600).unwrap();
+        let now = summer.with_ymd_and_hms(2022, 4, 1, 12, 0, 0).unwrap();
+        let retention = "30d".parse::<Retention>().unwrap();
+        let job = job("{retention: 30d, min: 0}");
+        // two snapshots of the same winter day, the later one after 23:00, which is past midnight in summer time
+        let timestamps = [
+            winter.with_ymd_and_hms(2022, 3, 20, 23, 30, 0).unwrap(),
+            winter.with_ymd_and_hms(2022, 3, 20, 12, 0, 0).unwrap(),
+        ];
+
+        let binned = |localized: bool| {
+            let intents = intents(&job, &timestamps);
+            let mut timebins = TimeBins::new(&retention, &now, BinAnchor::First);
+            if localized {
+                timebins.localize(berlin);
+            }
+            for intent in &intents {
+                let ts = intent.borrow().timestamp().unwrap();
+                timebins.store(&ts.with_timezone(now.offset()), Rc::clone(intent));
+            }
+            timebins.set_keep();
+            intents.iter().map(|i| i.borrow().intent.clone()).collect::<Vec<_>>()
+        };
+
+        // with the fixed offset of now, the late snapshot counts for the next day
+        assert_eq!(binned(false), vec![IntentType::Keep, IntentType::Keep]);
+        assert_eq!(binned(true), vec![IntentType::Delete, IntentType::Keep]);
+
+        let mut timebins = TimeBins::new(&retention, &now, BinAnchor::First);
+        timebins.localize(berlin);
+        assert_eq!(timebins.rd[0], summer.with_ymd_and_hms(2022, 4, 1, 0, 0, 0).unwrap());
+        assert_eq!(timebins.rd[12], winter.with_ymd_and_hms(2022, 3, 20, 0, 0, 0).unwrap());
+    }
+
+    #[test]
+    fn bins_anchored_to_newest_snapshot() {
+        let tz = FixedOffset::east_opt(0).unwrap();
+        let timestamps = [
+            tz.with_ymd_and_hms(2022, 1, 20, 12, 0, 0).unwrap(),
+            tz.with_ymd_and_hms(2022, 1, 19, 12, 0, 0).unwrap(),
+            tz.with_ymd_and_hms(2022, 1, 18, 12, 0, 0).unwrap(),
+            tz.with_ymd_and_hms(2021, 12, 1, 12, 0, 0).unwrap(),
+        ];
+        let job = job("{retention: 3d, min: 0}");
+        let retention = job.retention().unwrap();
+
+        let anchored = intents(&job, &timestamps);
+        let kept = keep_after_binning(&anchored, &retention, &timestamps[0], BinAnchor::First);
+        assert_eq!(
+            kept,
+            vec![IntentType::Keep, IntentType::Keep, IntentType::Keep, IntentType::Delete]
+        );
+
+        let local_now = Local::now();
+        let now = local_now.with_timezone(local_now.offset());
+        let unanchored = intents(&job, &timestamps);
+        let kept = keep_after_binning(&unanchored, &retention, &now, BinAnchor::First);
+        assert!(kept.iter().all(|i| *i == IntentType::Delete));
+    }
+
+    #[test]
+    fn keeps_all_recent() {
+        let tz = FixedOffset::east_opt(0).unwrap();
+        let now = tz.with_ymd_and_hms(2022, 1, 20, 12, 5, 0).unwrap();
+        // every 15 minutes for the last four hours, newest first
+        let timestamps = (0..16)
+            .map(|i| tz.with_ymd_and_hms(2022, 1, 20, 12, 0, 0).unwrap() - chrono::Duration::minutes(15 * i))
+            .collect::<Vec<_>>();
+        let job = job("{retention: 2h-all 4h, min: 0}");
+        let retention = job.retention().unwrap();
+        assert_eq!((retention.all_h, retention.h), (2, 4));
+
+        let all = intents(&job, &timestamps);
+        let kept = keep_after_binning(&all, &retention, &now, BinAnchor::First);
+        let reasons = all.iter().map(|i| i.borrow().keep_reason).collect::<Vec<_>>();
+
+        // 12:00 back to 10:15 are within the last two hours and all kept
+        assert!(reasons[..8].iter().all(|r| *r == Some(KeepReason::Recent)));
+        // before that, only the first snapshot of each hour: 10:00 represents its hour, 09:00 and 08:15 theirs
+        assert_eq!(
+            kept[8..],
+            [
+                IntentType::Keep,
+                IntentType::Delete,
+                IntentType::Delete,
+                IntentType::Delete,
+                IntentType::Keep,
+                IntentType::Delete,
+                IntentType::Delete,
+                IntentType::Keep,
+            ]
+        );
+        assert_eq!(reasons[8], Some(KeepReason::Hourly));
+
+        let hourly = "4h".parse::<Retention>().unwrap();
+        let kept = keep_after_binning(&intents(&job, &timestamps), &hourly, &now, BinAnchor::First);
+        assert_eq!(kept.iter().filter(|i| **i == IntentType::Keep).count(), 5);
+        assert!("4h 2h-all".parse::<Retention>().is_err());
+    }
+
+    #[test]
+    fn minute_bins() {
+        let tz = FixedOffset::east_opt(0).unwrap();
+        let now = tz.with_ymd_and_hms(2022, 1, 20, 12, 5, 30).unwrap();
+        // every 20 seconds for the last three minutes, newest first
+        let timestamps = (0..9)
+            .map(|i| tz.with_ymd_and_hms(2022, 1, 20, 12, 5, 20).unwrap() - chrono::Duration::seconds(20 * i))
+            .collect::<Vec<_>>();
+        let job = job("{retention: 2M, min: 0}");
+        let retention = job.retention().unwrap();
+
+        let all = intents(&job, &timestamps);
+        let kept = keep_after_binning(&all, &retention, &now, BinAnchor::First);
+        let reasons = all.iter().map(|i| i.borrow().keep_reason).collect::<Vec<_>>();
+
+        // the first snapshot of 12:05, 12:04 and 12:03 each, while the one of 12:02 represents the current hour
+        assert_eq!(
+            kept,
+            vec![
+                IntentType::Delete,
+                IntentType::Keep,
+                IntentType::Delete,
+                IntentType::Delete,
+                IntentType::Keep,
+                IntentType::Delete,
+                IntentType::Delete,
+                IntentType::Keep,
+                IntentType::Keep,
+            ]
+        );
+        assert_eq!(reasons[1], Some(KeepReason::Minutely));
+        assert_eq!(reasons[8], Some(KeepReason::Hourly));
+    }
+
+    #[test]
+    fn snapshot_represents_several_tiers() {
+        let tz = FixedOffset::east_opt(0).unwrap();
+        // a Wednesday, with one snapshot on Tuesday and one the week before
+        let now = tz.with_ymd_and_hms(2022, 1, 19, 12, 0, 0).unwrap();
+        let timestamps = [
+            tz.with_ymd_and_hms(2022, 1, 18, 12, 0, 0).unwrap(),
+            tz.with_ymd_and_hms(2022, 1, 12, 12, 0, 0).unwrap(),
+        ];
+        let job = job("{retention: 1d 1w, min: 0}");
+        let retention = job.retention().unwrap();
+
+        let intents = intents(&job, &timestamps);
+        let mut timebins = TimeBins::new(&retention, &now, BinAnchor::Last);
+        for intent in &intents {
+            let ts = intent.borrow().timestamp().unwrap();
+            timebins.store(&ts, Rc::clone(intent));
+        }
+        let tuesday = tz.with_ymd_and_hms(2022, 1, 18, 0, 0, 0).unwrap();
+        let this_week = tz.with_ymd_and_hms(2022, 1, 17, 0, 0, 0).unwrap();
+        assert!(Rc::ptr_eq(&timebins.d[&tuesday].1, &intents[0]));
+        assert!(Rc::ptr_eq(&timebins.w[&this_week].1, &intents[0]));
+
+        timebins.set_keep();
+        let reasons = intents.iter().map(|i| i.borrow().keep_reason).collect::<Vec<_>>();
+        assert_eq!(reasons, vec![Some(KeepReason::Daily), Some(KeepReason::Weekly)]);
+    }
+
+    #[test]
+    fn bin_anchors() {
+        let tz = FixedOffset::east_opt(0).unwrap();
+        let timestamps = [
+            tz.with_ymd_and_hms(2022, 1, 20, 18, 0, 0).unwrap(),
+            tz.with_ymd_and_hms(2022, 1, 20, 12, 0, 0).unwrap(),
+            tz.with_ymd_and_hms(2022, 1, 20, 6, 0, 0).unwrap(),
+            tz.with_ymd_and_hms(2022, 1, 19, 18, 0, 0).unwrap(),
+            tz.with_ymd_and_hms(2022, 1, 19, 6, 0, 0).unwrap(),
+        ];
+        let now = tz.with_ymd_and_hms(2022, 1, 21, 12, 0, 0).unwrap();
+        let job = job("{retention: 3d, min: 0}");
+        let retention = job.retention().unwrap();
+
+        let first = intents(&job, &timestamps);
+        let kept = keep_after_binning(&first, &retention, &now, BinAnchor::First);
+        assert_eq!(
+            kept,
+            vec![
+                IntentType::Delete,
+                IntentType::Delete,
+                IntentType::Keep,
+                IntentType::Delete,
+                IntentType::Keep
+            ]
+        );
+
+        let last = intents(&job, &timestamps);
+        let kept = keep_after_binning(&last, &retention, &now, BinAnchor::Last);
+        assert_eq!(
+            kept,
+            vec![
+                IntentType::Keep,
+                IntentType::Delete,
+                IntentType::Delete,
+                IntentType::Keep,
+                IntentType::Delete
+            ]
+        );
+    }
+
+    #[test]
+    fn keep_reasons() {
+        let tz = FixedOffset::east_opt(0).unwrap();
+        let timestamps = [
+            tz.with_ymd_and_hms(2022, 1, 20, 11, 0, 0).unwrap(),
+            tz.with_ymd_and_hms(2022, 1, 18, 12, 0, 0).unwrap(),
+            tz.with_ymd_and_hms(2021, 12, 1, 12, 0, 0).unwrap(),
+        ];
+        let now = tz.with_ymd_and_hms(2022, 1, 20, 12, 30, 0).unwrap();
+        let job = job("{retention: 2h 3d, min: 0}");
+        let retention = job.retention().unwrap();
+
+        let intents = intents(&job, &timestamps);
+        keep_after_binning(&intents, &retention, &now, BinAnchor::First);
+        let reasons = intents.iter().map(|i| i.borrow().keep_reason).collect::<Vec<_>>();
+        assert_eq!(reasons, vec![Some(KeepReason::Hourly), Some(KeepReason::Daily), None]);
+
+        let mut timebins = TimeBins::new(&retention, &now, BinAnchor::First);
+        for intent in &intents {
+            let ts = intent.borrow().timestamp().unwrap();
+            timebins.store(&ts, Rc::clone(intent));
+        }
+        assert_eq!(timebins.usage()[1], (KeepReason::Hourly, 1, 3));
+        assert_eq!(timebins.usage()[2], (KeepReason::Daily, 2, 4));
+    }
+
+    #[test]
+    fn bins_stop_before_oldest_snapshot() {
+        let tz = FixedOffset::east_opt(0).unwrap();
+        let timestamps = [
+            tz.with_ymd_and_hms(2022, 1, 20, 11, 0, 0).unwrap(),
+            tz.with_ymd_and_hms(2022, 1, 19, 12, 0, 0).unwrap(),
+            tz.with_ymd_and_hms(2022, 1, 18, 12, 0, 0).unwrap(),
+        ];
+        let now = tz.with_ymd_and_hms(2022, 1, 20, 12, 30, 0).unwrap();
+        let retention = "10000h 10000d".parse::<Retention>().unwrap();
+
+        let mut covering = TimeBins::covering(&retention, &now, BinAnchor::First, timestamps.last());
+        assert_eq!(covering.rh.len(), 51);
+        assert_eq!(covering.rd.len(), 4);
+        assert_eq!(covering.usage()[1], (KeepReason::Hourly, 0, 10001));
+
+        // the same snapshots are kept as with all bins
+        let job = job("{retention: 10000h 10000d, min: 0}");
+        let covered = intents(&job, &timestamps);
+        for intent in &covered {
+            let ts = intent.borrow().timestamp().unwrap();
+            covering.store(&ts, Rc::clone(intent));
+        }
+        covering.set_keep();
+        let all = intents(&job, &timestamps);
+        keep_after_binning(&all, &retention, &now, BinAnchor::First);
+        let reasons =
+            |intents: &[Rc<RefCell<Intent>>]| intents.iter().map(|i| i.borrow().keep_reason).collect::<Vec<_>>();
+        assert_eq!(reasons(&covered), reasons(&all));
+        assert_eq!(reasons(&covered), vec![Some(KeepReason::Hourly); 3]);
+    }
+
+    #[test]
+    fn bins_nearest_to_time_of_day() {
+        let tz = FixedOffset::east_opt(0).unwrap();
+        let timestamps = [
+            tz.with_ymd_and_hms(2022, 1, 20, 18, 0, 0).unwrap(),
+            tz.with_ymd_and_hms(2022, 1, 20, 12, 30, 0).unwrap(),
+            tz.with_ymd_and_hms(2022, 1, 20, 11, 0, 0).unwrap(),
+            tz.with_ymd_and_hms(2022, 1, 20, 6, 0, 0).unwrap(),
+            tz.with_ymd_and_hms(2022, 1, 19, 23, 0, 0).unwrap(),
+            tz.with_ymd_and_hms(2022, 1, 19, 1, 0, 0).unwrap(),
+        ];
+        let now = tz.with_ymd_and_hms(2022, 1, 21, 12, 0, 0).unwrap();
+        let job = job("{retention: 3d, min: 0}");
+        let retention = job.retention().unwrap();
+
+        let noon = intents(&job, &timestamps);
+        let kept = keep_after_binning(
+            &noon,
+            &retention,
+            &now,
+            BinAnchor::Nearest(NaiveTime::from_hms_opt(12, 0, 0).unwrap()),
+        );
+        assert_eq!(
+            kept,
+            vec![
+                IntentType::Delete,
+                IntentType::Keep,
+                IntentType::Delete,
+                IntentType::Delete,
+                IntentType::Delete,
+                IntentType::Keep
+            ]
+        );
+
+        // 23:00 and 01:00 are equally close to midnight, the earlier one is kept
+        let midnight = intents(&job, &timestamps);
+        let kept = keep_after_binning(
+            &midnight,
+            &retention,
+            &now,
+            BinAnchor::Nearest(NaiveTime::from_hms_opt(0, 0, 0).unwrap()),
+        );
+        assert_eq!(
+            kept,
+            vec![
+                IntentType::Delete,
+                IntentType::Delete,
+                IntentType::Delete,
+                IntentType::Keep,
+                IntentType::Delete,
+                IntentType::Keep
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_beyond_window() {
+        let tz = FixedOffset::east_opt(0).unwrap();
+        let timestamps = [
+            tz.with_ymd_and_hms(2022, 1, 20, 12, 0, 0).unwrap(),
+            tz.with_ymd_and_hms(2022, 1, 5, 12, 0, 0).unwrap(),
+            tz.with_ymd_and_hms(2022, 1, 4, 12, 0, 0).unwrap(),
+            tz.with_ymd_and_hms(2019, 6, 1, 12, 0, 0).unwrap(),
+        ];
+        let now = tz.with_ymd_and_hms(2022, 1, 21, 12, 0, 0).unwrap();
+        let job = job("{retention: 3d 1y, min: 0}");
+        let retention = job.retention().unwrap();
+        let store = |intents: &[Rc<RefCell<Intent<'_>>>], keep_beyond_window| {
+            let mut timebins = TimeBins::new(&retention, &now, BinAnchor::Last);
+            timebins.keep_beyond_window = keep_beyond_window;
+            for intent in intents {
+                let ts = intent.borrow().timestamp().unwrap();
+                timebins.store(&ts, Rc::clone(intent));
+            }
+            timebins.set_keep();
+            intents.iter().map(|i| i.borrow().keep_reason).collect::<Vec<_>>()
+        };
+
+        // the snapshot of 2022-01-04 is within the window, but not the representative of its bin
+        let deleting = intents(&job, &timestamps);
+        assert_eq!(
+            store(&deleting, false),
+            vec![Some(KeepReason::Daily), Some(KeepReason::Monthly), None, None]
+        );
+
+        let keeping = intents(&job, &timestamps);
+        assert_eq!(
+            store(&keeping, true),
+            vec![
+                Some(KeepReason::Daily),
+                Some(KeepReason::Monthly),
+                None,
+                Some(KeepReason::Beyond)
+            ]
+        );
+    }
+
+    #[test]
+    fn standing_in_never_keeps_less() {
+        let tz = FixedOffset::east_opt(0).unwrap();
+        let now = tz.with_ymd_and_hms(2022, 3, 20, 12, 0, 0).unwrap();
+        // a snapshot every 7 hours and 13 minutes, going back past the start of the year
+        let timestamps = (0..300)
+            .map(|i| now - Duration::minutes(i * (7 * 60 + 13)))
+            .collect::<Vec<_>>();
+        let job = job("{retention: 30M 12h 5d 2w 2m 1y, min: 0}");
+        let retention = job.retention().unwrap();
+        let ranges = |timebins: &TimeBins| {
+            [
+                &timebins.rn,
+                &timebins.rh,
+                &timebins.rd,
+                &timebins.rw,
+                &timebins.rm,
+                &timebins.ry,
+            ]
+            .map(|r| r.clone())
+        };
+        let trunc = |tier: usize, ts: &DateTime<FixedOffset>| match tier {
+            0 => duration_trunc_minute(ts),
+            1 => duration_trunc_hour(ts),
+            2 => duration_trunc_day(ts),
+            3 => duration_trunc_week(ts),
+            4 => duration_trunc_month(ts),
+            _ => duration_trunc_year(ts),
+        };
+
+        for anchor in [
+            BinAnchor::First,
+            BinAnchor::Last,
+            BinAnchor::Nearest(NaiveTime::from_hms_opt(3, 0, 0).unwrap()),
+        ] {
+            let intents = intents(&job, &timestamps);
+            let mut timebins = TimeBins::new(&retention, &now, anchor);
+            for intent in &intents {
+                let ts = intent.borrow().timestamp().unwrap();
+                timebins.store(&ts, Rc::clone(intent));
+            }
+            timebins.set_keep();
+
+            // each snapshot only competing for its bin in the finest tier that has one
+            let ranges = ranges(&timebins);
+            let mut tiers: [Bins; 6] = Default::default();
+            for (ts, intent) in timestamps.iter().zip(&intents) {
+                if let Some(tier) = (0..6).find(|&t| ranges[t].contains(&trunc(t, ts))) {
+                    TimeBins::insert(
+                        &mut tiers[tier],
+                        trunc(tier, ts),
+                        ts,
+                        Rc::clone(intent),
+                        anchor,
+                        false,
+                        false,
+                    );
+                }
+            }
+            let kept_by_finest = tiers
+                .iter()
+                .flat_map(|bins| bins.values())
+                .map(|(ts, _int, _stand_in)| *ts)
+                .collect::<Vec<_>>();
+
+            let kept = intents
+                .iter()
+                .filter(|i| i.borrow().intent == IntentType::Keep)
+                .map(|i| i.borrow().timestamp().unwrap())
+                .collect::<Vec<_>>();
+            assert!(kept_by_finest.iter().all(|ts| kept.contains(ts)), "{:?}", anchor);
         }
     }
 }
@@ -0,0 +1,193 @@
+use std::error::Error;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Duration, FixedOffset, TimeZone};
+use log::{info, warn};
+use tabled::{Style, Table, Tabled};
+
+use crate::job::Job;
+
+/// Directory in each snapshot directory that trashed snapshots are moved to
+pub const TRASH_DIRECTORY: &str = ".ghee-trash";
+
+/// Where `snapshot` is moved to when it is trashed at `trashed`: the trash of its directory, with the time of trashing
+/// in seconds since the epoch in front of its name
+pub fn trash_path(snapshot: &Path, trashed: &DateTime<FixedOffset>) -> PathBuf {
+    let directory = snapshot.parent().unwrap_or_else(|| Path::new("")).join(TRASH_DIRECTORY);
+    let name = snapshot.file_name().unwrap_or_default().to_string_lossy();
+    directory.join(format!("{}_{}", trashed.timestamp(), name))
+}
+
+/// Moves the snapshot into the trash of its directory instead of deleting it. Renaming a subvolume within its
+/// filesystem is cheap, and the snapshot stays intact until the trash is emptied.
+pub fn move_to_trash(snapshot: &Path, trashed: &DateTime<FixedOffset>) -> Result<PathBuf, Box<dyn Error>> {
+    let path = trash_path(snapshot, trashed);
+    if let Some(trash) = path.parent() {
+        fs::create_dir_all(trash)?;
+    }
+    fs::rename(snapshot, &path)?;
+
+    Ok(path)
+}
+
+/// A snapshot in the trash
+#[derive(Debug, PartialEq, Eq, Tabled)]
+pub struct TrashedSnapshot {
+    /// Where the snapshot was before it was trashed
+    pub original: String,
+    pub trashed: DateTime<FixedOffset>,
+    pub path: String,
+}
+
+impl TrashedSnapshot {
+    /// Reads the original location and time of trashing from the path of a snapshot in the trash
+    fn parse(path: &Path) -> Option<Self> {
+        let (trashed, name) = path.file_name()?.to_str()?.split_once('_')?;
+        let trashed = FixedOffset::east_opt(0)?
+            .timestamp_opt(trashed.parse().ok()?, 0)
+            .single()?;
+        let directory = path.parent()?.parent()?;
+
+        Some(Self {
+            original: directory.join(name).to_string_lossy().to_string(),
+            trashed,
+            path: path.to_string_lossy().to_string(),
+        })
+    }
+
+    /// Lists the snapshots in the trash of each job's snapshot directory, oldest first
+    pub fn gather(jobs: &[&Job]) -> Vec<Self> {
+        let mut directories = jobs.iter().map(|j| j.snapshot_directory()).collect::<Vec<_>>();
+        directories.sort();
+        directories.dedup();
+
+        let mut trashed = Vec::new();
+        for directory in directories {
+            let trash = Path::new(&directory).join(TRASH_DIRECTORY);
+            let entries = match fs::read_dir(&trash) {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => {
+                    warn!("Unable to read trash {:?}! Error: {}", trash, e);
+                    continue;
+                }
+            };
+            for entry in entries.flatten() {
+                match Self::parse(&entry.path()) {
+                    Some(snapshot) => trashed.push(snapshot),
+                    None => warn!("{:?} in the trash was not put there by ghee, leaving it", entry.path()),
+                }
+            }
+        }
+        trashed.sort_by(|a, b| (a.trashed, &a.path).cmp(&(b.trashed, &b.path)));
+
+        trashed
+    }
+
+    /// The snapshots that were trashed at least `grace` before `now`
+    pub fn expired(trashed: Vec<Self>, grace: Duration, now: &DateTime<FixedOffset>) -> Vec<Self> {
+        trashed.into_iter().filter(|t| t.trashed + grace <= *now).collect()
+    }
+
+    /// Deletes the trashed snapshots with `delete`, returning how many of them couldn't be deleted
+    pub fn empty(trashed: &[Self], delete: impl Fn(&Path) -> Result<(), Box<dyn Error>>) -> usize {
+        let mut failed = 0;
+        for snapshot in trashed {
+            if let Err(e) = delete(Path::new(&snapshot.path)) {
+                warn!("deleting trashed snapshot {} failed! error: {}", &snapshot.path, e);
+                failed += 1;
+            }
+        }
+
+        failed
+    }
+
+    pub fn print_tabled(trashed: &[Self]) {
+        if trashed.is_empty() {
+            info!("No trashed snapshots are due for deletion.");
+            return;
+        }
+
+        let table = Table::new(trashed).with(Style::modern()).to_string();
+        info!("{}", table);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::fs;
+    use std::path::Path;
+
+    use chrono::{Duration, FixedOffset, TimeZone};
+
+    use crate::job::Job;
+    use crate::trash::{move_to_trash, trash_path, TrashedSnapshot, TRASH_DIRECTORY};
+
+    #[test]
+    fn trashing_and_emptying() {
+        let dir = std::env::temp_dir().join(format!("ghee-trash-test-{}", std::process::id()));
+        let home: Job = serde_yaml::from_str(&format!(
+            "{{subvolume: /home, target: {}, trash: true, preserve: {{min: all}}}}",
+            dir.display()
+        ))
+        .unwrap();
+        let utc = FixedOffset::east_opt(0).unwrap();
+        let monday = utc.with_ymd_and_hms(2022, 1, 17, 12, 0, 0).unwrap();
+        let thursday = utc.with_ymd_and_hms(2022, 1, 20, 12, 0, 0).unwrap();
+
+        for (name, trashed) in [
+            ("home.2022-01-10T12:00:00Z", monday),
+            ("home.2022-01-11T12:00:00Z", thursday),
+        ] {
+            let snapshot = dir.join(name);
+            fs::create_dir_all(snapshot.join("sub")).unwrap();
+            let path = move_to_trash(&snapshot, &trashed).unwrap();
+            assert_eq!(path, trash_path(&snapshot, &trashed));
+            assert!(!snapshot.exists());
+            // still intact in the trash
+            assert!(path.join("sub").exists());
+        }
+        assert_eq!(
+            trash_path(&dir.join("home.2022-01-10T12:00:00Z"), &monday),
+            dir.join(TRASH_DIRECTORY).join("1642420800_home.2022-01-10T12:00:00Z")
+        );
+
+        let trashed = TrashedSnapshot::gather(&[&home]);
+        let originals = trashed
+            .iter()
+            .map(|t| (t.original.clone(), t.trashed))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            originals,
+            vec![
+                (
+                    dir.join("home.2022-01-10T12:00:00Z").to_string_lossy().to_string(),
+                    monday
+                ),
+                (
+                    dir.join("home.2022-01-11T12:00:00Z").to_string_lossy().to_string(),
+                    thursday
+                ),
+            ]
+        );
+
+        let now = utc.with_ymd_and_hms(2022, 1, 24, 12, 0, 0).unwrap();
+        let expired = TrashedSnapshot::expired(trashed, Duration::days(7), &now);
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].trashed, monday);
+
+        let deleted = RefCell::new(Vec::new());
+        let failed = TrashedSnapshot::empty(&expired, |path| {
+            deleted.borrow_mut().push(path.to_path_buf());
+            Ok(fs::remove_dir_all(path)?)
+        });
+        assert_eq!(failed, 0);
+        assert_eq!(*deleted.borrow(), vec![Path::new(&expired[0].path).to_path_buf()]);
+        assert_eq!(TrashedSnapshot::gather(&[&home]).len(), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
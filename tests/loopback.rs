@@ -0,0 +1,131 @@
+//! End-to-end tests against a real btrfs filesystem in a loopback image.
+//!
+//! These tests need root privileges as well as `mkfs.btrfs`, `btrfs`, `mount` and `umount`, so they are ignored by
+//! default. Run them with `sudo cargo test --test loopback -- --ignored`.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use chrono::{Duration, Local, SecondsFormat, TimeZone};
+
+struct Loopback {
+    dir: PathBuf,
+    mnt: PathBuf,
+}
+
+impl Loopback {
+    fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("ghee-{}-{}", name, std::process::id()));
+        let mnt = dir.join("mnt");
+        let image = dir.join("btrfs.img");
+        fs::create_dir_all(&mnt).unwrap();
+
+        run("truncate", &["-s", "256M", image.to_str().unwrap()]);
+        run("mkfs.btrfs", &["-q", image.to_str().unwrap()]);
+        run("mount", &["-o", "loop", image.to_str().unwrap(), mnt.to_str().unwrap()]);
+
+        Self { dir, mnt }
+    }
+
+    fn path(&self, path: &str) -> PathBuf {
+        self.mnt.join(path)
+    }
+
+    fn snapshots(&self, target: &str) -> Vec<String> {
+        let mut names = fs::read_dir(self.path(target))
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_str().unwrap().to_string())
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+}
+
+impl Drop for Loopback {
+    fn drop(&mut self) {
+        let _ = Command::new("umount").arg(&self.mnt).status();
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn run(program: &str, args: &[&str]) {
+    let status = Command::new(program).args(args).status().unwrap();
+    assert!(status.success(), "{} {:?} failed", program, args);
+}
+
+fn ghee(loopback: &Loopback, config: &Path, args: &[&str]) {
+    let status = Command::new(env!("CARGO_BIN_EXE_ghee"))
+        .arg("--config")
+        .arg(config)
+        .arg("--root")
+        .arg(&loopback.mnt)
+        .arg("--state-file")
+        .arg(loopback.dir.join("state"))
+        .args(args)
+        .status()
+        .unwrap();
+    assert!(status.success(), "ghee {:?} failed", args);
+}
+
+#[test]
+#[ignore]
+fn run_and_prune_follow_policy() {
+    let loopback = Loopback::new("run-and-prune");
+    run(
+        "btrfs",
+        &["-q", "subvolume", "create", loopback.path("data").to_str().unwrap()],
+    );
+    fs::create_dir(loopback.path("snaps")).unwrap();
+
+    let today = Local::now().date_naive();
+    let yesterday = today - Duration::days(1);
+    let existing = [
+        Local
+            .from_local_datetime(&yesterday.and_hms_opt(2, 0, 0).unwrap())
+            .unwrap(),
+        Local
+            .from_local_datetime(&yesterday.and_hms_opt(1, 0, 0).unwrap())
+            .unwrap(),
+        Local
+            .from_local_datetime(&(today - Duration::days(800)).and_hms_opt(1, 0, 0).unwrap())
+            .unwrap(),
+    ];
+    let names = existing
+        .iter()
+        .map(|ts| format!("data.{}", ts.to_rfc3339_opts(SecondsFormat::Secs, true)))
+        .collect::<Vec<_>>();
+    for name in &names {
+        let snapshot = loopback.path("snaps").join(name);
+        run(
+            "btrfs",
+            &[
+                "-q",
+                "subvolume",
+                "snapshot",
+                "-r",
+                loopback.path("data").to_str().unwrap(),
+                snapshot.to_str().unwrap(),
+            ],
+        );
+    }
+
+    let config = loopback.dir.join("ghee.yaml");
+    fs::write(
+        &config,
+        "jobs:\n  - subvolume: /data\n    target: /snaps\n    preserve:\n      retention: 2d\n      min: 0\n",
+    )
+    .unwrap();
+
+    ghee(&loopback, &config, &["run"]);
+
+    // the older snapshot of yesterday represents the day, the one from two years ago is out of every bin
+    let after_run = loopback.snapshots("snaps");
+    assert_eq!(after_run.len(), 2);
+    assert!(after_run.contains(&names[1]));
+    assert!(!after_run.contains(&names[0]));
+    assert!(!after_run.contains(&names[2]));
+
+    ghee(&loopback, &config, &["prune"]);
+    assert_eq!(loopback.snapshots("snaps"), after_run);
+}
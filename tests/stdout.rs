@@ -0,0 +1,44 @@
+//! Checks that ghee writes nothing but the requested output to stdout, even at the highest verbosity, so that it can be
+//! piped into other tools. Logs, including the trace output of parsing durations and retentions, go to stderr.
+
+use std::fs;
+use std::process::Command;
+
+#[test]
+fn dryrun_json_is_all_of_stdout() {
+    let dir = std::env::temp_dir().join(format!("ghee-stdout-{}", std::process::id()));
+    fs::create_dir_all(dir.join("snaps/home.2022-01-10T12:00:00Z")).unwrap();
+    let config = dir.join("ghee.yaml");
+    fs::write(
+        &config,
+        format!(
+            "jobs:\n  - subvolume: {}\n    target: {}\n    stale_after: 2d\n    preserve:\n      retention: 48h 14d 4w\n      min: 5d\n",
+            dir.join("home").display(),
+            dir.join("snaps").display()
+        ),
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_ghee"))
+        .arg("-vv")
+        .arg("--config")
+        .arg(&config)
+        .arg("--state-file")
+        .arg(dir.join("state"))
+        .arg("--hold-file")
+        .arg(dir.join("HOLD"))
+        .args(["--output", "json", "dryrun"])
+        .output()
+        .unwrap();
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let report: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    let intents = &report["intents"];
+    assert_eq!(intents.as_array().unwrap().len(), 1);
+    assert_eq!(intents[0]["name"], "home.2022-01-10T12:00:00Z");
+    assert!(report.get("executed").is_none());
+    assert_eq!(report["skipped"][0]["reason"], "not_subvolume");
+    assert!(!output.stderr.is_empty());
+
+    fs::remove_dir_all(&dir).unwrap();
+}